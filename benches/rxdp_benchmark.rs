@@ -42,6 +42,10 @@ pub fn benchmark_per_cpu_hash_map(c: &mut Criterion) {
     c.bench_function("per_cpu_lookup", |b| {
         b.iter(|| black_box(m1.lookup(&100u32).unwrap()))
     });
+    let mut values = Vec::new();
+    c.bench_function("per_cpu_lookup_into", |b| {
+        b.iter(|| black_box(m1.lookup_into(&100u32, &mut values).unwrap()))
+    });
     c.bench_function("per_cpu_delete", |b| b.iter(|| black_box(delete(&m1))));
 }
 
@@ -97,5 +101,109 @@ pub fn benchmark_hash_map(c: &mut Criterion) {
     c.bench_function("delete", |b| b.iter(|| black_box(delete(&mut m2))));
 }
 
-criterion_group!(benches, benchmark_hash_map, benchmark_per_cpu_hash_map);
+// Per-op overhead of single element syscalls, compared to batching the same number of entries.
+pub fn benchmark_syscall_batching(c: &mut Criterion) {
+    const N: u32 = 100;
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, "big_hash").unwrap();
+    let mut keys: Vec<u32> = (0..N).collect();
+    let mut vals: Vec<u32> = (0..N).map(|i| i + 1).collect();
+
+    c.bench_function("update_loop_100", |b| {
+        b.iter(|| {
+            for (k, v) in keys.iter().zip(vals.iter()) {
+                black_box(m.update(k, v, rxdp::MapFlags::BpfAny).unwrap());
+            }
+        })
+    });
+    c.bench_function("update_batch_100", |b| {
+        b.iter(|| black_box(m.update_batch(&mut keys, &mut vals, rxdp::MapFlags::BpfAny)))
+    });
+    c.bench_function("lookup_loop_100", |b| {
+        b.iter(|| {
+            for k in keys.iter() {
+                black_box(m.lookup(k).unwrap());
+            }
+        })
+    });
+    c.bench_function("lookup_batch_100", |b| {
+        b.iter(|| black_box(m.lookup_batch(N, None).unwrap()))
+    });
+    c.bench_function("lookup_missing", |b| {
+        b.iter(|| black_box(m.lookup(&(N + 1)).is_err()))
+    });
+
+    let pc: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, "pc_hash_big").unwrap();
+    c.bench_function("per_cpu_update_loop_100", |b| {
+        b.iter(|| {
+            for k in keys.iter() {
+                black_box(pc.update(k, &1u32, rxdp::MapFlags::BpfAny).unwrap());
+            }
+        })
+    });
+    c.bench_function("per_cpu_lookup_loop_100", |b| {
+        b.iter(|| {
+            for k in keys.iter() {
+                black_box(pc.lookup(k).unwrap());
+            }
+        })
+    });
+}
+
+// Single key per-cpu lookups and updates used to encode/decode the values of all CPUs through
+// a heap buffer, they now use a stack buffer. Each group runs both paths on the same map.
+pub fn benchmark_per_cpu_buffers(c: &mut Criterion) {
+    let obj = loaded_object();
+    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, "per_cpu_hash").unwrap();
+    let (key, val) = (100u32, 101u32);
+    let num_cpus = rxdp::num_cpus();
+    m.update(&key, &val, rxdp::MapFlags::BpfAny).unwrap();
+
+    let mut group = c.benchmark_group("per_cpu_update_buffer");
+    group.bench_function("heap", |b| {
+        // `update_values` still encodes into a heap buffer, like `update` did.
+        b.iter(|| {
+            let values = vec![val; num_cpus];
+            black_box(
+                m.update_values(&key, &values, rxdp::MapFlags::BpfAny)
+                    .unwrap(),
+            )
+        })
+    });
+    group.bench_function("stack", |b| {
+        b.iter(|| black_box(m.update(&key, &val, rxdp::MapFlags::BpfAny).unwrap()))
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("per_cpu_lookup_buffer");
+    group.bench_function("heap", |b| {
+        // What `get` did: look up into a heap buffer of 8 byte strides, then decode.
+        b.iter(|| {
+            let mut buf = vec![0u8; num_cpus * 8];
+            let rc = unsafe {
+                libbpf_sys::bpf_map_lookup_elem(
+                    m.map_fd(),
+                    &key as *const u32 as *const _,
+                    buf.as_mut_ptr() as *mut _,
+                )
+            };
+            assert_eq!(rc, 0);
+            let values: Vec<u32> = buf
+                .chunks_exact(8)
+                .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+                .collect();
+            black_box(values)
+        })
+    });
+    group.bench_function("stack", |b| b.iter(|| black_box(m.get(&key).unwrap())));
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    benchmark_hash_map,
+    benchmark_per_cpu_hash_map,
+    benchmark_syscall_batching,
+    benchmark_per_cpu_buffers
+);
 criterion_main!(benches);
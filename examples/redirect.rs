@@ -0,0 +1,46 @@
+//! Loads an XDP object and forwards packets using a DEVMAP.
+//!
+//! The eBPF side is expected to look something like:
+//!
+//! ```c
+//! struct bpf_map_def SEC("maps") tx_ports = {
+//!     .type = BPF_MAP_TYPE_DEVMAP,
+//!     .key_size = sizeof(__u32),
+//!     .value_size = sizeof(int),
+//!     .max_entries = 64,
+//! };
+//!
+//! SEC("xdp_redirect")
+//! int xdp_redirect_prog(struct xdp_md *ctx) {
+//!     return bpf_redirect_map(&tx_ports, 0, 0);
+//! }
+//! ```
+//!
+//! Usage: `redirect <elf file> <ingress iface> <egress iface>...`
+use rxdp::redirect;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 4 {
+        eprintln!(
+            "usage: {} <elf file> <ingress iface> <egress iface>...",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+
+    let obj = rxdp::XDPObject::new(&args[1]).unwrap().load().unwrap();
+
+    let routes: Vec<(u32, &str)> = args[3..]
+        .iter()
+        .enumerate()
+        .map(|(i, iface)| (i as u32, iface.as_str()))
+        .collect();
+    redirect::setup_devmap(&obj, "tx_ports", &routes).unwrap();
+
+    let prog = obj.get_program("xdp_redirect_prog").unwrap();
+    let mode = prog
+        .attach_best_effort(&args[2], rxdp::AttachFlags::empty())
+        .unwrap();
+    println!("Redirecting {} via {:?} mode", args[2], mode);
+}
@@ -0,0 +1,59 @@
+/* C ABI for rxdp, available when built with the `ffi` feature. See src/ffi.rs for details. */
+#ifndef RXDP_H
+#define RXDP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct XDPObject rxdp_object;
+typedef struct XDPLoadedObject rxdp_loaded_object;
+typedef struct DynMap rxdp_map;
+typedef struct RxdpPerfBuffer rxdp_perf_buffer;
+
+typedef void (*rxdp_sample_fn)(void *ctx, int cpu, void *data, uint32_t size);
+typedef void (*rxdp_lost_fn)(void *ctx, int cpu, uint64_t cnt);
+typedef int (*rxdp_map_entry_fn)(void *ctx, const uint8_t *key, size_t key_len,
+                                 const uint8_t *value, size_t value_len);
+
+/* Description of the last error on the calling thread, NULL if there was none. */
+const char *rxdp_last_error(void);
+
+rxdp_object *rxdp_object_open(const char *path);
+void rxdp_object_free(rxdp_object *obj);
+/* Consumes `obj`, even on failure. */
+rxdp_loaded_object *rxdp_object_load(rxdp_object *obj);
+void rxdp_loaded_object_free(rxdp_loaded_object *obj);
+
+int rxdp_program_attach(const rxdp_loaded_object *obj, const char *prog, const char *iface,
+                        uint32_t flags);
+int rxdp_program_detach(const rxdp_loaded_object *obj, const char *prog, const char *iface);
+
+rxdp_map *rxdp_map_open(const rxdp_loaded_object *obj, const char *name);
+void rxdp_map_free(rxdp_map *map);
+/* 0 if `map` is NULL. */
+uint32_t rxdp_map_key_size(const rxdp_map *map);
+size_t rxdp_map_value_len(const rxdp_map *map);
+int rxdp_map_lookup(const rxdp_map *map, const uint8_t *key, size_t key_len, uint8_t *value,
+                    size_t value_len);
+int rxdp_map_update(const rxdp_map *map, const uint8_t *key, size_t key_len,
+                    const uint8_t *value, size_t value_len, uint64_t flags);
+int rxdp_map_delete(const rxdp_map *map, const uint8_t *key, size_t key_len);
+/* Returns 0 once all entries were visited, 1 if `cb` returned non-zero to stop. */
+int rxdp_map_iterate(const rxdp_map *map, rxdp_map_entry_fn cb, void *ctx);
+
+/* `sample_cb` must not be NULL, `lost_cb` may be. */
+rxdp_perf_buffer *rxdp_perf_buffer_new(const rxdp_loaded_object *obj, const char *name,
+                                       size_t page_cnt, rxdp_sample_fn sample_cb,
+                                       rxdp_lost_fn lost_cb, void *ctx);
+int rxdp_perf_buffer_poll(const rxdp_perf_buffer *pb, int timeout_ms);
+void rxdp_perf_buffer_free(rxdp_perf_buffer *pb);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RXDP_H */
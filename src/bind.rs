@@ -0,0 +1,158 @@
+use errno::{set_errno, Errno};
+
+use crate::error::XDPError;
+use crate::object::XDPLoadedObject;
+use crate::percpu_map::ByteAligned;
+use crate::result::XDPResult;
+use crate::{
+    BloomFilter, DynMap, Map, OuterMap, PerCpuMap, PerfMap, ProgArrayMap, QueueMap, RingBuffer,
+    StackTraceMap,
+};
+
+/// Map handles that can be looked up by name in a loaded object, see [`bind_maps!`].
+pub trait BindMap: Sized {
+    /// Get access to the map `map_name` in `xdp`, checking that it matches the handle's types.
+    fn bind(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Self>;
+}
+
+impl<K: Default, V: Default> BindMap for Map<K, V> {
+    fn bind(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Self> {
+        Map::new(xdp, map_name)
+    }
+}
+
+impl<K: Default, V: ByteAligned> BindMap for PerCpuMap<K, V> {
+    fn bind(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Self> {
+        PerCpuMap::new(xdp, map_name)
+    }
+}
+
+impl<T: 'static + Copy + Send> BindMap for PerfMap<T> {
+    fn bind(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Self> {
+        PerfMap::new(xdp, map_name)
+    }
+}
+
+impl<V: Default> BindMap for QueueMap<V> {
+    fn bind(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Self> {
+        QueueMap::new(xdp, map_name)
+    }
+}
+
+impl<K: Default> BindMap for OuterMap<K> {
+    fn bind(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Self> {
+        OuterMap::new(xdp, map_name)
+    }
+}
+
+impl BindMap for ProgArrayMap {
+    fn bind(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Self> {
+        ProgArrayMap::new(xdp, map_name)
+    }
+}
+
+impl BindMap for StackTraceMap {
+    fn bind(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Self> {
+        StackTraceMap::new(xdp, map_name)
+    }
+}
+
+impl<V> BindMap for BloomFilter<V> {
+    fn bind(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Self> {
+        BloomFilter::new(xdp, map_name)
+    }
+}
+
+impl BindMap for DynMap {
+    fn bind(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Self> {
+        DynMap::new(xdp, map_name)
+    }
+}
+
+impl BindMap for RingBuffer {
+    fn bind(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Self> {
+        RingBuffer::new(xdp, map_name)
+    }
+}
+
+/// Combines the errors of binding several maps into a single error, with the code of the first
+/// failure. Used by [`bind_maps!`].
+#[doc(hidden)]
+pub fn _bind_error(errors: Vec<(&str, XDPError)>) -> XDPError {
+    let code = errors.first().map_or(22, |(_, e)| e.code());
+    let failures: Vec<String> = errors
+        .iter()
+        .map(|(name, e)| format!("{}: {}", name, e.description()))
+        .collect();
+
+    set_errno(Errno(code));
+    XDPError::new(&format!(
+        "Error binding {} map(s) [{}]",
+        errors.len(),
+        failures.join("; ")
+    ))
+}
+
+/// Declare a struct of map handles that are all looked up in a loaded object at once, with an
+/// error listing every map that is missing or doesn't match its handle's types.
+///
+/// The item form declares the struct, with a `bind(&XDPLoadedObject)` constructor. The
+/// expression form (`bind_maps!(obj, struct ...)`) declares the struct locally and binds it
+/// right away. Fields can be any [`BindMap`] handle.
+///
+/// # Example
+/// ```no_run
+/// use rxdp::{Map, MapLike, PerCpuMap};
+///
+/// rxdp::bind_maps!(pub struct MyMaps {
+///     counters: Map<u32, u64> = "counters",
+///     flows: PerCpuMap<u32, u64> = "flows",
+/// });
+///
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let maps = MyMaps::bind(&obj).unwrap();
+/// maps.counters.update(&1, &0, rxdp::MapFlags::BpfAny).unwrap();
+///
+/// let maps = rxdp::bind_maps!(&obj, struct Other {
+///     drops: Map<u32, u64> = "drops",
+/// });
+/// if let Err(e) = maps {
+///     // "Error binding 1 map(s) [drops: Unable to find map with name 'drops' in object ...]"
+///     eprintln!("{}", e.description());
+/// }
+/// ```
+#[macro_export]
+macro_rules! bind_maps {
+    ($vis:vis struct $name:ident { $($field:ident : $t:ty = $map:expr),+ $(,)? }) => {
+        $vis struct $name {
+            $($vis $field: $t,)+
+        }
+
+        impl $name {
+            /// Look up all maps in `xdp`. Fails with every missing or mismatched map.
+            $vis fn bind(xdp: &$crate::XDPLoadedObject) -> $crate::XDPResult<$name> {
+                let mut errors = ::std::vec::Vec::new();
+                $(
+                    let $field = match <$t as $crate::BindMap>::bind(xdp, $map) {
+                        Ok(m) => Some(m),
+                        Err(e) => {
+                            errors.push(($map, e));
+                            None
+                        }
+                    };
+                )+
+                if !errors.is_empty() {
+                    return Err($crate::_bind_error(errors));
+                }
+
+                Ok($name {
+                    $($field: $field.unwrap(),)+
+                })
+            }
+        }
+    };
+    ($xdp:expr, struct $name:ident { $($body:tt)+ }) => {{
+        $crate::bind_maps!(struct $name { $($body)+ });
+        $name::bind($xdp)
+    }};
+}
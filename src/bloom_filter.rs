@@ -0,0 +1,139 @@
+use errno::{set_errno, Errno};
+use std::{marker::PhantomData, mem::size_of, os::raw::c_void};
+
+use crate::compat;
+use crate::error::{get_errno, XDPError};
+use crate::map_common as mc;
+use crate::object::XDPLoadedObject;
+use crate::result::XDPResult;
+use crate::utils;
+use crate::{MapFlags, MapKind, MapType};
+
+// The `nr_hashes` limit, the kernel only reads the low 4 bits of `map_extra`.
+const MAX_HASHES: u32 = 15;
+
+/// Used for working with `BPF_MAP_TYPE_BLOOM_FILTER` maps (kernel 5.16 or later), which have no
+/// keys. Values can only be added and tested, not removed or read back.
+///
+/// [`contains`](BloomFilter::contains) never returns false for a value that was pushed, but can
+/// return true for a value that wasn't. The false positive rate goes down with the number of
+/// hash functions, and up with the number of values relative to `max_entries`.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// let blocked: rxdp::BloomFilter<[u8; 4]> = rxdp::BloomFilter::create(1024, Some(3)).unwrap();
+/// blocked.push(&[10, 0, 0, 1]).unwrap();
+///
+/// assert!(blocked.contains(&[10, 0, 0, 1]).unwrap());
+/// ```
+pub struct BloomFilter<V> {
+    map_fd: i32,
+    _val: PhantomData<V>,
+    max_entries: u32,
+    // True if the handle created `map_fd` itself, rather than borrowing it from an object.
+    owned: bool,
+}
+
+impl<V> BloomFilter<V> {
+    /// Create a new bloom filter sized for `max_entries` values, using `nr_hashes` hash
+    /// functions (1 to 15). The kernel picks the number of hash functions (5) if it is `None`.
+    pub fn create(max_entries: u32, nr_hashes: Option<u32>) -> XDPResult<BloomFilter<V>> {
+        if let Some(n) = nr_hashes.filter(|n| *n == 0 || *n > MAX_HASHES) {
+            set_errno(Errno(22));
+            fail!("Invalid nr_hashes {}, expected 1 to {}", n, MAX_HASHES);
+        }
+        // 0 is the kernel default.
+        let nr_hashes = nr_hashes.unwrap_or(0);
+
+        let value_size = size_of::<V>() as u32;
+        let map_fd = compat::create_map_extra(
+            MapType::BloomFilter as u32,
+            0,
+            value_size,
+            max_entries,
+            0,
+            nr_hashes as u64,
+        );
+        mc::check_rc(map_fd, (), "Error creating new map")?;
+
+        Ok(BloomFilter {
+            map_fd,
+            _val: PhantomData,
+            max_entries,
+            owned: true,
+        })
+    }
+
+    /// Get access to the bloom filter `map_name`. This will fail if the requested value size
+    /// doesn't match the value size defined in the ELF file.
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<BloomFilter<V>> {
+        let def = mc::find_map(xdp, map_name)?;
+        if def.map_type.kind() != MapKind::BloomFilter {
+            return mc::improper_type(map_name, def.map_type);
+        }
+
+        let req_val_size = size_of::<V>() as u32;
+        if req_val_size != def.value_size {
+            fail!(
+                "Incorrect value size, XDP map has size: {}, requested value size is {}.",
+                def.value_size,
+                req_val_size,
+            );
+        }
+
+        Ok(BloomFilter {
+            map_fd: def.fd,
+            _val: PhantomData,
+            max_entries: def.max_entries,
+            owned: false,
+        })
+    }
+
+    /// Add `value` to the filter.
+    pub fn push(&self, value: &V) -> XDPResult<()> {
+        let rc = mc::update_elem(
+            self.map_fd,
+            std::ptr::null(),
+            value as *const _ as *const c_void,
+            MapFlags::BpfAny.bits(),
+        );
+
+        mc::check_rc(rc, (), "Error pushing value")
+    }
+
+    /// True if `value` may have been pushed, false if it definitely wasn't.
+    pub fn contains(&self, value: &V) -> XDPResult<bool> {
+        // The kernel reads the value to test from the lookup's value buffer, and may write it
+        // back, so it gets a copy.
+        let mut buf = utils::as_bytes(value).to_vec();
+        let rc = mc::lookup_elem(
+            self.map_fd,
+            std::ptr::null(),
+            buf.as_mut_ptr() as *mut c_void,
+        );
+        if rc == -2 || (rc < 0 && get_errno() == 2) {
+            return Ok(false);
+        }
+
+        mc::check_rc(rc, true, "Error testing value")
+    }
+
+    /// File descriptor for this map.
+    pub fn map_fd(&self) -> i32 {
+        self.map_fd
+    }
+
+    /// The number of values the filter is sized for.
+    pub fn max_entries(&self) -> u32 {
+        self.max_entries
+    }
+}
+
+impl<V> Drop for BloomFilter<V> {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { libc::close(self.map_fd) };
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::map_common::{MapLike, MapValue};
+use crate::result::XDPResult;
+use crate::{Map, MapFlags};
+
+/// Caches the values looked up in a map for `ttl`, for values that are read far more often than
+/// they change, e.g. configuration read by many components of a control plane. Updates and
+/// deletes through the `CachedMap` are written through to the map and the cache.
+///
+/// Changes made to the map by eBPF programs or other processes are only seen once the cached
+/// value expires, or after [`invalidate`](CachedMap::invalidate). Expired values are dropped
+/// when looked up, and by a sweep of the whole cache at most once per `ttl`.
+///
+/// # Example
+/// ```no_run
+/// use rxdp::{CachedMap, Map, MapFlags};
+/// use std::time::Duration;
+///
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let m: Map<u32, u64> = Map::new(&obj, "config").unwrap();
+/// let cached = CachedMap::new(m, Duration::from_secs(1));
+///
+/// cached.update(&1, &100, MapFlags::BpfAny).unwrap();
+/// // Served from the cache, without a syscall.
+/// assert_eq!(cached.lookup(&1).unwrap().into_single(), 100);
+/// ```
+pub struct CachedMap<K, V, M = Map<K, V>> {
+    map: M,
+    ttl: Duration,
+    cache: Mutex<Cache<K, V>>,
+}
+
+struct Cache<K, V> {
+    entries: HashMap<K, (MapValue<V>, Instant)>,
+    // Bumped by every change to the cache, so a lookup doesn't cache a value read before an
+    // update or invalidation that happened during its syscall.
+    generation: u64,
+    last_sweep: Instant,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K: Hash + Eq, V> Cache<K, V> {
+    fn remove(&mut self, key: &K) {
+        self.entries.remove(key);
+        self.generation += 1;
+    }
+
+    // Drops all expired values, at most once per `ttl`.
+    fn sweep(&mut self, ttl: Duration) {
+        if self.last_sweep.elapsed() < ttl {
+            return;
+        }
+        self.entries.retain(|_, (_, at)| at.elapsed() < ttl);
+        self.last_sweep = Instant::now();
+    }
+}
+
+/// Hit and miss counts of a [`CachedMap`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Number of values currently cached, including expired ones not dropped yet.
+    pub entries: usize,
+}
+
+impl<K, V, M> CachedMap<K, V, M>
+where
+    K: Copy + Hash + Eq,
+    V: Default + Clone,
+    M: MapLike<K, V>,
+{
+    /// Cache the values of `map` for `ttl`.
+    pub fn new(map: M, ttl: Duration) -> CachedMap<K, V, M> {
+        CachedMap {
+            map,
+            ttl,
+            cache: Mutex::new(Cache {
+                entries: HashMap::new(),
+                generation: 0,
+                last_sweep: Instant::now(),
+                hits: 0,
+                misses: 0,
+            }),
+        }
+    }
+
+    /// The underlying map. Changes made through it bypass the cache.
+    pub fn inner(&self) -> &M {
+        &self.map
+    }
+
+    /// Lookup `key`, from the cache if it was looked up or updated less than `ttl` ago. Missing
+    /// keys aren't cached.
+    pub fn lookup(&self, key: &K) -> XDPResult<MapValue<V>> {
+        let generation = {
+            let mut cache = self.cache.lock().unwrap();
+            let cached = match cache.entries.get(key) {
+                Some((v, at)) if at.elapsed() < self.ttl => Some(v.clone()),
+                Some(_) => {
+                    cache.entries.remove(key);
+                    None
+                }
+                None => None,
+            };
+            match cached {
+                Some(v) => {
+                    cache.hits += 1;
+                    return Ok(v);
+                }
+                None => cache.misses += 1,
+            }
+            cache.sweep(self.ttl);
+            cache.generation
+        };
+
+        // Don't hold the lock for the syscall.
+        let value = self.map.lookup(key)?;
+        let mut cache = self.cache.lock().unwrap();
+        if cache.generation == generation {
+            cache.entries.insert(*key, (value.clone(), Instant::now()));
+        }
+        Ok(value)
+    }
+
+    /// Update `key` in the map and the cache.
+    pub fn update(&self, key: &K, value: &V, flags: MapFlags) -> XDPResult<()> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Err(e) = self.map.update(key, value, flags) {
+            cache.remove(key);
+            return Err(e);
+        }
+
+        // Lookups racing with the update mustn't cache the old value. For per-cpu maps,
+        // `update` sets the value of every CPU, which isn't cached.
+        cache.remove(key);
+        if !self.map.map_type().is_per_cpu() {
+            cache
+                .entries
+                .insert(*key, (MapValue::Single(value.clone()), Instant::now()));
+        }
+        Ok(())
+    }
+
+    /// Delete `key` from the map and the cache.
+    pub fn delete(&self, key: &K) -> XDPResult<()> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.remove(key);
+        self.map.delete(key)
+    }
+
+    /// Drop the cached value of `key`, so the next lookup reads it from the map.
+    pub fn invalidate(&self, key: &K) {
+        self.cache.lock().unwrap().remove(key);
+    }
+
+    /// Drop all cached values.
+    pub fn invalidate_all(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.entries.clear();
+        cache.generation += 1;
+    }
+
+    /// Number of lookups served from the cache and from the map.
+    pub fn stats(&self) -> CacheStats {
+        let cache = self.cache.lock().unwrap();
+        CacheStats {
+            hits: cache.hits,
+            misses: cache.misses,
+            entries: cache.entries.len(),
+        }
+    }
+}
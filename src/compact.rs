@@ -0,0 +1,129 @@
+//! Rebuilding heavily churned hash maps into fresh maps.
+//!
+//! A loaded program keeps using the map it was loaded with, so a compacted map only takes over
+//! once the eBPF side is pointed at it. Two strategies are supported:
+//!
+//! * **Selector**: the eBPF side reads the map through an array of maps, indexed by a selector
+//!   (as in [`DoubleBufferedConfig`](crate::DoubleBufferedConfig)). Store the compacted map's
+//!   fd in the inactive slot of the outer map, then flip the selector:
+//!
+//!   ```c
+//!   __u32 zero = 0;
+//!   __u32 *active = bpf_map_lookup_elem(&flows_selector, &zero);
+//!   void *flows = bpf_map_lookup_elem(&flows_outer, active ? active : &zero);
+//!   ```
+//!
+//! * **Pin**: [`compact_pinned`] replaces the pin of the map with the compacted map, so
+//!   objects loaded (or maps opened) after the swap reuse the compacted map. Programs that are
+//!   already loaded keep the old map.
+//!
+//! Entries written to the old map while it is copied are not carried over, so writers should
+//! be paused (or switched over with the selector) for the duration of the copy.
+use errno::{set_errno, Errno};
+use libbpf_sys as bpf;
+
+use crate::compat;
+use crate::error::XDPError;
+use crate::map_common::MapLike;
+use crate::map_compat;
+use crate::result::XDPResult;
+use crate::utils;
+use crate::{Map, MapFlags, MapType};
+
+// Number of entries copied per batch update.
+const COPY_CHUNK_SIZE: usize = 10_000;
+
+/// Copies the entries of the hash map `map` into a freshly created map with the same definition
+/// (type, key/value sizes, max entries, flags and name), and returns the new map. Only hash and
+/// LRU hash maps can be compacted, other map types fail with `EINVAL`.
+///
+/// The returned handle owns the new map's fd and closes it when dropped, so the map is freed
+/// unless it was swapped in (stored in an outer map or pinned) first. See the
+/// [module docs](crate::compact) for how to swap the new map in.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// use rxdp::MapLike;
+///
+/// let flows: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "flows_a").unwrap();
+/// let outer: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, "flows_outer").unwrap();
+/// let selector: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, "flows_selector").unwrap();
+///
+/// let fresh = rxdp::compact::compact(&flows).unwrap();
+/// let inactive = selector.lookup(&0).unwrap().into_single() ^ 1;
+/// outer
+///     .update(&inactive, &(fresh.map_fd() as u32), rxdp::MapFlags::BpfAny)
+///     .unwrap();
+/// selector.update(&0, &inactive, rxdp::MapFlags::BpfAny).unwrap();
+/// ```
+pub fn compact<K, V>(map: &Map<K, V>) -> XDPResult<Map<K, V>>
+where
+    K: Default + Copy,
+    V: Default + Copy,
+{
+    match map.map_type() {
+        MapType::Hash | MapType::LRUHash => (),
+        t => {
+            set_errno(Errno(22));
+            fail!("Compaction not supported for map type {:?}", t);
+        }
+    }
+
+    let info = map_compat::map_info(map.map_fd())?;
+    let map_fd = compat::create_map_opts(
+        info.type_,
+        info.key_size,
+        info.value_size,
+        info.max_entries,
+        info.map_flags,
+        info.name.as_ptr(),
+        None,
+    );
+    if map_fd < 0 {
+        fail_rc!(map_fd, "Error creating map for compaction");
+    }
+
+    let fresh = Map::from_owned_fd(map_fd, map.map_type(), info.max_entries);
+    let entries = map
+        .items()?
+        .into_iter()
+        .map(|kv| (kv.key, kv.value.into_single()));
+    fresh.populate_from_iter(entries, COPY_CHUNK_SIZE, MapFlags::BpfAny, |_, _| ())?;
+
+    Ok(fresh)
+}
+
+/// Same as [`compact`], then pins the new map at `pin_path` in place of the old one. The new map
+/// is pinned at a temporary path next to `pin_path` and renamed over it, so the pin is never
+/// missing.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let flows: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "flows").unwrap();
+/// let fresh = rxdp::compact::compact_pinned(&flows, "/sys/fs/bpf/flows").unwrap();
+/// ```
+pub fn compact_pinned<K, V>(map: &Map<K, V>, pin_path: &str) -> XDPResult<Map<K, V>>
+where
+    K: Default + Copy,
+    V: Default + Copy,
+{
+    let fresh = compact(map)?;
+
+    let tmp_path = format!("{}.compact", pin_path);
+    let tmp = utils::str_to_cstring(&tmp_path)?;
+    let rc = unsafe { bpf::bpf_obj_pin(fresh.map_fd(), tmp.as_ptr()) };
+    if rc < 0 {
+        fail_rc!(rc, "Error pinning compacted map at {}", tmp_path);
+    }
+    if let Err(e) = std::fs::rename(&tmp_path, pin_path) {
+        let _ = std::fs::remove_file(&tmp_path);
+        set_errno(Errno(e.raw_os_error().unwrap_or(0)));
+        fail!("Error replacing pinned map {}: {}", pin_path, e);
+    }
+
+    Ok(fresh)
+}
@@ -0,0 +1,451 @@
+//! Wrappers for libbpf functions that were replaced or removed between libbpf 0.x and 1.x.
+//!
+//! The libbpf 0.x API is used by default. Enabling the `libbpf-1` feature switches to the
+//! newer API, for builds against a libbpf-sys with libbpf 1.x, which no longer exports the
+//! deprecated functions. Every libbpf call that differs between the two goes through here.
+use libbpf_sys as bpf;
+use std::os::raw::{c_char, c_void};
+
+use crate::error::{get_errno, reset_errno};
+use crate::map_compat::MapAttrs;
+
+const EPERM: i32 = 1;
+
+pub(crate) fn create_map(
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+) -> i32 {
+    create_map_opts(
+        map_type,
+        key_size,
+        value_size,
+        max_entries,
+        map_flags,
+        std::ptr::null(),
+        None,
+    )
+}
+
+// Same as `create_map`, optionally naming the map and allocating it on `numa_node`.
+pub(crate) fn create_map_opts(
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    mut map_flags: u32,
+    name: *const c_char,
+    numa_node: Option<u32>,
+) -> i32 {
+    if numa_node.is_some() {
+        map_flags |= bpf::BPF_F_NUMA_NODE;
+    }
+
+    #[cfg(not(feature = "libbpf-1"))]
+    unsafe {
+        let mut attr: bpf::bpf_create_map_attr = std::mem::zeroed();
+        attr.name = name;
+        attr.map_type = map_type;
+        attr.map_flags = map_flags;
+        attr.key_size = key_size;
+        attr.value_size = value_size;
+        attr.max_entries = max_entries;
+        attr.numa_node = numa_node.unwrap_or(0);
+        bpf::bpf_create_map_xattr(&attr)
+    }
+
+    #[cfg(feature = "libbpf-1")]
+    unsafe {
+        let opts = bpf::bpf_map_create_opts {
+            sz: std::mem::size_of::<bpf::bpf_map_create_opts>() as _,
+            map_flags,
+            numa_node: numa_node.unwrap_or(0),
+            ..Default::default()
+        };
+        bpf::bpf_map_create(map_type, name, key_size, value_size, max_entries, &opts)
+    }
+}
+
+// Same as `create_map`, with the type specific `map_extra` attribute (e.g. the number of hash
+// functions of a bloom filter). libbpf 0.x has no way to pass it, so the map is created with the
+// bpf(2) syscall directly.
+pub(crate) fn create_map_extra(
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+    map_extra: u64,
+) -> i32 {
+    #[cfg(not(feature = "libbpf-1"))]
+    {
+        use crate::raw::{self, Attr, Cmd};
+
+        // struct { map_type, key_size, value_size, max_entries, map_flags, ... map_extra }
+        let mut attr = Attr::new()
+            .u32(0, map_type)
+            .u32(4, key_size)
+            .u32(8, value_size)
+            .u32(12, max_entries)
+            .u32(16, map_flags)
+            .u64(64, map_extra);
+        match unsafe { raw::bpf(Cmd::MapCreate, &mut attr) } {
+            Ok(fd) => fd,
+            Err(_) => -crate::error::get_errno(),
+        }
+    }
+
+    #[cfg(feature = "libbpf-1")]
+    unsafe {
+        let opts = bpf::bpf_map_create_opts {
+            sz: std::mem::size_of::<bpf::bpf_map_create_opts>() as _,
+            map_flags,
+            map_extra,
+            ..Default::default()
+        };
+        bpf::bpf_map_create(
+            map_type,
+            std::ptr::null(),
+            key_size,
+            value_size,
+            max_entries,
+            &opts,
+        )
+    }
+}
+
+// Creates an ARRAY_OF_MAPS or HASH_OF_MAPS map, with `inner_map_fd` as the template of its
+// inner maps.
+pub(crate) fn create_map_in_map(
+    map_type: u32,
+    key_size: u32,
+    inner_map_fd: i32,
+    max_entries: u32,
+    map_flags: u32,
+) -> i32 {
+    #[cfg(not(feature = "libbpf-1"))]
+    unsafe {
+        bpf::bpf_create_map_in_map(
+            map_type,
+            std::ptr::null(),
+            key_size as i32,
+            inner_map_fd,
+            max_entries as i32,
+            map_flags,
+        )
+    }
+
+    #[cfg(feature = "libbpf-1")]
+    unsafe {
+        let opts = bpf::bpf_map_create_opts {
+            sz: std::mem::size_of::<bpf::bpf_map_create_opts>() as _,
+            map_flags,
+            inner_map_fd: inner_map_fd as u32,
+            ..Default::default()
+        };
+        bpf::bpf_map_create(map_type, std::ptr::null(), key_size, 4, max_entries, &opts)
+    }
+}
+
+// Pin path of the map, null if the map won't be pinned.
+pub(crate) fn map_pin_path(map: *const bpf::bpf_map) -> *const c_char {
+    #[cfg(not(feature = "libbpf-1"))]
+    unsafe {
+        bpf::bpf_map__get_pin_path(map)
+    }
+
+    #[cfg(feature = "libbpf-1")]
+    unsafe {
+        bpf::bpf_map__pin_path(map)
+    }
+}
+
+// Attaches `prog_fd` to the interface, or detaches the current program if `prog_fd` is -1.
+// Returns 0 on success or a negative error code.
+pub(crate) fn set_xdp_fd(if_index: i32, prog_fd: i32, flags: u32) -> i32 {
+    #[cfg(not(feature = "libbpf-1"))]
+    unsafe {
+        bpf::bpf_set_link_xdp_fd(if_index, prog_fd, flags)
+    }
+
+    #[cfg(feature = "libbpf-1")]
+    unsafe {
+        if prog_fd < 0 {
+            bpf::bpf_xdp_detach(if_index, flags, std::ptr::null())
+        } else {
+            bpf::bpf_xdp_attach(if_index, prog_fd, flags, std::ptr::null())
+        }
+    }
+}
+
+// Attaches the program `prog_fd` to the interface with a BPF link, returning the link fd or a
+// negative error code. libbpf 0.x can't create XDP links, so the link is created with the bpf(2)
+// syscall directly, on any libbpf version.
+pub(crate) fn attach_xdp_link(prog_fd: i32, if_index: i32) -> i32 {
+    use crate::raw::{self, Attr, Cmd};
+
+    // struct { prog_fd, target_ifindex, attach_type, flags, ... }
+    let mut attr = Attr::new()
+        .u32(0, prog_fd as u32)
+        .u32(4, if_index as u32)
+        .u32(8, bpf::BPF_XDP);
+    match unsafe { raw::bpf(Cmd::LinkCreate, &mut attr) } {
+        Ok(fd) => fd,
+        Err(_) => -crate::error::get_errno(),
+    }
+}
+
+// Returns the ids of the XDP programs attached to the interface in (driver, generic, hardware)
+// mode, 0 if there is none. Returns a negative error code on failure.
+pub(crate) fn query_xdp(if_index: i32) -> Result<(u32, u32, u32), i32> {
+    #[cfg(not(feature = "libbpf-1"))]
+    let (rc, ids) = unsafe {
+        let mut info = bpf::xdp_link_info::default();
+        let rc = bpf::bpf_get_link_xdp_info(
+            if_index,
+            &mut info,
+            std::mem::size_of::<bpf::xdp_link_info>() as _,
+            0,
+        );
+        (rc, (info.drv_prog_id, info.skb_prog_id, info.hw_prog_id))
+    };
+
+    #[cfg(feature = "libbpf-1")]
+    let (rc, ids) = unsafe {
+        let mut opts = bpf::bpf_xdp_query_opts {
+            sz: std::mem::size_of::<bpf::bpf_xdp_query_opts>() as _,
+            ..Default::default()
+        };
+        let rc = bpf::bpf_xdp_query(if_index, 0, &mut opts);
+        (rc, (opts.drv_prog_id, opts.skb_prog_id, opts.hw_prog_id))
+    };
+
+    if rc < 0 {
+        return Err(rc);
+    }
+    Ok(ids)
+}
+
+// Probes fail with EPERM without the privileges to create maps or load programs, which libbpf
+// reports as the feature being supported or not. -EPERM is returned instead, so it isn't
+// mistaken for a kernel limitation.
+fn probe_result(supported: bool) -> Result<bool, i32> {
+    if get_errno() == EPERM {
+        return Err(-EPERM);
+    }
+    Ok(supported)
+}
+
+// True if the running kernel supports maps of `map_type`, see `probe_result` for errors.
+pub(crate) fn probe_map_type(map_type: u32) -> Result<bool, i32> {
+    reset_errno();
+
+    #[cfg(not(feature = "libbpf-1"))]
+    let supported = unsafe { bpf::bpf_probe_map_type(map_type, 0) };
+
+    #[cfg(feature = "libbpf-1")]
+    let supported = unsafe { bpf::libbpf_probe_bpf_map_type(map_type, std::ptr::null()) == 1 };
+
+    probe_result(supported)
+}
+
+// True if the running kernel supports programs of `prog_type`, see `probe_result` for errors.
+pub(crate) fn probe_prog_type(prog_type: u32) -> Result<bool, i32> {
+    reset_errno();
+
+    #[cfg(not(feature = "libbpf-1"))]
+    let supported = unsafe { bpf::bpf_probe_prog_type(prog_type, 0) };
+
+    #[cfg(feature = "libbpf-1")]
+    let supported = unsafe { bpf::libbpf_probe_bpf_prog_type(prog_type, std::ptr::null()) == 1 };
+
+    probe_result(supported)
+}
+
+// True if programs of `prog_type` can call the helper `helper_id` on the running kernel, see
+// `probe_result` for errors.
+pub(crate) fn probe_helper(prog_type: u32, helper_id: u32) -> Result<bool, i32> {
+    reset_errno();
+
+    #[cfg(not(feature = "libbpf-1"))]
+    let supported = unsafe { bpf::bpf_probe_helper(helper_id, prog_type, 0) };
+
+    #[cfg(feature = "libbpf-1")]
+    let supported =
+        unsafe { bpf::libbpf_probe_bpf_helper(prog_type, helper_id, std::ptr::null()) == 1 };
+
+    probe_result(supported)
+}
+
+// Sets whether the map is created when the object is loaded. libbpf 0.x creates every map
+// before libbpf 0.8, so an existing map is reused in place of a skipped one: an `Array` of one
+// element, which programs that aren't loaded never use. Returns 0 or a negative error code.
+pub(crate) fn set_map_autocreate(map: *mut bpf::bpf_map, autocreate: bool) -> i32 {
+    #[cfg(not(feature = "libbpf-1"))]
+    unsafe {
+        if autocreate {
+            return 0;
+        }
+        let fd = create_map(bpf::BPF_MAP_TYPE_ARRAY, 4, 4, 1, 0);
+        if fd < 0 {
+            return fd;
+        }
+        // libbpf keeps its own duplicate of the fd.
+        let rc = bpf::bpf_map__reuse_fd(map, fd);
+        libc::close(fd);
+        rc
+    }
+
+    #[cfg(feature = "libbpf-1")]
+    unsafe {
+        bpf::bpf_map__set_autocreate(map, autocreate)
+    }
+}
+
+// The map after `prev` in the object, the first map if `prev` is null. Null after the last one.
+pub(crate) fn next_map(
+    obj: *const bpf::bpf_object,
+    prev: *const bpf::bpf_map,
+) -> *mut bpf::bpf_map {
+    #[cfg(not(feature = "libbpf-1"))]
+    unsafe {
+        bpf::bpf_map__next(prev, obj)
+    }
+
+    #[cfg(feature = "libbpf-1")]
+    unsafe {
+        bpf::bpf_object__next_map(obj, prev)
+    }
+}
+
+// The program after `prev` in the object, the first program if `prev` is null. Null after the
+// last one.
+pub(crate) fn next_program(
+    obj: *const bpf::bpf_object,
+    prev: *const bpf::bpf_program,
+) -> *mut bpf::bpf_program {
+    #[cfg(not(feature = "libbpf-1"))]
+    unsafe {
+        bpf::bpf_program__next(prev as *mut _, obj)
+    }
+
+    #[cfg(feature = "libbpf-1")]
+    unsafe {
+        bpf::bpf_object__next_program(obj, prev)
+    }
+}
+
+pub(crate) fn program_type(prog: *const bpf::bpf_program) -> u32 {
+    #[cfg(not(feature = "libbpf-1"))]
+    unsafe {
+        bpf::bpf_program__get_type(prog as *mut _)
+    }
+
+    #[cfg(feature = "libbpf-1")]
+    unsafe {
+        bpf::bpf_program__type(prog)
+    }
+}
+
+// Definition of the map in the object. `map` must not be null.
+pub(crate) fn map_attrs(map: *const bpf::bpf_map) -> MapAttrs {
+    #[cfg(not(feature = "libbpf-1"))]
+    unsafe {
+        let def = &*bpf::bpf_map__def(map);
+        MapAttrs {
+            map_type: def.type_,
+            key_size: def.key_size,
+            value_size: def.value_size,
+            max_entries: def.max_entries,
+            map_flags: def.map_flags,
+        }
+    }
+
+    #[cfg(feature = "libbpf-1")]
+    unsafe {
+        MapAttrs {
+            map_type: bpf::bpf_map__type(map),
+            key_size: bpf::bpf_map__key_size(map),
+            value_size: bpf::bpf_map__value_size(map),
+            max_entries: bpf::bpf_map__max_entries(map),
+            map_flags: bpf::bpf_map__map_flags(map),
+        }
+    }
+}
+
+// Opens a perf buffer of `page_cnt` pages per CPU on the PERF_EVENT_ARRAY map `map_fd`.
+// Returns the buffer or the error reported by libbpf.
+pub(crate) fn perf_buffer_new(
+    map_fd: i32,
+    page_cnt: usize,
+    sample_cb: bpf::perf_buffer_sample_fn,
+    lost_cb: bpf::perf_buffer_lost_fn,
+    ctx: *mut c_void,
+) -> Result<*mut bpf::perf_buffer, i32> {
+    #[cfg(not(feature = "libbpf-1"))]
+    let pb = unsafe {
+        let opts = bpf::perf_buffer_opts {
+            sample_cb,
+            lost_cb,
+            ctx,
+        };
+        bpf::perf_buffer__new(map_fd, page_cnt as _, &opts)
+    };
+
+    #[cfg(feature = "libbpf-1")]
+    let pb = unsafe {
+        bpf::perf_buffer__new(
+            map_fd,
+            page_cnt as _,
+            sample_cb,
+            lost_cb,
+            ctx,
+            std::ptr::null(),
+        )
+    };
+
+    match unsafe { bpf::libbpf_get_error(pb as *const c_void) } {
+        0 => Ok(pb),
+        err => Err(err as i32),
+    }
+}
+
+// Runs the program once with `BPF_PROG_TEST_RUN` on `data`. Returns (retval, duration in ns)
+// or a negative error code.
+pub(crate) fn prog_test_run(prog_fd: i32, data: &[u8]) -> Result<(u32, u32), i32> {
+    #[cfg(not(feature = "libbpf-1"))]
+    let (rc, retval, duration) = unsafe {
+        let (mut retval, mut duration) = (0u32, 0u32);
+        let rc = bpf::bpf_prog_test_run(
+            prog_fd,
+            1,
+            data.as_ptr() as *mut _,
+            data.len() as u32,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &mut retval,
+            &mut duration,
+        );
+        (rc, retval, duration)
+    };
+
+    #[cfg(feature = "libbpf-1")]
+    let (rc, retval, duration) = unsafe {
+        let mut opts = bpf::bpf_test_run_opts {
+            sz: std::mem::size_of::<bpf::bpf_test_run_opts>() as _,
+            data_in: data.as_ptr() as *const c_void,
+            data_size_in: data.len() as u32,
+            repeat: 1,
+            ..Default::default()
+        };
+        let rc = bpf::bpf_prog_test_run_opts(prog_fd, &mut opts);
+        (rc, opts.retval, opts.duration)
+    };
+
+    if rc < 0 {
+        return Err(rc);
+    }
+    Ok((retval, duration))
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+
+use crate::kernel::{self, Feature, KernelVersion};
+use crate::MapType;
+
+// The kernel features rxdp's own APIs depend on, listed in every report.
+const FEATURES: [Feature; 5] = [
+    Feature::BatchOps,
+    Feature::DevMapHash,
+    Feature::RingBuffer,
+    Feature::MapPrograms,
+    Feature::XdpAttachType,
+];
+
+/// What the maps and programs of an object need from the kernel, and whether the running kernel
+/// has it, see [`XDPObject::compatibility_report`](crate::XDPObject::compatibility_report).
+///
+/// The fields can be inspected directly, and the `Display` implementation lists one map,
+/// program, helper or feature per line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompatibilityReport {
+    /// Version of the running kernel, `None` if it can't be determined.
+    pub kernel: Option<KernelVersion>,
+    /// The maps of the object, in definition order.
+    pub maps: Vec<MapSupport>,
+    /// The programs of the object, in definition order.
+    pub programs: Vec<ProgramSupport>,
+    /// Whether the kernel supports each of the features rxdp's APIs depend on, e.g. batch map
+    /// operations.
+    pub features: Vec<(Feature, bool)>,
+}
+
+/// A map of a [`CompatibilityReport`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapSupport {
+    pub name: String,
+    pub map_type: MapType,
+    /// True if the kernel supports maps of `map_type`.
+    pub supported: bool,
+}
+
+/// A program of a [`CompatibilityReport`]. Maps and helpers only used through subprograms
+/// aren't included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramSupport {
+    pub name: String,
+    /// The program type (`BPF_PROG_TYPE_*`).
+    pub prog_type: u32,
+    /// True if the kernel supports programs of `prog_type`.
+    pub type_supported: bool,
+    /// The maps the program uses.
+    pub maps: Vec<String>,
+    /// The helpers the program calls, by id (`BPF_FUNC_*`), and whether the kernel supports
+    /// them for `prog_type`. Not probed if the program type isn't supported.
+    pub helpers: Vec<(u32, bool)>,
+}
+
+impl CompatibilityReport {
+    /// True if the kernel supports every map and program of the object.
+    pub fn is_compatible(&self) -> bool {
+        self.maps.iter().all(|m| m.supported)
+            && self.programs.iter().all(|p| self.program_supported(p))
+    }
+
+    /// True if the kernel supports the type of the program `p`, the helpers it calls and the
+    /// maps it uses.
+    pub fn program_supported(&self, p: &ProgramSupport) -> bool {
+        p.type_supported
+            && p.helpers.iter().all(|(_, supported)| *supported)
+            && p.maps.iter().all(|name| {
+                self.maps
+                    .iter()
+                    .find(|m| &m.name == name)
+                    .is_none_or(|m| m.supported)
+            })
+    }
+
+    pub(crate) fn features() -> Vec<(Feature, bool)> {
+        FEATURES
+            .iter()
+            .map(|f| (*f, kernel::supported(*f)))
+            .collect()
+    }
+}
+
+impl fmt::Display for CompatibilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = |supported: bool| match supported {
+            true => "ok",
+            false => "unsupported",
+        };
+
+        match self.kernel {
+            Some(v) => writeln!(f, "kernel {}", v)?,
+            None => writeln!(f, "kernel unknown")?,
+        }
+        for m in self.maps.iter() {
+            writeln!(
+                f,
+                "map {} ({}): {}",
+                m.name,
+                m.map_type,
+                status(m.supported)
+            )?;
+        }
+        for p in self.programs.iter() {
+            writeln!(
+                f,
+                "program {} (type {}): {}",
+                p.name,
+                p.prog_type,
+                status(self.program_supported(p))
+            )?;
+            if !p.type_supported {
+                writeln!(f, "  program type {}: unsupported", p.prog_type)?;
+            }
+            for (id, _) in p.helpers.iter().filter(|(_, s)| !s) {
+                writeln!(f, "  helper {}: unsupported", id)?;
+            }
+            for m in self.maps.iter().filter(|m| !m.supported) {
+                if p.maps.contains(&m.name) {
+                    writeln!(f, "  map {}: unsupported", m.name)?;
+                }
+            }
+        }
+        for (feature, supported) in self.features.iter() {
+            writeln!(
+                f,
+                "feature {:?} (kernel >= {}): {}",
+                feature,
+                feature.min_version(),
+                status(*supported)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut report = CompatibilityReport {
+            kernel: Some(KernelVersion::new(5, 4, 0)),
+            maps: vec![
+                MapSupport {
+                    name: "counters".to_string(),
+                    map_type: MapType::Hash,
+                    supported: true,
+                },
+                MapSupport {
+                    name: "events".to_string(),
+                    map_type: MapType::RingBuffer,
+                    supported: false,
+                },
+            ],
+            programs: vec![ProgramSupport {
+                name: "xdp_main".to_string(),
+                prog_type: 6,
+                type_supported: true,
+                maps: vec!["counters".to_string()],
+                helpers: vec![(1, true), (130, true)],
+            }],
+            features: vec![(Feature::RingBuffer, false)],
+        };
+        assert!(report.program_supported(&report.programs[0]));
+        assert!(!report.is_compatible());
+        assert_eq!(
+            report.to_string(),
+            "kernel 5.4.0\n\
+             map counters (hash): ok\n\
+             map events (ringbuf): unsupported\n\
+             program xdp_main (type 6): ok\n\
+             feature RingBuffer (kernel >= 5.8.0): unsupported\n"
+        );
+
+        report.programs[0].maps.push("events".to_string());
+        report.programs[0].helpers[1].1 = false;
+        assert!(!report.program_supported(&report.programs[0]));
+        assert!(report
+            .to_string()
+            .contains("program xdp_main (type 6): unsupported\n  helper 130: unsupported\n  map events: unsupported\n"));
+
+        report.maps.pop();
+        report.programs[0].helpers.pop();
+        assert!(report.is_compatible());
+    }
+}
@@ -0,0 +1,99 @@
+//! Process-wide defaults, set once at startup so the same constants don't have to be threaded
+//! through every call site.
+//!
+//! The default pin path is used by objects that don't set one. Attach flags are always passed
+//! explicitly, [`AttachFlags::from_env`] returns the defaults for passing them. Environment
+//! variables take precedence over defaults set in code, so they can be changed per deployment.
+//!
+//! # Example
+//! ```no_run
+//! use rxdp::{config, AttachFlags};
+//!
+//! config::set_default_pin_path("/sys/fs/bpf/myapp");
+//! config::set_default_attach_flags(AttachFlags::SKB_MODE);
+//!
+//! // Pins under /sys/fs/bpf/myapp, unless RXDP_PIN_ROOT is set.
+//! let obj = rxdp::XDPObject::new("/path/to/elf/file").unwrap();
+//! # let obj = obj.load().unwrap();
+//! let prog = obj.get_program("prog_name").unwrap();
+//! prog.attach_to_interface("eth0", AttachFlags::from_env()).unwrap();
+//! ```
+use lazy_static::lazy_static;
+use std::sync::RwLock;
+
+use crate::AttachFlags;
+
+const DEFAULT_PIN_ROOT: &str = "/sys/fs/bpf";
+
+/// Environment variable with the default pin root path, see [`default_pin_path`].
+pub const PIN_ROOT_ENV: &str = "RXDP_PIN_ROOT";
+
+/// Environment variable with the default attach flags, see [`default_attach_flags`]. Flags
+/// are parsed with `AttachFlags::from_str`, e.g. `skb|update_if_noexist`.
+pub const ATTACH_FLAGS_ENV: &str = "RXDP_ATTACH_FLAGS";
+
+#[derive(Default)]
+struct Defaults {
+    pin_path: Option<String>,
+    attach_flags: Option<AttachFlags>,
+}
+
+lazy_static! {
+    static ref DEFAULTS: RwLock<Defaults> = RwLock::new(Defaults::default());
+}
+
+/// Set the default pin root path, used for objects that don't set one with
+/// [`XDPObjectBuilder::pin_root_path`](crate::XDPObjectBuilder::pin_root_path).
+pub fn set_default_pin_path(path: &str) {
+    DEFAULTS.write().unwrap().pin_path = Some(path.to_string());
+}
+
+/// The default pin root path: the value of [`PIN_ROOT_ENV`] if set, else the path set with
+/// [`set_default_pin_path`], else `/sys/fs/bpf`.
+pub fn default_pin_path() -> String {
+    configured_pin_path().unwrap_or_else(|| DEFAULT_PIN_ROOT.to_string())
+}
+
+// The default pin root path, if one was configured.
+pub(crate) fn configured_pin_path() -> Option<String> {
+    match std::env::var(PIN_ROOT_ENV).ok().filter(|p| !p.is_empty()) {
+        Some(p) => Some(p),
+        None => DEFAULTS.read().unwrap().pin_path.clone(),
+    }
+}
+
+/// Set the default attach flags, returned by [`AttachFlags::from_env`].
+pub fn set_default_attach_flags(flags: AttachFlags) {
+    DEFAULTS.write().unwrap().attach_flags = Some(flags);
+}
+
+/// The default attach flags: the flags in [`ATTACH_FLAGS_ENV`] if set and valid, else the flags
+/// set with [`set_default_attach_flags`], else no flags.
+pub fn default_attach_flags() -> AttachFlags {
+    let env = std::env::var(ATTACH_FLAGS_ENV).ok();
+    match env.and_then(|e| e.parse().ok()) {
+        Some(flags) => flags,
+        None => DEFAULTS
+            .read()
+            .unwrap()
+            .attach_flags
+            .unwrap_or_else(AttachFlags::empty),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_attach_flags() {
+        let parse = |s: &str| s.parse::<AttachFlags>().ok();
+        assert_eq!(parse("SKB_MODE"), Some(AttachFlags::SKB_MODE));
+        assert_eq!(
+            parse("drv_mode | UPDATE_IF_NOEXIST"),
+            Some(AttachFlags::DRV_MODE | AttachFlags::UPDATE_IF_NOEXIST)
+        );
+        assert_eq!(parse(""), Some(AttachFlags::empty()));
+        assert_eq!(parse("SKB_MODE|FAST"), None);
+    }
+}
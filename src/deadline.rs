@@ -0,0 +1,138 @@
+use crossbeam_channel::{bounded, unbounded, Sender};
+use errno::{set_errno, Errno};
+use std::{sync::Mutex, time::Duration};
+
+use crate::error::{get_errno, reset_errno};
+
+pub(crate) const ETIMEDOUT: i32 = 110;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs syscalls for a map handle on a worker thread, giving up on them after a timeout.
+///
+/// A syscall that is stuck in the kernel can't be interrupted. When an operation times out,
+/// the worker running it is abandoned (it exits once the syscall eventually returns) and a new
+/// worker is started for the next operation.
+#[doc(hidden)]
+pub struct Deadline {
+    timeout: Duration,
+    worker: Mutex<Option<Sender<Job>>>,
+}
+
+impl Deadline {
+    pub(crate) fn new(timeout: Duration) -> Deadline {
+        Deadline {
+            timeout,
+            worker: Mutex::new(None),
+        }
+    }
+
+    // Returns `None` if `f` didn't complete within the timeout.
+    fn run<T: Send + 'static>(&self, f: impl FnOnce() -> T + Send + 'static) -> Option<T> {
+        let (tx, rx) = bounded(1);
+        let job: Job = Box::new(move || {
+            tx.send(f()).ok();
+        });
+
+        {
+            let mut worker = self.worker.lock().unwrap();
+            let w = worker.get_or_insert_with(spawn_worker);
+            if let Err(e) = w.send(job) {
+                let w = worker.insert(spawn_worker());
+                w.send(e.into_inner()).ok();
+            }
+        }
+
+        match rx.recv_timeout(self.timeout) {
+            Ok(v) => Some(v),
+            Err(_) => {
+                self.worker.lock().unwrap().take();
+                None
+            }
+        }
+    }
+}
+
+fn spawn_worker() -> Sender<Job> {
+    let (s, r) = unbounded::<Job>();
+    std::thread::spawn(move || {
+        for job in r {
+            job();
+        }
+    });
+    s
+}
+
+// Runs the syscall `op` on the deadline's worker if there is one, otherwise on the calling
+// thread. `op` gets (copies of) two input buffers and an output buffer, which is copied back on
+// completion. Returns the syscall return code and sets errno as if the syscall ran on the
+// calling thread. If the deadline expires, returns -1 with errno set to ETIMEDOUT.
+pub(crate) fn call<F>(
+    deadline: Option<&Deadline>,
+    a: &[u8],
+    b: &[u8],
+    output: &mut [u8],
+    op: F,
+) -> i32
+where
+    F: FnOnce(&[u8], &[u8], &mut [u8]) -> i32 + Send + 'static,
+{
+    let d = match deadline {
+        None => return op(a, b, output),
+        Some(d) => d,
+    };
+
+    let (a, b) = (a.to_vec(), b.to_vec());
+    let mut out = vec![0u8; output.len()];
+    let r = d.run(move || {
+        reset_errno();
+        let rc = op(&a, &b, &mut out);
+        (rc, get_errno(), out)
+    });
+
+    match r {
+        Some((rc, e, out)) => {
+            output.copy_from_slice(&out);
+            set_errno(Errno(e));
+            rc
+        }
+        None => {
+            set_errno(Errno(ETIMEDOUT));
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_copies_output() {
+        let d = Deadline::new(Duration::from_secs(5));
+        let mut out = [0u8; 2];
+        let rc = call(Some(&d), &[1], &[2], &mut out, |a, b, o| {
+            o.copy_from_slice(&[a[0], b[0]]);
+            set_errno(Errno(2));
+            -1
+        });
+        assert_eq!(rc, -1);
+        assert_eq!(get_errno(), 2);
+        assert_eq!(out, [1, 2]);
+    }
+
+    #[test]
+    fn test_call_times_out() {
+        let d = Deadline::new(Duration::from_millis(10));
+        let rc = call(Some(&d), &[], &[], &mut [], |_, _, _| {
+            std::thread::sleep(Duration::from_millis(200));
+            0
+        });
+        assert_eq!(rc, -1);
+        assert_eq!(get_errno(), ETIMEDOUT);
+
+        // The stuck worker is replaced.
+        let rc = call(Some(&d), &[], &[], &mut [], |_, _, _| 0);
+        assert_eq!(rc, 0);
+    }
+}
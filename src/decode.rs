@@ -0,0 +1,447 @@
+//! Helpers for decoding packet headers, e.g. from packet snippets sent as perf events.
+//!
+//! Each header type has a `parse` function that takes the raw bytes and returns the header
+//! together with the remaining bytes, or `None` if the bytes are truncated or malformed.
+//! [`parse`] decodes a full Ethernet frame in one go.
+//!
+//! # Example
+//! ```no_run
+//! use rxdp::decode::{self, Transport};
+//!
+//! # let events: Vec<[u8; 128]> = vec![];
+//! for snippet in events.iter() {
+//!     if let Some(packet) = decode::parse(snippet) {
+//!         if let Some(Transport::Udp(udp)) = packet.transport {
+//!             if udp.dst_port == 53 {
+//!                 let dns = decode::Dns::parse(packet.payload);
+//!                 println!("{:?} -> {:?}", packet.ip, dns);
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+use std::convert::TryInto;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_IPV6: u16 = 0x86dd;
+pub const ETH_P_8021Q: u16 = 0x8100;
+pub const ETH_P_8021AD: u16 = 0x88a8;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+
+fn u16_at(b: &[u8], i: usize) -> u16 {
+    u16::from_be_bytes(b[i..i + 2].try_into().unwrap())
+}
+
+fn u32_at(b: &[u8], i: usize) -> u32 {
+    u32::from_be_bytes(b[i..i + 4].try_into().unwrap())
+}
+
+/// Ethernet header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ethernet {
+    pub dst: [u8; 6],
+    pub src: [u8; 6],
+    /// VLAN ID of the (outermost) 802.1Q/802.1ad tag, if any.
+    pub vlan: Option<u16>,
+    /// EtherType of the payload, after any VLAN tags.
+    pub ether_type: u16,
+}
+
+impl Ethernet {
+    pub fn parse(b: &[u8]) -> Option<(Ethernet, &[u8])> {
+        if b.len() < 14 {
+            return None;
+        }
+
+        let mut eth = Ethernet {
+            dst: b[0..6].try_into().unwrap(),
+            src: b[6..12].try_into().unwrap(),
+            vlan: None,
+            ether_type: u16_at(b, 12),
+        };
+
+        let mut rest = &b[14..];
+        while eth.ether_type == ETH_P_8021Q || eth.ether_type == ETH_P_8021AD {
+            if rest.len() < 4 {
+                return None;
+            }
+            eth.vlan.get_or_insert(u16_at(rest, 0) & 0x0fff);
+            eth.ether_type = u16_at(rest, 2);
+            rest = &rest[4..];
+        }
+
+        Some((eth, rest))
+    }
+}
+
+/// IPv4 header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4 {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+    /// Length of the header, in bytes.
+    pub header_len: u8,
+    /// Length of the whole IP packet, in bytes.
+    pub total_len: u16,
+}
+
+impl Ipv4 {
+    pub fn parse(b: &[u8]) -> Option<(Ipv4, &[u8])> {
+        if b.len() < 20 || b[0] >> 4 != 4 {
+            return None;
+        }
+
+        let header_len = (b[0] & 0x0f) * 4;
+        if header_len < 20 || b.len() < header_len as usize {
+            return None;
+        }
+
+        let ip = Ipv4 {
+            src: Ipv4Addr::from(u32_at(b, 12)),
+            dst: Ipv4Addr::from(u32_at(b, 16)),
+            protocol: b[9],
+            ttl: b[8],
+            header_len,
+            total_len: u16_at(b, 2),
+        };
+
+        Some((ip, &b[header_len as usize..]))
+    }
+}
+
+/// IPv6 header. Extension headers are not decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6 {
+    pub src: Ipv6Addr,
+    pub dst: Ipv6Addr,
+    pub next_header: u8,
+    pub hop_limit: u8,
+    pub payload_len: u16,
+}
+
+impl Ipv6 {
+    pub fn parse(b: &[u8]) -> Option<(Ipv6, &[u8])> {
+        if b.len() < 40 || b[0] >> 4 != 6 {
+            return None;
+        }
+
+        let src: [u8; 16] = b[8..24].try_into().unwrap();
+        let dst: [u8; 16] = b[24..40].try_into().unwrap();
+        let ip = Ipv6 {
+            src: Ipv6Addr::from(src),
+            dst: Ipv6Addr::from(dst),
+            next_header: b[6],
+            hop_limit: b[7],
+            payload_len: u16_at(b, 4),
+        };
+
+        Some((ip, &b[40..]))
+    }
+}
+
+/// UDP header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Udp {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub len: u16,
+    pub checksum: u16,
+}
+
+impl Udp {
+    pub fn parse(b: &[u8]) -> Option<(Udp, &[u8])> {
+        if b.len() < 8 {
+            return None;
+        }
+
+        let udp = Udp {
+            src_port: u16_at(b, 0),
+            dst_port: u16_at(b, 2),
+            len: u16_at(b, 4),
+            checksum: u16_at(b, 6),
+        };
+
+        Some((udp, &b[8..]))
+    }
+}
+
+/// TCP header. Options are skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tcp {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    /// Length of the header including options, in bytes.
+    pub header_len: u8,
+    /// FIN, SYN, RST, PSH, ACK, URG, ECE & CWR flags, from least to most significant bit.
+    pub flags: u8,
+    pub window: u16,
+}
+
+impl Tcp {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+
+    pub fn parse(b: &[u8]) -> Option<(Tcp, &[u8])> {
+        if b.len() < 20 {
+            return None;
+        }
+
+        let header_len = (b[12] >> 4) * 4;
+        if header_len < 20 || b.len() < header_len as usize {
+            return None;
+        }
+
+        let tcp = Tcp {
+            src_port: u16_at(b, 0),
+            dst_port: u16_at(b, 2),
+            seq: u32_at(b, 4),
+            ack: u32_at(b, 8),
+            header_len,
+            flags: b[13],
+            window: u16_at(b, 14),
+        };
+
+        Some((tcp, &b[header_len as usize..]))
+    }
+}
+
+/// DNS message header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dns {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: u16,
+    pub answers: u16,
+    pub authorities: u16,
+    pub additionals: u16,
+}
+
+impl Dns {
+    /// True if the message is a response.
+    pub fn is_response(&self) -> bool {
+        self.flags & 0x8000 != 0
+    }
+
+    /// The response code (RCODE).
+    pub fn rcode(&self) -> u8 {
+        (self.flags & 0x000f) as u8
+    }
+
+    pub fn parse(b: &[u8]) -> Option<(Dns, &[u8])> {
+        if b.len() < 12 {
+            return None;
+        }
+
+        let dns = Dns {
+            id: u16_at(b, 0),
+            flags: u16_at(b, 2),
+            questions: u16_at(b, 4),
+            answers: u16_at(b, 6),
+            authorities: u16_at(b, 8),
+            additionals: u16_at(b, 10),
+        };
+
+        Some((dns, &b[12..]))
+    }
+}
+
+/// Network layer header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Ip {
+    V4(Ipv4),
+    V6(Ipv6),
+}
+
+/// Transport layer header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Transport {
+    Udp(Udp),
+    Tcp(Tcp),
+}
+
+/// A decoded Ethernet frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub eth: Ethernet,
+    /// `None` for non-IP frames.
+    pub ip: Option<Ip>,
+    /// `None` for protocols other than UDP & TCP, or if the header is truncated.
+    pub transport: Option<Transport>,
+    /// The bytes after the last decoded header.
+    pub payload: &'a [u8],
+}
+
+/// Decode the Ethernet, IP and UDP/TCP headers of a frame. Returns `None` only if the
+/// Ethernet header is truncated, otherwise decodes as many headers as possible.
+pub fn parse(b: &[u8]) -> Option<Packet<'_>> {
+    let (eth, rest) = Ethernet::parse(b)?;
+    let mut packet = Packet {
+        eth,
+        ip: None,
+        transport: None,
+        payload: rest,
+    };
+
+    let (protocol, rest) = match eth.ether_type {
+        ETH_P_IP => match Ipv4::parse(rest) {
+            Some((ip, rest)) => {
+                packet.ip = Some(Ip::V4(ip));
+                (ip.protocol, rest)
+            }
+            None => return Some(packet),
+        },
+        ETH_P_IPV6 => match Ipv6::parse(rest) {
+            Some((ip, rest)) => {
+                packet.ip = Some(Ip::V6(ip));
+                (ip.next_header, rest)
+            }
+            None => return Some(packet),
+        },
+        _ => return Some(packet),
+    };
+    packet.payload = rest;
+
+    let transport = match protocol {
+        IPPROTO_UDP => Udp::parse(rest).map(|(h, rest)| (Transport::Udp(h), rest)),
+        IPPROTO_TCP => Tcp::parse(rest).map(|(h, rest)| (Transport::Tcp(h), rest)),
+        _ => None,
+    };
+    if let Some((t, rest)) = transport {
+        packet.transport = Some(t);
+        packet.payload = rest;
+    }
+
+    Some(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eth(ether_type: u16) -> Vec<u8> {
+        let mut b = vec![0xff; 6];
+        b.extend_from_slice(&[2, 0, 0, 0, 0, 1]);
+        b.extend_from_slice(&ether_type.to_be_bytes());
+        b
+    }
+
+    fn ipv4_udp(payload: &[u8]) -> Vec<u8> {
+        let mut b = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, IPPROTO_UDP, 0, 0];
+        b.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        b[2..4].copy_from_slice(&(28 + payload.len() as u16).to_be_bytes());
+        b.extend_from_slice(&[0x30, 0x39, 0, 53]);
+        b.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        b.extend_from_slice(&[0, 0]);
+        b.extend_from_slice(payload);
+        b
+    }
+
+    #[test]
+    fn test_parse_ipv4_udp_dns() {
+        let dns = [0x12, 0x34, 0x81, 0x83, 0, 1, 0, 0, 0, 0, 0, 0, 0xaa];
+        let mut frame = eth(ETH_P_IP);
+        frame.extend_from_slice(&ipv4_udp(&dns));
+
+        let p = parse(&frame).unwrap();
+        assert_eq!(p.eth.src, [2, 0, 0, 0, 0, 1]);
+        assert_eq!(p.eth.vlan, None);
+        match p.ip {
+            Some(Ip::V4(ip)) => {
+                assert_eq!(ip.src, Ipv4Addr::new(10, 0, 0, 1));
+                assert_eq!(ip.dst, Ipv4Addr::new(10, 0, 0, 2));
+                assert_eq!(ip.ttl, 64);
+                assert_eq!(ip.total_len, 41);
+            }
+            _ => panic!("expected IPv4"),
+        }
+        match p.transport {
+            Some(Transport::Udp(udp)) => {
+                assert_eq!(udp.src_port, 12345);
+                assert_eq!(udp.dst_port, 53);
+                assert_eq!(udp.len, 21);
+            }
+            _ => panic!("expected UDP"),
+        }
+
+        let (d, rest) = Dns::parse(p.payload).unwrap();
+        assert_eq!(d.id, 0x1234);
+        assert!(d.is_response());
+        assert_eq!(d.rcode(), 3);
+        assert_eq!(d.questions, 1);
+        assert_eq!(rest, &[0xaa]);
+    }
+
+    #[test]
+    fn test_parse_vlan_ipv6_tcp() {
+        let mut frame = eth(ETH_P_8021Q);
+        frame.extend_from_slice(&[0x20, 0x64]);
+        frame.extend_from_slice(&ETH_P_IPV6.to_be_bytes());
+
+        let mut ip = vec![0x60, 0, 0, 0, 0, 20, IPPROTO_TCP, 32];
+        ip.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        ip.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        frame.extend_from_slice(&ip);
+
+        let mut tcp = vec![0, 80, 0x1f, 0x90, 0, 0, 0, 1, 0, 0, 0, 0, 0x50];
+        tcp.extend_from_slice(&[Tcp::SYN | Tcp::ACK, 0xff, 0xff, 0, 0, 0, 0]);
+        frame.extend_from_slice(&tcp);
+
+        let p = parse(&frame).unwrap();
+        assert_eq!(p.eth.vlan, Some(100));
+        assert_eq!(p.eth.ether_type, ETH_P_IPV6);
+        match p.ip {
+            Some(Ip::V6(ip)) => {
+                assert_eq!(ip.src, Ipv6Addr::LOCALHOST);
+                assert_eq!(ip.hop_limit, 32);
+                assert_eq!(ip.payload_len, 20);
+            }
+            _ => panic!("expected IPv6"),
+        }
+        match p.transport {
+            Some(Transport::Tcp(tcp)) => {
+                assert_eq!(tcp.src_port, 80);
+                assert_eq!(tcp.dst_port, 8080);
+                assert_eq!(tcp.seq, 1);
+                assert_eq!(tcp.header_len, 20);
+                assert_eq!(tcp.flags, Tcp::SYN | Tcp::ACK);
+            }
+            _ => panic!("expected TCP"),
+        }
+        assert!(p.payload.is_empty());
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        assert!(parse(&[0u8; 13]).is_none());
+
+        // Truncated IPv4 header: Ethernet only
+        let mut frame = eth(ETH_P_IP);
+        frame.extend_from_slice(&[0x45, 0, 0]);
+        let p = parse(&frame).unwrap();
+        assert_eq!(p.ip, None);
+        assert_eq!(p.payload, &[0x45, 0, 0]);
+
+        // Truncated UDP header: payload starts after the IP header
+        let mut frame = eth(ETH_P_IP);
+        let ip = ipv4_udp(&[]);
+        frame.extend_from_slice(&ip[..24]);
+        let p = parse(&frame).unwrap();
+        assert!(p.ip.is_some());
+        assert_eq!(p.transport, None);
+        assert_eq!(p.payload.len(), 4);
+
+        assert!(Tcp::parse(&[0u8; 19]).is_none());
+        assert!(Dns::parse(&[0u8; 11]).is_none());
+    }
+}
@@ -0,0 +1,142 @@
+use errno::{set_errno, Errno};
+
+use crate::error::XDPError;
+use crate::map_common::MapLike;
+use crate::result::XDPResult;
+use crate::{Map, MapFlags, MapType, XDPLoadedObject};
+
+/// Atomically swappable configuration, stored in two identical eBPF maps plus a selector.
+///
+/// The selector is a `u32` array map whose first entry holds the index (0 or 1) of the active
+/// config map. A new config is first [`stage`](DoubleBufferedConfig::stage)d into the inactive
+/// map, then made live by a single [`commit`](DoubleBufferedConfig::commit) which flips the
+/// selector, so the eBPF side never sees a half-written config. The eBPF side has to pick the
+/// config map through the selector, e.g. with the two maps in an array of maps or as:
+///
+/// ```c
+/// __u32 zero = 0;
+/// __u32 *active = bpf_map_lookup_elem(&config_selector, &zero);
+/// void *config = (active && *active) ? (void *)&config_b : (void *)&config_a;
+/// struct config *c = bpf_map_lookup_elem(config, &key);
+/// ```
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let mut config: rxdp::DoubleBufferedConfig<u32, u64> =
+///     rxdp::DoubleBufferedConfig::new(&obj, ["config_a", "config_b"], "config_selector").unwrap();
+///
+/// config.update(&[(1, 100), (2, 200)]).unwrap();
+/// println!("config map {} is live", config.active());
+/// ```
+pub struct DoubleBufferedConfig<K, V> {
+    maps: [Map<K, V>; 2],
+    selector: Map<u32, u32>,
+    active: u32,
+    staged: bool,
+}
+
+impl<K: Default + Copy, V: Default + Copy> DoubleBufferedConfig<K, V> {
+    /// Creates a double buffered config on top of the config maps `maps` and the `selector`
+    /// array. Fails if the config maps don't have the same definition, or if the selector isn't
+    /// a `u32` array holding 0 or 1, since the eBPF side then can't follow the convention. An
+    /// existing selector value (e.g. from a pinned map) is kept.
+    pub fn new(
+        xdp: &XDPLoadedObject,
+        maps: [&str; 2],
+        selector: &str,
+    ) -> XDPResult<DoubleBufferedConfig<K, V>> {
+        let a: Map<K, V> = Map::new(xdp, maps[0])?;
+        let b: Map<K, V> = Map::new(xdp, maps[1])?;
+        if a.map_type() != b.map_type() || a.max_entries() != b.max_entries() {
+            set_errno(Errno(22));
+            fail!(
+                "Config maps {} ({:?}, {} entries) and {} ({:?}, {} entries) don't match",
+                maps[0],
+                a.map_type(),
+                a.max_entries(),
+                maps[1],
+                b.map_type(),
+                b.max_entries()
+            );
+        }
+
+        let selector: Map<u32, u32> = Map::new(xdp, selector)?;
+        if selector.map_type() != MapType::Array {
+            set_errno(Errno(22));
+            fail!("Improper map type, selector must be MapType::Array");
+        }
+
+        let active = selector.lookup(&0)?.into_single();
+        if active > 1 {
+            set_errno(Errno(22));
+            fail!("Selector points at config map {}, expected 0 or 1", active);
+        }
+
+        Ok(DoubleBufferedConfig {
+            maps: [a, b],
+            selector,
+            active,
+            staged: false,
+        })
+    }
+
+    /// Index (0 or 1) of the config map the eBPF side is currently reading.
+    pub fn active(&self) -> u32 {
+        self.active
+    }
+
+    /// The config map the eBPF side is currently reading.
+    pub fn active_map(&self) -> &Map<K, V> {
+        &self.maps[self.active as usize]
+    }
+
+    /// The config map that the next [`stage`](DoubleBufferedConfig::stage) writes to.
+    pub fn inactive_map(&self) -> &Map<K, V> {
+        &self.maps[(self.active ^ 1) as usize]
+    }
+
+    /// Replaces the contents of the inactive config map with `entries`. Existing entries are
+    /// deleted (or reset to `V::default()` for array maps). The new config isn't visible to the
+    /// eBPF side until it is [`commit`](DoubleBufferedConfig::commit)ted.
+    pub fn stage(&mut self, entries: &[(K, V)]) -> XDPResult<()> {
+        let m = self.inactive_map();
+        for kv in m.items()? {
+            if m.map_type().is_array() {
+                m.update(&kv.key, &V::default(), MapFlags::BpfAny)?;
+            } else {
+                m.delete(&kv.key)?;
+            }
+        }
+
+        for (k, v) in entries {
+            m.update(k, v, MapFlags::BpfAny)?;
+        }
+        self.staged = true;
+
+        Ok(())
+    }
+
+    /// Makes the staged config live by flipping the selector, and returns the index of the new
+    /// active config map. Fails with `EINVAL` if nothing was staged.
+    pub fn commit(&mut self) -> XDPResult<u32> {
+        if !self.staged {
+            set_errno(Errno(22));
+            fail!("No config staged");
+        }
+
+        let next = self.active ^ 1;
+        self.selector.update(&0, &next, MapFlags::BpfAny)?;
+        self.active = next;
+        self.staged = false;
+
+        Ok(next)
+    }
+
+    /// Stages and commits `entries`, returning the index of the new active config map.
+    pub fn update(&mut self, entries: &[(K, V)]) -> XDPResult<u32> {
+        self.stage(entries)?;
+        self.commit()
+    }
+}
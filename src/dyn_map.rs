@@ -0,0 +1,223 @@
+use errno::{set_errno, Errno};
+use libbpf_sys as bpf;
+use std::os::raw::c_void;
+
+use crate::map_common as mc;
+use crate::map_compat;
+use crate::object::XDPLoadedObject;
+use crate::percpu_codec;
+use crate::result::XDPResult;
+use crate::{MapFlags, MapType, XDPError};
+
+/// Untyped handle for any eBPF map, operating on raw key/value bytes.
+///
+/// Useful for map types rxdp doesn't have a typed handle for, including types newer than the
+/// [`MapType`] enum (see [`raw_map_type`](DynMap::raw_map_type)), or when the key/value types
+/// are only known at runtime.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let m = rxdp::DynMap::new(&obj, "map_name").unwrap();
+///
+/// let key = 7u32.to_ne_bytes();
+/// m.update(&key, &100u64.to_ne_bytes(), rxdp::MapFlags::BpfAny).unwrap();
+/// for key in m.keys().unwrap() {
+///     println!("{:?}: {:?}", key, m.lookup(&key).unwrap());
+/// }
+/// ```
+pub struct DynMap {
+    map_fd: i32,
+    map_type: MapType,
+    raw_map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    // Maps opened by id are owned by the handle, maps of an object by the object.
+    owned: bool,
+}
+
+impl DynMap {
+    /// Get access to the eBPF map `map_name`, whatever its type.
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<DynMap> {
+        let def = mc::find_map(xdp, map_name)?;
+
+        Ok(DynMap {
+            map_fd: def.fd,
+            map_type: def.map_type,
+            raw_map_type: def.raw_map_type,
+            key_size: def.key_size,
+            value_size: def.value_size,
+            max_entries: def.max_entries,
+            owned: false,
+        })
+    }
+
+    /// Get access to the map with kernel id `map_id`, e.g. a map of a program loaded by another
+    /// process (see [`sys::program_map_ids`](crate::sys::program_map_ids)). Requires
+    /// CAP_SYS_ADMIN. Permission errors name the likely cause: a missing capability, the
+    /// `kernel.unprivileged_bpf_disabled` sysctl or an LSM policy.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// for prog in rxdp::sys::attached_programs().unwrap() {
+    ///     for id in rxdp::sys::program_map_ids(prog.prog_id).unwrap().unwrap_or_default() {
+    ///         let m = rxdp::DynMap::from_id(id).unwrap();
+    ///         println!("{}: {:?}, {} keys", prog.name, m.map_type(), m.keys().unwrap().len());
+    ///     }
+    /// }
+    /// ```
+    pub fn from_id(map_id: u32) -> XDPResult<DynMap> {
+        let fd = mc::map_fd_by_id(map_id)?;
+        let info = match map_compat::map_info(fd) {
+            Ok(i) => i,
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                return Err(e);
+            }
+        };
+        Ok(DynMap {
+            map_fd: fd,
+            map_type: info.type_.into(),
+            raw_map_type: info.type_,
+            key_size: info.key_size,
+            value_size: info.value_size,
+            max_entries: info.max_entries,
+            owned: true,
+        })
+    }
+
+    /// File descriptor for this map.
+    pub fn map_fd(&self) -> i32 {
+        self.map_fd
+    }
+
+    /// The map type. [`MapType::Unspec`] for types rxdp doesn't know about.
+    pub fn map_type(&self) -> MapType {
+        self.map_type
+    }
+
+    /// The map type as defined by the kernel (`BPF_MAP_TYPE_*`).
+    pub fn raw_map_type(&self) -> u32 {
+        self.raw_map_type
+    }
+
+    pub fn key_size(&self) -> u32 {
+        self.key_size
+    }
+
+    pub fn value_size(&self) -> u32 {
+        self.value_size
+    }
+
+    pub fn max_entries(&self) -> u32 {
+        self.max_entries
+    }
+
+    /// Re-read the map's metadata (currently `max_entries`) from the kernel, in case the map
+    /// was sized differently than its definition.
+    pub fn refresh_info(&mut self) -> XDPResult<()> {
+        self.max_entries = map_compat::map_info(self.map_fd)?.max_entries;
+        Ok(())
+    }
+
+    /// Length of the value buffer used in lookups/updates. For per-cpu maps, this is one value
+    /// (padded to 8 bytes) per possible CPU.
+    pub fn value_len(&self) -> usize {
+        if self.map_type.is_per_cpu() {
+            percpu_codec::align(self.value_size) * crate::num_cpus()
+        } else {
+            self.value_size as usize
+        }
+    }
+
+    /// Lookup the value for `key`.
+    pub fn lookup(&self, key: &[u8]) -> XDPResult<Vec<u8>> {
+        self.check_key(key)?;
+        let mut value = vec![0u8; self.value_len()];
+        let rc = mc::lookup_elem(
+            self.map_fd,
+            key.as_ptr() as *const c_void,
+            value.as_mut_ptr() as *mut c_void,
+        );
+
+        mc::check_rc(rc, value, "Error looking up elem")
+    }
+
+    /// Update the value for `key`. `value` must be [`value_len`](DynMap::value_len) bytes.
+    pub fn update(&self, key: &[u8], value: &[u8], flags: MapFlags) -> XDPResult<()> {
+        self.check_key(key)?;
+        if value.len() != self.value_len() {
+            set_errno(Errno(22));
+            fail!(
+                "Incorrect value length {}, expected {}",
+                value.len(),
+                self.value_len()
+            );
+        }
+
+        let flags = flags.checked_bits()?;
+        let rc = mc::update_elem(
+            self.map_fd,
+            key.as_ptr() as *const c_void,
+            value.as_ptr() as *const c_void,
+            flags,
+        );
+
+        mc::check_rc(rc, (), "Error updating elem")
+    }
+
+    /// Delete `key` from the map.
+    pub fn delete(&self, key: &[u8]) -> XDPResult<()> {
+        self.check_key(key)?;
+        let rc = unsafe { bpf::bpf_map_delete_elem(self.map_fd, key.as_ptr() as *const c_void) };
+
+        mc::check_rc(rc, (), "Error deleting elem")
+    }
+
+    /// Returns all keys in the map.
+    pub fn keys(&self) -> XDPResult<Vec<Vec<u8>>> {
+        let mut keys: Vec<Vec<u8>> = Vec::new();
+        loop {
+            let mut key = vec![0u8; self.key_size as usize];
+            let prev = keys.last().map_or(std::ptr::null(), |k| k.as_ptr());
+            let rc = unsafe {
+                bpf::bpf_map_get_next_key(
+                    self.map_fd,
+                    prev as *const c_void,
+                    key.as_mut_ptr() as *mut c_void,
+                )
+            };
+            if rc < 0 {
+                if crate::error::get_errno() == 2 {
+                    return Ok(keys);
+                }
+                fail_rc!(rc, "Error getting next key");
+            }
+            keys.push(key);
+        }
+    }
+
+    fn check_key(&self, key: &[u8]) -> XDPResult<()> {
+        if key.len() != self.key_size as usize {
+            set_errno(Errno(22));
+            fail!(
+                "Incorrect key length {}, expected {}",
+                key.len(),
+                self.key_size
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for DynMap {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { libc::close(self.map_fd) };
+        }
+    }
+}
@@ -0,0 +1,449 @@
+//! Minimal ELF parsing, for metadata libbpf doesn't expose.
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOTE: u32 = 7;
+const SHT_REL: u32 = 9;
+const SHT_DYNSYM: u32 = 11;
+const PT_LOAD: u32 = 1;
+const STT_FUNC: u8 = 2;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+/// Returns the GNU build-id note of a 64-bit ELF file, `None` if there is none or `elf` isn't
+/// a valid ELF file.
+pub(crate) fn build_id(elf: &[u8]) -> Option<Vec<u8>> {
+    let r = Reader::new(elf)?;
+    for s in r.sections()?.iter().filter(|s| s.sh_type == SHT_NOTE) {
+        if let Some(id) = r.find_note(s.offset, s.offset.checked_add(s.size)?) {
+            return Some(id);
+        }
+    }
+
+    None
+}
+
+/// Returns the maps referenced by the programs of a 64-bit ELF file, as `(program, map)`
+/// pairs found in the relocations of the program sections. Maps only used through
+/// subprograms aren't included. `None` if `elf` isn't a valid ELF file.
+pub(crate) fn map_references(elf: &[u8]) -> Option<Vec<(String, String)>> {
+    let r = Reader::new(elf)?;
+    let sections = r.sections()?;
+    let shstrtab = sections.get(r.u16(0x3e)? as usize)?;
+    let symtab = match sections.iter().find(|s| s.sh_type == SHT_SYMTAB) {
+        Some(s) => s,
+        None => return Some(Vec::new()),
+    };
+    let strtab = sections.get(symtab.link)?;
+
+    let mut map_sections = Vec::new();
+    for (i, s) in sections.iter().enumerate() {
+        let name = r.str(shstrtab.offset.checked_add(s.name)?)?;
+        if name == "maps" || name == ".maps" {
+            map_sections.push(i);
+        }
+    }
+    let mut symbols = Vec::new();
+    for i in 0..symtab.size / 24 {
+        symbols.push(r.symbol(symtab.offset + i * 24)?);
+    }
+
+    let mut refs = Vec::new();
+    for rel in sections.iter().filter(|s| s.sh_type == SHT_REL) {
+        for i in 0..rel.size / 16 {
+            let offset = r.u64(rel.offset + i * 16)?;
+            let sym = symbols.get((r.u64(rel.offset + i * 16 + 8)? >> 32) as usize)?;
+            if !map_sections.contains(&sym.section) {
+                continue;
+            }
+            let prog = symbols.iter().find(|p| {
+                p.sym_type == STT_FUNC
+                    && p.section == rel.info
+                    && (p.value..p.value + p.size).contains(&offset)
+            });
+            if let Some(prog) = prog {
+                let names = (
+                    r.str(strtab.offset.checked_add(prog.name)?)?.to_string(),
+                    r.str(strtab.offset.checked_add(sym.name)?)?.to_string(),
+                );
+                if !refs.contains(&names) {
+                    refs.push(names);
+                }
+            }
+        }
+    }
+
+    Some(refs)
+}
+
+/// Returns the helpers called by the functions of a 64-bit ELF file, as `(function, helper
+/// ids)` pairs in symbol order. Only direct calls are found, helpers called by a subprogram
+/// are listed for the subprogram. `None` if `elf` isn't a valid ELF file.
+pub(crate) fn helper_calls(elf: &[u8]) -> Option<Vec<(String, Vec<u32>)>> {
+    let r = Reader::new(elf)?;
+    let sections = r.sections()?;
+    let symtab = match sections.iter().find(|s| s.sh_type == SHT_SYMTAB) {
+        Some(s) => s,
+        None => return Some(Vec::new()),
+    };
+    let strtab = sections.get(symtab.link)?;
+
+    let mut calls = Vec::new();
+    for i in 0..symtab.size / 24 {
+        let sym = r.symbol(symtab.offset + i * 24)?;
+        if sym.sym_type != STT_FUNC {
+            continue;
+        }
+        let section = sections.get(sym.section)?;
+        let start = section.offset.checked_add(sym.value as usize)?;
+
+        let mut helpers = Vec::new();
+        for insn in 0..sym.size as usize / 8 {
+            let off = start + insn * 8;
+            let code = *r.data.get(off)?;
+            let regs = *r.data.get(off + 1)?;
+            let src_reg = if r.big_endian { regs & 0xf } else { regs >> 4 };
+            // BPF_JMP | BPF_CALL, other source registers are calls to subprograms or kfuncs.
+            if code == 0x85 && src_reg == 0 {
+                let id = r.u32(off + 4)?;
+                if !helpers.contains(&id) {
+                    helpers.push(id);
+                }
+            }
+        }
+        let name = r.str(strtab.offset.checked_add(sym.name)?)?;
+        calls.push((name.to_string(), helpers));
+    }
+
+    Some(calls)
+}
+
+/// Returns the functions defined in a 64-bit ELF file, as `(name, address, size)` from both the
+/// static and the dynamic symbol table. `None` if `elf` isn't a valid ELF file.
+pub(crate) fn functions(elf: &[u8]) -> Option<Vec<(String, u64, u64)>> {
+    let r = Reader::new(elf)?;
+    let sections = r.sections()?;
+
+    let mut funcs = Vec::new();
+    for symtab in sections
+        .iter()
+        .filter(|s| s.sh_type == SHT_SYMTAB || s.sh_type == SHT_DYNSYM)
+    {
+        let strtab = sections.get(symtab.link)?;
+        for i in 0..symtab.size / 24 {
+            let sym = r.symbol(symtab.offset + i * 24)?;
+            // Undefined symbols are in section 0.
+            if sym.sym_type != STT_FUNC || sym.section == 0 {
+                continue;
+            }
+            let name = r.str(strtab.offset.checked_add(sym.name)?)?;
+            funcs.push((name.to_string(), sym.value, sym.size));
+        }
+    }
+    funcs.sort();
+    funcs.dedup();
+
+    Some(funcs)
+}
+
+/// Returns the difference between the virtual address and the file offset of the loadable
+/// segment of a 64-bit ELF file that contains `file_offset`, i.e. what to subtract from a symbol
+/// address to get its offset in the file. `None` if no segment contains it.
+pub(crate) fn segment_bias(elf: &[u8], file_offset: u64) -> Option<u64> {
+    let r = Reader::new(elf)?;
+    let phoff = r.u64(0x20)? as usize;
+    let phentsize = r.u16(0x36)? as usize;
+    let phnum = r.u16(0x38)? as usize;
+    for i in 0..phnum {
+        let ph = phoff.checked_add(i.checked_mul(phentsize)?)?;
+        let offset = r.u64(ph + 8)?;
+        let vaddr = r.u64(ph + 0x10)?;
+        let filesz = r.u64(ph + 0x20)?;
+        if r.u32(ph)? == PT_LOAD && (offset..offset + filesz).contains(&file_offset) {
+            return Some(vaddr.wrapping_sub(offset));
+        }
+    }
+
+    None
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+struct Section {
+    name: usize,
+    sh_type: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+    info: usize,
+}
+
+struct Symbol {
+    name: usize,
+    sym_type: u8,
+    section: usize,
+    value: u64,
+    size: u64,
+}
+
+impl<'a> Reader<'a> {
+    fn new(elf: &'a [u8]) -> Option<Reader<'a>> {
+        if elf.get(..4)? != b"\x7fELF" || *elf.get(4)? != 2 {
+            return None;
+        }
+        Some(Reader {
+            data: elf,
+            big_endian: *elf.get(5)? == 2,
+        })
+    }
+
+    fn sections(&self) -> Option<Vec<Section>> {
+        let shoff = self.u64(0x28)? as usize;
+        let shentsize = self.u16(0x3a)? as usize;
+        let shnum = self.u16(0x3c)? as usize;
+        let mut sections = Vec::with_capacity(shnum);
+        for i in 0..shnum {
+            let sh = shoff.checked_add(i.checked_mul(shentsize)?)?;
+            sections.push(Section {
+                name: self.u32(sh)? as usize,
+                sh_type: self.u32(sh + 4)?,
+                offset: self.u64(sh + 0x18)? as usize,
+                size: self.u64(sh + 0x20)? as usize,
+                link: self.u32(sh + 0x28)? as usize,
+                info: self.u32(sh + 0x2c)? as usize,
+            });
+        }
+
+        Some(sections)
+    }
+
+    fn symbol(&self, off: usize) -> Option<Symbol> {
+        Some(Symbol {
+            name: self.u32(off)? as usize,
+            sym_type: *self.data.get(off + 4)? & 0xf,
+            section: self.u16(off + 6)? as usize,
+            value: self.u64(off + 8)?,
+            size: self.u64(off + 16)?,
+        })
+    }
+
+    // The NUL terminated string at `off`.
+    fn str(&self, off: usize) -> Option<&'a str> {
+        let s = self.data.get(off..)?;
+        let end = s.iter().position(|b| *b == 0)?;
+        std::str::from_utf8(&s[..end]).ok()
+    }
+
+    fn bytes<const N: usize>(&self, off: usize) -> Option<[u8; N]> {
+        self.data.get(off..off.checked_add(N)?)?.try_into().ok()
+    }
+
+    fn u16(&self, off: usize) -> Option<u16> {
+        let b = self.bytes(off)?;
+        Some(match self.big_endian {
+            true => u16::from_be_bytes(b),
+            false => u16::from_le_bytes(b),
+        })
+    }
+
+    fn u32(&self, off: usize) -> Option<u32> {
+        let b = self.bytes(off)?;
+        Some(match self.big_endian {
+            true => u32::from_be_bytes(b),
+            false => u32::from_le_bytes(b),
+        })
+    }
+
+    fn u64(&self, off: usize) -> Option<u64> {
+        let b = self.bytes(off)?;
+        Some(match self.big_endian {
+            true => u64::from_be_bytes(b),
+            false => u64::from_le_bytes(b),
+        })
+    }
+
+    // Looks for the build-id in the notes between `start` and `end`.
+    fn find_note(&self, mut off: usize, end: usize) -> Option<Vec<u8>> {
+        let align = |n: usize| (n + 3) & !3;
+        while off + 12 <= end {
+            let namesz = self.u32(off)? as usize;
+            let descsz = self.u32(off + 4)? as usize;
+            let note_type = self.u32(off + 8)?;
+            let name = off + 12;
+            let desc = name.checked_add(align(namesz))?;
+            let next = desc.checked_add(align(descsz))?;
+            if next > end {
+                return None;
+            }
+
+            if note_type == NT_GNU_BUILD_ID && self.data.get(name..name + namesz)? == b"GNU\0" {
+                return Some(self.data.get(desc..desc + descsz)?.to_vec());
+            }
+            off = next;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An ELF header, a build-id note and the section headers (null + note section).
+    fn elf(big_endian: bool, note_type: u32) -> Vec<u8> {
+        let (u16b, u32b, u64b): (fn(u16) -> Vec<u8>, fn(u32) -> Vec<u8>, fn(u64) -> Vec<u8>) =
+            match big_endian {
+                true => (
+                    |v| v.to_be_bytes().to_vec(),
+                    |v| v.to_be_bytes().to_vec(),
+                    |v| v.to_be_bytes().to_vec(),
+                ),
+                false => (
+                    |v| v.to_le_bytes().to_vec(),
+                    |v| v.to_le_bytes().to_vec(),
+                    |v| v.to_le_bytes().to_vec(),
+                ),
+            };
+
+        let mut note = Vec::new();
+        note.extend(u32b(4));
+        note.extend(u32b(4));
+        note.extend(u32b(note_type));
+        note.extend(b"GNU\0");
+        note.extend([0xde, 0xad, 0xbe, 0xef]);
+
+        let note_off = 0x40u64;
+        let shoff = note_off + note.len() as u64;
+        let mut b = vec![0u8; 0x40];
+        b[..4].copy_from_slice(b"\x7fELF");
+        b[4] = 2;
+        b[5] = if big_endian { 2 } else { 1 };
+        b[0x28..0x30].copy_from_slice(&u64b(shoff));
+        b[0x3a..0x3c].copy_from_slice(&u16b(64));
+        b[0x3c..0x3e].copy_from_slice(&u16b(2));
+        b.extend(note.iter());
+
+        b.extend(vec![0u8; 64]);
+        let mut sh = vec![0u8; 64];
+        sh[4..8].copy_from_slice(&u32b(SHT_NOTE));
+        sh[0x18..0x20].copy_from_slice(&u64b(note_off));
+        sh[0x20..0x28].copy_from_slice(&u64b(note.len() as u64));
+        b.extend(sh);
+        b
+    }
+
+    #[test]
+    fn test_build_id() {
+        let id = Some(vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(build_id(&elf(false, NT_GNU_BUILD_ID)), id);
+        assert_eq!(build_id(&elf(true, NT_GNU_BUILD_ID)), id);
+        assert_eq!(build_id(&elf(false, 1)), None);
+    }
+
+    // Sections: null, .shstrtab, .strtab, xdp (2 programs), maps, .symtab, .relxdp.
+    fn elf_with_relocations() -> Vec<u8> {
+        let shstrtab = b"\0.shstrtab\0.strtab\0xdp\0maps\0.symtab\0.relxdp\0".to_vec();
+        let strtab = b"\0prog\0other\0mymap\0".to_vec();
+        let sym = |name: u32, info: u8, shndx: u16, value: u64, size: u64| {
+            let mut b = name.to_le_bytes().to_vec();
+            b.extend([info, 0]);
+            b.extend(shndx.to_le_bytes());
+            b.extend(value.to_le_bytes());
+            b.extend(size.to_le_bytes());
+            b
+        };
+        let mut symtab = vec![0u8; 24];
+        symtab.extend(sym(1, 0x12, 3, 0, 16));
+        symtab.extend(sym(6, 0x12, 3, 16, 16));
+        symtab.extend(sym(12, 0x11, 4, 0, 20));
+        // `other` calls helper 5, then a subprogram (src_reg 1).
+        let mut xdp = vec![0u8; 16];
+        xdp.extend([0x85, 0, 0, 0, 5, 0, 0, 0]);
+        xdp.extend([0x85, 0x10, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        let mut rel = Vec::new();
+        // Two references from `prog` to `mymap`, and one from `other` to a function.
+        for (offset, sym) in [(0u64, 3u64), (8, 3), (24, 1)].iter() {
+            rel.extend(offset.to_le_bytes());
+            rel.extend((sym << 32 | 1).to_le_bytes());
+        }
+
+        // (name, type, data, link, info)
+        let sections: Vec<(u32, u32, Vec<u8>, u32, u32)> = vec![
+            (0, 0, vec![], 0, 0),
+            (1, 3, shstrtab, 0, 0),
+            (11, 3, strtab, 0, 0),
+            (19, 1, xdp, 0, 0),
+            (23, 1, vec![0u8; 20], 0, 0),
+            (28, SHT_SYMTAB, symtab, 2, 1),
+            (36, SHT_REL, rel, 5, 3),
+        ];
+
+        let mut b = vec![0u8; 0x40];
+        let mut headers = Vec::new();
+        for (name, sh_type, data, link, info) in sections.iter() {
+            let mut sh = vec![0u8; 64];
+            sh[..4].copy_from_slice(&name.to_le_bytes());
+            sh[4..8].copy_from_slice(&sh_type.to_le_bytes());
+            sh[0x18..0x20].copy_from_slice(&(b.len() as u64).to_le_bytes());
+            sh[0x20..0x28].copy_from_slice(&(data.len() as u64).to_le_bytes());
+            sh[0x28..0x2c].copy_from_slice(&link.to_le_bytes());
+            sh[0x2c..0x30].copy_from_slice(&info.to_le_bytes());
+            headers.extend(sh);
+            b.extend(data.iter());
+        }
+        let shoff = b.len() as u64;
+        b.extend(headers);
+
+        b[..4].copy_from_slice(b"\x7fELF");
+        b[4] = 2;
+        b[5] = 1;
+        b[0x28..0x30].copy_from_slice(&shoff.to_le_bytes());
+        b[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        b[0x3c..0x3e].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+        b[0x3e..0x40].copy_from_slice(&1u16.to_le_bytes());
+        b
+    }
+
+    #[test]
+    fn test_map_references() {
+        let refs = map_references(&elf_with_relocations()).unwrap();
+        assert_eq!(refs, vec![("prog".to_string(), "mymap".to_string())]);
+
+        assert_eq!(map_references(&elf(false, NT_GNU_BUILD_ID)), Some(vec![]));
+        assert_eq!(map_references(b"not an elf file"), None);
+    }
+
+    #[test]
+    fn test_helper_calls() {
+        let calls = helper_calls(&elf_with_relocations()).unwrap();
+        assert_eq!(
+            calls,
+            vec![("prog".to_string(), vec![]), ("other".to_string(), vec![5])]
+        );
+
+        assert_eq!(helper_calls(&elf(false, NT_GNU_BUILD_ID)), Some(vec![]));
+        assert_eq!(helper_calls(b"not an elf file"), None);
+    }
+
+    #[test]
+    fn test_functions() {
+        let funcs = functions(&elf_with_relocations()).unwrap();
+        assert_eq!(
+            funcs,
+            vec![("other".to_string(), 16, 16), ("prog".to_string(), 0, 16)]
+        );
+
+        assert_eq!(segment_bias(&elf_with_relocations(), 0), None);
+        assert_eq!(functions(b"not an elf file"), None);
+    }
+
+    #[test]
+    fn test_build_id_invalid() {
+        assert_eq!(build_id(b"not an elf file"), None);
+        let mut b = elf(false, NT_GNU_BUILD_ID);
+        b.truncate(b.len() - 40);
+        assert_eq!(build_id(&b), None);
+        assert_eq!(build_id(&[]), None);
+    }
+}
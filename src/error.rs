@@ -16,29 +16,100 @@ use std::fmt;
 pub struct XDPError {
     code: i32,
     description: String,
+    raw_os_error: i32,
+    return_code: Option<i32>,
+    partial_update: Option<PartialUpdate>,
+}
+
+/// How far a batch update got before failing, see [`XDPError::partial_update`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialUpdate {
+    /// Number of entries that were applied, from the start of the batch.
+    pub applied: u32,
+    /// Index of the entry that failed. Entries after it were not attempted.
+    pub failed_index: u32,
 }
 
 impl XDPError {
     pub fn new(err_msg: &str) -> Self {
-        let mut e = errno();
+        XDPError::build(err_msg, errno().0, None)
+    }
+
+    /// Create an error for a libbpf call that returned `rc`.
+    ///
+    /// Some libbpf functions return `-errno` rather than setting errno (e.g. the netlink based
+    /// XDP attach functions). If `rc` is a negative error code other than -1, it is used as the
+    /// error code, otherwise errno is used.
+    /// # Example
+    /// ```
+    /// # use errno::{Errno, set_errno};
+    /// # use rxdp::XDPError;
+    /// set_errno(Errno(0));
+    ///
+    /// let e = XDPError::with_return_code("Attach failed", -19);
+    /// assert_eq!(e.code(), 19);
+    /// assert_eq!(e.raw_os_error(), 0);
+    /// assert_eq!(e.return_code(), Some(-19));
+    ///```
+    pub fn with_return_code(err_msg: &str, rc: i32) -> Self {
+        let e = errno().0;
+        let code = if rc < -1 { -rc } else { e };
+        let mut err = XDPError::build(err_msg, code, Some(rc));
+        err.raw_os_error = e;
+        err
+    }
 
+    fn build(err_msg: &str, code: i32, return_code: Option<i32>) -> Self {
         // Re-map ENOTSUPP -> ENOTSUP
-        if e.0 == 524 {
-            e = Errno(95)
-        }
+        let e = if code == 524 { Errno(95) } else { Errno(code) };
         XDPError {
             description: format!("{}: {}", err_msg, e),
             code: e.0,
+            raw_os_error: code,
+            return_code,
+            partial_update: None,
         }
     }
 
+    pub(crate) fn with_partial_update(mut self, applied: u32) -> Self {
+        self.partial_update = Some(PartialUpdate {
+            applied,
+            failed_index: applied,
+        });
+        self
+    }
+
+    /// The error code, derived from errno or the return code of the failed call.
     pub fn code(&self) -> i32 {
         self.code
     }
 
+    /// The value of errno when the error was created.
+    pub fn raw_os_error(&self) -> i32 {
+        self.raw_os_error
+    }
+
+    /// The return code of the failed call, if the error was created from one.
+    pub fn return_code(&self) -> Option<i32> {
+        self.return_code
+    }
+
+    /// For errors from [`update_batch`](crate::MapLike::update_batch), how many entries were
+    /// applied before the failure. The batch can be resumed from
+    /// [`failed_index`](PartialUpdate::failed_index).
+    pub fn partial_update(&self) -> Option<PartialUpdate> {
+        self.partial_update
+    }
+
     pub fn description(&self) -> &str {
         &self.description
     }
+
+    /// True if the operation didn't complete before the handle's deadline, see
+    /// [`Map::set_timeout`](crate::Map::set_timeout).
+    pub fn is_timed_out(&self) -> bool {
+        self.code == crate::deadline::ETIMEDOUT
+    }
 }
 
 impl fmt::Display for XDPError {
@@ -0,0 +1,430 @@
+//! Minimal C ABI, so control planes written in other languages can use rxdp. Enabled with
+//! the `ffi` feature. The declarations are in `include/rxdp.h`.
+//!
+//! The crate only builds as an `rlib`, so the C libraries are built explicitly, e.g. with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib` (or `staticlib`).
+//!
+//! Functions returning `int` return 0 (or a non-negative count) on success and `-errno` on
+//! failure. Functions returning a pointer return NULL on failure. In both cases,
+//! [`rxdp_last_error`] describes the last error on the calling thread.
+//!
+//! Objects, maps and perf buffers are opaque handles, which must be released with the
+//! matching `_free` function. Maps are accessed through [`DynMap`], using raw key/value bytes.
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+
+use libbpf_sys as bpf;
+
+use crate::compat;
+use crate::map_common as mc;
+use crate::result::XDPResult;
+use crate::{AttachFlags, DynMap, MapFlags, MapType, XDPError, XDPLoadedObject, XDPObject};
+
+const EINVAL: c_int = 22;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Called for every perf event sample, with the `ctx` passed to [`rxdp_perf_buffer_new`].
+pub type RxdpSampleFn =
+    unsafe extern "C" fn(ctx: *mut c_void, cpu: c_int, data: *mut c_void, size: u32);
+
+/// Called with the number of perf events lost on `cpu`.
+pub type RxdpLostFn = unsafe extern "C" fn(ctx: *mut c_void, cpu: c_int, cnt: u64);
+
+/// Called for every entry by [`rxdp_map_iterate`]. Returning non-zero stops the iteration.
+pub type RxdpMapEntryFn = unsafe extern "C" fn(
+    ctx: *mut c_void,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> c_int;
+
+/// A perf event map being read, see [`rxdp_perf_buffer_new`].
+pub struct RxdpPerfBuffer {
+    pb: *mut bpf::perf_buffer,
+}
+
+fn set_last_error(e: &XDPError) -> c_int {
+    let msg = CString::new(e.to_string()).unwrap_or_default();
+    LAST_ERROR.with(|l| *l.borrow_mut() = Some(msg));
+    if e.code() > 0 {
+        -e.code()
+    } else {
+        -EINVAL
+    }
+}
+
+fn to_rc(r: XDPResult<()>) -> c_int {
+    match r {
+        Ok(()) => 0,
+        Err(e) => set_last_error(&e),
+    }
+}
+
+fn to_ptr<T>(r: XDPResult<T>) -> *mut T {
+    match r {
+        Ok(v) => Box::into_raw(Box::new(v)),
+        Err(e) => {
+            set_last_error(&e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+fn invalid<T>(msg: &str) -> XDPResult<T> {
+    errno::set_errno(errno::Errno(EINVAL));
+    fail!(msg);
+}
+
+unsafe fn str_arg<'a>(s: *const c_char, what: &str) -> XDPResult<&'a str> {
+    if s.is_null() {
+        return invalid(&format!("{} is NULL", what));
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Ok(s),
+        Err(_) => invalid(&format!("{} is not valid UTF-8", what)),
+    }
+}
+
+unsafe fn bytes_arg<'a>(p: *const u8, len: usize, what: &str) -> XDPResult<&'a [u8]> {
+    if p.is_null() {
+        return invalid(&format!("{} is NULL", what));
+    }
+    Ok(std::slice::from_raw_parts(p, len))
+}
+
+unsafe fn ref_arg<'a, T>(p: *const T, what: &str) -> XDPResult<&'a T> {
+    match p.as_ref() {
+        Some(r) => Ok(r),
+        None => invalid(&format!("{} is NULL", what)),
+    }
+}
+
+/// Description of the last error on the calling thread, NULL if there was none. The string
+/// is valid until the next rxdp call on this thread.
+#[no_mangle]
+pub extern "C" fn rxdp_last_error() -> *const c_char {
+    LAST_ERROR.with(|l| l.borrow().as_ref().map_or(std::ptr::null(), |s| s.as_ptr()))
+}
+
+/// Open the ELF file at `path`. See [`XDPObject::new`].
+///
+/// # Safety
+/// `path` must be a valid NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_object_open(path: *const c_char) -> *mut XDPObject {
+    to_ptr(str_arg(path, "path").and_then(XDPObject::new))
+}
+
+/// Free an object that wasn't loaded.
+///
+/// # Safety
+/// `obj` must be NULL or returned by [`rxdp_object_open`], and not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_object_free(obj: *mut XDPObject) {
+    if !obj.is_null() {
+        drop(Box::from_raw(obj));
+    }
+}
+
+/// Load the object into the kernel. `obj` is consumed, even on failure.
+///
+/// # Safety
+/// `obj` must be returned by [`rxdp_object_open`], and not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_object_load(obj: *mut XDPObject) -> *mut XDPLoadedObject {
+    if obj.is_null() {
+        return to_ptr(invalid("obj is NULL"));
+    }
+    to_ptr(Box::from_raw(obj).load())
+}
+
+/// Free a loaded object. Attached programs stay attached, unless the object's drop policy
+/// says otherwise.
+///
+/// # Safety
+/// `obj` must be NULL or returned by [`rxdp_object_load`], and not used afterwards. Maps and
+/// perf buffers opened from it must be freed first.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_loaded_object_free(obj: *mut XDPLoadedObject) {
+    if !obj.is_null() {
+        drop(Box::from_raw(obj));
+    }
+}
+
+/// Attach the program `prog` to the interface `iface`, with `flags` from [`AttachFlags`]
+/// (`XDP_FLAGS_*`).
+///
+/// # Safety
+/// `obj` must be a loaded object, `prog` and `iface` valid NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_program_attach(
+    obj: *const XDPLoadedObject,
+    prog: *const c_char,
+    iface: *const c_char,
+    flags: u32,
+) -> c_int {
+    to_rc((|| {
+        let obj = ref_arg(obj, "obj")?;
+        let flags = match AttachFlags::from_bits(flags) {
+            Some(f) => f,
+            None => return invalid("Invalid attach flags"),
+        };
+        let prog = obj.get_program(str_arg(prog, "prog")?)?;
+        prog.attach_to_interface(str_arg(iface, "iface")?, flags)
+    })())
+}
+
+/// Detach the program `prog` from the interface `iface`.
+///
+/// # Safety
+/// `obj` must be a loaded object, `prog` and `iface` valid NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_program_detach(
+    obj: *const XDPLoadedObject,
+    prog: *const c_char,
+    iface: *const c_char,
+) -> c_int {
+    to_rc((|| {
+        let obj = ref_arg(obj, "obj")?;
+        let prog = obj.get_program(str_arg(prog, "prog")?)?;
+        prog.detach_from_interface(str_arg(iface, "iface")?)
+    })())
+}
+
+/// Open the map `name` of a loaded object.
+///
+/// # Safety
+/// `obj` must be a loaded object, `name` a valid NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_map_open(
+    obj: *const XDPLoadedObject,
+    name: *const c_char,
+) -> *mut DynMap {
+    to_ptr((|| {
+        DynMap::new(ref_arg(obj, "obj")?, str_arg(name, "name")?)
+    })())
+}
+
+/// Free a map handle. The map itself lives on as long as the object.
+///
+/// # Safety
+/// `map` must be NULL or returned by [`rxdp_map_open`], and not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_map_free(map: *mut DynMap) {
+    if !map.is_null() {
+        drop(Box::from_raw(map));
+    }
+}
+
+/// Key size of the map, in bytes. 0 if `map` is NULL.
+///
+/// # Safety
+/// `map` must be NULL or returned by [`rxdp_map_open`].
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_map_key_size(map: *const DynMap) -> u32 {
+    match ref_arg(map, "map") {
+        Ok(m) => m.key_size(),
+        Err(e) => {
+            set_last_error(&e);
+            0
+        }
+    }
+}
+
+/// Length of the value buffer for lookups & updates, see [`DynMap::value_len`]. 0 if `map`
+/// is NULL.
+///
+/// # Safety
+/// `map` must be NULL or returned by [`rxdp_map_open`].
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_map_value_len(map: *const DynMap) -> usize {
+    match ref_arg(map, "map") {
+        Ok(m) => m.value_len(),
+        Err(e) => {
+            set_last_error(&e);
+            0
+        }
+    }
+}
+
+/// Lookup `key`, writing the value to `value`, which must be `value_len` bytes (see
+/// [`rxdp_map_value_len`]).
+///
+/// # Safety
+/// `map` must be returned by [`rxdp_map_open`], `key` and `value` must point to `key_len`
+/// and `value_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_map_lookup(
+    map: *const DynMap,
+    key: *const u8,
+    key_len: usize,
+    value: *mut u8,
+    value_len: usize,
+) -> c_int {
+    to_rc((|| {
+        let map = ref_arg(map, "map")?;
+        if value.is_null() || value_len != map.value_len() {
+            return invalid("Invalid value buffer");
+        }
+        let v = map.lookup(bytes_arg(key, key_len, "key")?)?;
+        std::ptr::copy_nonoverlapping(v.as_ptr(), value, v.len());
+        Ok(())
+    })())
+}
+
+/// Update `key` with `value`, with `flags` from [`MapFlags`] (`BPF_ANY`, `BPF_NOEXIST` or
+/// `BPF_EXIST`).
+///
+/// # Safety
+/// `map` must be returned by [`rxdp_map_open`], `key` and `value` must point to `key_len`
+/// and `value_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_map_update(
+    map: *const DynMap,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+    flags: u64,
+) -> c_int {
+    to_rc((|| {
+        let flags = match MapFlags::from_bits(flags) {
+            Some(f) => f,
+            None => return invalid("Invalid map flags"),
+        };
+        ref_arg(map, "map")?.update(
+            bytes_arg(key, key_len, "key")?,
+            bytes_arg(value, value_len, "value")?,
+            flags,
+        )
+    })())
+}
+
+/// Delete `key` from the map.
+///
+/// # Safety
+/// `map` must be returned by [`rxdp_map_open`], `key` must point to `key_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_map_delete(
+    map: *const DynMap,
+    key: *const u8,
+    key_len: usize,
+) -> c_int {
+    to_rc((|| {
+        ref_arg(map, "map")?.delete(bytes_arg(key, key_len, "key")?)
+    })())
+}
+
+/// Call `cb` for every entry in the map. Returns 0 once all entries were visited, or 1 if a
+/// callback returned non-zero, which stops the iteration. Entries deleted while iterating are
+/// skipped.
+///
+/// # Safety
+/// `map` must be returned by [`rxdp_map_open`]. The key/value pointers passed to `cb` are
+/// only valid during the callback.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_map_iterate(
+    map: *const DynMap,
+    cb: Option<RxdpMapEntryFn>,
+    ctx: *mut c_void,
+) -> c_int {
+    let map = match ref_arg(map, "map") {
+        Ok(m) => m,
+        Err(e) => return set_last_error(&e),
+    };
+    let cb = match cb {
+        Some(cb) => cb,
+        None => return to_rc(invalid("cb is NULL")),
+    };
+    let keys = match map.keys() {
+        Ok(k) => k,
+        Err(e) => return set_last_error(&e),
+    };
+
+    for key in keys {
+        let value = match map.lookup(&key) {
+            Ok(v) => v,
+            Err(e) if e.code() == 2 => continue,
+            Err(e) => return set_last_error(&e),
+        };
+        let rc = cb(ctx, key.as_ptr(), key.len(), value.as_ptr(), value.len());
+        if rc != 0 {
+            return 1;
+        }
+    }
+
+    0
+}
+
+/// Read events from the perf event map `name`, using `page_cnt` pages per CPU (a power of 2).
+/// `sample_cb` and `lost_cb` (which may be NULL) are called from
+/// [`rxdp_perf_buffer_poll`], with `ctx`.
+///
+/// # Safety
+/// `obj` must be a loaded object, `name` a valid NUL terminated string. `ctx` must stay valid
+/// until the perf buffer is freed.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_perf_buffer_new(
+    obj: *const XDPLoadedObject,
+    name: *const c_char,
+    page_cnt: usize,
+    sample_cb: Option<RxdpSampleFn>,
+    lost_cb: Option<RxdpLostFn>,
+    ctx: *mut c_void,
+) -> *mut RxdpPerfBuffer {
+    to_ptr((|| {
+        if sample_cb.is_none() {
+            return invalid("sample_cb is NULL");
+        }
+        let def = mc::find_map(ref_arg(obj, "obj")?, str_arg(name, "name")?)?;
+        if def.map_type != MapType::PerfEventArray {
+            return invalid("Improper map type, must be MapType::PerfEventArray");
+        }
+
+        let pb = match compat::perf_buffer_new(def.fd, page_cnt, sample_cb, lost_cb, ctx) {
+            Ok(pb) => pb,
+            Err(rc) => fail_rc!(rc, "Error creating perf buffer"),
+        };
+
+        Ok(RxdpPerfBuffer { pb })
+    })())
+}
+
+/// Wait up to `timeout_ms` milliseconds for events, and call the callbacks for all available
+/// events. Returns the number of events handled.
+///
+/// # Safety
+/// `pb` must be returned by [`rxdp_perf_buffer_new`].
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_perf_buffer_poll(
+    pb: *const RxdpPerfBuffer,
+    timeout_ms: c_int,
+) -> c_int {
+    let pb = match ref_arg(pb, "pb") {
+        Ok(p) => p,
+        Err(e) => return set_last_error(&e),
+    };
+    let rc = bpf::perf_buffer__poll(pb.pb, timeout_ms);
+    if rc < 0 {
+        return set_last_error(&XDPError::with_return_code("Error polling perf buffer", rc));
+    }
+
+    rc
+}
+
+/// Free a perf buffer.
+///
+/// # Safety
+/// `pb` must be NULL or returned by [`rxdp_perf_buffer_new`], and not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rxdp_perf_buffer_free(pb: *mut RxdpPerfBuffer) {
+    if !pb.is_null() {
+        let pb = Box::from_raw(pb);
+        bpf::perf_buffer__free(pb.pb);
+    }
+}
@@ -0,0 +1,544 @@
+//! Inspection of the XDP programs attached to an interface, including programs chained
+//! behind a [libxdp][1] dispatcher, and preparation of interface settings XDP depends on.
+//!
+//! [1]: https://github.com/xdp-project/xdp-tools/tree/master/lib/libxdp
+use std::os::raw::{c_char, c_int, c_void};
+
+use errno::{set_errno, Errno};
+
+use crate::error::XDPError;
+use crate::map_common as mc;
+use crate::result::XDPResult;
+use crate::sys::{self, AttachedProgram};
+use crate::utils;
+use crate::AttachFlags;
+
+const DISPATCHER_PROG_NAME: &str = "xdp_dispatcher";
+const XDP_DISPATCHER_MAGIC: u8 = 236;
+const MAX_DISPATCHER_ACTIONS: usize = 10;
+// Offsets in `struct xdp_dispatcher_config`. Version 1 has no magic/version header, but the
+// arrays are at the same offsets because of alignment.
+const CHAIN_CALL_ACTIONS_OFFSET: usize = 4;
+const RUN_PRIOS_OFFSET: usize = CHAIN_CALL_ACTIONS_OFFSET + 4 * MAX_DISPATCHER_ACTIONS;
+const CONFIG_MIN_LEN: usize = RUN_PRIOS_OFFSET + 4 * MAX_DISPATCHER_ACTIONS;
+
+// From `linux/ethtool.h`.
+const ETHTOOL_GFLAGS: u32 = 0x25;
+const ETHTOOL_SFLAGS: u32 = 0x26;
+const ETHTOOL_GGRO: u32 = 0x2b;
+const ETHTOOL_SGRO: u32 = 0x2c;
+const ETH_FLAG_LRO: u32 = 1 << 15;
+
+/// The XDP programs attached to an interface, see [`xdp_chain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XdpChain {
+    /// Name of the interface.
+    pub interface: String,
+    /// Index of the interface.
+    pub if_index: i32,
+    /// Programs attached directly to the interface, one per attach mode.
+    pub attached: Vec<AttachedProgram>,
+    /// The libxdp dispatcher, if one of the attached programs is a dispatcher.
+    pub dispatcher: Option<Dispatcher>,
+}
+
+/// A libxdp dispatcher, which runs several programs one after another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dispatcher {
+    /// Kernel id of the dispatcher program.
+    pub prog_id: u32,
+    /// Mode the dispatcher is attached in.
+    pub mode: AttachFlags,
+    /// Version of the dispatcher config, `None` for version 1 (which has no version field) or
+    /// if the config couldn't be read.
+    pub version: Option<u8>,
+    /// Programs run by the dispatcher, in the order they run.
+    pub programs: Vec<ChainedProgram>,
+}
+
+/// A program run by a libxdp dispatcher. Fields are `None` when they can't be detected, e.g.
+/// because libxdp's pins aren't accessible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainedProgram {
+    /// Position of the program in the dispatcher.
+    pub slot: u32,
+    /// Kernel id of the program.
+    pub prog_id: Option<u32>,
+    /// Name of the program.
+    pub name: Option<String>,
+    /// Run priority, lower runs first.
+    pub priority: Option<u32>,
+    /// Bitmask of the XDP actions (`1 << action`) that continue on to the next program.
+    pub chain_call_actions: Option<u32>,
+}
+
+// The parts of `struct xdp_dispatcher_config` needed to describe the chain.
+#[derive(Debug, PartialEq, Eq)]
+struct DispatcherConfig {
+    version: Option<u8>,
+    num_progs: usize,
+    chain_call_actions: Vec<u32>,
+    run_prios: Vec<u32>,
+}
+
+/// Returns the XDP programs attached to the interface `if_name`. If a libxdp dispatcher is
+/// attached, the programs it runs are included, along with their priorities when they can be
+/// read from the dispatcher.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// let chain = rxdp::iface::xdp_chain("eth0").unwrap();
+/// match chain.dispatcher {
+///     Some(d) => {
+///         for p in d.programs {
+///             println!("{:?} (priority {:?})", p.name, p.priority);
+///         }
+///     }
+///     None => println!("attached: {:?}", chain.attached),
+/// }
+/// ```
+pub fn xdp_chain(if_name: &str) -> XDPResult<XdpChain> {
+    let if_index = utils::lookup_interface_by_name(if_name)?;
+    let attached = sys::interface_programs(if_index, if_name)?;
+
+    let mut dispatcher = None;
+    if let Some(p) = attached.iter().find(|p| p.name == DISPATCHER_PROG_NAME) {
+        dispatcher = Some(inspect_dispatcher(if_index, p)?);
+    }
+
+    Ok(XdpChain {
+        interface: if_name.to_string(),
+        if_index,
+        attached,
+        dispatcher,
+    })
+}
+
+fn inspect_dispatcher(if_index: i32, prog: &AttachedProgram) -> XDPResult<Dispatcher> {
+    let config = dispatcher_config(prog.prog_id)?;
+    let pinned = pinned_programs(if_index, prog.prog_id)?;
+
+    let num_progs = match &config {
+        Some(c) => c.num_progs,
+        None => pinned.len(),
+    };
+    let mut programs = Vec::with_capacity(num_progs);
+    for slot in 0..num_progs {
+        let prog_id = pinned.get(slot).copied().flatten();
+        let name = match prog_id {
+            Some(id) => sys::prog_name(id)?,
+            None => None,
+        };
+        programs.push(ChainedProgram {
+            slot: slot as u32,
+            prog_id,
+            name,
+            priority: config.as_ref().map(|c| c.run_prios[slot]),
+            chain_call_actions: config.as_ref().map(|c| c.chain_call_actions[slot]),
+        });
+    }
+
+    Ok(Dispatcher {
+        prog_id: prog.prog_id,
+        mode: prog.mode,
+        version: config.and_then(|c| c.version),
+        programs,
+    })
+}
+
+// Reads the config of the dispatcher from its `.rodata` map.
+fn dispatcher_config(prog_id: u32) -> XDPResult<Option<DispatcherConfig>> {
+    let mut map_ids = [0u32; 8];
+    let info = match sys::prog_info(prog_id, Some(&mut map_ids))? {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+
+    let n = (info.nr_map_ids as usize).min(map_ids.len());
+    for id in map_ids[..n].iter() {
+        let fd = unsafe { libbpf_sys::bpf_map_get_fd_by_id(*id) };
+        if fd < 0 {
+            continue;
+        }
+        let value = rodata(fd);
+        unsafe { libc::close(fd) };
+        if let Some(v) = value? {
+            return Ok(parse_dispatcher_config(&v));
+        }
+    }
+
+    Ok(None)
+}
+
+// Returns the contents of the map if it is a `.rodata` map, `None` otherwise.
+fn rodata(fd: i32) -> XDPResult<Option<Vec<u8>>> {
+    let mut info = libbpf_sys::bpf_map_info::default();
+    let mut info_len = std::mem::size_of::<libbpf_sys::bpf_map_info>() as u32;
+    let rc = unsafe {
+        libbpf_sys::bpf_obj_get_info_by_fd(fd, &mut info as *mut _ as *mut _, &mut info_len)
+    };
+    if rc < 0 {
+        fail_rc!(rc, "Error getting map info");
+    }
+    if !utils::cstring_to_str(info.name.as_ptr()).ends_with(".rodata") {
+        return Ok(None);
+    }
+
+    let key = 0u32;
+    let mut value = vec![0u8; info.value_size as usize];
+    let rc = mc::lookup_elem(
+        fd,
+        &key as *const u32 as *const c_void,
+        value.as_mut_ptr() as *mut c_void,
+    );
+    if rc < 0 {
+        fail_rc!(rc, "Error reading dispatcher config");
+    }
+
+    Ok(Some(value))
+}
+
+fn parse_dispatcher_config(b: &[u8]) -> Option<DispatcherConfig> {
+    if b.len() < CONFIG_MIN_LEN {
+        return None;
+    }
+
+    let (version, num_progs) = if b[0] == XDP_DISPATCHER_MAGIC {
+        (Some(b[1]), b[2] as usize)
+    } else {
+        (None, b[0] as usize)
+    };
+    if num_progs > MAX_DISPATCHER_ACTIONS {
+        return None;
+    }
+
+    let u32s = |offset: usize| {
+        b[offset..offset + 4 * MAX_DISPATCHER_ACTIONS]
+            .chunks_exact(4)
+            .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+            .collect::<Vec<u32>>()
+    };
+
+    Some(DispatcherConfig {
+        version,
+        num_progs,
+        chain_call_actions: u32s(CHAIN_CALL_ACTIONS_OFFSET),
+        run_prios: u32s(RUN_PRIOS_OFFSET),
+    })
+}
+
+// Returns the ids of the programs libxdp pinned for the dispatcher, indexed by slot. Returns
+// an empty list if the pins don't exist or aren't accessible.
+fn pinned_programs(if_index: i32, dispatcher_id: u32) -> XDPResult<Vec<Option<u32>>> {
+    let bpffs = std::env::var("LIBXDP_BPFFS").unwrap_or_else(|_| "/sys/fs/bpf".to_string());
+    let dir = format!("{}/xdp/dispatch-{}-{}", bpffs, if_index, dispatcher_id);
+
+    let mut ids = Vec::new();
+    for slot in 0..MAX_DISPATCHER_ACTIONS {
+        let path = utils::str_to_cstring(&format!("{}/prog{}-prog", dir, slot))?;
+        let fd = unsafe { libbpf_sys::bpf_obj_get(path.as_ptr()) };
+        if fd < 0 {
+            break;
+        }
+
+        let mut info = libbpf_sys::bpf_prog_info::default();
+        let mut info_len = std::mem::size_of::<libbpf_sys::bpf_prog_info>() as u32;
+        let rc = unsafe {
+            let rc = libbpf_sys::bpf_obj_get_info_by_fd(
+                fd,
+                &mut info as *mut _ as *mut _,
+                &mut info_len,
+            );
+            libc::close(fd);
+            rc
+        };
+        ids.push(if rc < 0 { None } else { Some(info.id) });
+    }
+
+    Ok(ids)
+}
+
+/// Interface settings to apply before attaching an XDP program, see [`prepare_for_xdp`].
+///
+/// Native XDP is rejected by many drivers while LRO is enabled or when the MTU doesn't fit a
+/// single page, and GRO merges packets before generic XDP programs see them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Requirements {
+    max_mtu: Option<u32>,
+    disable_lro: bool,
+    disable_gro: bool,
+}
+
+impl Requirements {
+    /// No changes, add them with the other methods.
+    pub fn new() -> Requirements {
+        Requirements::default()
+    }
+
+    /// Lower the MTU to `mtu` if it is larger. A smaller MTU is left as is.
+    pub fn max_mtu(mut self, mtu: u32) -> Requirements {
+        self.max_mtu = Some(mtu);
+        self
+    }
+
+    /// Disable large receive offload.
+    pub fn disable_lro(mut self) -> Requirements {
+        self.disable_lro = true;
+        self
+    }
+
+    /// Disable generic receive offload.
+    pub fn disable_gro(mut self) -> Requirements {
+        self.disable_gro = true;
+        self
+    }
+}
+
+/// Interface settings changed by [`prepare_for_xdp`]. The original settings are restored when
+/// it is dropped (or with [`restore`](PreparedInterface::restore)), so keep it for as long as
+/// the program is attached.
+#[derive(Debug)]
+pub struct PreparedInterface {
+    if_name: String,
+    mtu: Option<u32>,
+    lro: Option<bool>,
+    gro: Option<bool>,
+    restored: bool,
+}
+
+impl PreparedInterface {
+    /// Name of the interface.
+    pub fn interface(&self) -> &str {
+        &self.if_name
+    }
+
+    /// True if any setting was changed.
+    pub fn changed(&self) -> bool {
+        self.mtu.is_some() || self.lro.is_some() || self.gro.is_some()
+    }
+
+    /// Restore the original settings. Every setting is restored even if one fails, the first
+    /// error is returned.
+    pub fn restore(mut self) -> XDPResult<()> {
+        self.restore_settings()
+    }
+
+    /// Keep the new settings, instead of restoring them when dropped.
+    pub fn keep(mut self) {
+        self.restored = true;
+    }
+
+    fn restore_settings(&mut self) -> XDPResult<()> {
+        if self.restored {
+            return Ok(());
+        }
+        self.restored = true;
+
+        let sock = Socket::new()?;
+        let mut result = Ok(());
+        if let Some(gro) = self.gro {
+            result = result.and(sock.set_gro(&self.if_name, gro));
+        }
+        if let Some(lro) = self.lro {
+            result = result.and(sock.set_lro(&self.if_name, lro));
+        }
+        if let Some(mtu) = self.mtu {
+            result = result.and(sock.set_mtu(&self.if_name, mtu));
+        }
+
+        result
+    }
+}
+
+impl Drop for PreparedInterface {
+    fn drop(&mut self) {
+        let _ = self.restore_settings();
+    }
+}
+
+/// Apply the settings in `requirements` to the interface `if_name`, typically before attaching
+/// a program in native mode. Settings that already match are left untouched. If a change
+/// fails, the settings changed before it are restored.
+///
+/// Offloads are changed with the ethtool ioctl interface (`SIOCETHTOOL`), which requires
+/// `CAP_NET_ADMIN`.
+///
+/// # Example
+/// ```no_run
+/// use rxdp::iface::{self, Requirements};
+///
+/// let prepared =
+///     iface::prepare_for_xdp("eth0", Requirements::new().max_mtu(3498).disable_lro()).unwrap();
+/// // attach the program, and later after detaching:
+/// prepared.restore().unwrap();
+/// ```
+pub fn prepare_for_xdp(if_name: &str, requirements: Requirements) -> XDPResult<PreparedInterface> {
+    utils::lookup_interface_by_name(if_name)?;
+    let sock = Socket::new()?;
+    let mut prepared = PreparedInterface {
+        if_name: if_name.to_string(),
+        mtu: None,
+        lro: None,
+        gro: None,
+        restored: false,
+    };
+
+    if let Some(max_mtu) = requirements.max_mtu {
+        let mtu = sock.mtu(if_name)?;
+        if mtu > max_mtu {
+            sock.set_mtu(if_name, max_mtu)?;
+            prepared.mtu = Some(mtu);
+        }
+    }
+    if requirements.disable_lro && sock.lro(if_name)? {
+        sock.set_lro(if_name, false)?;
+        prepared.lro = Some(true);
+    }
+    if requirements.disable_gro && sock.gro(if_name)? {
+        sock.set_gro(if_name, false)?;
+        prepared.gro = Some(true);
+    }
+
+    Ok(prepared)
+}
+
+// `struct ifreq`, with only the members of `ifr_ifru` used here.
+#[repr(C)]
+struct IfReq {
+    name: [c_char; libc::IFNAMSIZ],
+    ifru: IfrIfru,
+}
+
+#[repr(C)]
+union IfrIfru {
+    mtu: c_int,
+    data: *mut c_void,
+    _size: [u8; 24],
+}
+
+// `struct ethtool_value`.
+#[repr(C)]
+struct EthtoolValue {
+    cmd: u32,
+    data: u32,
+}
+
+// Socket for interface ioctls.
+struct Socket(c_int);
+
+impl Socket {
+    fn new() -> XDPResult<Socket> {
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            fail!("Error creating socket for interface settings");
+        }
+        Ok(Socket(fd))
+    }
+
+    fn ifreq(if_name: &str) -> XDPResult<IfReq> {
+        let mut req: IfReq = unsafe { std::mem::zeroed() };
+        if if_name.len() >= req.name.len() {
+            set_errno(Errno(22));
+            fail!("Invalid interface name {}", if_name);
+        }
+        for (dst, src) in req.name.iter_mut().zip(if_name.bytes()) {
+            *dst = src as c_char;
+        }
+        Ok(req)
+    }
+
+    fn mtu(&self, if_name: &str) -> XDPResult<u32> {
+        let mut req = Socket::ifreq(if_name)?;
+        if unsafe { libc::ioctl(self.0, libc::SIOCGIFMTU as _, &mut req) } < 0 {
+            fail!("Error reading the MTU of {}", if_name);
+        }
+        Ok(unsafe { req.ifru.mtu } as u32)
+    }
+
+    fn set_mtu(&self, if_name: &str, mtu: u32) -> XDPResult<()> {
+        let mut req = Socket::ifreq(if_name)?;
+        req.ifru.mtu = mtu as c_int;
+        if unsafe { libc::ioctl(self.0, libc::SIOCSIFMTU as _, &mut req) } < 0 {
+            fail!("Error setting the MTU of {} to {}", if_name, mtu);
+        }
+        Ok(())
+    }
+
+    fn lro(&self, if_name: &str) -> XDPResult<bool> {
+        Ok(self.ethtool(if_name, ETHTOOL_GFLAGS, 0)? & ETH_FLAG_LRO != 0)
+    }
+
+    fn set_lro(&self, if_name: &str, enable: bool) -> XDPResult<()> {
+        let flags = self.ethtool(if_name, ETHTOOL_GFLAGS, 0)?;
+        let flags = match enable {
+            true => flags | ETH_FLAG_LRO,
+            false => flags & !ETH_FLAG_LRO,
+        };
+        self.ethtool(if_name, ETHTOOL_SFLAGS, flags)?;
+        Ok(())
+    }
+
+    fn gro(&self, if_name: &str) -> XDPResult<bool> {
+        Ok(self.ethtool(if_name, ETHTOOL_GGRO, 0)? != 0)
+    }
+
+    fn set_gro(&self, if_name: &str, enable: bool) -> XDPResult<()> {
+        self.ethtool(if_name, ETHTOOL_SGRO, enable as u32)?;
+        Ok(())
+    }
+
+    // Runs the ethtool command `cmd` with `data`, returns the data set by the kernel.
+    fn ethtool(&self, if_name: &str, cmd: u32, data: u32) -> XDPResult<u32> {
+        let mut value = EthtoolValue { cmd, data };
+        let mut req = Socket::ifreq(if_name)?;
+        req.ifru.data = &mut value as *mut EthtoolValue as *mut c_void;
+        if unsafe { libc::ioctl(self.0, libc::SIOCETHTOOL as _, &mut req) } < 0 {
+            fail!("Error running ethtool command {:#x} on {}", cmd, if_name);
+        }
+        Ok(value.data)
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(header: [u8; 4]) -> Vec<u8> {
+        let mut b = header.to_vec();
+        for _ in 0..MAX_DISPATCHER_ACTIONS {
+            b.extend_from_slice(&(1u32 << 2).to_ne_bytes());
+        }
+        for i in 0..MAX_DISPATCHER_ACTIONS as u32 {
+            b.extend_from_slice(&(10 + i).to_ne_bytes());
+        }
+        b
+    }
+
+    #[test]
+    fn test_parse_dispatcher_config() {
+        let c = parse_dispatcher_config(&config([XDP_DISPATCHER_MAGIC, 2, 3, 0])).unwrap();
+        assert_eq!(c.version, Some(2));
+        assert_eq!(c.num_progs, 3);
+        assert_eq!(c.chain_call_actions[0], 1 << 2);
+        assert_eq!(c.run_prios[..3], [10, 11, 12]);
+
+        // Version 1 has no header, only the number of programs (and padding).
+        let c = parse_dispatcher_config(&config([2, 0, 0, 0])).unwrap();
+        assert_eq!(c.version, None);
+        assert_eq!(c.num_progs, 2);
+        assert_eq!(c.run_prios[1], 11);
+    }
+
+    #[test]
+    fn test_parse_dispatcher_config_invalid() {
+        assert!(parse_dispatcher_config(&[0u8; 10]).is_none());
+        assert!(parse_dispatcher_config(&config([11, 0, 0, 0])).is_none());
+    }
+}
@@ -0,0 +1,177 @@
+//! Kernel version detection, and checks for kernel features some of the APIs depend on.
+//!
+//! Operations that need a newer kernel fail with `ENOTSUP` and an error message naming the
+//! required kernel version, e.g. "lookup_and_delete_batch requires kernel >= 5.6 (running
+//! 5.4.0)".
+use errno::{set_errno, Errno};
+use lazy_static::lazy_static;
+use std::fmt;
+
+use crate::error::XDPError;
+use crate::map_batch::{batching_support, BatchingSupport};
+use crate::probe;
+use crate::result::XDPResult;
+use crate::utils;
+
+const ENOTSUP: i32 = 95;
+
+lazy_static! {
+    static ref VERSION: Option<KernelVersion> = running_version();
+}
+
+/// Version of a Linux kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl KernelVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> KernelVersion {
+        KernelVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parse the version from a kernel release string, as returned by `uname -r` (e.g.
+    /// `5.15.0-91-generic`). Missing minor/patch numbers are 0.
+    ///
+    /// # Example
+    /// ```
+    /// use rxdp::kernel::KernelVersion;
+    /// assert_eq!(
+    ///     KernelVersion::parse("5.15.0-91-generic"),
+    ///     Some(KernelVersion::new(5, 15, 0))
+    /// );
+    /// assert_eq!(KernelVersion::parse("6.1"), Some(KernelVersion::new(6, 1, 0)));
+    /// assert_eq!(KernelVersion::parse("unknown"), None);
+    /// ```
+    pub fn parse(release: &str) -> Option<KernelVersion> {
+        let mut parts = release.split(|c: char| !c.is_ascii_digit());
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+        let patch = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+
+        Some(KernelVersion::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Kernel features used by rxdp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Feature {
+    /// Batch map operations (`BPF_MAP_*_BATCH`).
+    BatchOps,
+    /// `BPF_MAP_TYPE_DEVMAP_HASH` maps.
+    DevMapHash,
+    /// `BPF_MAP_TYPE_RINGBUF` maps.
+    RingBuffer,
+    /// Programs attached to DEVMAP/CPUMAP entries.
+    MapPrograms,
+    /// XDP programs loaded with the `BPF_XDP` expected attach type.
+    XdpAttachType,
+}
+
+impl Feature {
+    /// The first kernel version with the feature. Distribution kernels may have it backported
+    /// to older versions, see [`supported`].
+    pub fn min_version(&self) -> KernelVersion {
+        match self {
+            Feature::BatchOps => KernelVersion::new(5, 6, 0),
+            Feature::DevMapHash => KernelVersion::new(5, 4, 0),
+            Feature::RingBuffer => KernelVersion::new(5, 8, 0),
+            Feature::MapPrograms => KernelVersion::new(5, 8, 0),
+            Feature::XdpAttachType => KernelVersion::new(5, 9, 0),
+        }
+    }
+
+    // Result of probing the kernel for the feature, if there is a probe for it.
+    fn probe(&self) -> Option<bool> {
+        match self {
+            Feature::BatchOps => match batching_support() {
+                BatchingSupport::Supported => Some(true),
+                BatchingSupport::Unsupported => Some(false),
+                // Fall back to the kernel version.
+                BatchingSupport::ProbeFailed(_) => None,
+            },
+            Feature::XdpAttachType => Some(probe::xdp_attach_type_supported()),
+            _ => None,
+        }
+    }
+}
+
+/// Version of the running kernel.
+pub fn version() -> XDPResult<KernelVersion> {
+    match *VERSION {
+        Some(v) => Ok(v),
+        None => {
+            set_errno(Errno(ENOTSUP));
+            fail!("Unable to determine the kernel version");
+        }
+    }
+}
+
+/// True if the running kernel supports `feature`. The kernel is probed for features that can
+/// be probed (so backports are detected), otherwise the kernel version is compared to
+/// [`Feature::min_version`].
+pub fn supported(feature: Feature) -> bool {
+    match feature.probe() {
+        Some(supported) => supported,
+        None => VERSION.is_none_or(|v| v >= feature.min_version()),
+    }
+}
+
+/// Returns an `ENOTSUP` error naming the required kernel version if the running kernel
+/// doesn't support `feature`.
+///
+/// # Example
+/// ```no_run
+/// use rxdp::kernel::{self, Feature};
+///
+/// if let Err(e) = kernel::requires(Feature::RingBuffer) {
+///     // "RingBuffer requires kernel >= 5.8 (running 5.4.0)"
+///     eprintln!("{}", e.description());
+/// }
+/// ```
+pub fn requires(feature: Feature) -> XDPResult<()> {
+    require(feature, &format!("{:?}", feature))
+}
+
+// Same as `requires`, with the error message naming the operation `op` instead.
+pub(crate) fn require(feature: Feature, op: &str) -> XDPResult<()> {
+    if supported(feature) {
+        return Ok(());
+    }
+
+    let running = match *VERSION {
+        Some(v) => v.to_string(),
+        None => "unknown".to_string(),
+    };
+    let required = feature.min_version();
+    set_errno(Errno(ENOTSUP));
+    fail!(
+        "{} requires kernel >= {}.{} (running {})",
+        op,
+        required.major,
+        required.minor,
+        running
+    );
+}
+
+fn running_version() -> Option<KernelVersion> {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } < 0 {
+        return None;
+    }
+
+    KernelVersion::parse(&utils::cstring_to_str(uts.release.as_ptr()))
+}
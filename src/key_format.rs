@@ -0,0 +1,203 @@
+use std::fmt::{self, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::lpm::LpmKey;
+use crate::padding::NoPadding;
+
+/// Renders map keys as strings, so exported items (e.g. JSON object keys or Prometheus labels)
+/// are readable without formatting code in every consumer, see
+/// [`items_labeled`](crate::MapLike::items_labeled).
+///
+/// Integers are rendered in decimal, IP addresses and [`LpmKey`] prefixes in their usual
+/// notation, [`MacAddr`] as colon separated hex and [`FixedStr`] as its text. Implement it for
+/// your own structs with [`key_format!`](crate::key_format).
+pub trait KeyFormat {
+    /// Append the rendered key to `out`.
+    fn write_key(&self, out: &mut String);
+
+    /// The rendered key.
+    fn key_string(&self) -> String {
+        let mut out = String::new();
+        self.write_key(&mut out);
+        out
+    }
+}
+
+macro_rules! impl_key_format_display {
+    ($($t:ty),*) => {
+        $(impl KeyFormat for $t {
+            fn write_key(&self, out: &mut String) {
+                let _ = write!(out, "{}", self);
+            }
+        })*
+    };
+}
+
+impl_key_format_display!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+impl_key_format_display!(Ipv4Addr, Ipv6Addr, MacAddr);
+
+impl<const N: usize> KeyFormat for FixedStr<N> {
+    fn write_key(&self, out: &mut String) {
+        out.push_str(&self.to_string_lossy());
+    }
+}
+
+impl KeyFormat for LpmKey<[u8; 4]> {
+    fn write_key(&self, out: &mut String) {
+        let _ = write!(out, "{}/{}", Ipv4Addr::from(self.data()), self.prefixlen());
+    }
+}
+
+impl KeyFormat for LpmKey<[u8; 16]> {
+    fn write_key(&self, out: &mut String) {
+        let _ = write!(out, "{}/{}", Ipv6Addr::from(self.data()), self.prefixlen());
+    }
+}
+
+/// Implements [`KeyFormat`](crate::KeyFormat) for a struct, rendering the listed fields as
+/// `name=value` pairs separated by commas. Every listed field must be `KeyFormat` itself.
+///
+/// # Example
+/// ```
+/// use rxdp::{FixedStr, KeyFormat};
+///
+/// #[derive(Default, Clone, Copy)]
+/// #[repr(C)]
+/// struct Flow {
+///     ifindex: u32,
+///     proto: u16,
+///     port: u16,
+///     comm: FixedStr<8>,
+/// }
+///
+/// rxdp::key_format!(Flow { ifindex, proto, comm });
+///
+/// let flow = Flow { ifindex: 2, proto: 6, port: 80, comm: FixedStr::new("nginx") };
+/// assert_eq!(flow.key_string(), "ifindex=2,proto=6,comm=nginx");
+/// ```
+#[macro_export]
+macro_rules! key_format {
+    ($t:ident { $first:ident $(, $field:ident)* $(,)? }) => {
+        impl $crate::KeyFormat for $t {
+            fn write_key(&self, out: &mut String) {
+                out.push_str(concat!(stringify!($first), "="));
+                $crate::KeyFormat::write_key(&self.$first, out);
+                $(
+                    out.push_str(concat!(",", stringify!($field), "="));
+                    $crate::KeyFormat::write_key(&self.$field, out);
+                )*
+            }
+        }
+    };
+}
+
+/// A MAC address, laid out like the `unsigned char [ETH_ALEN]` fields of kernel structs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct MacAddr(pub [u8; 6]);
+
+unsafe impl NoPadding for MacAddr {}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            b[0], b[1], b[2], b[3], b[4], b[5]
+        )
+    }
+}
+
+/// A NUL-padded string of `N` bytes, laid out like the `char name[N]` fields of kernel structs
+/// (e.g. a task's `comm`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct FixedStr<const N: usize>(pub [u8; N]);
+
+unsafe impl<const N: usize> NoPadding for FixedStr<N> {}
+
+impl<const N: usize> FixedStr<N> {
+    /// The bytes of `s`, truncated to `N` bytes.
+    pub fn new(s: &str) -> FixedStr<N> {
+        let mut buf = [0u8; N];
+        let len = s.len().min(N);
+        buf[..len].copy_from_slice(&s.as_bytes()[..len]);
+        FixedStr(buf)
+    }
+
+    /// The bytes up to the first NUL, or all `N` if there is none.
+    pub fn as_bytes(&self) -> &[u8] {
+        let len = self.0.iter().position(|b| *b == 0).unwrap_or(N);
+        &self.0[..len]
+    }
+
+    /// The string up to the first NUL, with invalid UTF-8 replaced.
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(self.as_bytes()).into_owned()
+    }
+}
+
+impl<const N: usize> Default for FixedStr<N> {
+    fn default() -> Self {
+        FixedStr([0u8; N])
+    }
+}
+
+impl<const N: usize> fmt::Display for FixedStr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_string_lossy())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default, Clone, Copy)]
+    #[repr(C)]
+    struct Flow {
+        src: MacAddr,
+        vlan: u16,
+        comm: FixedStr<4>,
+    }
+
+    crate::key_format!(Flow { src, vlan, comm });
+
+    #[test]
+    fn test_key_format() {
+        assert_eq!(42u32.key_string(), "42");
+        assert_eq!((-1i64).key_string(), "-1");
+        assert_eq!(Ipv4Addr::new(10, 0, 0, 1).key_string(), "10.0.0.1");
+        assert_eq!(Ipv6Addr::LOCALHOST.key_string(), "::1");
+        assert_eq!(
+            LpmKey::ipv4(Ipv4Addr::new(10, 1, 0, 0), 16).key_string(),
+            "10.1.0.0/16"
+        );
+
+        let flow = Flow {
+            src: MacAddr([0, 0x1b, 0x21, 0xa, 0xb, 0xff]),
+            vlan: 100,
+            comm: FixedStr::new("curl"),
+        };
+        assert_eq!(
+            flow.key_string(),
+            "src=00:1b:21:0a:0b:ff,vlan=100,comm=curl"
+        );
+    }
+
+    #[test]
+    fn test_fixed_str() {
+        let s: FixedStr<4> = FixedStr::new("sshd-session");
+        assert_eq!(s.0, *b"sshd");
+        assert_eq!(s.as_bytes(), b"sshd");
+
+        let s: FixedStr<8> = FixedStr::new("ip");
+        assert_eq!(s.as_bytes(), b"ip");
+        assert_eq!(s.to_string(), "ip");
+        assert_eq!(FixedStr::<8>::default().key_string(), "");
+
+        let s = FixedStr([b'a', 0xff, 0, b'b']);
+        assert_eq!(s.to_string_lossy(), "a\u{fffd}");
+    }
+}
@@ -107,7 +107,8 @@
 
 //! ### Per CPU map operations
 //! Per CPU maps return the [`MapValue::Multi(Vec<T>)`](crate::MapValue) variant during lookup,
-//! one for each possible CPU:
+//! one for each possible CPU ([`PerCpuMap::get`](crate::PerCpuMap::get) returns them as
+//! [`PerCpuValues`](crate::PerCpuValues) instead):
 //! ```no_run
 //! # use rxdp;
 //! # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
@@ -130,13 +131,13 @@
 //! ```
 //!
 //! ### Perf event Map
-//! Perf events sent from eBPF can be retrieved via [`PerfMap`](crate::PerfMap).
+//! Perf events sent from eBPF can be retrieved via [`PerfMapBuilder`](crate::PerfMapBuilder).
 //! ```no_run
 //! # use rxdp;
 //! # use crossbeam_channel::Receiver;
 //! # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
-//! let mut perfmap = rxdp::PerfMap::<u32>::new(&obj, "map_name").unwrap();
-//! let r: Receiver<rxdp::PerfEvent<u32>> = perfmap.start_polling(10000);
+//! let handle = rxdp::PerfMapBuilder::<u32>::new(&obj, "map_name").unwrap().spawn(10000);
+//! let r: &Receiver<rxdp::PerfEvent<u32>> = handle.receiver().unwrap();
 //!
 //! // Wait for events on the receiver side of the channel
 //! loop {
@@ -161,33 +162,126 @@
 //!     next_key = r.next_key;
 //! }
 //! ```
+//! On older kernels the batch operations return an error naming the required kernel version, see
+//! the [`kernel`] module to check for this and other kernel features up front.
 //!
 
 #![doc(html_root_url = "https://docs.rs/rxdp/0.3.1")]
 mod macros;
 
+mod bind;
+mod bloom_filter;
+mod cached_map;
+mod cancel;
+pub mod compact;
+mod compat;
+mod compatibility;
+pub mod config;
+mod deadline;
+pub mod decode;
+mod double_buffer;
+mod dyn_map;
+mod elf;
 mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod iface;
+pub mod kernel;
+mod key_format;
+mod lpm;
 mod map;
 mod map_batch;
+mod map_builder;
 mod map_common;
+mod map_compat;
+mod map_dump;
 mod map_flags;
 mod map_types;
 mod object;
+mod outer_map;
+mod padding;
+mod percpu_codec;
 mod percpu_map;
+mod percpu_values;
 mod perf_event_handler;
 mod perf_map;
+mod perm;
+mod persist;
+mod pin_lock;
+mod probe;
+mod prog_array;
 mod program;
+mod queue_map;
+pub mod raw;
+pub mod redirect;
+#[cfg(feature = "pcap")]
+mod replay;
 mod result;
+mod ring_buffer;
+mod sampler;
+pub mod selftest;
+mod shared_region;
+mod special_fields;
+mod stack_trace;
+pub mod sys;
+mod tail_call;
+mod topology;
 mod utils;
+mod watch;
+#[cfg(not(feature = "libbpf-1"))]
+pub mod xsk;
 
-pub use error::XDPError;
+pub use bind::{_bind_error, BindMap};
+pub use bloom_filter::BloomFilter;
+pub use cached_map::{CacheStats, CachedMap};
+pub use cancel::CancelToken;
+pub use compatibility::{CompatibilityReport, MapSupport, ProgramSupport};
+pub use config::PIN_ROOT_ENV;
+pub use double_buffer::DoubleBufferedConfig;
+pub use dyn_map::DynMap;
+pub use error::{PartialUpdate, XDPError};
+pub use key_format::{FixedStr, KeyFormat, MacAddr};
+pub use lpm::LpmKey;
 pub use map::Map;
-pub use map_batch::{is_batching_supported, BatchResult};
+pub use map_batch::{
+    batching_support, is_batching_supported, set_batching_supported, BatchCursor, BatchResult,
+    BatchingSupport, CheckedItems, KeyPage, MapIter, PartialItems, ScrapeConsistency,
+};
+pub use map_builder::{MapBuilder, PerCpuMapBuilder};
 pub use map_common::{KeyValue, MapLike, MapValue};
+pub use map_dump::{DumpHeader, DUMP_MAGIC, DUMP_VERSION};
 pub use map_flags::MapFlags;
-pub use map_types::MapType;
-pub use object::{load_pinned_object, XDPLoadedObject, XDPObject};
-pub use percpu_map::{num_cpus, ByteAligned, PerCpuMap};
-pub use perf_map::{EventType, PerfEvent, PerfMap};
-pub use program::{AttachFlags, Program};
+pub use map_types::{MapKind, MapType};
+pub use object::{
+    load_pinned_object, DropPolicy, PinConfig, Unsupported, UnsupportedPolicy, XDPLoadedObject,
+    XDPObject, XDPObjectBuilder,
+};
+pub use outer_map::OuterMap;
+#[doc(hidden)]
+pub use padding::_assert_no_padding;
+pub use padding::NoPadding;
+pub use percpu_map::{num_cpus, Aggregation, ByteAligned, PerCpuMap};
+pub use percpu_values::PerCpuValues;
+pub use perf_map::{
+    ChannelStats, EventSender, EventType, PerfEvent, PerfMap, PerfMapBuilder, PollHandle,
+};
+pub use pin_lock::PinLock;
+pub use prog_array::ProgArrayMap;
+pub use program::{
+    AttachDowngrade, AttachFlags, AttachMode, BenchmarkStats, ExpectedAttachType, Link, ProgInfo,
+    ProgType, Program, ProgramKind,
+};
+pub use queue_map::{QueueMap, StackMap};
+#[cfg(feature = "pcap")]
+pub use replay::ReplayReport;
 pub use result::XDPResult;
+pub use ring_buffer::{RingBuffer, RingStats};
+pub use sampler::{ProgStats, Sampler, SamplerBuilder, StatsSnapshot};
+pub use shared_region::SharedRegion;
+pub use special_fields::{
+    BpfSpinLock, BpfTimer, SlotArray, SpecialField, SpecialFieldType, SpecialFields,
+};
+pub use stack_trace::{StackTraceMap, Symbol, Symbolizer};
+pub use tail_call::TailCallTable;
+pub use topology::{cpu_topology, CpuInfo};
+pub use utils::ktime_get_ns;
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::map_common::MapLike;
+use crate::padding::NoPadding;
+use crate::result::XDPResult;
+use crate::Map;
+
+/// Key of a `BPF_MAP_TYPE_LPM_TRIE` map, laid out like the kernel's `struct bpf_lpm_trie_key`:
+/// the prefix length in bits, immediately followed by the data.
+///
+/// The kernel compares the data bit by bit in memory order, so multi-byte data such as IP
+/// addresses must be in network byte order, e.g. `[u8; 4]` or `u32::to_be`. The key size of
+/// the map must be `4 + size_of::<T>()`.
+///
+/// Lookups return the value of the longest prefix matching the key, see
+/// [`Map::longest_match`].
+///
+/// # Example
+/// ```no_run
+/// use rxdp::{LpmKey, Map, MapFlags, MapLike};
+/// use std::net::Ipv4Addr;
+///
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let m: Map<LpmKey<[u8; 4]>, u32> = Map::new(&obj, "routes").unwrap();
+/// m.update(&LpmKey::ipv4(Ipv4Addr::new(10, 0, 0, 0), 8), &1, MapFlags::BpfAny).unwrap();
+/// m.update(&LpmKey::ipv4(Ipv4Addr::new(10, 1, 0, 0), 16), &2, MapFlags::BpfAny).unwrap();
+///
+/// let addr = Ipv4Addr::new(10, 1, 2, 3).octets();
+/// assert_eq!(m.longest_match(addr).unwrap(), Some(2));
+/// ```
+#[repr(C, packed)]
+pub struct LpmKey<T> {
+    prefixlen: u32,
+    data: T,
+}
+
+impl<T: Copy> LpmKey<T> {
+    /// The first `prefixlen` bits of `data`.
+    pub fn new(prefixlen: u32, data: T) -> LpmKey<T> {
+        LpmKey { prefixlen, data }
+    }
+
+    /// All bits of `data`, e.g. to look up the longest prefix matching an address.
+    pub fn host(data: T) -> LpmKey<T> {
+        LpmKey::new(Self::max_prefixlen(), data)
+    }
+
+    /// The number of bits in `T`, i.e. the largest valid prefix length.
+    pub fn max_prefixlen() -> u32 {
+        (size_of::<T>() * 8) as u32
+    }
+
+    pub fn prefixlen(&self) -> u32 {
+        self.prefixlen
+    }
+
+    pub fn data(&self) -> T {
+        self.data
+    }
+}
+
+impl LpmKey<[u8; 4]> {
+    /// The IPv4 prefix `addr/prefixlen`.
+    pub fn ipv4(addr: Ipv4Addr, prefixlen: u32) -> LpmKey<[u8; 4]> {
+        LpmKey::new(prefixlen, addr.octets())
+    }
+}
+
+impl LpmKey<[u8; 16]> {
+    /// The IPv6 prefix `addr/prefixlen`.
+    pub fn ipv6(addr: Ipv6Addr, prefixlen: u32) -> LpmKey<[u8; 16]> {
+        LpmKey::new(prefixlen, addr.octets())
+    }
+}
+
+// Derives don't work on packed structs with type parameters, since they take references to
+// (possibly unaligned) fields.
+impl<T: Copy> Clone for LpmKey<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Copy> Copy for LpmKey<T> {}
+
+impl<T: Copy + Default> Default for LpmKey<T> {
+    fn default() -> Self {
+        LpmKey::new(0, T::default())
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for LpmKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LpmKey")
+            .field("prefixlen", &self.prefixlen())
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
+impl<T: Copy + PartialEq> PartialEq for LpmKey<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.prefixlen() == other.prefixlen() && self.data() == other.data()
+    }
+}
+
+impl<T: Copy + Eq> Eq for LpmKey<T> {}
+
+impl<T: Copy + Hash> Hash for LpmKey<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.prefixlen().hash(state);
+        self.data().hash(state);
+    }
+}
+
+// The struct is packed, so there is no padding between or after the fields.
+unsafe impl<T: NoPadding> NoPadding for LpmKey<T> {}
+
+impl<T: Copy + Default, V: Default> Map<LpmKey<T>, V> {
+    /// The value of the longest prefix in the map that matches `data`, `None` if no prefix
+    /// matches.
+    pub fn longest_match(&self, data: T) -> XDPResult<Option<V>> {
+        match self.lookup(&LpmKey::host(data)) {
+            Ok(v) => Ok(Some(v.into_single())),
+            Err(e) if e.code() == 2 => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(size_of::<LpmKey<[u8; 4]>>(), 8);
+        assert_eq!(size_of::<LpmKey<u64>>(), 12);
+        assert_eq!(size_of::<LpmKey<[u8; 5]>>(), 9);
+
+        let key = LpmKey::ipv4(Ipv4Addr::new(192, 168, 1, 0), 24);
+        assert_eq!(
+            utils::as_bytes(&key),
+            [&24u32.to_ne_bytes()[..], &[192, 168, 1, 0]].concat()
+        );
+        assert_eq!(LpmKey::<[u8; 16]>::max_prefixlen(), 128);
+        assert_eq!(LpmKey::host(1u64).prefixlen(), 64);
+    }
+}
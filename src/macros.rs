@@ -4,3 +4,10 @@ macro_rules! fail {
     ( $n:tt ) => { return Err(XDPError::new($n)) };
     ( $n:literal, $( $arg:tt )* ) => { return Err(XDPError::new(&format!($n, $($arg)*))) };
 }
+
+macro_rules! fail_rc {
+    ( $rc:expr, $n:tt ) => { return Err(XDPError::with_return_code($n, $rc)) };
+    ( $rc:expr, $n:literal, $( $arg:tt )* ) => {
+        return Err(XDPError::with_return_code(&format!($n, $($arg)*), $rc))
+    };
+}
@@ -1,12 +1,21 @@
+use crossbeam_channel::Receiver;
 use errno::{set_errno, Errno};
-use std::{marker::PhantomData, mem::size_of, os::raw::c_void};
+use std::{
+    collections::HashMap, hash::Hash, marker::PhantomData, mem::size_of, os::raw::c_void,
+    time::Duration,
+};
 
+use crate::deadline::Deadline;
 use crate::map_batch::*;
 use crate::map_common as mc;
 use crate::map_common::{MapLike, MapValue};
+use crate::map_compat;
 use crate::object::XDPLoadedObject;
+use crate::persist::Persist;
 use crate::result::XDPResult;
-use crate::{KeyValue, MapType, XDPError};
+use crate::utils;
+use crate::watch::Watcher;
+use crate::{KeyValue, MapBuilder, MapFlags, MapKind, MapType, NoPadding, XDPError};
 
 /// Used for working with normal eBPF maps.
 pub struct Map<K, V> {
@@ -15,10 +24,16 @@ pub struct Map<K, V> {
     _val: PhantomData<V>,
     map_type: MapType,
     max_entries: u32,
+    deadline: Option<Deadline>,
+    watcher: Watcher<K, V>,
+    persist: Option<Persist>,
+    // True if the handle opened `map_fd` itself, rather than borrowing it from an object.
+    owned: bool,
 }
 
 impl<K: Default, V: Default> Map<K, V> {
     /// Create a new map.
+    #[deprecated(note = "use rxdp::MapBuilder")]
     pub fn create(
         map_type: MapType,
         key_size: u32,
@@ -26,11 +41,13 @@ impl<K: Default, V: Default> Map<K, V> {
         max_entries: u32,
         map_flags: u32,
     ) -> XDPResult<Map<K, V>> {
-        if map_type.is_per_cpu() {
-            set_errno(Errno(22));
-            fail!("Improper map type, use rxdp::PerCpuMap::create");
-        }
-        Map::<K, V>::_create(map_type, key_size, value_size, max_entries, map_flags, true)
+        MapBuilder::new()
+            .map_type(map_type)
+            .key_size(key_size)
+            .value_size(value_size)
+            .max_entries(max_entries)
+            .map_flags(map_flags)
+            .create()
     }
 
     pub(crate) fn _create(
@@ -39,53 +56,594 @@ impl<K: Default, V: Default> Map<K, V> {
         value_size: u32,
         max_entries: u32,
         map_flags: u32,
-        check_batch: bool,
     ) -> XDPResult<Map<K, V>> {
         let map_fd = mc::create_map(map_type, key_size, value_size, max_entries, map_flags);
+        mc::check_rc(map_fd, (), "Error creating new map")?;
+        Ok(Map::from_parts(map_fd, map_type, max_entries))
+    }
 
-        if check_batch {
-            let _ = is_batching_supported();
-        }
-
-        let m = Map {
+    pub(crate) fn from_parts(map_fd: i32, map_type: MapType, max_entries: u32) -> Map<K, V> {
+        Map {
             map_fd,
             _key: PhantomData,
             _val: PhantomData,
             map_type,
             max_entries,
-        };
+            deadline: None,
+            watcher: Watcher::new(),
+            persist: None,
+            owned: false,
+        }
+    }
 
-        mc::check_rc(map_fd, m, "Error creating new map")
+    // Same as `from_parts`, closing `map_fd` when the handle is dropped.
+    pub(crate) fn from_owned_fd(map_fd: i32, map_type: MapType, max_entries: u32) -> Map<K, V> {
+        let mut m = Map::from_parts(map_fd, map_type, max_entries);
+        m.owned = true;
+        m
     }
 
     /// Get access to the eBPF map `map_name`. This will fail if the requested key/value sizes
     /// don't match the key/value sizes defined in the ELF file.
     pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Map<K, V>> {
-        let (map_fd, vsize, mtype, max_entries) = mc::validate_map::<K>(xdp, map_name)?;
+        let def = mc::validate_map::<K>(xdp, map_name)?;
+        Self::check_def(&def, map_name)?;
+
+        Ok(Map::from_parts(def.fd, def.map_type, def.max_entries))
+    }
+
+    /// Get access to the map pinned at `path`, e.g. by another process, without loading the
+    /// object it belongs to. This will fail if the requested key/value sizes don't match the
+    /// key/value sizes of the pinned map.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// let m: rxdp::Map<u32, u64> = rxdp::Map::from_pinned("/sys/fs/bpf/myapp/counters").unwrap();
+    /// ```
+    pub fn from_pinned(path: &str) -> XDPResult<Map<K, V>> {
+        let def = mc::open_pinned::<K>(path)?;
+        if let Err(e) = Self::check_def(&def, path) {
+            unsafe { libc::close(def.fd) };
+            return Err(e);
+        }
+
+        Ok(Map::from_owned_fd(def.fd, def.map_type, def.max_entries))
+    }
+
+    /// Get access to the map with kernel id `map_id`, e.g. a map created by another process
+    /// and found with `bpftool map list`. Requires CAP_SYS_ADMIN. This will fail if the
+    /// requested key/value sizes don't match the key/value sizes of the map.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// let m: rxdp::Map<u32, u64> = rxdp::Map::from_id(42).unwrap();
+    /// ```
+    pub fn from_id(map_id: u32) -> XDPResult<Map<K, V>> {
+        let fd = mc::map_fd_by_id(map_id)?;
+        Self::adopt_fd(fd, &format!("map id {}", map_id))
+    }
+
+    /// Get access to the map `fd`, e.g. one received from another process over a unix socket.
+    /// The handle works on a duplicate of `fd`, so the caller keeps ownership of `fd`. This
+    /// will fail if the requested key/value sizes don't match the key/value sizes of the map.
+    pub fn from_fd(fd: i32) -> XDPResult<Map<K, V>> {
+        let dup = mc::dup_map_fd(fd)?;
+        Self::adopt_fd(dup, &format!("map fd {}", fd))
+    }
+
+    // Wraps the map `fd` in a handle that closes it once `fd` is checked, or closes it right
+    // away if the checks fail.
+    fn adopt_fd(fd: i32, name: &str) -> XDPResult<Map<K, V>> {
+        let def = mc::map_def_from_fd::<K>(fd).and_then(|def| {
+            Self::check_def(&def, name)?;
+            Ok(def)
+        });
+        match def {
+            Ok(def) => Ok(Map::from_owned_fd(fd, def.map_type, def.max_entries)),
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                Err(e)
+            }
+        }
+    }
 
-        let map_type: MapType = mtype.into();
-        if map_type.is_per_cpu() {
-            set_errno(Errno(22));
-            fail!("Improper map type, use rxdp::PerCPUMap::new");
+    // Checks that the map `name` can be accessed as a `Map<K, V>`.
+    fn check_def(def: &mc::MapDef, name: &str) -> XDPResult<()> {
+        if def.map_type.is_per_cpu() || def.map_type.kind() == MapKind::QueueMap {
+            return mc::improper_type(name, def.map_type);
         }
 
         let req_val_size = size_of::<V>() as u32;
-        if req_val_size != vsize {
+        if req_val_size != def.value_size {
             fail!(
                 "Incorrect value size, XDP map has size: {}, requested value size is {}.",
-                vsize,
+                def.value_size,
                 req_val_size,
             );
         }
 
-        Ok(Map {
-            map_fd,
-            _key: PhantomData,
-            _val: PhantomData,
-            map_type,
-            max_entries,
+        Ok(())
+    }
+
+    /// Re-read the map's metadata (currently `max_entries`) from the kernel, in case the map
+    /// was sized differently than its definition.
+    pub fn refresh_info(&mut self) -> XDPResult<()> {
+        self.max_entries = map_compat::map_info(self.map_fd)?.max_entries;
+        Ok(())
+    }
+
+    /// Same as [`new`](Map::new), but only accepts key & value types that are guaranteed not
+    /// to leak uninitialized padding bytes into the map (see [`NoPadding`](crate::NoPadding)).
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let m: rxdp::Map<u32, u64> = rxdp::Map::new_checked(&obj, "map_name").unwrap();
+    /// ```
+    pub fn new_checked(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<Map<K, V>>
+    where
+        K: NoPadding,
+        V: NoPadding,
+    {
+        Self::new(xdp, map_name)
+    }
+
+    /// Set a deadline for operations on this handle. Once set, `lookup`, `update` and `delete`
+    /// run on a worker thread and fail with `ETIMEDOUT` (see
+    /// [`XDPError::is_timed_out`](crate::XDPError::is_timed_out)) if the kernel doesn't
+    /// complete them within `timeout`. This protects control loops against syscalls stalling
+    /// when the kernel is under severe memory pressure, at the cost of a thread hand-off per
+    /// operation. `None` (the default) runs all operations on the calling thread.
+    ///
+    /// **NOTE**: batch operations and `items()` are not covered by the deadline and can still
+    /// block.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(Deadline::new);
+    }
+
+    /// Pin the map at `path` once this handle is dropped, or when the process exits
+    /// (`exit()` or returning from `main`), whichever comes first. State created at runtime then
+    /// survives a restart, without pinning the map up front. Calling this again replaces `path`.
+    ///
+    /// `path` must be on a BPF filesystem, which is checked right away. Errors while pinning
+    /// are ignored, since there is no one to report them to. A map that is already pinned at
+    /// `path` (e.g. because it was loaded from there) stays pinned.
+    ///
+    /// **NOTE**: the map is not pinned if the process is killed by a signal.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let mut m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "sessions").unwrap();
+    /// m.persist_on_exit("/sys/fs/bpf/myapp/sessions").unwrap();
+    /// ```
+    pub fn persist_on_exit(&mut self, path: &str) -> XDPResult<()> {
+        let persist = Persist::new(self.map_fd, path)?;
+        if let Some(old) = self.persist.replace(persist) {
+            old.cancel();
+        }
+        Ok(())
+    }
+}
+
+impl<K: Default + Copy, V: Default> Map<K, V> {
+    /// Lookup the value for `key`. Same as [`lookup`](MapLike::lookup), without the
+    /// [`MapValue`] wrapper.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "map_name").unwrap();
+    /// let value: u64 = m.get(&0).unwrap();
+    /// ```
+    pub fn get(&self, key: &K) -> XDPResult<V> {
+        self.lookup(key).map(MapValue::into_single)
+    }
+
+    /// Read the values at indexes `start..start + len` of an `Array` map. If the map was
+    /// created with `BPF_F_MMAPABLE`, the values are copied out of a single mapping of the
+    /// array, otherwise each index is looked up directly. Fails with `E2BIG` if the range goes
+    /// past the end of the map.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "array_name").unwrap();
+    /// let counters: Vec<u64> = m.read_range(0, 16).unwrap();
+    /// ```
+    pub fn read_range(&self, start: u32, len: u32) -> XDPResult<Vec<V>> {
+        mc::check_range(self.map_type, MapType::Array, start, len, self.max_entries)?;
+        if self.is_mmapable() {
+            return self.read_mmap(start, len);
+        }
+
+        (start..start + len)
+            .map(|i| self.get(&utils::from_bytes(&i.to_ne_bytes())))
+            .collect()
+    }
+
+    fn is_mmapable(&self) -> bool {
+        map_compat::map_info(self.map_fd)
+            .map(|info| info.map_flags & libbpf_sys::BPF_F_MMAPABLE != 0)
+            .unwrap_or(false)
+    }
+
+    // Copies values out of a read-only mapping of the whole array.
+    fn read_mmap(&self, start: u32, len: u32) -> XDPResult<Vec<V>> {
+        // Array elements are 8 byte aligned.
+        let stride = (size_of::<V>() + 7) & !7;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let data_len = (stride * self.max_entries as usize + page_size - 1) & !(page_size - 1);
+        let data = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                data_len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                self.map_fd,
+                0,
+            )
+        };
+        if data == libc::MAP_FAILED {
+            fail!("Error mapping array");
+        }
+
+        let values = (start..start + len)
+            .map(|i| unsafe {
+                let ptr = (data as *const u8).add(i as usize * stride);
+                std::ptr::read_unaligned(ptr as *const V)
+            })
+            .collect();
+        unsafe { libc::munmap(data, data_len) };
+
+        Ok(values)
+    }
+
+    // All items of an `Array` map, read with `read_range`.
+    fn array_items(&self) -> XDPResult<Vec<KeyValue<K, MapValue<V>>>> {
+        let values = self.read_range(0, self.max_entries)?;
+        Ok((0..self.max_entries)
+            .zip(values)
+            .map(|(i, v)| KeyValue {
+                key: utils::from_bytes(&i.to_ne_bytes()),
+                value: MapValue::Single(v),
+            })
+            .collect())
+    }
+
+    fn scrape(map_fd: i32, shard: Shard) -> XDPResult<Vec<KeyValue<K, MapValue<V>>>> {
+        let mut keys: Vec<K> = Vec::with_capacity(BATCH_SIZE as usize);
+        let mut vals: Vec<V> = Vec::with_capacity(BATCH_SIZE as usize);
+
+        let mut result = Vec::with_capacity(BATCH_SIZE as usize);
+        let mut next_key = shard.start_token();
+
+        loop {
+            keys.resize_with(BATCH_SIZE as usize, Default::default);
+            vals.resize_with(BATCH_SIZE as usize, Default::default);
+            let r = mc::lookup_batch_prealloc(
+                map_fd,
+                BATCH_SIZE,
+                size_of::<K>(),
+                next_key.as_deref(),
+                &mut keys,
+                &mut vals,
+                false,
+            )?;
+            populate_batch_result(r.num_items, &mut result, &mut keys, &mut vals);
+
+            next_key = shard.next(r.next_key);
+            if next_key.is_none() {
+                break;
+            }
+        }
+
+        shard.trim(&mut result);
+        Ok(result)
+    }
+
+    /// Counts the keys in the map, grouped by `project(key)`. E.g. for a map keyed by IPv4
+    /// address, `|ip| ip & 0xffffff00` (in host byte order) gives the number of entries per /24.
+    ///
+    /// The map is read in batches (if supported by the kernel) and only the counts are kept, so
+    /// this works on very large maps without reading all items into memory first.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "src_ips").unwrap();
+    /// let mut by_prefix: Vec<_> = m
+    ///     .key_histogram(|ip| u32::from_be(*ip) >> 8)
+    ///     .unwrap()
+    ///     .into_iter()
+    ///     .collect();
+    /// by_prefix.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    /// ```
+    pub fn key_histogram<P, F>(&self, mut project: F) -> XDPResult<HashMap<P, u64>>
+    where
+        P: Hash + Eq,
+        F: FnMut(&K) -> P,
+    {
+        let mut hist = HashMap::new();
+        self.scan(|key, _| *hist.entry(project(key)).or_insert(0) += 1)?;
+
+        Ok(hist)
+    }
+
+    /// Returns the keys of all entries whose value holds a timestamp older than `ttl`. The
+    /// timestamp is a `u64` at byte offset `ts_offset` in the value, as returned by
+    /// `bpf_ktime_get_ns()` in eBPF (see [`ktime_get_ns`](crate::ktime_get_ns)).
+    ///
+    /// The map is read in batches (if supported by the kernel), so this is suitable for
+    /// user-space assisted expiry of large conntrack style maps.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # use std::time::Duration;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// #[derive(Default, Clone, Copy)]
+    /// #[repr(C)]
+    /// struct Flow {
+    ///     packets: u64,
+    ///     last_seen: u64,
+    /// }
+    ///
+    /// let m: rxdp::Map<u32, Flow> = rxdp::Map::new(&obj, "flows").unwrap();
+    /// let stale = m.expired_entries(8, Duration::from_secs(60)).unwrap();
+    /// println!("{} stale flows", stale.len());
+    /// ```
+    pub fn expired_entries(&self, ts_offset: usize, ttl: Duration) -> XDPResult<Vec<K>> {
+        check_ts_offset::<V>(ts_offset)?;
+        let now = utils::ktime_get_ns();
+
+        let mut expired = Vec::new();
+        self.scan(|key, value| {
+            if is_expired(value, ts_offset, ttl, now) {
+                expired.push(*key);
+            }
+        })?;
+
+        Ok(expired)
+    }
+
+    /// Deletes all entries whose timestamp is older than `ttl` (see
+    /// [`expired_entries`](Map::expired_entries)) and returns the number of deleted entries.
+    /// Each entry is checked again right before deleting it, so entries refreshed by the eBPF
+    /// side in the meantime are kept.
+    pub fn delete_expired(&self, ts_offset: usize, ttl: Duration) -> XDPResult<usize> {
+        let mut deleted = 0;
+        for key in self.expired_entries(ts_offset, ttl)? {
+            let value = match self.lookup(&key) {
+                Ok(v) => v.into_single(),
+                Err(e) if e.code() == 2 => continue,
+                Err(e) => return Err(e),
+            };
+            if !is_expired(&value, ts_offset, ttl, utils::ktime_get_ns()) {
+                continue;
+            }
+
+            match self.delete(&key) {
+                Ok(_) => deleted += 1,
+                Err(e) if e.code() == 2 => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// Copies all entries into `other` and returns the number of copied entries, e.g. to
+    /// migrate the state of a pinned map into the map of a newly loaded object during an
+    /// upgrade. Entries are read and written in batches (if supported by the kernel), and
+    /// existing entries in `other` are overwritten.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let old_obj = rxdp::XDPObject::new("/tmp/old").unwrap().load().unwrap();
+    /// # let new_obj = rxdp::XDPObject::new("/tmp/new").unwrap().load().unwrap();
+    /// let old: rxdp::Map<u32, u64> = rxdp::Map::new(&old_obj, "flows").unwrap();
+    /// let new: rxdp::Map<u32, u64> = rxdp::Map::new(&new_obj, "flows").unwrap();
+    /// let n = old.copy_to(&new).unwrap();
+    /// println!("migrated {} flows", n);
+    /// ```
+    /// **NOTE**: The copy isn't atomic. Entries updated by an eBPF program while the map is
+    /// copied may be copied with either value, or not at all if they are inserted behind the
+    /// current read position. If an update fails, the entries before it have been copied.
+    pub fn copy_to(&self, other: &Map<K, V>) -> XDPResult<u64>
+    where
+        V: Copy,
+    {
+        self.copy_to_with(other, |key, value| Some((*key, *value)))
+    }
+
+    /// Same as [`copy_to`](Map::copy_to), converting each entry with `f` first, e.g. when the
+    /// new map has a different value layout. Entries for which `f` returns `None` aren't
+    /// copied. Returns the number of copied entries.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let old_obj = rxdp::XDPObject::new("/tmp/old").unwrap().load().unwrap();
+    /// # let new_obj = rxdp::XDPObject::new("/tmp/new").unwrap().load().unwrap();
+    /// let old: rxdp::Map<u32, u32> = rxdp::Map::new(&old_obj, "flows").unwrap();
+    /// let new: rxdp::Map<u32, u64> = rxdp::Map::new(&new_obj, "flows").unwrap();
+    /// old.copy_to_with(&new, |key, packets| Some((*key, *packets as u64)))
+    ///     .unwrap();
+    /// ```
+    pub fn copy_to_with<K2, V2, F>(&self, other: &Map<K2, V2>, mut f: F) -> XDPResult<u64>
+    where
+        K2: Default + Copy,
+        V2: Default,
+        F: FnMut(&K, &V) -> Option<(K2, V2)>,
+    {
+        let mut keys: Vec<K2> = Vec::with_capacity(BATCH_SIZE as usize);
+        let mut vals: Vec<V2> = Vec::with_capacity(BATCH_SIZE as usize);
+        let mut copied = 0;
+        self.try_scan(|key, value| {
+            if let Some((k, v)) = f(key, value) {
+                keys.push(k);
+                vals.push(v);
+            }
+            if keys.len() >= BATCH_SIZE as usize {
+                copied += other.update_batch(&mut keys, &mut vals, MapFlags::BpfAny)? as u64;
+                keys.clear();
+                vals.clear();
+            }
+            Ok(())
+        })?;
+
+        if !keys.is_empty() {
+            copied += other.update_batch(&mut keys, &mut vals, MapFlags::BpfAny)? as u64;
+        }
+
+        Ok(copied)
+    }
+
+    // Calls `f` for every entry in the map, reading the map in batches if possible.
+    fn scan<F: FnMut(&K, &V)>(&self, mut f: F) -> XDPResult<()> {
+        self.try_scan(|key, value| {
+            f(key, value);
+            Ok(())
         })
     }
+
+    // Same as `scan`, stopping at the first error returned by `f`.
+    fn try_scan<F: FnMut(&K, &V) -> XDPResult<()>>(&self, mut f: F) -> XDPResult<()> {
+        if self.map_type == MapType::DevMap || !is_batching_supported() {
+            let mut key: K = Default::default();
+            let mut prev: Option<K> = None;
+            loop {
+                let prev_ptr = prev
+                    .as_ref()
+                    .map_or(std::ptr::null(), |k| k as *const K as *const c_void);
+                match self.get_next_key(prev_ptr, &mut key) {
+                    Ok(()) => (),
+                    Err(e) if e.code() == 2 => break,
+                    Err(e) => return Err(e),
+                }
+                match self.lookup(&key) {
+                    Ok(v) => f(&key, &v.into_single())?,
+                    // Deleted since reading the key, or a DEVMAP entry of a deleted interface.
+                    Err(e) if e.code() == 2 || self.map_type == MapType::DevMap => (),
+                    Err(e) => return Err(e),
+                }
+                prev = Some(key);
+            }
+            return Ok(());
+        }
+
+        let mut keys: Vec<K> = Vec::with_capacity(BATCH_SIZE as usize);
+        let mut vals: Vec<V> = Vec::with_capacity(BATCH_SIZE as usize);
+        let mut next_key = None;
+        loop {
+            keys.resize_with(BATCH_SIZE as usize, Default::default);
+            vals.resize_with(BATCH_SIZE as usize, Default::default);
+            let r = mc::lookup_batch_prealloc(
+                self.map_fd,
+                BATCH_SIZE,
+                size_of::<K>(),
+                next_key.as_deref(),
+                &mut keys,
+                &mut vals,
+                false,
+            )?;
+            let n = r.num_items as usize;
+            for (key, value) in keys[..n].iter().zip(vals[..n].iter()) {
+                f(key, value)?;
+            }
+
+            next_key = Shard::ALL.next(r.next_key);
+            if next_key.is_none() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn check_ts_offset<V>(ts_offset: usize) -> XDPResult<()> {
+    if ts_offset + size_of::<u64>() > size_of::<V>() {
+        set_errno(Errno(22));
+        fail!(
+            "Timestamp offset {} out of bounds for value size {}",
+            ts_offset,
+            size_of::<V>()
+        );
+    }
+
+    Ok(())
+}
+
+// Whether the `u64` timestamp at `ts_offset` in `value` is more than `ttl` before `now`.
+fn is_expired<V>(value: &V, ts_offset: usize, ttl: Duration, now: u64) -> bool {
+    let ts: u64 = utils::from_bytes(&utils::as_bytes(value)[ts_offset..ts_offset + 8]);
+    now.saturating_sub(ts) > ttl.as_nanos() as u64
+}
+
+impl<K, V> Map<K, V>
+where
+    K: Copy + Send + 'static,
+    V: Default + Copy + Send + 'static,
+{
+    /// Watch the value of `key`, checking it every `interval`. The returned receiver gets the
+    /// current value right away, then every time it changes. `None` means the key doesn't
+    /// exist (e.g. it was deleted).
+    ///
+    /// All watched keys of a map are polled by a single background thread, which looks up each
+    /// key only once per interval, no matter how many times it is watched. The thread stops
+    /// polling a key once its receiver is dropped, and exits when the map handle is dropped.
+    ///
+    /// **NOTE**: Keys are looked up one syscall each, not with batch lookups. Batch lookups
+    /// only walk the whole map, so they would read every entry to find the watched keys.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # use std::time::Duration;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, "flags").unwrap();
+    /// let r = m.watch_key(&0, Duration::from_millis(100));
+    /// while let Ok(v) = r.recv() {
+    ///     println!("flags: {:?}", v);
+    /// }
+    /// ```
+    pub fn watch_key(&self, key: &K, interval: Duration) -> Receiver<Option<V>> {
+        self.watcher.watch(self.map_fd, *key, interval)
+    }
+}
+
+impl<K, V> Map<K, V>
+where
+    K: Default + Copy + Hash + Eq + Send,
+    V: Default + Send,
+{
+    /// Returns all items in the map, like [`items`](MapLike::items), but splits the map into
+    /// (at most) `shards` ranges that are scraped in parallel, each on its own thread. This
+    /// can greatly reduce the time it takes to read very large maps on many-core machines.
+    ///
+    /// Falls back to [`items`](MapLike::items) if the kernel doesn't support batching, or for
+    /// maps that aren't scraped using batches.
+    pub fn par_items(&self, shards: u32) -> XDPResult<Vec<KeyValue<K, MapValue<V>>>> {
+        if shards <= 1 || self.map_type == MapType::DevMap || !is_batching_supported() {
+            return self.items();
+        }
+
+        let map_fd = self.map_fd;
+        par_scrape(
+            crate::map_batch::shards(self.map_type, self.max_entries, shards),
+            |shard| Self::scrape(map_fd, shard),
+        )
+    }
 }
 
 impl<K: Default + Copy, V: Default> MapLike<K, V> for Map<K, V> {
@@ -105,10 +663,14 @@ impl<K: Default + Copy, V: Default> MapLike<K, V> for Map<K, V> {
         self.max_entries
     }
 
+    fn deadline(&self) -> Option<&Deadline> {
+        self.deadline.as_ref()
+    }
+
     fn lookup_batch_impl(
         &self,
         batch_size: u32,
-        next_key: Option<u32>,
+        next_key: Option<&[u8]>,
         delete: bool,
     ) -> XDPResult<BatchResult<K, MapValue<V>>> {
         let mut keys: Vec<K> = Vec::with_capacity(batch_size as usize);
@@ -119,6 +681,7 @@ impl<K: Default + Copy, V: Default> MapLike<K, V> for Map<K, V> {
         let r = mc::lookup_batch_prealloc(
             self.map_fd,
             batch_size,
+            size_of::<K>(),
             next_key,
             &mut keys,
             &mut vals,
@@ -129,7 +692,7 @@ impl<K: Default + Copy, V: Default> MapLike<K, V> for Map<K, V> {
 
         Ok(BatchResult {
             items: result,
-            next_key: r.next_key,
+            next_key: r.next_key.map(BatchCursor::batch),
             num_items: r.num_items,
         })
     }
@@ -169,35 +732,24 @@ impl<K: Default + Copy, V: Default> MapLike<K, V> for Map<K, V> {
     }
 
     fn items(&self) -> XDPResult<Vec<KeyValue<K, MapValue<V>>>> {
-        if self.map_type == MapType::DevMap || self.max_entries < 50 || !is_batching_supported() {
+        let small = self.max_entries < 50 || !reads_in_batches(self.map_type);
+        if self.map_type == MapType::Array && (small || self.is_mmapable()) {
+            return self.array_items();
+        }
+        if small {
             return self._items();
         }
-        let mut keys: Vec<K> = Vec::with_capacity(BATCH_SIZE as usize);
-        let mut vals: Vec<V> = Vec::with_capacity(BATCH_SIZE as usize);
 
-        let mut result = Vec::with_capacity(BATCH_SIZE as usize);
-        let mut next_key = None;
-
-        loop {
-            keys.resize_with(BATCH_SIZE as usize, Default::default);
-            vals.resize_with(BATCH_SIZE as usize, Default::default);
-            let r = mc::lookup_batch_prealloc(
-                self.map_fd,
-                BATCH_SIZE,
-                next_key,
-                &mut keys,
-                &mut vals,
-                false,
-            )?;
-            populate_batch_result(r.num_items, &mut result, &mut keys, &mut vals);
+        Self::scrape(self.map_fd, Shard::ALL)
+    }
+}
 
-            if r.next_key.is_none() {
-                break;
-            }
-            next_key = r.next_key;
+impl<K, V> Drop for Map<K, V> {
+    fn drop(&mut self) {
+        self.watcher.stop();
+        if self.owned {
+            unsafe { libc::close(self.map_fd) };
         }
-
-        Ok(result)
     }
 }
 
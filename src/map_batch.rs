@@ -1,7 +1,17 @@
-use lazy_static::lazy_static;
+use errno::{set_errno, Errno};
 use libbpf_sys as bpf;
+use std::{
+    collections::{BinaryHeap, HashSet, VecDeque},
+    hash::Hash,
+    mem::size_of,
+    os::raw::c_void,
+    sync::Mutex,
+};
 
-use crate::{KeyValue, Map, MapFlags, MapLike, MapType};
+use crate::cancel::CancelToken;
+use crate::error::XDPError;
+use crate::utils;
+use crate::{KeyValue, Map, MapFlags, MapLike, MapType, MapValue, XDPResult};
 
 const RXDP_BATCH_ENV: &'static str = "rxdp_batching_supported";
 pub(crate) const BATCH_SIZE: u32 = 100;
@@ -11,46 +21,744 @@ pub(crate) const BATCH_OPTS: bpf::bpf_map_batch_opts = bpf::bpf_map_batch_opts {
     flags: 0u64,
 };
 
-lazy_static! {
-    static ref BATCHING_SUPPORTED: bool = check_batching_supported();
+// Whether batch operations are supported, `None` until the first batch operation or
+// `set_batching_supported`.
+static BATCHING: Mutex<Option<BatchingSupport>> = Mutex::new(None);
+
+/// Whether the kernel supports batch map operations, see [`batching_support`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchingSupport {
+    /// Batch operations work, or were declared to work with [`set_batching_supported`].
+    Supported,
+    /// The kernel rejected a batch operation, or they were declared not to work with
+    /// [`set_batching_supported`].
+    Unsupported,
+    /// The probe couldn't tell, e.g. because creating the probe map was denied (`EPERM`). Holds
+    /// the errno of the failed step. Batch operations aren't used.
+    ProbeFailed(i32),
 }
 
 /// The result of a batch operation.
 pub struct BatchResult<K, V> {
     pub items: Vec<KeyValue<K, V>>,
-    pub next_key: Option<u32>,
+    /// Where the next batch starts, `None` if all items were read.
+    pub next_key: Option<BatchCursor>,
     pub num_items: u32,
 }
 
 pub(crate) struct BatchResultInternal {
-    pub(crate) next_key: Option<u32>,
+    pub(crate) next_key: Option<Vec<u8>>,
     pub(crate) num_items: u32,
 }
 
-fn check_batching_supported() -> bool {
-    if let Ok(v) = std::env::var(RXDP_BATCH_ENV) {
-        match v.as_str() {
-            "0" => return false,
-            _ => return true,
+/// Position in a map to continue reading items from, returned by batch lookups and when
+/// [`MapLike::items_until`](crate::MapLike::items_until) stops early. The contents are opaque,
+/// and only valid for the map it was returned for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchCursor(Position);
+
+impl BatchCursor {
+    pub(crate) fn batch(token: Vec<u8>) -> BatchCursor {
+        BatchCursor(Position::Batch(token))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Position {
+    Start,
+    // Token returned by the kernel's batch lookup, see `batch_token_size`.
+    Batch(Vec<u8>),
+    // The last key read with `bpf_map_get_next_key`.
+    Key(Vec<u8>),
+}
+
+/// Items read by [`MapLike::items_until`](crate::MapLike::items_until).
+pub struct PartialItems<K, V> {
+    pub items: Vec<KeyValue<K, V>>,
+    /// Where to continue reading, `None` if all items were read.
+    pub cursor: Option<BatchCursor>,
+}
+
+impl<K, V> PartialItems<K, V> {
+    /// True if all items were read.
+    pub fn is_complete(&self) -> bool {
+        self.cursor.is_none()
+    }
+}
+
+/// Whether the items read by [`MapLike::items_checked`](crate::MapLike::items_checked) are a
+/// consistent snapshot of the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrapeConsistency {
+    /// No keys were added or removed while the map was read.
+    Clean,
+    /// Keys were added or removed while the map was read, so items may be missing or
+    /// duplicated. Read the map again for a consistent snapshot.
+    Dirty,
+}
+
+/// Items read by [`MapLike::items_checked`](crate::MapLike::items_checked).
+pub struct CheckedItems<K, V> {
+    pub items: Vec<KeyValue<K, V>>,
+    pub consistency: ScrapeConsistency,
+}
+
+impl<K, V> CheckedItems<K, V> {
+    /// True if the items are a consistent snapshot of the map.
+    pub fn is_clean(&self) -> bool {
+        self.consistency == ScrapeConsistency::Clean
+    }
+}
+
+// Reads all items, and checks whether keys were added or removed meanwhile by walking the keys
+// before and after.
+pub(crate) fn items_checked<K, V, M>(m: &M) -> XDPResult<CheckedItems<K, MapValue<V>>>
+where
+    K: Default + Hash + Eq,
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    let before = m.keys()?;
+    let items = m.items()?;
+    let after = m.keys()?;
+
+    Ok(CheckedItems {
+        consistency: consistency(&before, &after, &items),
+        items,
+    })
+}
+
+// Clean if both key walks returned the same set of keys, and the items have each of those keys
+// once. A walk that restarts because its current key was deleted returns keys more than once.
+fn consistency<K: Hash + Eq, V>(
+    before: &[K],
+    after: &[K],
+    items: &[KeyValue<K, V>],
+) -> ScrapeConsistency {
+    let before_set: HashSet<&K> = before.iter().collect();
+    let after_set: HashSet<&K> = after.iter().collect();
+    let mut seen = HashSet::with_capacity(items.len());
+    let unique = items.iter().all(|kv| seen.insert(&kv.key));
+    let clean = before_set.len() == before.len()
+        && after_set.len() == after.len()
+        && unique
+        && before_set == after_set
+        && seen == before_set;
+    match clean {
+        true => ScrapeConsistency::Clean,
+        false => ScrapeConsistency::Dirty,
+    }
+}
+
+/// A page of items in key order, returned by [`MapLike::items_page`](crate::MapLike::items_page).
+pub struct KeyPage<K, V> {
+    pub items: Vec<KeyValue<K, V>>,
+    /// The last key of the page, to pass in for the next page. `None` on the last page.
+    pub next_after: Option<K>,
+}
+
+// Reads the `limit` items with the smallest keys greater than `after`, sorted by key. Items
+// are streamed from the map, and only the `limit + 1` smallest are kept: the extra one tells
+// whether there is a next page.
+pub(crate) fn key_page<K, V, M>(
+    m: &M,
+    after: Option<&K>,
+    limit: usize,
+) -> XDPResult<KeyPage<K, MapValue<V>>>
+where
+    K: Ord + Clone + Default,
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    if limit == 0 {
+        set_errno(Errno(22));
+        fail!("Invalid page limit 0");
+    }
+
+    let mut heap = BinaryHeap::new();
+    for kv in MapIter::new(m) {
+        let kv = kv?;
+        if after.is_some_and(|a| kv.key <= *a) {
+            continue;
+        }
+        if heap.len() > limit {
+            match heap.peek() {
+                Some(ByKey(max)) if kv.key >= max.key => continue,
+                _ => heap.pop(),
+            };
+        }
+        heap.push(ByKey(kv));
+    }
+
+    let more = heap.len() > limit;
+    let mut items: Vec<_> = heap.into_sorted_vec().into_iter().map(|b| b.0).collect();
+    items.truncate(limit);
+    let next_after = match more {
+        true => items.last().map(|kv| kv.key.clone()),
+        false => None,
+    };
+    Ok(KeyPage { items, next_after })
+}
+
+// An item ordered by its key alone.
+struct ByKey<K, V>(KeyValue<K, V>);
+
+impl<K: Ord, V> Ord for ByKey<K, V> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.key.cmp(&other.0.key)
+    }
+}
+
+impl<K: Ord, V> PartialOrd for ByKey<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, V> PartialEq for ByKey<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.key == other.0.key
+    }
+}
+
+impl<K: Ord, V> Eq for ByKey<K, V> {}
+
+// Size of the kernel's batch tokens for maps with `key_size` byte keys. Array maps use the last
+// key read, hash maps a `u32` bucket index.
+pub(crate) fn batch_token_size(key_size: usize) -> usize {
+    key_size.max(size_of::<u32>())
+}
+
+// The batch token of `cursor`, `None` to start from the beginning of the map. Fails with
+// `EINVAL` if the cursor isn't from a batch lookup on a map with `K` keys.
+pub(crate) fn batch_token<K>(cursor: Option<&BatchCursor>) -> XDPResult<Option<&[u8]>> {
+    match cursor.map(|c| &c.0) {
+        None | Some(Position::Start) => Ok(None),
+        Some(Position::Batch(t)) if t.len() == batch_token_size(size_of::<K>()) => Ok(Some(t)),
+        Some(_) => {
+            set_errno(Errno(22));
+            fail!("Invalid cursor for a batch lookup on this map");
+        }
+    }
+}
+
+// Reads items in chunks of `BATCH_SIZE`, checking `cancel` between chunks.
+pub(crate) fn items_until<K, V, M>(
+    m: &M,
+    cancel: &CancelToken,
+    cursor: Option<BatchCursor>,
+) -> XDPResult<PartialItems<K, MapValue<V>>>
+where
+    K: Default,
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    let batch = reads_in_batches(m.map_type());
+
+    let mut pos = cursor.map_or(Position::Start, |c| c.0);
+    let valid = match &pos {
+        Position::Start => true,
+        Position::Batch(t) => batch && t.len() == batch_token_size(size_of::<K>()),
+        Position::Key(k) => !batch && k.len() == size_of::<K>(),
+    };
+    if !valid {
+        set_errno(Errno(22));
+        fail!("Invalid cursor for this map");
+    }
+
+    let mut items = Vec::new();
+    loop {
+        if cancel.is_cancelled() {
+            return Ok(PartialItems {
+                items,
+                cursor: Some(BatchCursor(pos)),
+            });
+        }
+
+        if !read_chunk(m, batch, &mut pos, &mut items)? {
+            return Ok(PartialItems {
+                items,
+                cursor: None,
+            });
+        }
+    }
+}
+
+// Reads the next chunk of items after `pos`, with a batch lookup if `batch` is true. Returns
+// false once the map is exhausted.
+fn read_chunk<K, V, M>(
+    m: &M,
+    batch: bool,
+    pos: &mut Position,
+    items: &mut Vec<KeyValue<K, MapValue<V>>>,
+) -> XDPResult<bool>
+where
+    K: Default,
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    match batch {
+        true => {
+            let next_key = match &*pos {
+                Position::Batch(t) => Some(t.as_slice()),
+                _ => None,
+            };
+            let r = m.lookup_batch_impl(BATCH_SIZE, next_key, false)?;
+            items.extend(r.items);
+            Ok(r.next_key.map(|c| *pos = c.0).is_some())
+        }
+        false => next_keys(m, pos, items),
+    }
+}
+
+/// Iterator over the items of a map, returned by [`MapLike::iter`](crate::MapLike::iter).
+/// Items are read in chunks as the iterator advances, so only one chunk is held in memory at
+/// a time. Iteration stops after the first error.
+pub struct MapIter<'a, K, V, M: ?Sized> {
+    map: &'a M,
+    batch: bool,
+    pos: Position,
+    items: VecDeque<KeyValue<K, MapValue<V>>>,
+    done: bool,
+}
+
+impl<'a, K, V, M> MapIter<'a, K, V, M>
+where
+    K: Default,
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    pub(crate) fn new(map: &'a M) -> MapIter<'a, K, V, M> {
+        MapIter {
+            map,
+            batch: reads_in_batches(map.map_type()),
+            pos: Position::Start,
+            items: VecDeque::new(),
+            done: false,
+        }
+    }
+}
+
+impl<'a, K, V, M> Iterator for MapIter<'a, K, V, M>
+where
+    K: Default,
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    type Item = XDPResult<KeyValue<K, MapValue<V>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(kv) = self.items.pop_front() {
+                return Some(Ok(kv));
+            }
+            if self.done {
+                return None;
+            }
+
+            let mut items = Vec::new();
+            match read_chunk(self.map, self.batch, &mut self.pos, &mut items) {
+                Ok(more) => self.done = !more,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+            self.items.extend(items);
+        }
+    }
+}
+
+// Reads up to `BATCH_SIZE` items after `pos` one by one. Returns false once the map is exhausted.
+fn next_keys<K, V, M>(
+    m: &M,
+    pos: &mut Position,
+    items: &mut Vec<KeyValue<K, MapValue<V>>>,
+) -> XDPResult<bool>
+where
+    K: Default,
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    for _ in 0..BATCH_SIZE {
+        let mut key = K::default();
+        let prev = match pos {
+            Position::Key(k) => k.as_ptr() as *const c_void,
+            _ => std::ptr::null(),
+        };
+        match m.get_next_key(prev, &mut key) {
+            Ok(()) => (),
+            Err(e) if e.code() == 2 => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        *pos = Position::Key(utils::as_bytes(&key).to_vec());
+
+        match m.lookup(&key) {
+            Ok(value) => items.push(KeyValue { key, value }),
+            // Deleted since reading the key, or a DEVMAP entry of a deleted interface.
+            Err(e) if e.code() == 2 || m.map_type() == MapType::DevMap => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
+
+// Reads all keys with `bpf_map_get_next_key`.
+pub(crate) fn keys<K, V, M>(m: &M) -> XDPResult<Vec<K>>
+where
+    K: Default,
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    let mut keys: Vec<K> = Vec::new();
+    loop {
+        let prev = match keys.last() {
+            Some(k) => k as *const K as *const c_void,
+            None => std::ptr::null(),
+        };
+        let mut key = K::default();
+        match m.get_next_key(prev, &mut key) {
+            Ok(()) => keys.push(key),
+            Err(e) if e.code() == 2 => return Ok(keys),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Removes all entries, see `MapLike::clear`.
+pub(crate) fn clear<K, V, M>(m: &M) -> XDPResult<u32>
+where
+    K: Default,
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    let map_type = m.map_type();
+    match map_type {
+        MapType::Array | MapType::PerCPUArray => return zero_fill(m),
+        t if t.is_array() => {
+            set_errno(Errno(22));
+            fail!("Clear not supported on map type {}", t);
         }
+        _ => {}
+    }
+
+    let mut cleared = 0;
+    if reads_in_batches(map_type) {
+        let mut token: Option<Vec<u8>> = None;
+        loop {
+            let r = match m.lookup_batch_impl(BATCH_SIZE, token.as_deref(), true) {
+                Ok(r) => r,
+                // Map types without `lookup_and_delete_batch` support, the remaining keys are
+                // deleted one by one.
+                Err(e) if e.code() == 22 || e.code() == 95 => break,
+                Err(e) => return Err(e),
+            };
+            cleared += r.items.len() as u32;
+            match r.next_key.map(|c| c.0) {
+                Some(Position::Batch(t)) => token = Some(t),
+                _ => return Ok(cleared),
+            }
+        }
+    }
+
+    for key in keys(m)? {
+        match m.delete(&key) {
+            Ok(()) => cleared += 1,
+            // Deleted by someone else in the meantime.
+            Err(e) if e.code() == 2 => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(cleared)
+}
+
+// Array entries can't be deleted, so they are overwritten with zeroes.
+fn zero_fill<K, V, M>(m: &M) -> XDPResult<u32>
+where
+    K: Default,
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    if size_of::<K>() != size_of::<u32>() {
+        set_errno(Errno(22));
+        fail!("Array keys must be 4 bytes, got {}", size_of::<K>());
+    }
+
+    let n = m.max_entries();
+    let mut keys: Vec<K> = (0..n)
+        .map(|i| utils::from_bytes(&i.to_ne_bytes()))
+        .collect();
+    let mut values: Vec<V> = (0..n).map(|_| V::default()).collect();
+    m.update_batch(&mut keys, &mut values, MapFlags::BpfExist)
+}
+
+// A range of the batch cursor space, scraped by a single thread. For array maps the cursor is
+// the last key returned, for hash maps it is the next bucket to read. Either way its first 4
+// bytes are a native endian `u32`.
+#[derive(Clone, Copy)]
+pub(crate) struct Shard {
+    pub(crate) start: Option<u32>,
+    end: u32,
+    is_array: bool,
+}
+
+impl Shard {
+    pub(crate) const ALL: Shard = Shard {
+        start: None,
+        end: u32::MAX,
+        is_array: false,
+    };
+
+    // The batch token to start the shard from.
+    pub(crate) fn start_token(&self) -> Option<Vec<u8>> {
+        self.start.map(|s| s.to_ne_bytes().to_vec())
+    }
+
+    // Returns the batch token to continue from, or `None` once the shard is exhausted.
+    pub(crate) fn next(&self, next_key: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        let last = match self.is_array {
+            true => self.end - 1,
+            false => self.end,
+        };
+        next_key.filter(|t| utils::from_bytes::<u32>(&t[..4]) < last)
+    }
+
+    // Array batches can run past the end of the shard, drop any of those keys.
+    pub(crate) fn trim<K, T>(&self, result: &mut Vec<KeyValue<K, T>>) {
+        if self.is_array {
+            result.retain(|kv| utils::from_bytes::<u32>(utils::as_bytes(&kv.key)) < self.end);
+        }
+    }
+}
+
+// Splits the cursor space of a map into (at most) `n` shards.
+pub(crate) fn shards(map_type: MapType, max_entries: u32, n: u32) -> Vec<Shard> {
+    let is_array = map_type.is_array();
+    let total = match is_array {
+        true => max_entries,
+        // The kernel rounds the number of hash buckets up to a power of 2.
+        false => max_entries.next_power_of_two(),
+    };
+    let n = n.max(1).min(total.max(1));
+    let per_shard = total.div_ceil(n);
+
+    (0..n)
+        .map(|i| i * per_shard)
+        .take_while(|start| *start < total)
+        .map(|start| Shard {
+            start: match (start, is_array) {
+                (0, _) => None,
+                (s, true) => Some(s - 1),
+                (s, false) => Some(s),
+            },
+            end: (start + per_shard).min(total),
+            is_array,
+        })
+        .collect()
+}
+
+// Scrapes each shard on its own scoped thread and merges the results.
+pub(crate) fn par_scrape<K, T, F>(shards: Vec<Shard>, f: F) -> XDPResult<Vec<KeyValue<K, T>>>
+where
+    K: Copy + Hash + Eq + Send,
+    T: Send,
+    F: Fn(Shard) -> XDPResult<Vec<KeyValue<K, T>>> + Sync,
+{
+    let is_array = shards.iter().any(|s| s.is_array);
+    let f = &f;
+    let parts = std::thread::scope(|s| {
+        let handles: Vec<_> = shards
+            .into_iter()
+            .map(|shard| s.spawn(move || f(shard)))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<XDPResult<Vec<_>>>()
+    })?;
+
+    let mut result = Vec::with_capacity(parts.iter().map(|p| p.len()).sum());
+    if is_array {
+        parts.into_iter().for_each(|p| result.extend(p));
+        return Ok(result);
     }
 
-    match Map::<u32, u32>::_create(MapType::Hash, 4, 4, 10, 0, false).and_then(|m| {
-        m.update(&0u32, &0u32, MapFlags::BpfAny)
-            .and_then(|_| m.lookup_batch_impl(10, None, false))
-    }) {
-        Err(_) => {
-            std::env::set_var(RXDP_BATCH_ENV, "0");
-            false
+    // A hash batch returns whole buckets, so the last batch of a shard can overlap with the
+    // start of the next one.
+    let mut seen = HashSet::with_capacity(result.capacity());
+    for kv in parts.into_iter().flatten() {
+        if seen.insert(kv.key) {
+            result.push(kv);
         }
-        Ok(_) => {
-            std::env::set_var(RXDP_BATCH_ENV, "1");
-            true
+    }
+
+    Ok(result)
+}
+
+// Runs a batch lookup on a temporary map, unless the `rxdp_batching_supported` environment
+// variable already tells the result.
+fn probe_batching() -> BatchingSupport {
+    if let Ok(v) = std::env::var(RXDP_BATCH_ENV) {
+        match v.as_str() {
+            "0" => return BatchingSupport::Unsupported,
+            _ => return BatchingSupport::Supported,
         }
     }
+
+    let m = match Map::<u32, u32>::_create(MapType::Hash, 4, 4, 10, 0) {
+        Ok(m) => m,
+        Err(e) => return BatchingSupport::ProbeFailed(e.code()),
+    };
+    if let Err(e) = m.update(&0u32, &0u32, MapFlags::BpfAny) {
+        return BatchingSupport::ProbeFailed(e.code());
+    }
+
+    let support = match m.lookup_batch_impl(10, None, false) {
+        Ok(_) => BatchingSupport::Supported,
+        // Kernels without the batch commands reject them as invalid.
+        Err(e) if e.code() == 22 || e.code() == 95 => BatchingSupport::Unsupported,
+        Err(e) => return BatchingSupport::ProbeFailed(e.code()),
+    };
+    let v = match support {
+        BatchingSupport::Supported => "1",
+        _ => "0",
+    };
+    std::env::set_var(RXDP_BATCH_ENV, v);
+
+    support
+}
+
+// True if all items of a map of `map_type` can be read with batch lookups. DEV_MAP entries of
+// deleted interfaces fail the whole batch, and LPM tries don't support batch operations.
+pub(crate) fn reads_in_batches(map_type: MapType) -> bool {
+    match map_type {
+        MapType::DevMap | MapType::LPMTrie | MapType::PerCPUArray => false,
+        _ => is_batching_supported(),
+    }
 }
 
 /// True if kernel supports eBPF batch syscalls
 pub fn is_batching_supported() -> bool {
-    *BATCHING_SUPPORTED
+    batching_support() == BatchingSupport::Supported
+}
+
+/// Whether batch operations are used. Unless set with [`set_batching_supported`] or the
+/// `rxdp_batching_supported` environment variable (`0` or `1`), the kernel is probed with a
+/// temporary map on the first call, and the result is kept for the life of the process.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// if let rxdp::BatchingSupport::ProbeFailed(errno) = rxdp::batching_support() {
+///     eprintln!("batching probe failed with errno {}, falling back to single lookups", errno);
+/// }
+/// ```
+pub fn batching_support() -> BatchingSupport {
+    let mut batching = BATCHING.lock().unwrap();
+    *batching.get_or_insert_with(probe_batching)
+}
+
+/// Declare whether the kernel supports batch operations, instead of probing it. Useful on
+/// locked-down hosts where creating the probe map is denied (or audited), when the kernel
+/// version is known. Overrides the result of an earlier probe.
+pub fn set_batching_supported(supported: bool) {
+    let support = match supported {
+        true => BatchingSupport::Supported,
+        false => BatchingSupport::Unsupported,
+    };
+    *BATCHING.lock().unwrap() = Some(support);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consistency() {
+        let kv = |key: u32| KeyValue { key, value: 0u32 };
+        let items = vec![kv(1), kv(2), kv(3)];
+        assert_eq!(
+            consistency(&[1, 2, 3], &[3, 2, 1], &items),
+            ScrapeConsistency::Clean
+        );
+        // A key was added or removed during the read
+        assert_eq!(
+            consistency(&[1, 2, 3], &[1, 2, 3, 4], &items),
+            ScrapeConsistency::Dirty
+        );
+        assert_eq!(
+            consistency(&[1, 2, 3, 4], &[1, 2, 3, 4], &items),
+            ScrapeConsistency::Dirty
+        );
+        // One key was removed and another added, keeping the count
+        assert_eq!(
+            consistency(&[1, 2, 3], &[1, 2, 4], &items),
+            ScrapeConsistency::Dirty
+        );
+        assert_eq!(
+            consistency(&[1, 2, 4], &[1, 2, 4], &items),
+            ScrapeConsistency::Dirty
+        );
+        // The walk restarted
+        assert_eq!(
+            consistency(&[1, 2, 1], &[1, 2, 3], &items),
+            ScrapeConsistency::Dirty
+        );
+        let items = vec![kv(1), kv(2), kv(1)];
+        assert_eq!(
+            consistency(&[1, 2, 3], &[1, 2, 3], &items),
+            ScrapeConsistency::Dirty
+        );
+    }
+
+    fn starts_and_ends(v: Vec<Shard>) -> Vec<(Option<u32>, u32)> {
+        v.iter().map(|s| (s.start, s.end)).collect()
+    }
+
+    #[test]
+    fn test_array_shards() {
+        let s = shards(MapType::Array, 10, 3);
+        assert_eq!(
+            starts_and_ends(s),
+            vec![(None, 4), (Some(3), 8), (Some(7), 10)]
+        );
+
+        let s = shards(MapType::Array, 2, 8);
+        assert_eq!(starts_and_ends(s), vec![(None, 1), (Some(0), 2)]);
+    }
+
+    #[test]
+    fn test_hash_shards() {
+        let s = shards(MapType::Hash, 100, 4);
+        assert_eq!(
+            starts_and_ends(s),
+            vec![(None, 32), (Some(32), 64), (Some(64), 96), (Some(96), 128)]
+        );
+    }
+
+    #[test]
+    fn test_shard_next() {
+        let s = shards(MapType::Array, 10, 2);
+        // Shards: [0, 5), [5, 10)
+        let t = |k: u32| Some(k.to_ne_bytes().to_vec());
+        assert_eq!(s[0].next(t(3)), t(3));
+        assert_eq!(s[0].next(t(4)), None);
+        assert_eq!(s[1].start_token(), t(4));
+        assert_eq!(s[1].next(t(8)), t(8));
+        assert_eq!(s[1].next(t(9)), None);
+        assert_eq!(s[1].next(None), None);
+
+        // Tokens of array maps with wider keys start with the index.
+        let wide = [9u32.to_ne_bytes(), [0; 4]].concat();
+        assert_eq!(s[1].next(Some(wide)), None);
+
+        let s = shards(MapType::Hash, 16, 2);
+        assert_eq!(s[0].start_token(), None);
+        assert_eq!(s[0].next(t(7)), t(7));
+        assert_eq!(s[0].next(t(8)), None);
+        assert_eq!(Shard::ALL.next(t(8)), t(8));
+    }
 }
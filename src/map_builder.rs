@@ -0,0 +1,321 @@
+use errno::{set_errno, Errno};
+use libbpf_sys as bpf;
+use std::{marker::PhantomData, mem::size_of, path::Path};
+
+use crate::compat;
+use crate::error::XDPError;
+use crate::map_common::{self as mc, MapDef};
+use crate::object::{self, XDPLoadedObject};
+use crate::percpu_codec::PerCpuCodec;
+use crate::percpu_map::ByteAligned;
+use crate::perm::{self, Requires};
+use crate::result::XDPResult;
+use crate::utils;
+use crate::{Map, MapType, PerCpuMap};
+
+// Options shared by the map builders.
+#[derive(Debug, Clone, Default)]
+struct MapOptions {
+    map_type: Option<MapType>,
+    key_size: Option<u32>,
+    value_size: Option<u32>,
+    max_entries: Option<u32>,
+    map_flags: u32,
+    name: Option<String>,
+    numa_node: Option<u32>,
+    pin_path: Option<String>,
+}
+
+impl MapOptions {
+    // Creates the map, with the sizes of `K` and `V` unless set explicitly. Returns the map fd.
+    fn create<K, V>(&self, map_type: MapType) -> XDPResult<i32> {
+        let max_entries = match self.max_entries {
+            Some(n) => n,
+            None => {
+                set_errno(Errno(22));
+                fail!("max_entries must be set to create a map");
+            }
+        };
+        let name = match self.name.as_ref() {
+            Some(n) => Some(utils::str_to_cstring(n)?),
+            None => None,
+        };
+
+        let map_fd = compat::create_map_opts(
+            map_type as u32,
+            self.key_size.unwrap_or(size_of::<K>() as u32),
+            self.value_size.unwrap_or(size_of::<V>() as u32),
+            max_entries,
+            self.map_flags,
+            name.as_ref().map_or(std::ptr::null(), |n| n.as_ptr()),
+            self.numa_node,
+        );
+        if map_fd < 0 {
+            fail_rc!(
+                map_fd,
+                "Error creating new map{}",
+                perm::hint(Requires::Bpf)
+            );
+        }
+
+        if let Err(e) = self.pin(map_fd) {
+            unsafe { libc::close(map_fd) };
+            return Err(e);
+        }
+
+        Ok(map_fd)
+    }
+
+    fn name(&self) -> XDPResult<&str> {
+        match self.name.as_deref() {
+            Some(n) => Ok(n),
+            None => {
+                set_errno(Errno(22));
+                fail!("A map name must be set to build from an object");
+            }
+        }
+    }
+
+    // Checks that a map from an object matches the requested type and number of entries.
+    fn check(&self, def: &MapDef) -> XDPResult<()> {
+        if let Some(map_type) = self.map_type.filter(|t| *t != def.map_type) {
+            set_errno(Errno(22));
+            fail!(
+                "Incorrect map type, XDP map has type: {:?}, requested type is {:?}",
+                def.map_type,
+                map_type
+            );
+        }
+        if let Some(n) = self.max_entries.filter(|n| *n != def.max_entries) {
+            set_errno(Errno(22));
+            fail!(
+                "Incorrect max_entries, XDP map has {}, requested {}",
+                def.max_entries,
+                n
+            );
+        }
+
+        self.pin(def.fd)
+    }
+
+    fn pin(&self, map_fd: i32) -> XDPResult<()> {
+        let path = match self.pin_path.as_ref() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        object::check_pin_dir(Path::new(path))?;
+        if let Some(dir) = Path::new(path).parent() {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                fail!("Error creating pin directory {}: {}", dir.display(), e);
+            }
+        }
+
+        let p = utils::str_to_cstring(path)?;
+        let rc = unsafe { bpf::bpf_obj_pin(map_fd, p.as_ptr()) };
+        if rc < 0 {
+            fail_rc!(rc, "Error pinning map at {}", path);
+        }
+
+        Ok(())
+    }
+}
+
+// Setters shared by the map builders.
+macro_rules! map_options {
+    () => {
+        /// Type of the map.
+        pub fn map_type(mut self, map_type: MapType) -> Self {
+            self.opts.map_type = Some(map_type);
+            self
+        }
+
+        /// Key size in bytes, defaults to the size of `K`.
+        pub fn key_size(mut self, key_size: u32) -> Self {
+            self.opts.key_size = Some(key_size);
+            self
+        }
+
+        /// Value size in bytes, defaults to the size of `V`.
+        pub fn value_size(mut self, value_size: u32) -> Self {
+            self.opts.value_size = Some(value_size);
+            self
+        }
+
+        /// Maximum number of entries, required to create a map.
+        pub fn max_entries(mut self, max_entries: u32) -> Self {
+            self.opts.max_entries = Some(max_entries);
+            self
+        }
+
+        /// Raw `BPF_F_*` map flags, in addition to any set by other options.
+        pub fn map_flags(mut self, map_flags: u32) -> Self {
+            self.opts.map_flags |= map_flags;
+            self
+        }
+
+        /// Don't preallocate the map's elements (`BPF_F_NO_PREALLOC`). Only valid for hash maps.
+        pub fn no_prealloc(mut self) -> Self {
+            self.opts.map_flags |= bpf::BPF_F_NO_PREALLOC;
+            self
+        }
+
+        /// Name of the map. Required to build from an object, optional when creating a map.
+        pub fn name(mut self, name: &str) -> Self {
+            self.opts.name = Some(name.to_string());
+            self
+        }
+
+        /// Allocate the map's memory on NUMA node `node`.
+        pub fn numa_node(mut self, node: u32) -> Self {
+            self.opts.numa_node = Some(node);
+            self
+        }
+
+        /// Pin the map at `path`, which must be on a BPF filesystem. Missing parent directories
+        /// are created.
+        pub fn pin_path(mut self, path: &str) -> Self {
+            self.opts.pin_path = Some(path.to_string());
+            self
+        }
+    };
+}
+
+/// Builder for [`Map`], either for a map in a loaded object ([`build`](MapBuilder::build)) or
+/// for a new map ([`create`](MapBuilder::create)).
+///
+/// # Example
+/// ```no_run
+/// use rxdp::{MapBuilder, MapType};
+///
+/// let m = MapBuilder::<u32, u64>::new()
+///     .map_type(MapType::Hash)
+///     .max_entries(1024)
+///     .no_prealloc()
+///     .name("sessions")
+///     .create()
+///     .unwrap();
+///
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let m2 = MapBuilder::<u32, u64>::new()
+///     .name("map_name")
+///     .pin_path("/sys/fs/bpf/myapp/map_name")
+///     .build(&obj)
+///     .unwrap();
+/// ```
+pub struct MapBuilder<K, V> {
+    opts: MapOptions,
+    _key: PhantomData<K>,
+    _val: PhantomData<V>,
+}
+
+impl<K: Default, V: Default> MapBuilder<K, V> {
+    pub fn new() -> MapBuilder<K, V> {
+        MapBuilder {
+            opts: MapOptions::default(),
+            _key: PhantomData,
+            _val: PhantomData,
+        }
+    }
+
+    map_options!();
+
+    /// Get access to the map `name` in `xdp`. Fails in the same cases as [`Map::new`], or if
+    /// the map type or `max_entries` was set and doesn't match the map. Flags and the NUMA node
+    /// only apply to new maps and are ignored.
+    pub fn build(self, xdp: &XDPLoadedObject) -> XDPResult<Map<K, V>> {
+        let name = self.opts.name()?;
+        let m = Map::new(xdp, name)?;
+        self.opts.check(&mc::find_map(xdp, name)?)?;
+        Ok(m)
+    }
+
+    /// Create a new map. The map type defaults to [`MapType::Hash`]. Per-cpu map types
+    /// are rejected, use [`PerCpuMapBuilder`] instead.
+    pub fn create(self) -> XDPResult<Map<K, V>> {
+        let map_type = self.opts.map_type.unwrap_or(MapType::Hash);
+        if map_type.is_per_cpu() {
+            set_errno(Errno(22));
+            fail!("Improper map type, use rxdp::PerCpuMapBuilder");
+        }
+
+        let map_fd = self.opts.create::<K, V>(map_type)?;
+        Ok(Map::from_parts(
+            map_fd,
+            map_type,
+            self.opts.max_entries.unwrap_or_default(),
+        ))
+    }
+}
+
+impl<K: Default, V: Default> Default for MapBuilder<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builder for [`PerCpuMap`], see [`MapBuilder`].
+///
+/// # Example
+/// ```no_run
+/// use rxdp::{MapType, PerCpuMapBuilder};
+///
+/// let m = PerCpuMapBuilder::<u32, u64>::new()
+///     .map_type(MapType::PerCPUArray)
+///     .max_entries(16)
+///     .create()
+///     .unwrap();
+/// ```
+pub struct PerCpuMapBuilder<K, V> {
+    opts: MapOptions,
+    _key: PhantomData<K>,
+    _val: PhantomData<V>,
+}
+
+impl<K: Default, V: ByteAligned> PerCpuMapBuilder<K, V> {
+    pub fn new() -> PerCpuMapBuilder<K, V> {
+        PerCpuMapBuilder {
+            opts: MapOptions::default(),
+            _key: PhantomData,
+            _val: PhantomData,
+        }
+    }
+
+    map_options!();
+
+    /// Get access to the per-cpu map `name` in `xdp`. Fails in the same cases as
+    /// [`PerCpuMap::new`], or if the map type or `max_entries` was set and doesn't match the
+    /// map. Flags and the NUMA node only apply to new maps and are ignored.
+    pub fn build(self, xdp: &XDPLoadedObject) -> XDPResult<PerCpuMap<K, V>> {
+        let name = self.opts.name()?;
+        let m = PerCpuMap::new(xdp, name)?;
+        self.opts.check(&mc::find_map(xdp, name)?)?;
+        Ok(m)
+    }
+
+    /// Create a new per-cpu map. The map type defaults to [`MapType::PerCPUHash`].
+    pub fn create(self) -> XDPResult<PerCpuMap<K, V>> {
+        let map_type = self.opts.map_type.unwrap_or(MapType::PerCPUHash);
+        if !map_type.is_per_cpu() {
+            set_errno(Errno(22));
+            fail!("Improper map type, use rxdp::MapBuilder");
+        }
+
+        let value_size = self.opts.value_size.unwrap_or(size_of::<V>() as u32);
+        let codec = PerCpuCodec::for_type::<V>(value_size)?;
+
+        let map_fd = self.opts.create::<K, V>(map_type)?;
+        Ok(PerCpuMap::from_parts(
+            map_fd,
+            map_type,
+            self.opts.max_entries.unwrap_or_default(),
+            codec,
+        ))
+    }
+}
+
+impl<K: Default, V: ByteAligned> Default for PerCpuMapBuilder<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,9 +1,21 @@
 use errno::{set_errno, Errno};
 use libbpf_sys as bpf;
-use std::{mem::size_of, os::raw::c_void};
+use std::{
+    hash::Hash,
+    io::{Read, Write},
+    mem::size_of,
+    os::raw::c_void,
+};
 
+use crate::cancel::CancelToken;
+use crate::compat;
+use crate::deadline::{self, Deadline};
 use crate::error::{get_errno, reset_errno};
+use crate::kernel::{self, Feature};
+use crate::key_format::KeyFormat;
 use crate::map_batch::*;
+use crate::map_compat;
+use crate::perm::{self, Requires};
 use crate::utils;
 use crate::{BatchResult, MapFlags, MapType, XDPError, XDPLoadedObject, XDPResult};
 
@@ -14,8 +26,14 @@ pub struct KeyValue<K, V> {
     pub value: V,
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 /// Return value from eBPF maps.
+///
+/// [`MapLike`] methods return it because they are shared by [`Map`](crate::Map) and
+/// [`PerCpuMap`](crate::PerCpuMap). Changing them would break every caller of `lookup` and
+/// `items`, so the typed alternatives are inherent methods instead: `Map::get` returns `V`, and
+/// [`PerCpuMap::get`](crate::PerCpuMap::get) and
+/// [`get_items`](crate::PerCpuMap::get_items) return [`PerCpuValues`](crate::PerCpuValues).
 pub enum MapValue<V> {
     /// Result from cpu-shared maps.
     Single(V),
@@ -55,6 +73,95 @@ impl<V> MapValue<V> {
             MapValue::Single(r) => r,
         }
     }
+
+    /// Convert the map value into a `V`, like [`into_single`](MapValue::into_single), but
+    /// returns `None` instead of panicking if `Multi` is empty:
+    /// ```
+    /// use rxdp::MapValue;
+    /// assert_eq!(MapValue::Multi(vec![1u32, 2u32]).try_into_single(), Some(1u32));
+    /// assert_eq!(MapValue::<u32>::Multi(vec![]).try_into_single(), None);
+    /// ```
+    pub fn try_into_single(self) -> Option<V> {
+        match self {
+            MapValue::Multi(r) => r.into_iter().next(),
+            MapValue::Single(r) => Some(r),
+        }
+    }
+
+    /// Returns a reference to the first value:
+    /// ```
+    /// use rxdp::MapValue;
+    /// assert_eq!(MapValue::Multi(vec![1u32, 2u32]).first(), Some(&1u32));
+    /// assert_eq!(MapValue::Single(1u32).first(), Some(&1u32));
+    /// ```
+    pub fn first(&self) -> Option<&V> {
+        self.get(0)
+    }
+
+    /// Returns a reference to the value for `cpu`. `Single` values are only returned for
+    /// `cpu` 0:
+    /// ```
+    /// use rxdp::MapValue;
+    /// assert_eq!(MapValue::Multi(vec![1u32, 2u32]).get(1), Some(&2u32));
+    /// assert_eq!(MapValue::Multi(vec![1u32, 2u32]).get(2), None);
+    /// assert_eq!(MapValue::Single(1u32).get(1), None);
+    /// ```
+    pub fn get(&self, cpu: usize) -> Option<&V> {
+        match self {
+            MapValue::Multi(r) => r.get(cpu),
+            MapValue::Single(r) if cpu == 0 => Some(r),
+            MapValue::Single(_) => None,
+        }
+    }
+
+    /// Number of values, 1 for `Single`.
+    pub fn len(&self) -> usize {
+        match self {
+            MapValue::Multi(r) => r.len(),
+            MapValue::Single(_) => 1,
+        }
+    }
+
+    /// True if there are no values, which can only happen for an empty `Multi`.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over the values, regardless of the variant:
+    /// ```
+    /// use rxdp::MapValue;
+    /// let total: u32 = MapValue::Multi(vec![1u32, 2u32]).iter().sum();
+    /// assert_eq!(total, 3);
+    /// assert_eq!(MapValue::Single(1u32).iter().count(), 1);
+    /// ```
+    pub fn iter(&self) -> std::slice::Iter<'_, V> {
+        self.as_slice().iter()
+    }
+
+    fn as_slice(&self) -> &[V] {
+        match self {
+            MapValue::Multi(r) => r,
+            MapValue::Single(r) => std::slice::from_ref(r),
+        }
+    }
+}
+
+impl<V> IntoIterator for MapValue<V> {
+    type Item = V;
+    type IntoIter = std::vec::IntoIter<V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_vec().into_iter()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a MapValue<V> {
+    type Item = &'a V;
+    type IntoIter = std::slice::Iter<'a, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
 }
 
 /// This trait exposes the functionality of update/lookup/delete of underlying eBPF maps.
@@ -71,6 +178,11 @@ pub trait MapLike<K, V: Default> {
     #[doc(hidden)]
     fn update_batching_not_supported(&self) -> bool;
 
+    #[doc(hidden)]
+    fn update_values(&self, key: &K, values: &[V], flags: MapFlags) -> XDPResult<()> {
+        self.update(key, &values[0], flags)
+    }
+
     #[doc(hidden)]
     fn update_batch_impl(
         &self,
@@ -90,11 +202,40 @@ pub trait MapLike<K, V: Default> {
         (rc, count)
     }
 
+    #[doc(hidden)]
+    fn lookup_batch_into_impl(
+        &self,
+        keys: &mut [K],
+        vals: &mut [V],
+        next_key: Option<&[u8]>,
+    ) -> XDPResult<(u32, Option<BatchCursor>)> {
+        if vals.len() != keys.len() {
+            set_errno(Errno(22));
+            fail!(
+                "Expected {} values for {} keys, got {}",
+                keys.len(),
+                keys.len(),
+                vals.len()
+            );
+        }
+
+        let r = lookup_batch_prealloc(
+            self.map_fd(),
+            keys.len() as u32,
+            size_of::<K>(),
+            next_key,
+            keys,
+            vals,
+            false,
+        )?;
+        Ok((r.num_items, r.next_key.map(BatchCursor::batch)))
+    }
+
     #[doc(hidden)]
     fn lookup_batch_impl(
         &self,
         batch_size: u32,
-        next_key: Option<u32>,
+        next_key: Option<&[u8]>,
         delete: bool,
     ) -> XDPResult<BatchResult<K, MapValue<V>>>;
 
@@ -109,13 +250,51 @@ pub trait MapLike<K, V: Default> {
     /// The maximum number of entries the map supports
     fn max_entries(&self) -> u32;
 
+    #[doc(hidden)]
+    fn deadline(&self) -> Option<&Deadline> {
+        None
+    }
+
     /// Lookup an element from the underlying eBPF map.
     fn lookup(&self, key: &K) -> XDPResult<MapValue<V>> {
         let mut value: V = Default::default();
-        let rc = crate::map_common::lookup_elem(
-            self.map_fd(),
-            key as *const _ as *const c_void,
-            &mut value as *mut _ as *mut c_void,
+        let fd = self.map_fd();
+        let rc = deadline::call(
+            self.deadline(),
+            utils::as_bytes(key),
+            &[],
+            utils::as_bytes_mut(&mut value),
+            move |k, _, v| {
+                lookup_elem(
+                    fd,
+                    k.as_ptr() as *const c_void,
+                    v.as_mut_ptr() as *mut c_void,
+                )
+            },
+        );
+
+        crate::map_common::check_rc(rc, MapValue::Single(value), "Error looking up elem")
+    }
+
+    /// Same as [`lookup`](MapLike::lookup), with `flags` passed to the kernel. The only lookup
+    /// flag is [`BpfLock`](MapFlags::BpfLock), to copy the value while holding its spin lock.
+    fn lookup_with_flags(&self, key: &K, flags: MapFlags) -> XDPResult<MapValue<V>> {
+        let flags = flags.checked_bits()?;
+        let mut value: V = Default::default();
+        let fd = self.map_fd();
+        let rc = deadline::call(
+            self.deadline(),
+            utils::as_bytes(key),
+            &[],
+            utils::as_bytes_mut(&mut value),
+            move |k, _, v| unsafe {
+                bpf::bpf_map_lookup_elem_flags(
+                    fd,
+                    k.as_ptr() as *const c_void,
+                    v.as_mut_ptr() as *mut c_void,
+                    flags,
+                )
+            },
         );
 
         crate::map_common::check_rc(rc, MapValue::Single(value), "Error looking up elem")
@@ -123,12 +302,20 @@ pub trait MapLike<K, V: Default> {
 
     /// Update an element in the underlying eBPF map.
     fn update(&self, key: &K, value: &V, flags: MapFlags) -> XDPResult<()> {
-        crate::map_common::update_elem(
-            self.map_fd(),
-            key as *const _ as *const c_void,
-            value as *const _ as *const c_void,
-            flags as u64,
-        )
+        let flags = flags.checked_bits()?;
+        let fd = self.map_fd();
+        let rc = deadline::call(
+            self.deadline(),
+            utils::as_bytes(key),
+            utils::as_bytes(value),
+            &mut [],
+            move |k, v, _| {
+                let (k, v) = (k.as_ptr() as *const c_void, v.as_ptr() as *const c_void);
+                update_elem(fd, k, v, flags)
+            },
+        );
+
+        crate::map_common::check_rc(rc, (), "Error updating elem")
     }
 
     /// Delete an element from the underlying eBPF map.
@@ -139,8 +326,14 @@ pub trait MapLike<K, V: Default> {
             fail!("Delete not supported on this map type");
         }
 
-        let rc =
-            unsafe { bpf::bpf_map_delete_elem(self.map_fd(), key as *const _ as *const c_void) };
+        let fd = self.map_fd();
+        let rc = deadline::call(
+            self.deadline(),
+            utils::as_bytes(key),
+            &[],
+            &mut [],
+            move |k, _, _| unsafe { bpf::bpf_map_delete_elem(fd, k.as_ptr() as *const c_void) },
+        );
 
         crate::map_common::check_rc(rc, (), "Error deleting elem")
     }
@@ -148,12 +341,30 @@ pub trait MapLike<K, V: Default> {
     /// Update a batch of elements in the underlying eBPF map. If the kernel supports it, this
     /// will use the `BPF_MAP_UPDATE_BATCH` syscall to update all elements in 1 call. Otherwise,
     /// it is equivalent to calling `update()` in a loop for every element.
+    ///
+    /// If the update fails partway, the entries before the failed one have been applied. The
+    /// error's [`partial_update`](crate::XDPError::partial_update) tells where to resume:
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// # let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "map_name").unwrap();
+    /// use rxdp::MapLike;
+    ///
+    /// let mut keys = vec![1u32, 2, 3];
+    /// let mut values = vec![10u64, 20, 30];
+    /// if let Err(e) = m.update_batch(&mut keys, &mut values, rxdp::MapFlags::BpfNoExist) {
+    ///     if let Some(p) = e.partial_update() {
+    ///         println!("{} applied, key {} failed", p.applied, keys[p.failed_index as usize]);
+    ///     }
+    /// }
+    /// ```
     fn update_batch(
         &self,
         keys: &mut Vec<K>,
         values: &mut Vec<V>,
         flags: MapFlags,
     ) -> XDPResult<u32> {
+        let elem_flags = flags.checked_bits()?;
         let num_keys = keys.len();
         let num_vals = values.len();
         if num_keys != num_vals {
@@ -167,7 +378,9 @@ pub trait MapLike<K, V: Default> {
 
         if self.update_batching_not_supported() {
             for i in 0..num_keys {
-                self.update(&keys[i], &values[i], flags)?
+                if let Err(e) = self.update(&keys[i], &values[i], flags) {
+                    return Err(e.with_partial_update(i as u32));
+                }
             }
 
             return Ok(num_keys as u32);
@@ -175,17 +388,146 @@ pub trait MapLike<K, V: Default> {
 
         let opts = bpf::bpf_map_batch_opts {
             sz: 24u64,
-            elem_flags: flags as u64,
+            elem_flags,
             flags: 0u64,
         };
         let (rc, count) = self.update_batch_impl(keys, values, &opts);
+        if rc < 0 {
+            let e = XDPError::with_return_code("Error updating batch of elements", rc);
+            return Err(e.with_partial_update(count));
+        }
 
-        crate::map_common::check_rc(rc, count, "Error updating batch of elements")
+        Ok(count)
+    }
+
+    /// Delete a batch of elements from the underlying eBPF map. If the kernel supports it, this
+    /// will use the `BPF_MAP_DELETE_BATCH` syscall to delete all elements in 1 call. Otherwise,
+    /// it is equivalent to calling `delete()` in a loop for every key. Returns the number of
+    /// deleted elements.
+    ///
+    /// The batch stops at the first key that can't be deleted, e.g. because it doesn't exist
+    /// (`ENOENT`). As with [`update_batch`](MapLike::update_batch), the error's
+    /// [`partial_update`](crate::XDPError::partial_update) tells how many keys were deleted.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// # let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "map_name").unwrap();
+    /// use rxdp::MapLike;
+    ///
+    /// let evicted = vec![1u32, 2, 3];
+    /// m.delete_batch(&evicted).unwrap();
+    /// ```
+    fn delete_batch(&self, keys: &[K]) -> XDPResult<u32> {
+        // Array map types do not support deletes, do an early return to save a syscall.
+        if self.map_type().is_array() {
+            set_errno(Errno(22));
+            fail!("Delete not supported on this map type");
+        }
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        if self.update_batching_not_supported() {
+            for (i, key) in keys.iter().enumerate() {
+                if let Err(e) = self.delete(key) {
+                    return Err(e.with_partial_update(i as u32));
+                }
+            }
+
+            return Ok(keys.len() as u32);
+        }
+
+        let opts = bpf::bpf_map_batch_opts {
+            sz: 24u64,
+            elem_flags: 0u64,
+            flags: 0u64,
+        };
+        let mut count = keys.len() as u32;
+        let rc = unsafe {
+            bpf::bpf_map_delete_batch(
+                self.map_fd(),
+                keys.as_ptr() as *mut c_void,
+                &mut count,
+                &opts,
+            )
+        };
+        if rc < 0 {
+            let e = XDPError::with_return_code("Error deleting batch of elements", rc);
+            return Err(e.with_partial_update(count));
+        }
+
+        Ok(count)
+    }
+
+    /// Populate the map with the `(key, value)` pairs from `iter`, updating up to `chunk_size`
+    /// entries per [`update_batch`](MapLike::update_batch). Entries are streamed from `iter`,
+    /// so very large maps can be filled without collecting all entries first. If a batch
+    /// update fails partway, the rest of that batch is retried with single updates.
+    ///
+    /// `progress` is called after every chunk with the number of entries written so far and the
+    /// total number of entries, if `iter` knows its length. Returns the number of entries
+    /// written.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// # let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "map_name").unwrap();
+    /// use rxdp::MapLike;
+    ///
+    /// let entries = (0..1_000_000u32).map(|i| (i, 0u64));
+    /// m.populate_from_iter(entries, 10_000, rxdp::MapFlags::BpfAny, |done, total| {
+    ///     println!("{}/{:?}", done, total);
+    /// })
+    /// .unwrap();
+    /// ```
+    fn populate_from_iter<I, F>(
+        &self,
+        iter: I,
+        chunk_size: usize,
+        flags: MapFlags,
+        mut progress: F,
+    ) -> XDPResult<usize>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = (K, V)>,
+        F: FnMut(usize, Option<usize>),
+    {
+        if chunk_size == 0 {
+            set_errno(Errno(22));
+            fail!("Chunk size must be greater than 0");
+        }
+
+        let mut iter = iter.into_iter();
+        let total = iter.size_hint().1;
+        let mut keys = Vec::with_capacity(chunk_size);
+        let mut values = Vec::with_capacity(chunk_size);
+        let mut done = 0;
+        loop {
+            keys.clear();
+            values.clear();
+            for (k, v) in iter.by_ref().take(chunk_size) {
+                keys.push(k);
+                values.push(v);
+            }
+            if keys.is_empty() {
+                break;
+            }
+
+            populate_chunk(self, &mut keys, &mut values, flags)?;
+            done += keys.len();
+            progress(done, total);
+        }
+
+        Ok(done)
     }
 
     /// Lookup a batch of elements from the underlying eBPF map. Returns a
-    /// [`BatchResult`](crate::BatchResult) that includes the next key to pass in to
-    /// continue looking up elements:
+    /// [`BatchResult`](crate::BatchResult) that includes the cursor to pass in to
+    /// continue looking up elements. Cursors are opaque and sized for the map's keys, passing
+    /// one from a map with a different key size fails with `EINVAL`:
     /// ```no_run
     /// # use rxdp;
     /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
@@ -212,18 +554,59 @@ pub trait MapLike<K, V: Default> {
     fn lookup_batch(
         &self,
         batch_size: u32,
-        next_key: Option<u32>,
+        next_key: Option<BatchCursor>,
     ) -> XDPResult<BatchResult<K, MapValue<V>>> {
-        if !is_batching_supported() {
-            set_errno(Errno(95));
-            fail!("Batching not supported");
-        }
+        kernel::require(Feature::BatchOps, "lookup_batch")?;
+
+        let token = batch_token::<K>(next_key.as_ref())?;
+        self.lookup_batch_impl(batch_size, token, false)
+    }
+
+    /// Same as [`lookup_batch`](MapLike::lookup_batch), but reads the items into caller
+    /// buffers instead of returning them, so pollers can reuse the same buffers on every call.
+    /// Up to `keys.len()` items are read into the start of `keys` and `vals`. Returns the number
+    /// of items read, and the cursor to continue from (`None` once all items were read).
+    ///
+    /// For per-cpu maps, `vals` holds the values of all possible CPUs for each key one after
+    /// the other, so it needs `keys.len() * num_cpus()` entries, and the size of `V` must be a
+    /// multiple of 8 bytes. Fails with `EINVAL` if `vals` has the wrong length.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// # let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "map_name").unwrap();
+    /// use rxdp::MapLike;
+    ///
+    /// let mut keys = vec![0u32; 256];
+    /// let mut vals = vec![0u64; 256];
+    /// let mut next_key = None;
+    /// loop {
+    ///     let (n, next) = m.lookup_batch_into(&mut keys, &mut vals, next_key).unwrap();
+    ///     for (k, v) in keys[..n as usize].iter().zip(&vals[..n as usize]) {
+    ///         // do something with `k` and `v`...
+    ///     }
+    ///
+    ///     match next {
+    ///         Some(c) => next_key = Some(c),
+    ///         None => break,
+    ///     }
+    /// }
+    /// ```
+    fn lookup_batch_into(
+        &self,
+        keys: &mut [K],
+        vals: &mut [V],
+        next_key: Option<BatchCursor>,
+    ) -> XDPResult<(u32, Option<BatchCursor>)> {
+        kernel::require(Feature::BatchOps, "lookup_batch_into")?;
 
-        self.lookup_batch_impl(batch_size, next_key, false)
+        let token = batch_token::<K>(next_key.as_ref())?;
+        self.lookup_batch_into_impl(keys, vals, token)
     }
 
     /// Lookup and delete a batch of elements from the underlying eBPF map. Returns a
-    /// [`BatchResult`](crate::BatchResult) that includes the next key to pass in to
+    /// [`BatchResult`](crate::BatchResult) that includes the cursor to pass in to
     /// continue looking up elements:
     /// ```no_run
     /// # use rxdp;
@@ -251,12 +634,9 @@ pub trait MapLike<K, V: Default> {
     fn lookup_and_delete_batch(
         &self,
         batch_size: u32,
-        next_key: Option<u32>,
+        next_key: Option<BatchCursor>,
     ) -> XDPResult<BatchResult<K, MapValue<V>>> {
-        if !is_batching_supported() {
-            set_errno(Errno(95));
-            fail!("Batching not supported");
-        }
+        kernel::require(Feature::BatchOps, "lookup_and_delete_batch")?;
 
         // Array map types do not support deletes, do an early return to save a syscall.
         if self.map_type().is_array() {
@@ -264,17 +644,367 @@ pub trait MapLike<K, V: Default> {
             fail!("Delete not supported on this map type");
         }
 
-        self.lookup_batch_impl(batch_size, next_key, true)
+        let token = batch_token::<K>(next_key.as_ref())?;
+        self.lookup_batch_impl(batch_size, token, true)
     }
 
     /// Returns all items in the map. Note that for Array type maps, this will always
     /// return `max_entries` number of items.
     fn items(&self) -> XDPResult<Vec<KeyValue<K, MapValue<V>>>>;
+
+    /// Returns an iterator over the items in the map, which reads them in batches as it
+    /// advances instead of reading the whole map into memory like [`items`](MapLike::items).
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// # let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "map_name").unwrap();
+    /// use rxdp::MapLike;
+    ///
+    /// let mut total = 0;
+    /// for kv in m.iter() {
+    ///     total += kv.unwrap().value.into_single();
+    /// }
+    /// ```
+    /// **NOTE**: Like [`lookup_batch`](MapLike::lookup_batch), a map that is updated while it
+    /// is iterated can return items more than once, or skip items.
+    fn iter(&self) -> MapIter<'_, K, V, Self>
+    where
+        K: Default,
+        Self: Sized,
+    {
+        MapIter::new(self)
+    }
+
+    /// Same as [`items`](MapLike::items), but also reports whether the items are a consistent
+    /// snapshot. Reading a large map takes many syscalls, and keys that are added or removed
+    /// meanwhile can be missed or returned twice. This reads the keys (without their values)
+    /// before and after reading the items, and reports
+    /// [`Dirty`](crate::ScrapeConsistency::Dirty) if the two sets of keys differ, or the items
+    /// don't have exactly those keys:
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// # let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "map_name").unwrap();
+    /// use rxdp::MapLike;
+    ///
+    /// let items = loop {
+    ///     let r = m.items_checked().unwrap();
+    ///     if r.is_clean() {
+    ///         break r.items;
+    ///     }
+    /// };
+    /// ```
+    /// The check costs two extra walks over the keys, one `bpf_map_get_next_key` syscall per
+    /// key each, on top of reading the items, and holds both sets of keys in memory while
+    /// comparing them.
+    ///
+    /// **NOTE**: Only changes to the set of keys are detected, not updates of values.
+    fn items_checked(&self) -> XDPResult<CheckedItems<K, MapValue<V>>>
+    where
+        K: Default + Hash + Eq,
+    {
+        crate::map_batch::items_checked(self)
+    }
+
+    /// Returns all keys in the map, without reading any values. Cheaper than
+    /// [`items`](MapLike::items) when only the keys are needed (e.g. to decide what to
+    /// delete), especially for per-cpu maps.
+    ///
+    /// **NOTE**: Keys are read one at a time with `bpf_map_get_next_key`. If a key is deleted
+    /// while the map is read, the walk can start over and return keys more than once.
+    fn keys(&self) -> XDPResult<Vec<K>>
+    where
+        K: Default,
+    {
+        crate::map_batch::keys(self)
+    }
+
+    /// Removes all entries from the map and returns how many there were, e.g. to reset state
+    /// between tests. Hash maps are drained with
+    /// [`lookup_and_delete_batch`](MapLike::lookup_and_delete_batch) if the kernel supports it
+    /// for the map type, or key by key otherwise. `Array` and `PerCPUArray` entries can't be deleted, so all
+    /// `max_entries` of them are set to `V::default()` instead.
+    ///
+    /// **NOTE**: The map isn't cleared atomically. Entries inserted by an eBPF program while it
+    /// is being cleared may be kept.
+    fn clear(&self) -> XDPResult<u32>
+    where
+        K: Default,
+    {
+        crate::map_batch::clear(self)
+    }
+
+    /// Same as [`items`](MapLike::items), sorted by key, so the result doesn't depend on the
+    /// kernel's iteration order (e.g. for diffing two scrapes).
+    fn items_sorted(&self) -> XDPResult<Vec<KeyValue<K, MapValue<V>>>>
+    where
+        K: Ord,
+    {
+        let mut items = self.items()?;
+        items.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        Ok(items)
+    }
+
+    /// Same as [`items`](MapLike::items), with keys rendered by [`KeyFormat`], e.g. to export
+    /// them as JSON object keys or Prometheus labels.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// use rxdp::MapLike;
+    ///
+    /// let m: rxdp::Map<rxdp::LpmKey<[u8; 4]>, u64> = rxdp::Map::new(&obj, "route_hits").unwrap();
+    /// for kv in m.items_labeled().unwrap() {
+    ///     // e.g. route_hits{prefix="10.0.0.0/8"} 42
+    ///     println!("route_hits{{prefix=\"{}\"}} {}", kv.key, kv.value.into_single());
+    /// }
+    /// ```
+    fn items_labeled(&self) -> XDPResult<Vec<KeyValue<String, MapValue<V>>>>
+    where
+        K: KeyFormat,
+    {
+        let items = self.items()?;
+        Ok(items
+            .into_iter()
+            .map(|kv| KeyValue {
+                key: kv.key.key_string(),
+                value: kv.value,
+            })
+            .collect())
+    }
+
+    /// Returns up to `limit` items with keys greater than `after`, sorted by key. Pages are
+    /// defined by the keys rather than by the kernel's iteration order, so they stay stable
+    /// while the map is updated: an item is only missed if it is inserted behind the page
+    /// being read.
+    ///
+    /// Each page reads the whole map, since the kernel doesn't iterate hash maps in key order,
+    /// but only keeps `limit` items in memory. Fails with `EINVAL` if `limit` is 0.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// # let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "map_name").unwrap();
+    /// use rxdp::MapLike;
+    ///
+    /// let mut after = None;
+    /// loop {
+    ///     let page = m.items_page(after.as_ref(), 50).unwrap();
+    ///     // render `page.items`...
+    ///
+    ///     match page.next_after {
+    ///         Some(k) => after = Some(k),
+    ///         None => break,
+    ///     }
+    /// }
+    /// ```
+    fn items_page(&self, after: Option<&K>, limit: usize) -> XDPResult<KeyPage<K, MapValue<V>>>
+    where
+        K: Ord + Clone + Default,
+    {
+        crate::map_batch::key_page(self, after, limit)
+    }
+
+    /// Same as [`items`](MapLike::items), but stops once `cancel` is cancelled, e.g. when its
+    /// deadline passes, instead of reading the whole map. Items are read in batches and
+    /// `cancel` is checked between batches, so this stops cleanly with the items read so far.
+    /// Pass the returned cursor in to continue from where it stopped, `None` starts at the
+    /// beginning of the map:
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// # let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "map_name").unwrap();
+    /// use rxdp::{CancelToken, MapLike};
+    /// use std::time::Duration;
+    ///
+    /// let mut cursor = None;
+    /// loop {
+    ///     let cancel = CancelToken::with_timeout(Duration::from_millis(500));
+    ///     let r = m.items_until(&cancel, cursor).unwrap();
+    ///     // do something with `r.items`...
+    ///
+    ///     if r.is_complete() {
+    ///         break;
+    ///     }
+    ///     cursor = r.cursor;
+    /// }
+    /// ```
+    /// **NOTE**: Like [`lookup_batch`](MapLike::lookup_batch), a map that is updated between
+    /// calls can return items more than once, or skip items.
+    fn items_until(
+        &self,
+        cancel: &CancelToken,
+        cursor: Option<BatchCursor>,
+    ) -> XDPResult<PartialItems<K, MapValue<V>>>
+    where
+        K: Default,
+    {
+        crate::map_batch::items_until(self, cancel, cursor)
+    }
+
+    /// Same as [`lookup`](MapLike::lookup), with the key and value as raw bytes, for tools that
+    /// only know the key/value layout at runtime. `key` must be exactly `size_of::<K>()` bytes.
+    /// The returned value is `size_of::<V>()` bytes, or for per-cpu maps, the values for all
+    /// possible CPUs one after another (without padding).
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// # let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "map_name").unwrap();
+    /// use rxdp::MapLike;
+    ///
+    /// m.update_bytes(&7u32.to_ne_bytes(), &100u64.to_ne_bytes(), rxdp::MapFlags::BpfAny)
+    ///     .unwrap();
+    /// assert_eq!(m.lookup_bytes(&7u32.to_ne_bytes()).unwrap(), 100u64.to_ne_bytes());
+    /// ```
+    fn lookup_bytes(&self, key: &[u8]) -> XDPResult<Vec<u8>> {
+        let key: K = from_checked_bytes(key, "key")?;
+        Ok(value_to_bytes(&self.lookup(&key)?))
+    }
+
+    /// Same as [`update`](MapLike::update), with the key and value as raw bytes, in the layout
+    /// described in [`lookup_bytes`](MapLike::lookup_bytes). For per-cpu maps, `value` holds
+    /// one value for each possible CPU.
+    fn update_bytes(&self, key: &[u8], value: &[u8], flags: MapFlags) -> XDPResult<()> {
+        let key: K = from_checked_bytes(key, "key")?;
+        let n = crate::map_dump::values_per_entry(self.map_type()) as usize;
+        if value.len() != n * size_of::<V>() {
+            set_errno(Errno(22));
+            fail!(
+                "Incorrect value length {}, expected {}",
+                value.len(),
+                n * size_of::<V>()
+            );
+        }
+
+        let values: Vec<V> = value
+            .chunks_exact(size_of::<V>())
+            .map(|c| utils::from_bytes(c))
+            .collect();
+        if self.map_type().is_per_cpu() {
+            self.update_values(&key, &values, flags)
+        } else {
+            self.update(&key, &values[0], flags)
+        }
+    }
+
+    /// Same as [`items`](MapLike::items), with the keys and values as raw bytes, in the layout
+    /// described in [`lookup_bytes`](MapLike::lookup_bytes).
+    fn items_bytes(&self) -> XDPResult<Vec<KeyValue<Vec<u8>, Vec<u8>>>> {
+        Ok(self
+            .items()?
+            .iter()
+            .map(|kv| KeyValue {
+                key: utils::as_bytes(&kv.key).to_vec(),
+                value: value_to_bytes(&kv.value),
+            })
+            .collect())
+    }
+
+    /// Write all items in the map to `writer`, using the versioned binary format described in
+    /// [`DumpHeader`](crate::DumpHeader). Returns the number of entries written:
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// # let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, "map_name").unwrap();
+    /// use rxdp::MapLike;
+    /// use std::{fs::File, io::BufWriter};
+    ///
+    /// let mut w = BufWriter::new(File::create("/tmp/map.dump").unwrap());
+    /// let n = m.export_to(&mut w).unwrap();
+    /// ```
+    fn export_to(&self, writer: &mut dyn Write) -> XDPResult<u64> {
+        crate::map_dump::export(self, writer)
+    }
+
+    /// Same as [`export_to`](MapLike::export_to), but stops once `cancel` is cancelled, see
+    /// [`items_until`](MapLike::items_until). Every call writes a complete dump of the items
+    /// it read. Returns the number of entries written, and the cursor to continue from if it
+    /// stopped early.
+    fn export_until(
+        &self,
+        writer: &mut dyn Write,
+        cancel: &CancelToken,
+        cursor: Option<BatchCursor>,
+    ) -> XDPResult<(u64, Option<BatchCursor>)>
+    where
+        K: Default,
+    {
+        let r = self.items_until(cancel, cursor)?;
+        let n = crate::map_dump::write_dump(self, &r.items, writer)?;
+        Ok((n, r.cursor))
+    }
+
+    /// Read entries previously written by [`export_to`](MapLike::export_to) from `reader` and
+    /// update them in this map. Returns the number of entries imported.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the dump is malformed, or if the map type, key/value sizes or
+    /// number of CPUs recorded in the dump don't match this map.
+    fn import_from(&self, reader: &mut dyn Read, flags: MapFlags) -> XDPResult<u64> {
+        crate::map_dump::import(self, reader, flags)
+    }
+}
+
+// Builds a `T` from `bytes`, failing with EINVAL if it isn't `size_of::<T>()` long.
+fn from_checked_bytes<T>(bytes: &[u8], what: &str) -> XDPResult<T> {
+    if bytes.len() != size_of::<T>() {
+        set_errno(Errno(22));
+        fail!(
+            "Incorrect {} length {}, expected {}",
+            what,
+            bytes.len(),
+            size_of::<T>()
+        );
+    }
+
+    Ok(utils::from_bytes(bytes))
+}
+
+fn value_to_bytes<V>(value: &MapValue<V>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(value.len() * size_of::<V>());
+    for v in value.iter() {
+        bytes.extend_from_slice(utils::as_bytes(v));
+    }
+    bytes
+}
+
+// Updates all entries, retrying the entries after the first failed one with single updates if
+// the batch update fails partway.
+fn populate_chunk<K, V, M>(
+    m: &M,
+    keys: &mut Vec<K>,
+    values: &mut Vec<V>,
+    flags: MapFlags,
+) -> XDPResult<()>
+where
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    let start = match m.update_batch(keys, values, flags) {
+        Ok(_) => return Ok(()),
+        Err(e) => match e.partial_update() {
+            Some(p) => p.failed_index as usize,
+            None => return Err(e),
+        },
+    };
+
+    for i in start..keys.len() {
+        m.update(&keys[i], &values[i], flags)?;
+    }
+
+    Ok(())
 }
 
 pub(crate) fn check_rc<T>(rc: i32, ret: T, err_msg: &str) -> XDPResult<T> {
     if rc < 0 {
-        fail!(err_msg);
+        fail_rc!(rc, err_msg);
     }
 
     Ok(ret)
@@ -287,25 +1017,17 @@ pub(crate) fn create_map(
     max_entries: u32,
     map_flags: u32,
 ) -> i32 {
-    unsafe {
-        bpf::bpf_create_map(
-            map_type as u32,
-            key_size as i32,
-            value_size as i32,
-            max_entries as i32,
-            map_flags,
-        )
-    }
+    compat::create_map(
+        map_type as u32,
+        key_size,
+        value_size,
+        max_entries,
+        map_flags,
+    )
 }
 
-pub(crate) fn update_elem(
-    fd: i32,
-    key: *const c_void,
-    val: *const c_void,
-    flags: u64,
-) -> XDPResult<()> {
-    let rc = unsafe { bpf::bpf_map_update_elem(fd, key, val, flags) };
-    check_rc(rc, (), "Error updating elem")
+pub(crate) fn update_elem(fd: i32, key: *const c_void, val: *const c_void, flags: u64) -> i32 {
+    unsafe { bpf::bpf_map_update_elem(fd, key, val, flags) }
 }
 
 pub(crate) fn lookup_elem(fd: i32, key: *const c_void, val: *mut c_void) -> i32 {
@@ -322,16 +1044,51 @@ pub(crate) fn update_batch(
     unsafe { bpf::bpf_map_update_batch(fd, key, val, count, opts) }
 }
 
+// Checks that `read_range` is called on an array map of type `expected`, for indexes within
+// the map.
+pub(crate) fn check_range(
+    map_type: MapType,
+    expected: MapType,
+    start: u32,
+    len: u32,
+    max_entries: u32,
+) -> XDPResult<()> {
+    if map_type != expected {
+        set_errno(Errno(22));
+        fail!(
+            "read_range requires a {:?} map, not {:?}",
+            expected,
+            map_type
+        );
+    }
+    if start.checked_add(len).is_none_or(|end| end > max_entries) {
+        set_errno(Errno(7));
+        fail!(
+            "Range {}+{} out of bounds, the map has {} entries",
+            start,
+            len,
+            max_entries
+        );
+    }
+
+    Ok(())
+}
+
+// Looks up a batch of `key_size` byte keys, continuing from the batch token `next_key`. The
+// kernel reads and writes tokens of the key size for array maps, and a 4 byte bucket index for
+// hash maps, so tokens are sized to fit both.
 pub(crate) fn lookup_batch_prealloc<K, T>(
     map_fd: i32,
     batch_size: u32,
-    next_key: Option<u32>,
-    keys: &mut Vec<K>,
-    vals: &mut Vec<T>,
+    key_size: usize,
+    next_key: Option<&[u8]>,
+    keys: &mut [K],
+    vals: &mut [T],
     delete: bool,
 ) -> XDPResult<BatchResultInternal> {
     let mut count = batch_size;
-    let mut nkey = 0u32;
+    let token_size = batch_token_size(key_size);
+    let mut nkey = vec![0u8; token_size];
 
     reset_errno();
     let bpf_func = if delete {
@@ -344,7 +1101,7 @@ pub(crate) fn lookup_batch_prealloc<K, T>(
         bpf_func(
             map_fd,
             fkey,
-            &mut nkey as *mut _ as *mut c_void,
+            nkey.as_mut_ptr() as *mut c_void,
             keys.as_mut_ptr() as *mut c_void,
             vals.as_mut_ptr() as *mut c_void,
             &mut count,
@@ -353,7 +1110,11 @@ pub(crate) fn lookup_batch_prealloc<K, T>(
     };
 
     let mut rc = match next_key {
-        Some(mut k) => lookup(&mut k as *mut _ as *mut c_void),
+        Some(k) => {
+            let mut k = k.to_vec();
+            k.resize(token_size, 0);
+            lookup(k.as_mut_ptr() as *mut c_void)
+        }
         None => lookup(std::ptr::null_mut() as *mut c_void),
     };
 
@@ -375,41 +1136,141 @@ pub(crate) fn lookup_batch_prealloc<K, T>(
     check_rc(rc, ret, "Error looking up batch of elements")
 }
 
-pub(crate) fn validate_map<K>(
-    xdp: &XDPLoadedObject,
-    map_name: &str,
-) -> XDPResult<(i32, u32, u32, u32)> {
-    let name = utils::str_to_cstring(map_name)?;
-    let (map_fd, map, map_def) = unsafe {
-        let map_fd = bpf::bpf_object__find_map_fd_by_name(xdp.object, name.as_ptr());
-        let map = bpf::bpf_object__find_map_by_name(xdp.object, name.as_ptr());
+/// Definition of a map in a loaded object.
+pub(crate) struct MapDef {
+    pub(crate) fd: i32,
+    pub(crate) key_size: u32,
+    pub(crate) value_size: u32,
+    pub(crate) map_type: MapType,
+    pub(crate) raw_map_type: u32,
+    pub(crate) max_entries: u32,
+}
 
-        let map_def = bpf::bpf_map__def(map);
-        (map_fd, map, map_def)
-    };
+/// Finds the map `map_name` in `xdp`, and checks that its key size matches `K`. The key size
+/// isn't checked for keyless maps, or for perf event arrays, where the keys are managed by
+/// libbpf.
+pub(crate) fn validate_map<K>(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<MapDef> {
+    let def = find_map(xdp, map_name)?;
+    check_key_size::<K>(&def)?;
 
-    if map_fd < 0 || map.is_null() || map_def.is_null() {
-        fail!("Unable to find map with name '{}'", map_name);
+    Ok(def)
+}
+
+// Sanity check key size.
+fn check_key_size<K>(def: &MapDef) -> XDPResult<()> {
+    let req_key_size = size_of::<K>() as u32;
+    let check_key = !def.map_type.is_keyless() && def.map_type != MapType::PerfEventArray;
+    if check_key && req_key_size != def.key_size {
+        let hint = match def.map_type {
+            MapType::LPMTrie => " Use rxdp::LpmKey for the key of LPM tries.",
+            _ => "",
+        };
+        fail!(
+            "Incorrect key size, XDP map has size: {}, requested key size is {}.{}",
+            def.key_size,
+            req_key_size,
+            hint,
+        );
     }
 
-    // Sanity check key & value sizes.
-    let (ksize, vsize, mtype, max_entries) = unsafe {
-        (
-            (*map_def).key_size,
-            (*map_def).value_size,
-            (*map_def).type_,
-            (*map_def).max_entries,
-        )
+    Ok(())
+}
+
+/// Opens the map pinned at `path`, and checks that its key size matches `K`. The caller owns
+/// the returned fd.
+pub(crate) fn open_pinned<K>(path: &str) -> XDPResult<MapDef> {
+    let s = utils::str_to_cstring(path)?;
+    let fd = unsafe { bpf::bpf_obj_get(s.as_ptr()) };
+    if fd < 0 {
+        fail!("Error opening pinned map {}", path);
+    }
+
+    map_def_from_fd::<K>(fd).inspect_err(|_| unsafe {
+        libc::close(fd);
+    })
+}
+
+// Opens the map with kernel id `map_id`. The caller owns the returned fd.
+pub(crate) fn map_fd_by_id(map_id: u32) -> XDPResult<i32> {
+    let fd = unsafe { bpf::bpf_map_get_fd_by_id(map_id) };
+    if fd < 0 {
+        fail!(
+            "Error getting fd for map {}{}",
+            map_id,
+            perm::hint(Requires::SysAdmin)
+        );
+    }
+
+    Ok(fd)
+}
+
+// Duplicates the map `fd`, with close-on-exec set. The caller owns the returned fd.
+pub(crate) fn dup_map_fd(fd: i32) -> XDPResult<i32> {
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup < 0 {
+        fail!("Error duplicating map fd {}", fd);
+    }
+
+    Ok(dup)
+}
+
+// The definition of the map `fd` as reported by the kernel, checking that its key size
+// matches `K`.
+pub(crate) fn map_def_from_fd<K>(fd: i32) -> XDPResult<MapDef> {
+    let info = map_compat::map_info(fd)?;
+    let def = MapDef {
+        fd,
+        key_size: info.key_size,
+        value_size: info.value_size,
+        map_type: info.type_.into(),
+        raw_map_type: info.type_,
+        max_entries: info.max_entries,
     };
+    check_key_size::<K>(&def)?;
 
-    let req_key_size = size_of::<K>() as u32;
-    if req_key_size != ksize {
+    Ok(def)
+}
+
+/// Fails with the handle to use instead, for constructors that don't accept maps of `map_type`.
+pub(crate) fn improper_type<T>(map_name: &str, map_type: MapType) -> XDPResult<T> {
+    set_errno(Errno(22));
+    fail!(
+        "Improper map type, '{}' is a MapType::{:?} map, use {}",
+        map_name,
+        map_type,
+        map_type.kind().constructor()
+    );
+}
+
+/// Finds the map `map_name` in `xdp`. `max_entries` is read back from the kernel, since it
+/// can differ from the ELF definition (e.g. perf event arrays without `max_entries` are sized
+/// to the number of CPUs on load).
+pub(crate) fn find_map(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<MapDef> {
+    let name = utils::str_to_cstring(map_name)?;
+    let (map_fd, map) = unsafe {
+        let map_fd = bpf::bpf_object__find_map_fd_by_name(xdp.object, name.as_ptr());
+        let map = bpf::bpf_object__find_map_by_name(xdp.object, name.as_ptr());
+        (map_fd, map)
+    };
+
+    if map_fd < 0 || map.is_null() {
         fail!(
-            "Incorrect key size, XDP map has size: {}, requested key size is {}.",
-            ksize,
-            req_key_size,
+            "Unable to find map with name '{}' in object {}",
+            map_name,
+            xdp.path()
         );
     }
 
-    Ok((map_fd, vsize, mtype, max_entries))
+    let info = map_compat::map_info(map_fd)?;
+    let attrs = compat::map_attrs(map);
+    let def = MapDef {
+        fd: map_fd,
+        key_size: attrs.key_size,
+        value_size: attrs.value_size,
+        map_type: attrs.map_type.into(),
+        raw_map_type: attrs.map_type,
+        max_entries: info.max_entries,
+    };
+
+    Ok(def)
 }
@@ -0,0 +1,246 @@
+use errno::{set_errno, Errno};
+use libbpf_sys as bpf;
+use std::path::Path;
+
+use crate::compat;
+use crate::error::XDPError;
+use crate::map_types::MapType;
+use crate::result::XDPResult;
+use crate::utils;
+
+/// Map definition attributes that have to match for libbpf to reuse a pinned map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct MapAttrs {
+    pub(crate) map_type: u32,
+    pub(crate) key_size: u32,
+    pub(crate) value_size: u32,
+    pub(crate) max_entries: u32,
+    pub(crate) map_flags: u32,
+}
+
+// Flags the kernel sets on its own when creating a map of the given type. A map definition
+// from an ELF file won't have them, so a pinned map of that type can't be reused as-is.
+fn kernel_flags(map_type: MapType) -> u32 {
+    match map_type {
+        // Lookups return a pointer straight to the stored ifindex, so newer kernels make
+        // these maps read-only from the program side.
+        MapType::DevMap | MapType::DevMapHash => bpf::BPF_F_RDONLY_PROG,
+        _ => 0,
+    }
+}
+
+/// Returns the attributes `def` should be loaded with to reuse the `pinned` map.
+///
+/// Flags set by the kernel are only copied over if the pinned map actually has them, since
+/// older kernels don't set them. With `adopt_flags`, all of the pinned map's flags are used
+/// instead (e.g. a pinned map created with `BPF_F_NO_PREALLOC` by an older version of the
+/// object). Any other difference means the pinned map can't be reused.
+pub(crate) fn reconcile(def: MapAttrs, pinned: MapAttrs, adopt_flags: bool) -> XDPResult<MapAttrs> {
+    let map_type = MapType::from(def.map_type);
+    let map_flags = match adopt_flags {
+        true => pinned.map_flags,
+        false => def.map_flags | (pinned.map_flags & kernel_flags(map_type)),
+    };
+    let mut wanted = MapAttrs { map_flags, ..def };
+
+    // libbpf sizes perf event arrays without max_entries to the number of CPUs on load.
+    if map_type == MapType::PerfEventArray && def.max_entries == 0 {
+        wanted.max_entries = pinned.max_entries;
+    }
+
+    if wanted != pinned {
+        let only_flags = MapAttrs {
+            map_flags: pinned.map_flags,
+            ..wanted
+        } == pinned;
+        let hint = match only_flags {
+            true => " (only flags differ, see PinConfig::adopt_pinned_flags)",
+            false => "",
+        };
+        set_errno(Errno(22));
+        fail!(
+            "Pinned map doesn't match map definition: {}{}",
+            diff(&pinned, &wanted),
+            hint
+        );
+    }
+
+    Ok(wanted)
+}
+
+// Describes the attributes that differ between a pinned map and its definition.
+fn diff(pinned: &MapAttrs, def: &MapAttrs) -> String {
+    let fields = [
+        ("map_type", pinned.map_type, def.map_type),
+        ("key_size", pinned.key_size, def.key_size),
+        ("value_size", pinned.value_size, def.value_size),
+        ("max_entries", pinned.max_entries, def.max_entries),
+    ];
+    let mut d: Vec<String> = fields
+        .iter()
+        .filter(|(_, p, d)| p != d)
+        .map(|(name, p, d)| format!("{} {} (pinned) != {} (definition)", name, p, d))
+        .collect();
+    if pinned.map_flags != def.map_flags {
+        d.push(format!(
+            "map_flags {:#x} (pinned) != {:#x} (definition)",
+            pinned.map_flags, def.map_flags
+        ));
+    }
+
+    d.join(", ")
+}
+
+/// Adjusts `map` so that libbpf reuses the map pinned at `pin_path`, if there is one. See
+/// [`reconcile`] for `adopt_flags`.
+pub(crate) unsafe fn sanitize_pinned_map(
+    map: *mut bpf::bpf_map,
+    pin_path: &str,
+    adopt_flags: bool,
+) -> XDPResult<()> {
+    if !Path::new(pin_path).exists() {
+        return Ok(());
+    }
+
+    let def = compat::map_attrs(map);
+    let wanted = reconcile(def, pinned_attrs(pin_path)?, adopt_flags)?;
+
+    if wanted.map_flags != def.map_flags {
+        let rc = bpf::bpf_map__set_map_flags(map, wanted.map_flags);
+        if rc < 0 {
+            fail_rc!(rc, "Error setting map flags for pinned map");
+        }
+    }
+
+    Ok(())
+}
+
+fn pinned_attrs(pin_path: &str) -> XDPResult<MapAttrs> {
+    let s = utils::str_to_cstring(pin_path)?;
+    let fd = unsafe { bpf::bpf_obj_get(s.as_ptr()) };
+    if fd < 0 {
+        fail!("Error retrieving pinned map");
+    }
+
+    let info = map_info(fd);
+    unsafe { libc::close(fd) };
+    let info = info?;
+
+    Ok(MapAttrs {
+        map_type: info.type_,
+        key_size: info.key_size,
+        value_size: info.value_size,
+        max_entries: info.max_entries,
+        map_flags: info.map_flags,
+    })
+}
+
+/// Kernel info of the map `fd`.
+pub(crate) fn map_info(fd: i32) -> XDPResult<bpf::bpf_map_info> {
+    let mut info = bpf::bpf_map_info::default();
+    let mut info_len = std::mem::size_of::<bpf::bpf_map_info>() as u32;
+    let rc =
+        unsafe { bpf::bpf_obj_get_info_by_fd(fd, &mut info as *mut _ as *mut _, &mut info_len) };
+    if rc < 0 {
+        fail_rc!(rc, "Error retrieving map info");
+    }
+
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attrs(map_type: MapType, map_flags: u32) -> MapAttrs {
+        MapAttrs {
+            map_type: map_type as u32,
+            key_size: 4,
+            value_size: 4,
+            max_entries: 10,
+            map_flags,
+        }
+    }
+
+    #[test]
+    fn test_reconcile_matrix() {
+        let rdonly = bpf::BPF_F_RDONLY_PROG;
+        let no_prealloc = bpf::BPF_F_NO_PREALLOC;
+        let cases = [
+            // (type, def flags, pinned flags, expected flags)
+            (MapType::DevMap, 0, rdonly, Some(rdonly)),
+            (MapType::DevMap, 0, 0, Some(0)),
+            (MapType::DevMapHash, 0, rdonly, Some(rdonly)),
+            (MapType::DevMapHash, no_prealloc, rdonly, None),
+            (MapType::XSKMap, 0, 0, Some(0)),
+            (MapType::XSKMap, 0, rdonly, None),
+            (MapType::SockMap, 0, 0, Some(0)),
+            (MapType::SockHash, 0, rdonly, None),
+            (MapType::Hash, no_prealloc, no_prealloc, Some(no_prealloc)),
+            (MapType::Hash, 0, rdonly, None),
+        ];
+
+        for (map_type, def, pinned, expected) in cases.iter() {
+            let got = reconcile(attrs(*map_type, *def), attrs(*map_type, *pinned), false);
+            assert_eq!(got.ok().map(|a| a.map_flags), *expected);
+        }
+    }
+
+    #[test]
+    fn test_reconcile_mismatch() {
+        let def = attrs(MapType::DevMap, 0);
+        let pinned = MapAttrs {
+            value_size: 8,
+            ..attrs(MapType::DevMap, bpf::BPF_F_RDONLY_PROG)
+        };
+        let e = reconcile(def, pinned, false).unwrap_err();
+        assert_eq!(e.code(), 22);
+        assert!(e
+            .description()
+            .contains("value_size 8 (pinned) != 4 (definition)"));
+
+        let pinned = attrs(MapType::DevMapHash, bpf::BPF_F_RDONLY_PROG);
+        assert!(reconcile(def, pinned, false).is_err());
+    }
+
+    #[test]
+    fn test_reconcile_perf_event_array_entries() {
+        let pinned = attrs(MapType::PerfEventArray, 0);
+        let def = MapAttrs {
+            max_entries: 0,
+            ..pinned
+        };
+        assert_eq!(reconcile(def, pinned, false).unwrap(), pinned);
+
+        let def = MapAttrs {
+            max_entries: 0,
+            ..attrs(MapType::Array, 0)
+        };
+        assert!(reconcile(def, attrs(MapType::Array, 0), false).is_err());
+    }
+
+    #[test]
+    fn test_reconcile_adopt_flags() {
+        let no_prealloc = bpf::BPF_F_NO_PREALLOC;
+        let def = attrs(MapType::Hash, 0);
+        let pinned = attrs(MapType::Hash, no_prealloc);
+
+        let e = reconcile(def, pinned, false).unwrap_err();
+        assert!(e
+            .description()
+            .contains("map_flags 0x1 (pinned) != 0x0 (definition)"));
+        assert!(e.description().contains("adopt_pinned_flags"));
+        assert_eq!(reconcile(def, pinned, true).unwrap().map_flags, no_prealloc);
+        assert_eq!(
+            reconcile(attrs(MapType::Hash, no_prealloc), def, true).unwrap(),
+            def
+        );
+
+        // Only the flags are adopted.
+        let pinned = MapAttrs {
+            max_entries: 20,
+            ..pinned
+        };
+        assert!(reconcile(def, pinned, true).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use errno::{set_errno, Errno};
+use std::{
+    convert::TryInto,
+    io::{Read, Write},
+    mem::size_of,
+};
+
+use crate::error::XDPError;
+use crate::map_common::{KeyValue, MapLike, MapValue};
+use crate::result::XDPResult;
+use crate::utils;
+use crate::{MapFlags, MapType};
+
+/// Magic bytes at the start of every map dump.
+pub const DUMP_MAGIC: [u8; 8] = *b"RXDPDUMP";
+
+/// Current version of the map dump format.
+pub const DUMP_VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = 36;
+
+/// Header of the rxdp map dump format, written by
+/// [`MapLike::export_to`](crate::MapLike::export_to).
+///
+/// A dump is laid out as follows, with all header fields little-endian:
+///
+/// | Field        | Type      |
+/// |--------------|-----------|
+/// | magic        | `[u8; 8]` |
+/// | version      | `u32`     |
+/// | map_type     | `u32`     |
+/// | key_size     | `u32`     |
+/// | value_size   | `u32`     |
+/// | num_cpus     | `u32`     |
+/// | entries      | `u64`     |
+///
+/// followed by `entries` records of `key_size` key bytes and `num_cpus * value_size` value
+/// bytes. Keys and values are written in their in-memory (host) representation, so dumps are
+/// only portable between hosts with the same endianness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DumpHeader {
+    /// Format version.
+    pub version: u32,
+    /// Type of the exported map.
+    pub map_type: u32,
+    /// Size of each key, in bytes.
+    pub key_size: u32,
+    /// Size of each (per-cpu) value, in bytes.
+    pub value_size: u32,
+    /// Number of values per entry. Always 1 for maps that aren't per-cpu.
+    pub num_cpus: u32,
+    /// Number of entries following the header.
+    pub entries: u64,
+}
+
+impl DumpHeader {
+    /// Write the header to `writer`.
+    pub fn write_to(&self, writer: &mut dyn Write) -> XDPResult<()> {
+        let mut buf = Vec::with_capacity(HEADER_SIZE);
+        buf.extend_from_slice(&DUMP_MAGIC);
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(&self.map_type.to_le_bytes());
+        buf.extend_from_slice(&self.key_size.to_le_bytes());
+        buf.extend_from_slice(&self.value_size.to_le_bytes());
+        buf.extend_from_slice(&self.num_cpus.to_le_bytes());
+        buf.extend_from_slice(&self.entries.to_le_bytes());
+
+        write_all(writer, &buf)
+    }
+
+    /// Read and validate a header from `reader`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the magic bytes don't match or the version is unsupported.
+    pub fn read_from(reader: &mut dyn Read) -> XDPResult<DumpHeader> {
+        let mut buf = [0u8; HEADER_SIZE];
+        read_exact(reader, &mut buf)?;
+
+        if buf[..8] != DUMP_MAGIC {
+            set_errno(Errno(22));
+            fail!("Invalid map dump, bad magic bytes");
+        }
+
+        let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+        let header = DumpHeader {
+            version: u32_at(8),
+            map_type: u32_at(12),
+            key_size: u32_at(16),
+            value_size: u32_at(20),
+            num_cpus: u32_at(24),
+            entries: u64::from_le_bytes(buf[28..].try_into().unwrap()),
+        };
+
+        if header.version != DUMP_VERSION {
+            set_errno(Errno(95));
+            fail!("Unsupported map dump version {}", header.version);
+        }
+
+        Ok(header)
+    }
+}
+
+pub(crate) fn export<K, V, M>(m: &M, writer: &mut dyn Write) -> XDPResult<u64>
+where
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    write_dump(m, &m.items()?, writer)
+}
+
+// Writes a dump of `items`, read from `m`. Returns the number of entries written.
+pub(crate) fn write_dump<K, V, M>(
+    m: &M,
+    items: &[KeyValue<K, MapValue<V>>],
+    writer: &mut dyn Write,
+) -> XDPResult<u64>
+where
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    let header = DumpHeader {
+        version: DUMP_VERSION,
+        map_type: m.map_type() as u32,
+        key_size: size_of::<K>() as u32,
+        value_size: size_of::<V>() as u32,
+        num_cpus: values_per_entry(m.map_type()),
+        entries: items.len() as u64,
+    };
+    header.write_to(writer)?;
+
+    for kv in items.iter() {
+        write_all(writer, utils::as_bytes(&kv.key))?;
+        match &kv.value {
+            MapValue::Single(v) => write_all(writer, utils::as_bytes(v))?,
+            MapValue::Multi(vals) => {
+                for v in vals.iter() {
+                    write_all(writer, utils::as_bytes(v))?;
+                }
+            }
+        }
+    }
+
+    Ok(header.entries)
+}
+
+pub(crate) fn import<K, V, M>(m: &M, reader: &mut dyn Read, flags: MapFlags) -> XDPResult<u64>
+where
+    V: Default,
+    M: MapLike<K, V> + ?Sized,
+{
+    let header = DumpHeader::read_from(reader)?;
+
+    let expected = DumpHeader {
+        map_type: m.map_type() as u32,
+        key_size: size_of::<K>() as u32,
+        value_size: size_of::<V>() as u32,
+        num_cpus: values_per_entry(m.map_type()),
+        ..header
+    };
+    if header != expected {
+        set_errno(Errno(22));
+        fail!(
+            "Map dump doesn't match map. Dump: {:?}, map: {:?}",
+            header,
+            expected
+        );
+    }
+
+    let mut key_buf = vec![0u8; header.key_size as usize];
+    let mut val_buf = vec![0u8; header.value_size as usize];
+    let mut values: Vec<V> = Vec::with_capacity(header.num_cpus as usize);
+    for _ in 0..header.entries {
+        read_exact(reader, &mut key_buf)?;
+        let key: K = utils::from_bytes(&key_buf);
+
+        values.clear();
+        for _ in 0..header.num_cpus {
+            read_exact(reader, &mut val_buf)?;
+            values.push(utils::from_bytes(&val_buf));
+        }
+
+        m.update_values(&key, &values, flags)?;
+    }
+
+    Ok(header.entries)
+}
+
+pub(crate) fn values_per_entry(map_type: MapType) -> u32 {
+    if map_type.is_per_cpu() {
+        crate::num_cpus() as u32
+    } else {
+        1
+    }
+}
+
+fn write_all(writer: &mut dyn Write, buf: &[u8]) -> XDPResult<()> {
+    if let Err(e) = writer.write_all(buf) {
+        set_errno(Errno(e.raw_os_error().unwrap_or(5)));
+        fail!("Error writing map dump: {}", e);
+    }
+
+    Ok(())
+}
+
+fn read_exact(reader: &mut dyn Read, buf: &mut [u8]) -> XDPResult<()> {
+    if let Err(e) = reader.read_exact(buf) {
+        set_errno(Errno(e.raw_os_error().unwrap_or(5)));
+        fail!("Error reading map dump: {}", e);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = DumpHeader {
+            version: DUMP_VERSION,
+            map_type: MapType::PerCPUHash as u32,
+            key_size: 4,
+            value_size: 8,
+            num_cpus: 3,
+            entries: 5_000_000,
+        };
+
+        let mut buf = Vec::new();
+        header.write_to(&mut buf).unwrap();
+        assert_eq!(buf.len(), HEADER_SIZE);
+        assert_eq!(DumpHeader::read_from(&mut buf.as_slice()).unwrap(), header);
+    }
+
+    #[test]
+    fn test_header_bad_magic() {
+        let mut buf = vec![0u8; HEADER_SIZE];
+        assert_eq!(
+            DumpHeader::read_from(&mut buf.as_slice())
+                .unwrap_err()
+                .code(),
+            22
+        );
+
+        buf.truncate(4);
+        assert!(DumpHeader::read_from(&mut buf.as_slice()).is_err());
+    }
+}
@@ -1,15 +1,58 @@
+// The flags keep the names of the enum they replaced.
+#![allow(non_upper_case_globals)]
+
+use errno::{set_errno, Errno};
 use libbpf_sys as bpf;
 
-/// Flags that control map `update` behaviour.
-#[derive(Copy, Clone)]
-#[repr(u32)]
-pub enum MapFlags {
-    /// Create a new element or update an existing element.
-    BpfAny = bpf::BPF_ANY,
+use crate::error::XDPError;
+use crate::result::XDPResult;
+
+bitflags::bitflags! {
+    /// Flags that control map `update` behaviour. Flags combine with `|`, e.g.
+    /// `MapFlags::BpfExist | MapFlags::BpfLock`.
+    pub struct MapFlags: u64 {
+        /// Create a new element or update an existing element.
+        const BpfAny = bpf::BPF_ANY as u64;
+
+        /// Create a new element only if it did not exist.
+        const BpfNoExist = bpf::BPF_NOEXIST as u64;
+
+        /// Update an existing element.
+        const BpfExist = bpf::BPF_EXIST as u64;
+
+        /// Copy the value while holding the `struct bpf_spin_lock` it contains. Also accepted
+        /// by [`lookup_with_flags`](crate::MapLike::lookup_with_flags).
+        const BpfLock = bpf::BPF_F_LOCK as u64;
+    }
+}
+
+impl Default for MapFlags {
+    fn default() -> Self {
+        MapFlags::BpfAny
+    }
+}
 
-    /// Create a new element only if it did not exist.
-    BpfNoExist = bpf::BPF_NOEXIST,
+impl MapFlags {
+    /// The flags as passed to the kernel. Fails with `EINVAL` for combinations the kernel
+    /// rejects, i.e. both `BpfNoExist` and `BpfExist`.
+    ///
+    /// # Example
+    /// ```
+    /// use rxdp::MapFlags;
+    ///
+    /// assert_eq!((MapFlags::BpfExist | MapFlags::BpfLock).checked_bits().unwrap(), 6);
+    /// let err = (MapFlags::BpfNoExist | MapFlags::BpfExist).checked_bits().unwrap_err();
+    /// assert_eq!(err.code(), 22);
+    /// ```
+    pub fn checked_bits(&self) -> XDPResult<u64> {
+        if self.contains(MapFlags::BpfNoExist | MapFlags::BpfExist) {
+            set_errno(Errno(22));
+            fail!(
+                "Invalid map flags {:?}, BpfNoExist and BpfExist are exclusive",
+                self
+            );
+        }
 
-    /// Update an existing element.
-    BpfExist = bpf::BPF_EXIST,
+        Ok(self.bits())
+    }
 }
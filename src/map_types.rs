@@ -1,6 +1,12 @@
+use errno::{set_errno, Errno};
+use std::{fmt, str::FromStr};
+
+use crate::error::XDPError;
+
 #[allow(non_camel_case_types)]
 #[repr(u32)]
-#[derive(PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[non_exhaustive]
 /// Valid eBPF map types
 pub enum MapType {
     Unspec = libbpf_sys::BPF_MAP_TYPE_UNSPEC,
@@ -31,6 +37,13 @@ pub enum MapType {
     DevMapHash = libbpf_sys::BPF_MAP_TYPE_DEVMAP_HASH,
     StructOpts = libbpf_sys::BPF_MAP_TYPE_STRUCT_OPS,
     RingBuffer = libbpf_sys::BPF_MAP_TYPE_RINGBUF,
+    // Newer than the libbpf-sys bindings.
+    InodeStorage = 28,
+    TaskStorage = 29,
+    BloomFilter = 30,
+    UserRingBuffer = 31,
+    CgrpStorage = 32,
+    Arena = 33,
 }
 
 impl From<u32> for MapType {
@@ -64,12 +77,116 @@ impl From<u32> for MapType {
             25 => MapType::DevMapHash,
             26 => MapType::StructOpts,
             27 => MapType::RingBuffer,
+            28 => MapType::InodeStorage,
+            29 => MapType::TaskStorage,
+            30 => MapType::BloomFilter,
+            31 => MapType::UserRingBuffer,
+            32 => MapType::CgrpStorage,
+            33 => MapType::Arena,
             _ => MapType::Unspec,
         }
     }
 }
 
+/// The rxdp handle to use for a map, see [`MapType::kind`].
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[non_exhaustive]
+pub enum MapKind {
+    /// [`Map`](crate::Map)
+    Map,
+    /// [`PerCpuMap`](crate::PerCpuMap)
+    PerCpuMap,
+    /// [`PerfMap`](crate::PerfMap)
+    PerfMap,
+    /// [`RingBuffer`](crate::RingBuffer)
+    RingBuffer,
+    /// [`QueueMap`](crate::QueueMap)
+    QueueMap,
+    /// [`OuterMap`](crate::OuterMap)
+    OuterMap,
+    /// [`ProgArrayMap`](crate::ProgArrayMap)
+    ProgArrayMap,
+    /// [`StackTraceMap`](crate::StackTraceMap)
+    StackTraceMap,
+    /// [`BloomFilter`](crate::BloomFilter)
+    BloomFilter,
+}
+
+impl MapKind {
+    /// The constructor of the handle, e.g. `rxdp::PerCpuMap::new`.
+    pub fn constructor(&self) -> &'static str {
+        match *self {
+            MapKind::Map => "rxdp::Map::new",
+            MapKind::PerCpuMap => "rxdp::PerCpuMap::new",
+            MapKind::PerfMap => "rxdp::PerfMap::new",
+            MapKind::RingBuffer => "rxdp::RingBuffer::new",
+            MapKind::QueueMap => "rxdp::QueueMap::new",
+            MapKind::OuterMap => "rxdp::OuterMap::new",
+            MapKind::ProgArrayMap => "rxdp::ProgArrayMap::new",
+            MapKind::StackTraceMap => "rxdp::StackTraceMap::new",
+            MapKind::BloomFilter => "rxdp::BloomFilter::new",
+        }
+    }
+}
+
 impl MapType {
+    /// Name of the map type, as used by the kernel (`BPF_MAP_TYPE_<NAME>`) and bpftool, e.g.
+    /// `lru_percpu_hash`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            MapType::Unspec => "unspec",
+            MapType::Hash => "hash",
+            MapType::Array => "array",
+            MapType::ProgArray => "prog_array",
+            MapType::PerfEventArray => "perf_event_array",
+            MapType::PerCPUHash => "percpu_hash",
+            MapType::PerCPUArray => "percpu_array",
+            MapType::StackTrace => "stack_trace",
+            MapType::CgroupArray => "cgroup_array",
+            MapType::LRUHash => "lru_hash",
+            MapType::LRUPerCPUHash => "lru_percpu_hash",
+            MapType::LPMTrie => "lpm_trie",
+            MapType::ArrayOfMaps => "array_of_maps",
+            MapType::HashOfMaps => "hash_of_maps",
+            MapType::DevMap => "devmap",
+            MapType::SockMap => "sockmap",
+            MapType::CPUMap => "cpumap",
+            MapType::XSKMap => "xskmap",
+            MapType::SockHash => "sockhash",
+            MapType::CgroupStorage => "cgroup_storage",
+            MapType::ReusePortSockArray => "reuseport_sockarray",
+            MapType::PerCPUCgroupStorage => "percpu_cgroup_storage",
+            MapType::Queue => "queue",
+            MapType::Stack => "stack",
+            MapType::SKStorage => "sk_storage",
+            MapType::DevMapHash => "devmap_hash",
+            MapType::StructOpts => "struct_ops",
+            MapType::RingBuffer => "ringbuf",
+            MapType::InodeStorage => "inode_storage",
+            MapType::TaskStorage => "task_storage",
+            MapType::BloomFilter => "bloom_filter",
+            MapType::UserRingBuffer => "user_ringbuf",
+            MapType::CgrpStorage => "cgrp_storage",
+            MapType::Arena => "arena",
+        }
+    }
+
+    /// The handle to use for maps of this type. Any map can also be accessed with
+    /// [`DynMap`](crate::DynMap).
+    pub fn kind(&self) -> MapKind {
+        match *self {
+            t if t.is_per_cpu() => MapKind::PerCpuMap,
+            MapType::PerfEventArray => MapKind::PerfMap,
+            MapType::RingBuffer => MapKind::RingBuffer,
+            MapType::Queue | MapType::Stack => MapKind::QueueMap,
+            MapType::ArrayOfMaps | MapType::HashOfMaps => MapKind::OuterMap,
+            MapType::ProgArray => MapKind::ProgArrayMap,
+            MapType::StackTrace => MapKind::StackTraceMap,
+            MapType::BloomFilter => MapKind::BloomFilter,
+            _ => MapKind::Map,
+        }
+    }
+
     pub fn is_per_cpu(&self) -> bool {
         match *self {
             MapType::PerCPUArray
@@ -80,6 +197,20 @@ impl MapType {
         }
     }
 
+    /// True for map types without keys, which are accessed in FIFO/LIFO order or consumed as a
+    /// stream instead.
+    pub fn is_keyless(&self) -> bool {
+        matches!(
+            self,
+            MapType::Queue
+                | MapType::Stack
+                | MapType::RingBuffer
+                | MapType::BloomFilter
+                | MapType::UserRingBuffer
+                | MapType::Arena
+        )
+    }
+
     pub fn is_array(&self) -> bool {
         match *self {
             MapType::Array
@@ -92,14 +223,94 @@ impl MapType {
     }
 }
 
+impl fmt::Display for MapType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for MapType {
+    type Err = XDPError;
+
+    /// Parse a map type from its [`name`](MapType::name), case-insensitive and optionally
+    /// prefixed with `BPF_MAP_TYPE_`.
+    ///
+    /// # Example
+    /// ```
+    /// use rxdp::MapType;
+    /// assert_eq!("lru_hash".parse::<MapType>().unwrap(), MapType::LRUHash);
+    /// assert_eq!("BPF_MAP_TYPE_RINGBUF".parse::<MapType>().unwrap(), MapType::RingBuffer);
+    /// assert!("hashmap".parse::<MapType>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        let name = s.strip_prefix("bpf_map_type_").unwrap_or(&s);
+        match (0..=MapType::Arena as u32)
+            .map(MapType::from)
+            .find(|t| t.name() == name)
+        {
+            Some(t) => Ok(t),
+            None => {
+                set_errno(Errno(22));
+                Err(XDPError::new(&format!("Unknown map type '{}'", s)))
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_from_u32() {
-        for i in 0..27 {
+        for i in 0..34 {
             assert_eq!(i, MapType::from(i) as u32);
         }
     }
+
+    #[test]
+    fn test_name_roundtrip() {
+        for i in 0..34 {
+            let t = MapType::from(i);
+            assert_eq!(t.to_string().parse::<MapType>().unwrap(), t);
+        }
+        assert_eq!(
+            "PerCpu_Hash".parse::<MapType>().unwrap(),
+            MapType::PerCPUHash
+        );
+    }
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(MapType::Hash.kind(), MapKind::Map);
+        assert_eq!(MapType::LRUPerCPUHash.kind(), MapKind::PerCpuMap);
+        assert_eq!(MapType::PerfEventArray.kind(), MapKind::PerfMap);
+        assert_eq!(MapType::RingBuffer.kind(), MapKind::RingBuffer);
+        assert_eq!(MapType::Stack.kind(), MapKind::QueueMap);
+        assert_eq!(MapType::HashOfMaps.kind(), MapKind::OuterMap);
+        assert_eq!(MapType::ProgArray.kind(), MapKind::ProgArrayMap);
+        assert_eq!(MapType::StackTrace.kind(), MapKind::StackTraceMap);
+        assert_eq!(MapType::BloomFilter.kind(), MapKind::BloomFilter);
+        assert_eq!(
+            MapType::PerCPUArray.kind().constructor(),
+            "rxdp::PerCpuMap::new"
+        );
+    }
+
+    #[test]
+    fn test_is_keyless() {
+        let keyless = [
+            MapType::Queue,
+            MapType::Stack,
+            MapType::RingBuffer,
+            MapType::BloomFilter,
+            MapType::UserRingBuffer,
+            MapType::Arena,
+        ];
+        for i in 0..34 {
+            let t = MapType::from(i);
+            assert_eq!(t.is_keyless(), keyless.contains(&t));
+        }
+    }
 }
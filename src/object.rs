@@ -1,62 +1,637 @@
+use crate::compat;
+use crate::compatibility::{CompatibilityReport, MapSupport, ProgramSupport};
+use crate::config;
+use crate::elf;
 use crate::error::{get_errno, reset_errno, XDPError};
-use crate::program::Program;
+use crate::kernel;
+use crate::map_common as mc;
+use crate::map_compat;
+use crate::map_types::{MapKind, MapType};
+use crate::perm::{self, Requires};
+use crate::probe;
+use crate::program::{self, ExpectedAttachType, ProgType, Program, ProgramKind};
 use crate::result::XDPResult;
 use crate::utils;
 
+use errno::{set_errno, Errno};
 use libbpf_sys as bpf;
 use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
 use std::path::Path;
 
+const BPF_FS_MAGIC: i64 = 0xcafe4a11;
+
 /// Convenience wrapper around an XDP object
 pub struct XDPObject {
     object: *mut bpf::bpf_object,
+    path: String,
+    pin_root_path: String,
+    legacy_attach_type_workaround: Option<bool>,
+    unsupported_policy: UnsupportedPolicy,
+}
+
+/// Builder for an [`XDPObject`], for options that have to be set when opening the object.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// let obj = rxdp::XDPObjectBuilder::new("/path/to/elf/file")
+///     .legacy_attach_type_workaround(false)
+///     .build()
+///     .unwrap();
+/// ```
+pub struct XDPObjectBuilder<'a> {
+    file_path: &'a str,
+    legacy_attach_type_workaround: Option<bool>,
+    kconfig: Vec<(String, String)>,
+    pin_root_path: Option<String>,
+    unsupported_policy: UnsupportedPolicy,
+}
+
+impl<'a> XDPObjectBuilder<'a> {
+    /// Create a builder for the ELF file at `file_path`.
+    pub fn new(file_path: &'a str) -> XDPObjectBuilder<'a> {
+        XDPObjectBuilder {
+            file_path,
+            legacy_attach_type_workaround: None,
+            kconfig: Vec::new(),
+            pin_root_path: None,
+            unsupported_policy: UnsupportedPolicy::Fail,
+        }
+    }
+
+    /// Whether to clear the `BPF_XDP` expected attach type of XDP programs while loading, for
+    /// older kernels that reject it. By default this is only done if the running kernel is
+    /// detected to need it. Programs with any other attach type are never changed.
+    pub fn legacy_attach_type_workaround(mut self, enable: bool) -> XDPObjectBuilder<'a> {
+        self.legacy_attach_type_workaround = Some(enable);
+        self
+    }
+
+    /// Set the value of the kconfig extern `name` (e.g. `CONFIG_HZ`), instead of reading it
+    /// from the running kernel's config. Needed to load objects using `__kconfig` externs on
+    /// kernels that don't expose their config (`/proc/config.gz` or `/boot/config-*`).
+    ///
+    /// `value` is used as it would appear in a kernel config: a number, `y`/`n`/`m` for
+    /// tristate values, or a double-quoted string.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// let obj = rxdp::XDPObjectBuilder::new("/path/to/elf/file")
+    ///     .kconfig_override("CONFIG_HZ", 250)
+    ///     .kconfig_override("CONFIG_BPF_JIT", "y")
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn kconfig_override<V: std::fmt::Display>(
+        mut self,
+        name: &str,
+        value: V,
+    ) -> XDPObjectBuilder<'a> {
+        self.kconfig.retain(|(n, _)| n != name);
+        self.kconfig.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Directory for maps declared with `LIBBPF_PIN_BY_NAME` pinning in the ELF file (e.g.
+    /// `__uint(pinning, LIBBPF_PIN_BY_NAME)`), which libbpf pins (or reuses) at
+    /// `<path>/<map name>` when loading. Also the default path of
+    /// [`XDPObject::pinned_maps`].
+    ///
+    /// Defaults to [`config::default_pin_path`], i.e. the value of the
+    /// [`PIN_ROOT_ENV`](config::PIN_ROOT_ENV) environment variable if set, else the
+    /// process-wide default, else `/sys/fs/bpf`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// let obj = rxdp::XDPObjectBuilder::new("/path/to/elf/file")
+    ///     .pin_root_path("/sys/fs/bpf/myapp")
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn pin_root_path(mut self, path: &str) -> XDPObjectBuilder<'a> {
+        self.pin_root_path = Some(path.to_string());
+        self
+    }
+
+    /// What to do with maps and programs the running kernel doesn't support, see
+    /// [`UnsupportedPolicy`]. Defaults to [`UnsupportedPolicy::Fail`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// let obj = rxdp::XDPObjectBuilder::new("/path/to/elf/file")
+    ///     .unsupported_policy(rxdp::UnsupportedPolicy::Skip)
+    ///     .build()
+    ///     .unwrap()
+    ///     .load()
+    ///     .unwrap();
+    /// for prog in obj.skipped().programs.iter() {
+    ///     println!("WARNING: program {} not loaded", prog);
+    /// }
+    /// ```
+    pub fn unsupported_policy(mut self, policy: UnsupportedPolicy) -> XDPObjectBuilder<'a> {
+        self.unsupported_policy = policy;
+        self
+    }
+
+    /// Read the ELF file and attempt to create a bpf object.
+    ///
+    /// Maps the ELF file declares as pinned are checked the same way as maps set with
+    /// [`XDPObject::pinned_maps`]: already pinned maps must match the map definition, and new
+    /// maps must be pinned on a writable BPF filesystem.
+    pub fn build(self) -> XDPResult<XDPObject> {
+        let pin_root = self
+            .pin_root_path
+            .clone()
+            .or_else(config::configured_pin_path);
+
+        let path = utils::str_to_cstring(self.file_path)?;
+        let object = if self.kconfig.is_empty() && pin_root.is_none() {
+            // The returned pointer is non-null, even on error. Reset the errno value and check
+            // after.
+            reset_errno();
+            let object = unsafe { bpf::bpf_object__open(path.as_ptr()) };
+            if get_errno() != 0 {
+                fail!("Error creating object from ELF file {}", self.file_path)
+            }
+            object
+        } else {
+            let kconfig = match self.kconfig.is_empty() {
+                true => None,
+                false => Some(utils::str_to_cstring(&kconfig_string(&self.kconfig)?)?),
+            };
+            let pin_root_path = match pin_root.as_ref() {
+                Some(p) => Some(utils::str_to_cstring(p)?),
+                None => None,
+            };
+            let opts = bpf::bpf_object_open_opts {
+                sz: std::mem::size_of::<bpf::bpf_object_open_opts>() as _,
+                kconfig: kconfig.as_ref().map_or(std::ptr::null(), |k| k.as_ptr()),
+                pin_root_path: pin_root_path
+                    .as_ref()
+                    .map_or(std::ptr::null(), |p| p.as_ptr()),
+                ..unsafe { std::mem::zeroed() }
+            };
+            let object = unsafe { bpf::bpf_object__open_file(path.as_ptr(), &opts) };
+            let err = unsafe { bpf::libbpf_get_error(object as *const std::os::raw::c_void) };
+            if err != 0 {
+                fail_rc!(
+                    err as i32,
+                    "Error creating object from ELF file {}",
+                    self.file_path
+                );
+            }
+            object
+        };
+
+        let obj = XDPObject {
+            object,
+            path: self.file_path.to_string(),
+            pin_root_path: pin_root.unwrap_or_else(config::default_pin_path),
+            legacy_attach_type_workaround: self.legacy_attach_type_workaround,
+            unsupported_policy: self.unsupported_policy,
+        };
+        obj.check_declared_pins()?;
+
+        Ok(obj)
+    }
+}
+
+// Formats kconfig overrides the way libbpf expects them, one `NAME=value` per line.
+fn kconfig_string(kconfig: &[(String, String)]) -> XDPResult<String> {
+    let mut s = String::new();
+    for (name, value) in kconfig.iter() {
+        let valid_name = name.starts_with("CONFIG_")
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name || value.is_empty() || value.contains('\n') {
+            set_errno(Errno(22));
+            fail!("Invalid kconfig override {}={}", name, value);
+        }
+        s.push_str(&format!("{}={}\n", name, value));
+    }
+
+    Ok(s)
+}
+
+/// Options for pinning maps, see [`XDPObject::pin_maps`].
+pub struct PinConfig<'a> {
+    maps: &'a HashSet<String>,
+    path: Option<&'a str>,
+    adopt_flags: bool,
+    on_fallback: Option<FallbackFn<'a>>,
+}
+
+type FallbackFn<'a> = Box<dyn FnMut(&str, &XDPError) + 'a>;
+
+impl<'a> PinConfig<'a> {
+    /// Pin `maps` (by name) under `/sys/fs/bpf`.
+    pub fn new(maps: &'a HashSet<String>) -> PinConfig<'a> {
+        PinConfig {
+            maps,
+            path: None,
+            adopt_flags: false,
+            on_fallback: None,
+        }
+    }
+
+    /// Pin maps under `path` instead of `/sys/fs/bpf`.
+    pub fn path(mut self, path: &'a str) -> PinConfig<'a> {
+        self.path = Some(path);
+        self
+    }
+
+    /// Reuse already pinned maps with their own map flags, when the flags in the map definition
+    /// differ (e.g. the map was pinned by an older version of the object, created with
+    /// `BPF_F_NO_PREALLOC`). The map type, key/value sizes and `max_entries` still have to
+    /// match. By default, only flags the kernel sets on its own are reconciled and any other
+    /// difference is an error.
+    pub fn adopt_pinned_flags(mut self) -> PinConfig<'a> {
+        self.adopt_flags = true;
+        self
+    }
+
+    /// If a map can't be pinned (e.g. the pin path is on a read-only bpffs, not on a bpffs at
+    /// all, or not accessible), create it unpinned instead of failing. `on_fallback` is called
+    /// with the map name and the error for every map that won't be pinned.
+    ///
+    /// The pin directory of maps that aren't pinned yet is checked up front, it has to be on a
+    /// writable bpffs. Without a fallback, pinning other filesystems is left to libbpf and
+    /// the kernel when the object is loaded.
+    pub fn fallback_to_unpinned<F>(mut self, on_fallback: F) -> PinConfig<'a>
+    where
+        F: FnMut(&str, &XDPError) + 'a,
+    {
+        self.on_fallback = Some(Box::new(on_fallback));
+        self
+    }
+}
+
+/// What to do with maps and programs of an object the running kernel doesn't support, e.g. ring
+/// buffers on kernels older than 5.8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsupportedPolicy {
+    /// Load everything, loading fails if the kernel rejects a map or program.
+    Fail,
+    /// Skip the maps and programs listed by [`XDPObject::unsupported`], and report them with
+    /// [`XDPLoadedObject::skipped`]. Without the `libbpf-1` feature, a one element `Array` is
+    /// created in place of each skipped map.
+    Skip,
+}
+
+/// Maps and programs of an object the running kernel doesn't support.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Unsupported {
+    /// Maps with a type the kernel doesn't support, and their type.
+    pub maps: Vec<(String, MapType)>,
+    /// Programs with a type the kernel doesn't support, or that use one of `maps`.
+    pub programs: Vec<String>,
+}
+
+impl Unsupported {
+    pub fn is_empty(&self) -> bool {
+        self.maps.is_empty() && self.programs.is_empty()
+    }
+}
+
+/// What happens to program attachments when an [`XDPLoadedObject`] is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Leave programs attached, so the datapath keeps running after the process exits.
+    Leave,
+    /// Detach programs from every interface they were attached to through this object. Links
+    /// created by [`Program::attach`] are destroyed when they are dropped, regardless of the
+    /// policy.
+    Detach,
 }
 
 /// Struct for an XDP object that has been loaded
 pub struct XDPLoadedObject {
     pub(crate) object: *mut bpf::bpf_object,
+    path: String,
     programs: HashMap<String, Program>,
     program_names: Vec<String>,
+    drop_policy: DropPolicy,
+    skipped: Unsupported,
 }
 
 impl XDPObject {
     /// Read the ELF file at `file_path` and attempt to create a bpf object
     pub fn new(file_path: &str) -> XDPResult<Self> {
-        // The returned pointer is non-null, even on error. Reset the errno value and check after.
-        reset_errno();
-        let object = unsafe { bpf::bpf_object__open(utils::str_to_cstring(file_path)?.as_ptr()) };
-        if get_errno() != 0 {
-            fail!("Error creating object from ELF file")
-        } else {
-            Ok(Self { object })
-        }
+        XDPObjectBuilder::new(file_path).build()
     }
 
     /// Loads any previously pinned maps from the fs and/or sets maps to be pinned. Will use `path`
-    /// if provided, else defaults to `/sys/fs/bpf/` when looking for/pinning maps.
+    /// if provided, else defaults to the object's pin root path (`/sys/fs/bpf/` unless set with
+    /// [`XDPObjectBuilder::pin_root_path`]) when looking for/pinning maps.
+    ///
+    /// Flags the kernel sets on its own for some map types (e.g. `BPF_F_RDONLY_PROG` for
+    /// DEVMAP & DEVMAP_HASH) are copied from already pinned maps so they can be reused. Returns
+    /// an error if an already pinned map doesn't match the map definition.
     pub fn pinned_maps(&self, maps: &HashSet<String>, path: Option<&str>) -> XDPResult<()> {
-        let base_path = path.unwrap_or("/sys/fs/bpf").trim_end_matches('/');
+        let mut config = PinConfig::new(maps);
+        config.path = path;
+        self.pin_maps(config)
+    }
+
+    /// Same as [`pinned_maps`](XDPObject::pinned_maps), with the options in `config`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap();
+    /// # use std::collections::HashSet;
+    /// let mut maps = HashSet::new();
+    /// maps.insert("my_map_name".to_string());
+    ///
+    /// let config = rxdp::PinConfig::new(&maps).fallback_to_unpinned(|name, err| {
+    ///     println!("WARNING: map {} will not be pinned: {}", name, err);
+    /// });
+    /// obj.pin_maps(config).unwrap();
+    /// ```
+    pub fn pin_maps(&self, mut config: PinConfig) -> XDPResult<()> {
+        let base_path = config
+            .path
+            .unwrap_or(&self.pin_root_path)
+            .trim_end_matches('/');
 
         unsafe {
-            let mut map: *mut bpf::bpf_map = std::ptr::null_mut();
-            map = bpf::bpf_map__next(map, self.object);
+            let mut map = compat::next_map(self.object, std::ptr::null());
             while !map.is_null() {
                 let map_name = utils::cstring_to_str(bpf::bpf_map__name(map));
-                if maps.contains(&map_name) {
+                if config.maps.contains(&map_name) {
                     let pin_path = format!("{}/{}", base_path, map_name);
-                    sanitize_special_maps(map, &pin_path)?;
-                    let pin_path = utils::str_to_cstring(&pin_path)?;
-                    let rc = bpf::bpf_map__set_pin_path(map, pin_path.as_ptr());
-                    if rc < 0 {
-                        fail!("error setting pin path");
+                    let check_dir = config.on_fallback.is_some();
+                    if let Err(e) = set_pin_path(map, &pin_path, config.adopt_flags, check_dir) {
+                        match config.on_fallback.as_mut() {
+                            Some(f) => {
+                                bpf::bpf_map__set_pin_path(map, std::ptr::null());
+                                f(&map_name, &e);
+                            }
+                            None => return Err(e),
+                        }
                     }
                 }
-                map = bpf::bpf_map__next(map, self.object);
+                map = compat::next_map(self.object, map);
             }
         }
         Ok(())
     }
 
+    /// Pin root path of the object, see [`XDPObjectBuilder::pin_root_path`].
+    pub fn pin_root_path(&self) -> &str {
+        &self.pin_root_path
+    }
+
+    // Checks the pin paths libbpf set for maps declared with `LIBBPF_PIN_BY_NAME`.
+    fn check_declared_pins(&self) -> XDPResult<()> {
+        unsafe {
+            let mut map = compat::next_map(self.object, std::ptr::null());
+            while !map.is_null() {
+                let pin_path = compat::map_pin_path(map);
+                if !pin_path.is_null() {
+                    set_pin_path(map, &utils::cstring_to_str(pin_path), false, false)?;
+                }
+                map = compat::next_map(self.object, map);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the expected attach type of the program `name`, overriding the type libbpf derives
+    /// from the section name. Programs that run from DEVMAP or CPUMAP entries must be loaded
+    /// with [`ExpectedAttachType::DevMap`] or [`ExpectedAttachType::CpuMap`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// let obj = rxdp::XDPObject::new("/tmp/foo").unwrap();
+    /// obj.set_expected_attach_type("egress_prog", rxdp::ExpectedAttachType::DevMap)
+    ///     .unwrap();
+    /// let obj = obj.load().unwrap();
+    /// ```
+    pub fn set_expected_attach_type(
+        &self,
+        name: &str,
+        attach_type: ExpectedAttachType,
+    ) -> XDPResult<()> {
+        let prog = self.find_program(name)?;
+        unsafe { bpf::bpf_program__set_expected_attach_type(prog, attach_type.raw()) };
+        Ok(())
+    }
+
+    /// Set the type of the program `name`, overriding the type libbpf derives from the section
+    /// name. Older libbpf versions don't recognize all section names (or the object may use
+    /// custom ones), and loading the object then fails with an unknown program type.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// let obj = rxdp::XDPObject::new("/tmp/foo").unwrap();
+    /// obj.set_program_type("my_filter", rxdp::ProgType::Xdp).unwrap();
+    /// let obj = obj.load().unwrap();
+    /// ```
+    pub fn set_program_type(&self, name: &str, prog_type: ProgType) -> XDPResult<()> {
+        let prog = self.find_program(name)?;
+        unsafe { bpf::bpf_program__set_type(prog, prog_type.raw()) };
+        Ok(())
+    }
+
+    /// Set the map `inner_map_fd` as the template of the inner maps of the map-in-map `name`
+    /// (see [`OuterMap`](crate::OuterMap)). Required to load map-in-maps defined in a `maps`
+    /// section, which don't describe their inner maps.
+    pub fn set_inner_map_fd(&self, name: &str, inner_map_fd: i32) -> XDPResult<()> {
+        let s = utils::str_to_cstring(name)?;
+        let map = unsafe { bpf::bpf_object__find_map_by_name(self.object, s.as_ptr()) };
+        if map.is_null() {
+            set_errno(Errno(2));
+            fail!("No such map '{}' in object {}", name, self.path);
+        }
+
+        let rc = unsafe { bpf::bpf_map__set_inner_map_fd(map, inner_map_fd) };
+        mc::check_rc(rc, (), "Error setting inner map fd")
+    }
+
+    fn find_program(&self, name: &str) -> XDPResult<*mut bpf::bpf_program> {
+        let s = utils::str_to_cstring(name)?;
+        let prog = unsafe { bpf::bpf_object__find_program_by_name(self.object, s.as_ptr()) };
+        if prog.is_null() {
+            set_errno(Errno(2));
+            fail!("No such program '{}' in object {}", name, self.path);
+        }
+
+        Ok(prog)
+    }
+
+    /// Name of the object, as set by libbpf (the file name without the `.o` extension).
+    pub fn name(&self) -> String {
+        object_name(self.object)
+    }
+
+    /// Path of the ELF file the object was created from.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// GNU build-id of the ELF file (as a hex string), if it has one.
+    pub fn build_id(&self) -> Option<String> {
+        build_id(&self.path)
+    }
+
+    /// Maps and programs of the object the running kernel doesn't support, found by probing the
+    /// kernel for the map and program types. Programs that use an unsupported map directly
+    /// (rather than through a subprogram) are listed too.
+    ///
+    /// Probing needs the same privileges as loading, this fails with `EPERM` without them.
+    pub fn unsupported(&self) -> XDPResult<Unsupported> {
+        // Helpers aren't probed, so programs are only unsupported because of their type or maps.
+        let report = self.probe_support(false)?;
+        Ok(Unsupported {
+            maps: report
+                .maps
+                .iter()
+                .filter(|m| !m.supported)
+                .map(|m| (m.name.clone(), m.map_type))
+                .collect(),
+            programs: report
+                .programs
+                .iter()
+                .filter(|p| !report.program_supported(p))
+                .map(|p| p.name.clone())
+                .collect(),
+        })
+    }
+
+    /// What the maps and programs of the object need from the kernel, checked against the
+    /// running kernel without loading anything: map and program types, and the helpers each
+    /// program calls (found by scanning its instructions). Also lists the kernel features
+    /// rxdp's own APIs depend on.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// let obj = rxdp::XDPObject::new("/tmp/foo").unwrap();
+    /// let report = obj.compatibility_report().unwrap();
+    /// if !report.is_compatible() {
+    ///     eprintln!("{}", report);
+    /// }
+    /// ```
+    pub fn compatibility_report(&self) -> XDPResult<CompatibilityReport> {
+        let mut report = self.probe_support(true)?;
+        report.kernel = kernel::version().ok();
+        report.features = CompatibilityReport::features();
+        Ok(report)
+    }
+
+    // Probes the kernel for the map and program types of the object, and for the helpers each
+    // program calls if `helpers` is set. The kernel version and features aren't filled in.
+    fn probe_support(&self, helpers: bool) -> XDPResult<CompatibilityReport> {
+        let elf = std::fs::read(&self.path).ok();
+        let parsed = elf.as_ref().and_then(|e| {
+            let refs = elf::map_references(e)?;
+            let calls = match helpers {
+                true => elf::helper_calls(e)?,
+                false => Vec::new(),
+            };
+            Some((refs, calls))
+        });
+        let (refs, calls) = match parsed {
+            Some(p) => p,
+            None => {
+                set_errno(Errno(22));
+                fail!("Error reading the instructions of {}", self.path);
+            }
+        };
+
+        let mut report = CompatibilityReport {
+            kernel: None,
+            maps: Vec::new(),
+            programs: Vec::new(),
+            features: Vec::new(),
+        };
+        let mut probed = HashMap::new();
+        unsafe {
+            let mut map = compat::next_map(self.object, std::ptr::null());
+            while !map.is_null() {
+                let map_type = compat::map_attrs(map).map_type;
+                report.maps.push(MapSupport {
+                    name: utils::cstring_to_str(bpf::bpf_map__name(map)),
+                    map_type: map_type.into(),
+                    supported: probe_once(&mut probed, map_type, || {
+                        compat::probe_map_type(map_type)
+                    })?,
+                });
+                map = compat::next_map(self.object, map);
+            }
+
+            let mut probed = HashMap::new();
+            let mut prog = compat::next_program(self.object, std::ptr::null());
+            while !prog.is_null() {
+                let name = utils::cstring_to_str(bpf::bpf_program__name(prog));
+                let prog_type = compat::program_type(prog);
+                let type_supported = probe_once(&mut probed, (prog_type, None), || {
+                    compat::probe_prog_type(prog_type)
+                })?;
+                let helpers = calls
+                    .iter()
+                    .find(|(f, _)| *f == name)
+                    .map_or(&[][..], |(_, h)| h.as_slice())
+                    .iter()
+                    .map(|id| {
+                        let supported = type_supported
+                            && probe_once(&mut probed, (prog_type, Some(*id)), || {
+                                compat::probe_helper(prog_type, *id)
+                            })?;
+                        Ok((*id, supported))
+                    })
+                    .collect::<XDPResult<_>>()?;
+                report.programs.push(ProgramSupport {
+                    maps: refs
+                        .iter()
+                        .filter(|(p, _)| *p == name)
+                        .map(|(_, m)| m.clone())
+                        .collect(),
+                    name,
+                    prog_type,
+                    type_supported,
+                    helpers,
+                });
+                prog = compat::next_program(self.object, prog);
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Keeps libbpf from creating/loading the unsupported maps and programs.
+    fn skip_unsupported(&self) -> XDPResult<Unsupported> {
+        let unsupported = self.unsupported()?;
+        for (name, map_type) in unsupported.maps.iter() {
+            let s = utils::str_to_cstring(name)?;
+            let rc = unsafe {
+                let map = bpf::bpf_object__find_map_by_name(self.object, s.as_ptr());
+                compat::set_map_autocreate(map, false)
+            };
+            if rc < 0 {
+                fail_rc!(
+                    rc,
+                    "Unable to skip map {}, the kernel doesn't support MapType::{:?}",
+                    name,
+                    map_type
+                );
+            }
+        }
+        for name in unsupported.programs.iter() {
+            let prog = self.find_program(name)?;
+            unsafe { bpf::bpf_program__set_autoload(prog, false) };
+        }
+
+        Ok(unsupported)
+    }
+
     /// Load eBPF maps and programs into the kernel
     pub fn load(self) -> XDPResult<XDPLoadedObject> {
         XDPLoadedObject::new(self)
@@ -65,46 +640,120 @@ impl XDPObject {
 
 impl XDPLoadedObject {
     fn new(obj: XDPObject) -> XDPResult<Self> {
-        let obj = obj.object;
+        let legacy = obj
+            .legacy_attach_type_workaround
+            .unwrap_or_else(|| !probe::xdp_attach_type_supported());
+        let skipped = match obj.unsupported_policy {
+            UnsupportedPolicy::Fail => Unsupported::default(),
+            UnsupportedPolicy::Skip => obj.skip_unsupported()?,
+        };
+        let (obj, path) = (obj.object, obj.path);
         unsafe {
-            let mut prog: *mut bpf::bpf_program = std::ptr::null_mut();
-            prog = bpf::bpf_program__next(prog, obj);
+            let mut prog = compat::next_program(obj, std::ptr::null());
             while !prog.is_null() {
-                // Workaround for older kernels that fail if `expected_attach_type` is set
-                // to anything other than 0.
-                bpf::bpf_program__set_expected_attach_type(prog, 0);
-                prog = bpf::bpf_program__next(prog, obj);
+                // Workaround for older kernels that fail if `expected_attach_type` is set for
+                // XDP programs. DEVMAP/CPUMAP programs need their attach type on any kernel.
+                if legacy && bpf::bpf_program__get_expected_attach_type(prog) == bpf::BPF_XDP {
+                    bpf::bpf_program__set_expected_attach_type(prog, 0);
+                }
+                prog = compat::next_program(obj, prog);
             }
 
-            if bpf::bpf_object__load(obj) < 0 {
-                fail!("Error loading object");
+            let rc = bpf::bpf_object__load(obj);
+            if rc < 0 {
+                fail_rc!(rc, "Error loading object {}", path);
             }
         }
 
+        let build_id = build_id(&path);
         let mut programs = HashMap::new();
         let mut program_names = Vec::new();
 
         unsafe {
-            let mut prog: *mut bpf::bpf_program = std::ptr::null_mut();
-            prog = bpf::bpf_program__next(prog, obj);
+            let mut prog = compat::next_program(obj, std::ptr::null());
             while !prog.is_null() {
+                if !bpf::bpf_program__autoload(prog) {
+                    prog = compat::next_program(obj, prog);
+                    continue;
+                }
                 let prog_name = utils::cstring_to_str(bpf::bpf_program__name(prog));
-                programs.insert(prog_name.clone(), Program::new(prog)?);
+                programs.insert(prog_name.clone(), Program::new(prog, build_id.clone())?);
                 program_names.push(prog_name);
-                if bpf::bpf_program__get_type(prog) == bpf::BPF_PROG_TYPE_XDP {
+                if compat::program_type(prog) == bpf::BPF_PROG_TYPE_XDP
+                    && bpf::bpf_program__get_expected_attach_type(prog) == 0
+                {
                     bpf::bpf_program__set_expected_attach_type(prog, bpf::BPF_XDP);
                 }
-                prog = bpf::bpf_program__next(prog, obj);
+                prog = compat::next_program(obj, prog);
             }
         }
 
         return Ok(Self {
             object: obj,
+            path,
             programs,
             program_names,
+            drop_policy: DropPolicy::Leave,
+            skipped,
         });
     }
 
+    /// Set what happens to program attachments when this object is dropped. Defaults to
+    /// [`DropPolicy::Leave`].
+    ///
+    /// **NOTE**: with [`DropPolicy::Detach`], any program attached to one of the interfaces in
+    /// the same mode is removed, even if it was replaced by another process in the meantime.
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) {
+        self.drop_policy = policy;
+    }
+
+    /// Returns the current [`DropPolicy`].
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.drop_policy
+    }
+
+    /// Name of the object, as set by libbpf (the file name without the `.o` extension).
+    pub fn name(&self) -> String {
+        object_name(self.object)
+    }
+
+    /// Path of the ELF file the object was created from.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// GNU build-id of the ELF file (as a hex string), if it has one.
+    pub fn build_id(&self) -> Option<String> {
+        build_id(&self.path)
+    }
+
+    /// The handle to use for the map `map_name`, so generic code can check a map before
+    /// constructing it.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rxdp::{Map, MapKind, PerCpuMap};
+    ///
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// match obj.map_kind("counters").unwrap() {
+    ///     MapKind::PerCpuMap => {
+    ///         let m: PerCpuMap<u32, u64> = PerCpuMap::new(&obj, "counters").unwrap();
+    ///     }
+    ///     _ => {
+    ///         let m: Map<u32, u64> = Map::new(&obj, "counters").unwrap();
+    ///     }
+    /// }
+    /// ```
+    pub fn map_kind(&self, map_name: &str) -> XDPResult<MapKind> {
+        Ok(mc::find_map(self, map_name)?.map_type.kind())
+    }
+
+    /// Maps and programs that weren't loaded because the kernel doesn't support them, with
+    /// [`UnsupportedPolicy::Skip`].
+    pub fn skipped(&self) -> &Unsupported {
+        &self.skipped
+    }
+
     /// Returns a list of eBPF program names
     pub fn get_program_names(&self) -> &Vec<String> {
         &self.program_names
@@ -112,38 +761,163 @@ impl XDPLoadedObject {
 
     /// Returns a reference to an underlying eBPF program
     pub fn get_program(&self, name: &str) -> XDPResult<&Program> {
+        if self.skipped.programs.iter().any(|p| p == name) {
+            set_errno(Errno(95));
+            fail!(
+                "Program '{}' in object {} was skipped, the kernel doesn't support it",
+                name,
+                self.path
+            );
+        }
         if !self.programs.contains_key(name) {
-            fail!("No such program");
+            fail!("No such program '{}' in object {}", name, self.path);
         }
 
         Ok(&self.programs.get(name).unwrap())
     }
+
+    /// Same as [`get_program`](XDPLoadedObject::get_program), but fails with `EINVAL` if the
+    /// kernel didn't load the program as an XDP program, e.g. because it is a tracepoint or tc
+    /// program, or its section name wasn't recognized.
+    pub fn get_xdp_program(&self, name: &str) -> XDPResult<&Program> {
+        let prog = self.get_program(name)?;
+        let prog_type = prog.prog_type()?;
+        if ProgramKind::from_prog_type(prog_type) != ProgramKind::Xdp {
+            set_errno(Errno(22));
+            fail!(
+                "Program '{}' in object {} is a {} program, not xdp",
+                name,
+                self.path,
+                program::prog_type_name(prog_type)
+            );
+        }
+
+        Ok(prog)
+    }
 }
 
-/// Load a pinned object from a path. Returns the object fd.
-pub fn load_pinned_object(pin_path: &str) -> XDPResult<i32> {
-    let s = utils::str_to_cstring(pin_path)?;
-    let prog_fd = unsafe { bpf::bpf_obj_get(s.as_ptr()) };
+impl Drop for XDPLoadedObject {
+    fn drop(&mut self) {
+        if self.drop_policy == DropPolicy::Detach {
+            for prog in self.programs.values() {
+                prog.detach_all();
+            }
+        }
+    }
+}
 
-    if prog_fd < 0 {
-        fail!("Error retrieving pinned object");
+// Result of `probe` for `key`, probing only once per key. Fails if the process isn't allowed
+// to probe the kernel, rather than reporting everything as unsupported.
+fn probe_once<K: Hash + Eq>(
+    probed: &mut HashMap<K, bool>,
+    key: K,
+    probe: impl FnOnce() -> Result<bool, i32>,
+) -> XDPResult<bool> {
+    if let Some(supported) = probed.get(&key) {
+        return Ok(*supported);
+    }
+    match probe() {
+        Ok(supported) => Ok(*probed.entry(key).or_insert(supported)),
+        Err(rc) => {
+            set_errno(Errno(-rc));
+            fail_rc!(
+                rc,
+                "Unable to probe the kernel for supported features{}",
+                perm::hint(Requires::Bpf)
+            );
+        }
     }
+}
 
-    Ok(prog_fd)
+fn object_name(object: *mut bpf::bpf_object) -> String {
+    utils::cstring_to_str(unsafe { bpf::bpf_object__name(object) })
 }
 
-unsafe fn sanitize_special_maps(map: *mut bpf::bpf_map, pin_path: &str) -> XDPResult<()> {
-    let map_def = bpf::bpf_map__def(map);
+impl std::fmt::Debug for XDPObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("XDPObject")
+            .field("name", &self.name())
+            .field("path", &self.path)
+            .field("pin_root_path", &self.pin_root_path)
+            .finish()
+    }
+}
 
-    // DEVMAP sets map_flags = 0x80 automatically. In order to reuse the
-    // pinned map, the flags have to match.
-    if (*map_def).type_ == bpf::BPF_MAP_TYPE_DEVMAP && Path::new(pin_path).exists() {
-        let mut existing_flags = (*map_def).map_flags;
-        existing_flags |= 0x80;
-        if bpf::bpf_map__set_map_flags(map, existing_flags) < 0 {
-            fail!("Error setting BPF_MAP_TYPE_DEVMAP map flags for pinned map");
-        }
+impl std::fmt::Debug for XDPLoadedObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("XDPLoadedObject")
+            .field("name", &self.name())
+            .field("path", &self.path)
+            .field("programs", &self.program_names)
+            .finish()
+    }
+}
+
+// Reads the build-id of the ELF file at `path`.
+fn build_id(path: &str) -> Option<String> {
+    let elf = std::fs::read(path).ok()?;
+    elf::build_id(&elf).map(|id| utils::hex(&id))
+}
+
+// Sets the pin path of `map`. With `check_dir`, new pins are checked up front with
+// `check_pin_dir` instead of failing when the object is loaded.
+unsafe fn set_pin_path(
+    map: *mut bpf::bpf_map,
+    pin_path: &str,
+    adopt_flags: bool,
+    check_dir: bool,
+) -> XDPResult<()> {
+    if Path::new(pin_path).exists() {
+        map_compat::sanitize_pinned_map(map, pin_path, adopt_flags)?;
+    } else if check_dir {
+        check_pin_dir(Path::new(pin_path))?;
+    }
+
+    let pin_path = utils::str_to_cstring(pin_path)?;
+    let rc = bpf::bpf_map__set_pin_path(map, pin_path.as_ptr());
+    if rc < 0 {
+        fail_rc!(rc, "Error setting pin path");
     }
 
     Ok(())
 }
+
+// Checks that a new map can be pinned at `pin_path`. Missing directories are created by
+// libbpf, so the closest existing ancestor is checked instead.
+pub(crate) fn check_pin_dir(pin_path: &Path) -> XDPResult<()> {
+    let mut dir = pin_path;
+    while !dir.exists() {
+        dir = match dir.parent() {
+            Some(p) => p,
+            None => break,
+        };
+    }
+    let dir_str = dir.to_string_lossy();
+    let s = utils::str_to_cstring(&dir_str)?;
+
+    let mut st: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(s.as_ptr(), &mut st) } < 0 {
+        fail!("Error checking pin path {}", dir_str);
+    }
+    if st.f_type as i64 != BPF_FS_MAGIC {
+        set_errno(Errno(22));
+        fail!("Pin path {} is not on a BPF filesystem", dir_str);
+    }
+    if unsafe { libc::access(s.as_ptr(), libc::W_OK) } < 0 {
+        fail!("Pin path {} is not writable", dir_str);
+    }
+
+    Ok(())
+}
+
+/// Load a pinned object from a path. Returns the object fd.
+pub fn load_pinned_object(pin_path: &str) -> XDPResult<i32> {
+    let s = utils::str_to_cstring(pin_path)?;
+    let prog_fd = unsafe { bpf::bpf_obj_get(s.as_ptr()) };
+
+    if prog_fd < 0 {
+        fail_rc!(prog_fd, "Error retrieving pinned object");
+    }
+
+    Ok(prog_fd)
+}
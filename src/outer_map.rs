@@ -0,0 +1,196 @@
+use errno::{set_errno, Errno};
+use libbpf_sys as bpf;
+use std::{marker::PhantomData, mem::size_of, os::raw::c_void};
+
+use crate::error::{get_errno, XDPError};
+use crate::map_common as mc;
+use crate::map_compat;
+use crate::object::XDPLoadedObject;
+use crate::result::XDPResult;
+use crate::{compat, Map, MapFlags, MapKind, MapType};
+
+/// Used for working with map-in-map types (`ArrayOfMaps`, `HashOfMaps`), whose values are other
+/// maps.
+///
+/// Inner maps are inserted by fd and the kernel returns their id on lookup. Use
+/// [`get`](OuterMap::get) to open an inner map as a typed [`Map`].
+///
+/// The kernel needs a template of the inner maps (its type, key/value sizes and max entries)
+/// to load an object with a map-in-map defined in a `maps` section, set with
+/// [`XDPObject::set_inner_map_fd`](crate::XDPObject::set_inner_map_fd) before loading.
+///
+/// # Example
+/// ```no_run
+/// use rxdp::{MapBuilder, MapFlags, MapLike, OuterMap};
+///
+/// let obj = rxdp::XDPObject::new("/tmp/foo").unwrap();
+/// let template = MapBuilder::<u32, u64>::new().max_entries(1024).create().unwrap();
+/// obj.set_inner_map_fd("per_tenant", template.map_fd()).unwrap();
+/// let obj = obj.load().unwrap();
+///
+/// let outer: OuterMap<u32> = OuterMap::new(&obj, "per_tenant").unwrap();
+/// let tenant = MapBuilder::<u32, u64>::new().max_entries(1024).create().unwrap();
+/// outer.insert(&7, tenant.map_fd(), MapFlags::BpfAny).unwrap();
+///
+/// let inner = outer.get::<u32, u64>(&7).unwrap().unwrap();
+/// inner.update(&1, &100, MapFlags::BpfAny).unwrap();
+/// ```
+pub struct OuterMap<K> {
+    map_fd: i32,
+    _key: PhantomData<K>,
+    map_type: MapType,
+    max_entries: u32,
+    // True if the handle created `map_fd` itself, rather than borrowing it from an object.
+    owned: bool,
+}
+
+impl<K: Default> OuterMap<K> {
+    /// Create a new `ArrayOfMaps` or `HashOfMaps` map, using the map `inner_map_fd` as the
+    /// template of its inner maps.
+    pub fn create(
+        map_type: MapType,
+        inner_map_fd: i32,
+        max_entries: u32,
+        map_flags: u32,
+    ) -> XDPResult<OuterMap<K>> {
+        if map_type.kind() != MapKind::OuterMap {
+            set_errno(Errno(22));
+            fail!(
+                "Improper map type {:?}, expected ArrayOfMaps or HashOfMaps",
+                map_type
+            );
+        }
+
+        let key_size = size_of::<K>() as u32;
+        let map_fd = compat::create_map_in_map(
+            map_type as u32,
+            key_size,
+            inner_map_fd,
+            max_entries,
+            map_flags,
+        );
+        mc::check_rc(map_fd, (), "Error creating new map")?;
+
+        Ok(OuterMap {
+            map_fd,
+            _key: PhantomData,
+            map_type,
+            max_entries,
+            owned: true,
+        })
+    }
+
+    /// Get access to the map-in-map `map_name`. This will fail if the requested key size
+    /// doesn't match the key size defined in the ELF file.
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<OuterMap<K>> {
+        let def = mc::validate_map::<K>(xdp, map_name)?;
+        if def.map_type.kind() != MapKind::OuterMap {
+            return mc::improper_type(map_name, def.map_type);
+        }
+
+        Ok(OuterMap {
+            map_fd: def.fd,
+            _key: PhantomData,
+            map_type: def.map_type,
+            max_entries: def.max_entries,
+            owned: false,
+        })
+    }
+
+    /// Insert the map `inner_map_fd` at `key`. The map must match the inner map template.
+    pub fn insert(&self, key: &K, inner_map_fd: i32, flags: MapFlags) -> XDPResult<()> {
+        let flags = flags.checked_bits()?;
+        let rc = mc::update_elem(
+            self.map_fd,
+            key as *const _ as *const c_void,
+            &inner_map_fd as *const _ as *const c_void,
+            flags,
+        );
+
+        mc::check_rc(rc, (), "Error inserting inner map")
+    }
+
+    /// The id of the inner map at `key`, `None` if there is none.
+    pub fn inner_id(&self, key: &K) -> XDPResult<Option<u32>> {
+        let mut id = 0u32;
+        let rc = mc::lookup_elem(
+            self.map_fd,
+            key as *const _ as *const c_void,
+            &mut id as *mut _ as *mut c_void,
+        );
+        if rc == -2 || (rc < 0 && get_errno() == 2) {
+            return Ok(None);
+        }
+
+        mc::check_rc(rc, Some(id), "Error looking up inner map")
+    }
+
+    /// Open the inner map at `key` as a typed [`Map`], `None` if there is none. This will fail
+    /// if the requested key/value sizes don't match the inner map.
+    pub fn get<IK: Default, IV: Default>(&self, key: &K) -> XDPResult<Option<Map<IK, IV>>> {
+        let id = match self.inner_id(key)? {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        let fd = unsafe { bpf::bpf_map_get_fd_by_id(id) };
+        if fd < 0 {
+            fail!("Error getting fd for inner map {}", id);
+        }
+        let info = match map_compat::map_info(fd) {
+            Ok(i) => i,
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                return Err(e);
+            }
+        };
+        let map_type = MapType::from(info.type_);
+        // Closes `fd` if the checks below fail.
+        let map = Map::from_owned_fd(fd, map_type, info.max_entries);
+
+        let sizes = (size_of::<IK>() as u32, size_of::<IV>() as u32);
+        if sizes != (info.key_size, info.value_size) {
+            set_errno(Errno(22));
+            fail!(
+                "Incorrect key/value size, inner map has sizes: {}/{}, requested sizes are {}/{}",
+                info.key_size,
+                info.value_size,
+                sizes.0,
+                sizes.1,
+            );
+        }
+        if map_type.is_per_cpu() {
+            return mc::improper_type(&format!("inner map {}", id), map_type);
+        }
+
+        Ok(Some(map))
+    }
+
+    /// Remove the inner map at `key`. Not supported by `ArrayOfMaps`.
+    pub fn delete(&self, key: &K) -> XDPResult<()> {
+        let rc = unsafe { bpf::bpf_map_delete_elem(self.map_fd, key as *const _ as *const c_void) };
+        mc::check_rc(rc, (), "Error deleting inner map")
+    }
+
+    /// File descriptor for this map.
+    pub fn map_fd(&self) -> i32 {
+        self.map_fd
+    }
+
+    pub fn map_type(&self) -> MapType {
+        self.map_type
+    }
+
+    /// The maximum number of inner maps.
+    pub fn max_entries(&self) -> u32 {
+        self.max_entries
+    }
+}
+
+impl<K> Drop for OuterMap<K> {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { libc::close(self.map_fd) };
+        }
+    }
+}
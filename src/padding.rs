@@ -0,0 +1,75 @@
+/// Marker for types without padding bytes, so every byte passed to the kernel is initialized.
+///
+/// Keys and values are copied into eBPF maps byte for byte. If a type has padding, whatever
+/// happens to be in those bytes (e.g. stack memory) ends up in the map, and two keys that
+/// compare equal in Rust can end up as different keys in the kernel. The
+/// [`new_checked`](crate::Map::new_checked) constructors only accept `NoPadding` keys & values,
+/// use them instead of `new` to opt into the check.
+///
+/// Implement it for your own `#[repr(C)]` structs with [`no_padding!`](crate::no_padding),
+/// which verifies at compile time that the struct has no padding. The check is compile time
+/// only: the layout of a type isn't known at run time, so keys and values passed to the other
+/// constructors aren't checked for padding.
+///
+/// # Safety
+/// The type must not contain any padding bytes, and all its fields must be `NoPadding`.
+pub unsafe trait NoPadding: Copy {}
+
+macro_rules! impl_no_padding {
+    ($($t:ty),*) => {
+        $(unsafe impl NoPadding for $t {})*
+    };
+}
+
+impl_no_padding!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+unsafe impl<T: NoPadding, const N: usize> NoPadding for [T; N] {}
+
+#[doc(hidden)]
+pub const fn _assert_no_padding<T: NoPadding>() {}
+
+/// Implements [`NoPadding`](crate::NoPadding) for a `#[repr(C)]` struct, listing all of its
+/// fields. Fails to compile if the struct has padding, a field is missing or has a different
+/// type, or a field type isn't `NoPadding` itself.
+///
+/// # Example
+/// ```
+/// #[derive(Default, Clone, Copy)]
+/// #[repr(C)]
+/// struct Flow {
+///     packets: u64,
+///     bytes: u32,
+///     proto: u16,
+///     flags: [u8; 2],
+/// }
+///
+/// rxdp::no_padding!(Flow { packets: u64, bytes: u32, proto: u16, flags: [u8; 2] });
+/// ```
+///
+/// A struct with padding is rejected:
+/// ```compile_fail
+/// #[derive(Default, Clone, Copy)]
+/// #[repr(C)]
+/// struct Flow {
+///     packets: u64,
+///     bytes: u32,
+/// }
+///
+/// rxdp::no_padding!(Flow { packets: u64, bytes: u32 });
+/// ```
+#[macro_export]
+macro_rules! no_padding {
+    ($t:ident { $($field:ident : $ft:ty),+ $(,)? }) => {
+        const _: () = {
+            $($crate::_assert_no_padding::<$ft>();)+
+            assert!(
+                ::std::mem::size_of::<$t>() == 0 $(+ ::std::mem::size_of::<$ft>())+,
+                concat!(stringify!($t), " has padding")
+            );
+        };
+        const _: fn(&$t) = |v| {
+            $(let _: &$ft = &v.$field;)+
+        };
+        unsafe impl $crate::NoPadding for $t {}
+    };
+}
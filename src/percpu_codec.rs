@@ -0,0 +1,278 @@
+//! Buffers exchanged with the kernel for per-cpu map values.
+//!
+//! The kernel copies one value for each possible CPU, every value taking the map's value size
+//! rounded up to 8 bytes (see `bpf_map_value_size` in `kernel/bpf/syscall.c`), also for values
+//! wider than 8 bytes. The stride
+//! depends on the map definition only, the Rust value type may be smaller than the map's value.
+use errno::{set_errno, Errno};
+use std::cell::RefCell;
+use std::mem::size_of;
+
+use crate::error::XDPError;
+use crate::percpu_map::{num_cpus, ByteAligned};
+use crate::result::XDPResult;
+
+// Largest entry `with_entry` keeps on the stack, e.g. 8 byte values on 64 CPUs.
+const STACK_ENTRY_SIZE: usize = 512;
+
+thread_local! {
+    // Buffer for entries larger than `STACK_ENTRY_SIZE`.
+    static SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Bytes the kernel uses for a per-cpu value of `value_size` bytes: `round_up(value_size, 8)`.
+/// Values wider than 8 bytes, e.g. `u128` or structs, aren't aligned to their own size.
+pub(crate) const fn stride(value_size: usize) -> usize {
+    value_size.div_ceil(8) * 8
+}
+
+/// Rounds `v` up to the next multiple of 8.
+pub(crate) fn align(v: u32) -> usize {
+    stride(v as usize)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PerCpuCodec {
+    stride: usize,
+    num_cpus: usize,
+}
+
+impl PerCpuCodec {
+    /// Codec for a map with values of `value_size` bytes, checking that values of type `V` fit.
+    pub(crate) fn for_type<V: ByteAligned>(value_size: u32) -> XDPResult<PerCpuCodec> {
+        PerCpuCodec::with_cpus::<V>(value_size, num_cpus())
+    }
+
+    fn with_cpus<V: ByteAligned>(value_size: u32, num_cpus: usize) -> XDPResult<PerCpuCodec> {
+        let codec = PerCpuCodec {
+            stride: align(value_size),
+            num_cpus,
+        };
+        if V::ALIGNED_SIZE > codec.stride {
+            set_errno(Errno(22));
+            fail!(
+                "Incorrect value size, per-cpu map values take {} bytes, requested value size is {}",
+                codec.stride,
+                size_of::<V>()
+            );
+        }
+
+        Ok(codec)
+    }
+
+    /// Bytes used by the value of a single CPU.
+    pub(crate) fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Bytes used by the values of all CPUs for a single key.
+    pub(crate) fn entry_size(&self) -> usize {
+        self.stride * self.num_cpus
+    }
+
+    /// A zeroed buffer for the values of `entries` keys.
+    pub(crate) fn buffer(&self, entries: usize) -> Vec<u8> {
+        vec![0u8; entries * self.entry_size()]
+    }
+
+    /// Runs `f` with a zeroed buffer for the values of a single key, on the stack if it is
+    /// small enough, else in a buffer reused by the thread's later calls.
+    pub(crate) fn with_entry<R>(&self, f: impl FnOnce(&mut [u8]) -> R) -> R {
+        let size = self.entry_size();
+        if size <= STACK_ENTRY_SIZE {
+            let mut buf = [0u8; STACK_ENTRY_SIZE];
+            return f(&mut buf[..size]);
+        }
+
+        SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+            Ok(mut buf) => {
+                buf.clear();
+                buf.resize(size, 0);
+                f(&mut buf)
+            }
+            // Nested call, `f` of the outer call holds the buffer.
+            Err(_) => f(&mut self.buffer(1)),
+        })
+    }
+
+    /// Appends a value to `out`, padded to the stride.
+    pub(crate) fn encode<V: ByteAligned>(&self, value: V, out: &mut Vec<u8>) {
+        let b = value.align();
+        let n = b.len().min(self.stride);
+        out.extend_from_slice(&b[..n]);
+        out.resize(out.len() + self.stride - n, 0);
+    }
+
+    /// Appends `value` for every CPU to `out`.
+    pub(crate) fn encode_all<V: ByteAligned>(&self, value: V, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.resize(start + self.entry_size(), 0);
+        self.fill(value, &mut out[start..]);
+    }
+
+    /// Writes `value` for every CPU to `entry`, a zeroed buffer of `entry_size` bytes.
+    pub(crate) fn fill<V: ByteAligned>(&self, value: V, entry: &mut [u8]) {
+        let b = value.align();
+        let n = b.len().min(self.stride);
+        for c in entry.chunks_exact_mut(self.stride) {
+            c[..n].copy_from_slice(&b[..n]);
+        }
+    }
+
+    /// The values of all CPUs in `entry`, in CPU order.
+    pub(crate) fn decode<'a, V: ByteAligned + 'a>(
+        &self,
+        entry: &'a [u8],
+    ) -> impl Iterator<Item = V> + 'a {
+        let len = V::ALIGNED_SIZE;
+        entry
+            .chunks_exact(self.stride)
+            .map(move |c| V::from_aligned(&c[..len]))
+    }
+
+    /// The entries of the first `n` keys in `buf`, in key order.
+    pub(crate) fn entries<'a>(&self, buf: &'a [u8], n: usize) -> std::slice::ChunksExact<'a, u8> {
+        buf[..n * self.entry_size()].chunks_exact(self.entry_size())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Bytes<const N: usize>([u8; N]);
+
+    impl<const N: usize> Default for Bytes<N> {
+        fn default() -> Self {
+            Bytes([0; N])
+        }
+    }
+
+    impl<const N: usize> ByteAligned for Bytes<N> {
+        fn align(self) -> Vec<u8> {
+            let mut v = self.0.to_vec();
+            v.resize(align(N as u32), 0);
+            v
+        }
+
+        fn from_aligned(chunk: &[u8]) -> Self {
+            assert_eq!(chunk.len(), align(N as u32));
+            let mut b = [0u8; N];
+            b.copy_from_slice(&chunk[..N]);
+            Bytes(b)
+        }
+    }
+
+    fn roundtrip<const N: usize>() {
+        let codec = PerCpuCodec::with_cpus::<Bytes<N>>(N as u32, 3).unwrap();
+        assert_eq!(codec.stride() % 8, 0);
+        assert!(codec.stride() >= N && codec.stride() < N + 8);
+
+        let values: Vec<Bytes<N>> = (0..3u8).map(|c| Bytes([c + 1; N])).collect();
+        let mut buf = Vec::new();
+        for v in values.iter() {
+            codec.encode(*v, &mut buf);
+        }
+        assert_eq!(buf.len(), codec.entry_size());
+        for (cpu, chunk) in buf.chunks_exact(codec.stride()).enumerate() {
+            assert!(chunk[..N].iter().all(|b| *b == cpu as u8 + 1));
+            assert!(chunk[N..].iter().all(|b| *b == 0));
+        }
+
+        let decoded: Vec<Bytes<N>> = codec.decode(&buf).collect();
+        assert_eq!(decoded, values);
+    }
+
+    macro_rules! roundtrip_sizes {
+        ($($n:literal)*) => { $( roundtrip::<$n>(); )* };
+    }
+
+    #[test]
+    fn test_struct_values() {
+        roundtrip_sizes!(
+            1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31
+            32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49 50 51 52 53 54 55 56 57 58 59
+            60 61 62 63 64
+        );
+    }
+
+    #[test]
+    fn test_smaller_type_than_map_value() {
+        // A u32 map value read as u64 and a 12 byte value read as u64: the stride follows the
+        // map, not the type.
+        let codec = PerCpuCodec::with_cpus::<u64>(4, 2).unwrap();
+        assert_eq!(codec.stride(), 8);
+        let codec = PerCpuCodec::with_cpus::<u64>(12, 2).unwrap();
+        assert_eq!(codec.stride(), 16);
+
+        let mut buf = Vec::new();
+        codec.encode_all(7u64, &mut buf);
+        assert_eq!(buf.len(), 32);
+        assert_eq!(codec.decode::<u64>(&buf).collect::<Vec<_>>(), vec![7, 7]);
+
+        assert!(PerCpuCodec::with_cpus::<u128>(8, 2).is_err());
+    }
+
+    #[test]
+    fn test_wide_values() {
+        assert_eq!(stride(16), 16);
+        assert_eq!(stride(20), 24);
+
+        // A u128 read from a map with 20 byte values: each CPU's value starts 24 bytes after
+        // the previous one, not at the next 16 byte boundary.
+        let codec = PerCpuCodec::with_cpus::<u128>(20, 3).unwrap();
+        assert_eq!(codec.stride(), 24);
+        let values = [1u128 << 100, u128::MAX, 3];
+        let mut buf = Vec::new();
+        for v in values.iter() {
+            codec.encode(*v, &mut buf);
+        }
+        assert_eq!(buf.len(), 72);
+        assert_eq!(&buf[24..40], &u128::MAX.to_ne_bytes());
+        assert_eq!(codec.decode::<u128>(&buf).collect::<Vec<_>>(), values);
+
+        let codec = PerCpuCodec::with_cpus::<i128>(16, 2).unwrap();
+        let mut buf = Vec::new();
+        codec.encode_all(-5i128, &mut buf);
+        assert_eq!(buf.len(), 32);
+        assert_eq!(codec.decode::<i128>(&buf).collect::<Vec<_>>(), vec![-5, -5]);
+    }
+
+    #[test]
+    fn test_with_entry() {
+        for num_cpus in [1, 64, 65, 256].iter() {
+            let codec = PerCpuCodec::with_cpus::<u64>(8, *num_cpus).unwrap();
+            let decoded: Vec<u64> = codec.with_entry(|entry| {
+                assert_eq!(entry.len(), codec.entry_size());
+                assert!(entry.iter().all(|b| *b == 0));
+                codec.fill(3u64, entry);
+                codec.decode(entry).collect()
+            });
+            assert_eq!(decoded, vec![3; *num_cpus]);
+        }
+
+        // Nested calls get separate buffers
+        let codec = PerCpuCodec::with_cpus::<u64>(8, 128).unwrap();
+        codec.with_entry(|outer| {
+            codec.fill(1u64, outer);
+            codec.with_entry(|inner| assert!(inner.iter().all(|b| *b == 0)));
+            assert!(codec.decode::<u64>(outer).all(|v| v == 1));
+        });
+    }
+
+    #[test]
+    fn test_entries() {
+        let codec = PerCpuCodec::with_cpus::<u32>(4, 2).unwrap();
+        let mut buf = Vec::new();
+        for v in [1u32, 2, 3, 4, 5, 6].iter() {
+            codec.encode(*v, &mut buf);
+        }
+
+        let values: Vec<Vec<u32>> = codec
+            .entries(&buf, 2)
+            .map(|e| codec.decode(e).collect())
+            .collect();
+        assert_eq!(values, vec![vec![1, 2], vec![3, 4]]);
+    }
+}
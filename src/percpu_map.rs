@@ -1,14 +1,68 @@
 use errno::{set_errno, Errno};
 use lazy_static::lazy_static;
 use libbpf_sys as bpf;
-use std::{convert::TryInto, marker::PhantomData, mem::size_of, os::raw::c_void};
-
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    hash::Hash,
+    marker::PhantomData,
+    ops::Add,
+    os::raw::c_void,
+    sync::atomic::{AtomicU8, Ordering},
+    time::Duration,
+};
+
+use crate::deadline::{self, Deadline};
 use crate::map_batch::*;
 use crate::map_common as mc;
 use crate::map_common::{MapLike, MapValue};
+use crate::map_compat;
 use crate::object::XDPLoadedObject;
+use crate::percpu_codec::PerCpuCodec;
+use crate::percpu_values::PerCpuValues;
 use crate::result::XDPResult;
-use crate::{KeyValue, MapFlags, MapType, XDPError};
+use crate::topology;
+use crate::utils;
+use crate::{KeyValue, MapFlags, MapType, NoPadding, PerCpuMapBuilder, XDPError};
+
+// `BPF_F_CPU`: update the value of the CPU in the upper 32 bits of the flags only.
+const BPF_F_CPU: u64 = 8;
+
+// Whether the kernel supports `BPF_F_CPU`, probed on the first `update_cpu`.
+static CPU_FLAG: AtomicU8 = AtomicU8::new(CPU_FLAG_UNKNOWN);
+const CPU_FLAG_UNKNOWN: u8 = 0;
+const CPU_FLAG_SUPPORTED: u8 = 1;
+const CPU_FLAG_UNSUPPORTED: u8 = 2;
+
+// Probes `BPF_F_CPU` with an update of a scratch per-cpu array, kernels without the flag
+// reject it with EINVAL. The result isn't kept if the probe map can't be created.
+fn cpu_flag_supported() -> bool {
+    match CPU_FLAG.load(Ordering::Relaxed) {
+        CPU_FLAG_SUPPORTED => return true,
+        CPU_FLAG_UNSUPPORTED => return false,
+        _ => (),
+    }
+
+    let fd = mc::create_map(MapType::PerCPUArray, 4, 8, 1, 0);
+    if fd < 0 {
+        return false;
+    }
+    let (key, value) = (0u32, 0u64);
+    let rc = mc::update_elem(
+        fd,
+        &key as *const u32 as *const c_void,
+        &value as *const u64 as *const c_void,
+        BPF_F_CPU,
+    );
+    unsafe { libc::close(fd) };
+
+    let state = match rc {
+        0 => CPU_FLAG_SUPPORTED,
+        _ => CPU_FLAG_UNSUPPORTED,
+    };
+    CPU_FLAG.store(state, Ordering::Relaxed);
+    state == CPU_FLAG_SUPPORTED
+}
 
 lazy_static! {
     static ref NUM_CPUS: usize = crate::utils::num_cpus().unwrap();
@@ -21,11 +75,15 @@ pub struct PerCpuMap<K, V> {
     _val: PhantomData<V>,
     map_type: MapType,
     max_entries: u32,
-    value_size: usize,
+    codec: PerCpuCodec,
+    deadline: Option<Deadline>,
+    // True if the handle opened `map_fd` itself, rather than borrowing it from an object.
+    owned: bool,
 }
 
 impl<K: Default, V: ByteAligned> PerCpuMap<K, V> {
     /// Create a new map.
+    #[deprecated(note = "use rxdp::PerCpuMapBuilder")]
     pub fn create(
         map_type: MapType,
         key_size: u32,
@@ -33,47 +91,497 @@ impl<K: Default, V: ByteAligned> PerCpuMap<K, V> {
         max_entries: u32,
         map_flags: u32,
     ) -> XDPResult<PerCpuMap<K, V>> {
-        if !map_type.is_per_cpu() {
-            set_errno(Errno(22));
-            fail!("Improper map type, use rxdp::Map::create");
-        }
-
-        let map_fd = mc::create_map(map_type, key_size, value_size, max_entries, map_flags);
+        PerCpuMapBuilder::new()
+            .map_type(map_type)
+            .key_size(key_size)
+            .value_size(value_size)
+            .max_entries(max_entries)
+            .map_flags(map_flags)
+            .create()
+    }
 
-        let m = PerCpuMap {
+    pub(crate) fn from_parts(
+        map_fd: i32,
+        map_type: MapType,
+        max_entries: u32,
+        codec: PerCpuCodec,
+    ) -> PerCpuMap<K, V> {
+        PerCpuMap {
             map_fd,
             _key: PhantomData,
             _val: PhantomData,
             map_type,
             max_entries,
-            value_size: align(value_size),
-        };
+            codec,
+            deadline: None,
+            owned: false,
+        }
+    }
 
-        mc::check_rc(map_fd, m, "Error creating new map")
+    // Same as `from_parts`, closing `map_fd` when the handle is dropped.
+    pub(crate) fn from_owned_fd(
+        map_fd: i32,
+        map_type: MapType,
+        max_entries: u32,
+        codec: PerCpuCodec,
+    ) -> PerCpuMap<K, V> {
+        let mut m = PerCpuMap::from_parts(map_fd, map_type, max_entries, codec);
+        m.owned = true;
+        m
     }
 
     /// Get access to the eBPF map `map_name`. This will fail if the requested key size
     /// doesn't match the key size defined in the ELF file.
     pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<PerCpuMap<K, V>> {
-        let (map_fd, _, mtype, max_entries) = mc::validate_map::<K>(xdp, map_name)?;
+        let def = mc::validate_map::<K>(xdp, map_name)?;
+        let codec = Self::codec(&def, map_name)?;
+
+        Ok(PerCpuMap::from_parts(
+            def.fd,
+            def.map_type,
+            def.max_entries,
+            codec,
+        ))
+    }
+
+    /// Get access to the per-cpu map pinned at `path`, e.g. by another process, without
+    /// loading the object it belongs to. This will fail if the requested key/value sizes don't
+    /// match the key/value sizes of the pinned map.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// let m: rxdp::PerCpuMap<u32, u64> =
+    ///     rxdp::PerCpuMap::from_pinned("/sys/fs/bpf/myapp/stats").unwrap();
+    /// ```
+    pub fn from_pinned(path: &str) -> XDPResult<PerCpuMap<K, V>> {
+        let def = mc::open_pinned::<K>(path)?;
+        let codec = match Self::codec(&def, path) {
+            Ok(c) => c,
+            Err(e) => {
+                unsafe { libc::close(def.fd) };
+                return Err(e);
+            }
+        };
+
+        Ok(PerCpuMap::from_owned_fd(
+            def.fd,
+            def.map_type,
+            def.max_entries,
+            codec,
+        ))
+    }
+
+    /// Get access to the per-cpu map with kernel id `map_id`, e.g. a map created by another
+    /// process. Requires CAP_SYS_ADMIN. This will fail if the requested key/value sizes don't
+    /// match the key/value sizes of the map.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// let m: rxdp::PerCpuMap<u32, u64> = rxdp::PerCpuMap::from_id(42).unwrap();
+    /// ```
+    pub fn from_id(map_id: u32) -> XDPResult<PerCpuMap<K, V>> {
+        let fd = mc::map_fd_by_id(map_id)?;
+        Self::adopt_fd(fd, &format!("map id {}", map_id))
+    }
+
+    /// Get access to the per-cpu map `fd`, e.g. one received from another process over a unix
+    /// socket. The handle works on a duplicate of `fd`, so the caller keeps ownership of `fd`.
+    /// This will fail if the requested key/value sizes don't match the key/value sizes of the
+    /// map.
+    pub fn from_fd(fd: i32) -> XDPResult<PerCpuMap<K, V>> {
+        let dup = mc::dup_map_fd(fd)?;
+        Self::adopt_fd(dup, &format!("map fd {}", fd))
+    }
+
+    // Wraps the map `fd` in a handle that closes it once `fd` is checked, or closes it right
+    // away if the checks fail.
+    fn adopt_fd(fd: i32, name: &str) -> XDPResult<PerCpuMap<K, V>> {
+        let def = mc::map_def_from_fd::<K>(fd).and_then(|def| Ok((Self::codec(&def, name)?, def)));
+        match def {
+            Ok((codec, def)) => Ok(PerCpuMap::from_owned_fd(
+                fd,
+                def.map_type,
+                def.max_entries,
+                codec,
+            )),
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                Err(e)
+            }
+        }
+    }
+
+    // Checks that the map `name` is a per-cpu map, and returns the codec for its values.
+    fn codec(def: &mc::MapDef, name: &str) -> XDPResult<PerCpuCodec> {
+        if !def.map_type.is_per_cpu() {
+            return mc::improper_type(name, def.map_type);
+        }
+
+        PerCpuCodec::for_type::<V>(def.value_size)
+    }
+
+    /// Re-read the map's metadata (currently `max_entries`) from the kernel, in case the map
+    /// was sized differently than its definition.
+    pub fn refresh_info(&mut self) -> XDPResult<()> {
+        self.max_entries = map_compat::map_info(self.map_fd)?.max_entries;
+        Ok(())
+    }
 
-        let map_type: MapType = mtype.into();
-        if !map_type.is_per_cpu() {
+    /// Same as [`new`](PerCpuMap::new), but only accepts key types that are guaranteed not to
+    /// leak uninitialized padding bytes into the map (see [`NoPadding`](crate::NoPadding)).
+    /// Values are always converted with [`ByteAligned`], which writes out their bytes explicitly.
+    pub fn new_checked(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<PerCpuMap<K, V>>
+    where
+        K: NoPadding,
+    {
+        Self::new(xdp, map_name)
+    }
+
+    /// Set a deadline for operations on this handle. Once set, `lookup`, `update` and `delete`
+    /// run on a worker thread and fail with `ETIMEDOUT` (see
+    /// [`XDPError::is_timed_out`](crate::XDPError::is_timed_out)) if the kernel doesn't
+    /// complete them within `timeout`. `None` (the default) runs all operations on the calling
+    /// thread. See [`Map::set_timeout`](crate::Map::set_timeout) for details.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(Deadline::new);
+    }
+}
+
+impl<K: Default + Copy, V: ByteAligned> PerCpuMap<K, V> {
+    fn scrape(
+        map_fd: i32,
+        codec: PerCpuCodec,
+        shard: Shard,
+    ) -> XDPResult<Vec<KeyValue<K, MapValue<V>>>> {
+        Self::scrape_with(map_fd, codec, shard, |n, result, keys, vals| {
+            populate_batch_result(&codec, n, result, keys, vals)
+        })
+    }
+
+    // Scrapes the map in batches, with `populate` converting each batch of `n` keys and their
+    // per-cpu values into items.
+    fn scrape_with<T, F>(
+        map_fd: i32,
+        codec: PerCpuCodec,
+        shard: Shard,
+        mut populate: F,
+    ) -> XDPResult<Vec<KeyValue<K, T>>>
+    where
+        F: FnMut(u32, &mut Vec<KeyValue<K, T>>, &mut Vec<K>, &mut Vec<u8>),
+    {
+        let mut keys: Vec<K> = Vec::with_capacity(BATCH_SIZE as usize);
+        let mut vals = codec.buffer(BATCH_SIZE as usize);
+
+        let mut result = Vec::with_capacity(BATCH_SIZE as usize);
+        let mut next_key = shard.start_token();
+
+        loop {
+            keys.resize_with(BATCH_SIZE as usize, Default::default);
+
+            let r = mc::lookup_batch_prealloc(
+                map_fd,
+                BATCH_SIZE,
+                size_of::<K>(),
+                next_key.as_deref(),
+                &mut keys,
+                &mut vals,
+                false,
+            )?;
+            populate(r.num_items, &mut result, &mut keys, &mut vals);
+
+            next_key = shard.next(r.next_key);
+            if next_key.is_none() {
+                break;
+            }
+        }
+
+        shard.trim(&mut result);
+        Ok(result)
+    }
+}
+
+impl<K: Default + Copy, V: ByteAligned> PerCpuMap<K, V> {
+    /// Lookup the value for `key`, one value for each possible CPU. Same as
+    /// [`lookup`](MapLike::lookup), without the [`MapValue`] wrapper.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let m: rxdp::PerCpuMap<u32, u64> = rxdp::PerCpuMap::new(&obj, "map_name").unwrap();
+    /// let values = m.get(&0).unwrap();
+    /// println!("cpu 0: {}, total: {}", values[0], values.sum());
+    /// ```
+    pub fn get(&self, key: &K) -> XDPResult<PerCpuValues<V>> {
+        let mut values = Vec::with_capacity(*NUM_CPUS);
+        self.lookup_into(key, &mut values)?;
+        Ok(PerCpuValues::from(values))
+    }
+
+    /// Returns all items in the map, with one value for each possible CPU. Same as
+    /// [`items`](MapLike::items), without the [`MapValue`] wrapper.
+    pub fn get_items(&self) -> XDPResult<Vec<KeyValue<K, PerCpuValues<V>>>> {
+        let items = self.items()?;
+        Ok(items
+            .into_iter()
+            .map(|kv| KeyValue {
+                key: kv.key,
+                value: PerCpuValues::from(kv.value.into_vec()),
+            })
+            .collect())
+    }
+
+    /// Same as [`get`](PerCpuMap::get), but reads the values into `values` (replacing its
+    /// contents), so pollers can reuse the same buffer for every lookup.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let m: rxdp::PerCpuMap<u32, u64> = rxdp::PerCpuMap::new(&obj, "map_name").unwrap();
+    /// let mut values = Vec::new();
+    /// for key in 0..16 {
+    ///     m.lookup_into(&key, &mut values).unwrap();
+    ///     println!("{}: {}", key, values.iter().sum::<u64>());
+    /// }
+    /// ```
+    pub fn lookup_into(&self, key: &K, values: &mut Vec<V>) -> XDPResult<()> {
+        self.lookup_flags_into(key, values, 0)
+    }
+
+    // Same as `lookup_into`, with lookup `flags` passed to the kernel.
+    fn lookup_flags_into(&self, key: &K, values: &mut Vec<V>, flags: u64) -> XDPResult<()> {
+        let fd = self.map_fd;
+        let codec = self.codec;
+        values.clear();
+        let rc = codec.with_entry(|value| {
+            let rc = deadline::call(
+                self.deadline.as_ref(),
+                utils::as_bytes(key),
+                &[],
+                value,
+                move |k, _, v| {
+                    let (k, v) = (k.as_ptr() as *const c_void, v.as_mut_ptr() as *mut c_void);
+                    match flags {
+                        0 => mc::lookup_elem(fd, k, v),
+                        _ => unsafe { bpf::bpf_map_lookup_elem_flags(fd, k, v, flags) },
+                    }
+                },
+            );
+
+            if rc >= 0 {
+                values.extend(codec.decode::<V>(value));
+            }
+            rc
+        });
+
+        mc::check_rc(rc, (), "Error looking up elem")
+    }
+
+    /// Read the values at indexes `start..start + len` of a `PerCPUArray` map, looking up each
+    /// index directly. Fails with `E2BIG` if the range goes past the end of the map.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let m: rxdp::PerCpuMap<u32, u64> = rxdp::PerCpuMap::new(&obj, "array_name").unwrap();
+    /// let totals: Vec<u64> = m.read_range(0, 16).unwrap().iter().map(|v| v.sum()).collect();
+    /// ```
+    pub fn read_range(&self, start: u32, len: u32) -> XDPResult<Vec<PerCpuValues<V>>> {
+        mc::check_range(
+            self.map_type,
+            MapType::PerCPUArray,
+            start,
+            len,
+            self.max_entries,
+        )?;
+
+        (start..start + len)
+            .map(|i| self.get(&utils::from_bytes(&i.to_ne_bytes())))
+            .collect()
+    }
+
+    /// Number of bytes the value of each CPU takes in the buffers exchanged with the kernel:
+    /// the map's value size, rounded up to a multiple of 8.
+    pub fn value_stride(&self) -> usize {
+        self.codec.stride()
+    }
+
+    // Updates `key` with `values`, already encoded for each possible CPU.
+    fn update_aligned(&self, key: &K, values: &[u8], flags: MapFlags) -> XDPResult<()> {
+        let flags = flags.checked_bits()?;
+        mc::check_rc(
+            self.update_raw(key, values, flags),
+            (),
+            "Error updating elem",
+        )
+    }
+
+    fn update_raw(&self, key: &K, values: &[u8], flags: u64) -> i32 {
+        let fd = self.map_fd;
+        deadline::call(
+            self.deadline.as_ref(),
+            utils::as_bytes(key),
+            values,
+            &mut [],
+            move |k, v, _| {
+                let (k, v) = (k.as_ptr() as *const c_void, v.as_ptr() as *const c_void);
+                mc::update_elem(fd, k, v, flags)
+            },
+        )
+    }
+
+    /// Update the value of a single CPU for `key`, leaving the values of the other CPUs as they
+    /// are. If `key` doesn't exist, it is created with zero values for the other CPUs.
+    ///
+    /// Kernels with the `BPF_F_CPU` update flag update the single value atomically. On older
+    /// kernels, this is emulated by looking up the values of all CPUs, replacing the value of
+    /// `cpu` and updating all of them, which is **not atomic**: changes to the values of other
+    /// CPUs (e.g. by the eBPF program) between the lookup and the update are lost.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let m: rxdp::PerCpuMap<u32, u64> = rxdp::PerCpuMap::new(&obj, "map_name").unwrap();
+    /// m.update_cpu(&0, 3, &100).unwrap();
+    /// assert_eq!(m.get(&0).unwrap()[3], 100);
+    /// ```
+    pub fn update_cpu(&self, key: &K, cpu: usize, value: &V) -> XDPResult<()> {
+        if cpu >= *NUM_CPUS {
             set_errno(Errno(22));
-            fail!("Improper map type, use rxdp::Map::new");
+            fail!("Invalid cpu {}, there are {} possible CPUs", cpu, *NUM_CPUS);
         }
 
-        Ok(PerCpuMap {
-            map_fd,
-            _key: PhantomData,
-            _val: PhantomData,
-            map_type,
-            max_entries,
-            value_size: align(size_of::<V>() as u32),
+        if cpu_flag_supported() {
+            let mut encoded = Vec::with_capacity(self.codec.stride());
+            self.codec.encode(*value, &mut encoded);
+            let rc = self.update_raw(key, &encoded, BPF_F_CPU | (cpu as u64) << 32);
+            return mc::check_rc(rc, (), "Error updating elem");
+        }
+
+        let mut values = match self.get(key) {
+            Ok(v) => v.into_vec(),
+            Err(e) if e.code() == 2 => vec![V::default(); *NUM_CPUS],
+            Err(e) => return Err(e),
+        };
+        values[cpu] = *value;
+        self.update_values(key, &values, MapFlags::BpfAny)
+    }
+}
+
+impl<K, V> PerCpuMap<K, V>
+where
+    K: Default + Copy,
+    V: ByteAligned + Add<Output = V>,
+{
+    /// Lookup the value for `key` and sum the per-cpu values by NUMA node (see
+    /// [`cpu_topology`](crate::cpu_topology)). Useful for spotting traffic that is skewed
+    /// towards one node on multi-socket systems.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let m: rxdp::PerCpuMap<u32, u64> = rxdp::PerCpuMap::new(&obj, "map_name").unwrap();
+    /// for (node, packets) in m.lookup_by_node(&0).unwrap() {
+    ///     println!("node {}: {} packets", node, packets);
+    /// }
+    /// ```
+    pub fn lookup_by_node(&self, key: &K) -> XDPResult<BTreeMap<u32, V>> {
+        let values = self.get(key)?;
+        Ok(topology::sum_by_node(
+            values.as_slice(),
+            topology::cpu_nodes(),
+        ))
+    }
+}
+
+impl<K, V> PerCpuMap<K, V>
+where
+    K: Default + Copy,
+    V: ByteAligned + Add<Output = V> + Ord,
+{
+    /// Returns all items in the map, like [`items`](MapLike::items), with the values of all
+    /// CPUs combined into a single value by `aggregation`. The values are combined while the
+    /// batches are read, so the per-cpu values are never collected for the whole map.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let m: rxdp::PerCpuMap<u32, u64> = rxdp::PerCpuMap::new(&obj, "counters").unwrap();
+    /// for kv in m.items_aggregated(rxdp::Aggregation::Sum).unwrap() {
+    ///     println!("{}: {}", kv.key, kv.value);
+    /// }
+    /// ```
+    pub fn items_aggregated(&self, aggregation: Aggregation) -> XDPResult<Vec<KeyValue<K, V>>> {
+        if self.max_entries < 50 || !reads_in_batches(self.map_type) {
+            let mut result = Vec::new();
+            let mut key: K = Default::default();
+            let mut prev: Option<K> = None;
+            loop {
+                let prev_ptr = prev
+                    .as_ref()
+                    .map_or(std::ptr::null(), |k| k as *const K as *const c_void);
+                match self.get_next_key(prev_ptr, &mut key) {
+                    Ok(()) => (),
+                    Err(e) if e.code() == 2 => break,
+                    Err(e) => return Err(e),
+                }
+                match self.get(&key) {
+                    Ok(values) => {
+                        let value = aggregation.fold(values.into_iter());
+                        result.push(KeyValue { key, value });
+                    }
+                    // Deleted since reading the key, e.g. evicted from an LRU map.
+                    Err(e) if e.code() == 2 => (),
+                    Err(e) => return Err(e),
+                }
+                prev = Some(key);
+            }
+            return Ok(result);
+        }
+
+        let codec = self.codec;
+        Self::scrape_with(self.map_fd, codec, Shard::ALL, |n, result, keys, vals| {
+            let values = codec
+                .entries(vals, n as usize)
+                .map(|e| aggregation.fold(codec.decode(e)));
+            for (key, value) in keys.drain(..n as usize).zip(values) {
+                result.push(KeyValue { key, value });
+            }
         })
     }
 }
 
+impl<K, V> PerCpuMap<K, V>
+where
+    K: Default + Copy + Hash + Eq + Send,
+    V: ByteAligned + Send,
+{
+    /// Returns all items in the map, like [`items`](MapLike::items), but splits the map into
+    /// (at most) `shards` ranges that are scraped in parallel, each on its own thread. This
+    /// can greatly reduce the time it takes to read very large maps on many-core machines.
+    ///
+    /// Falls back to [`items`](MapLike::items) if the kernel doesn't support batching, or for
+    /// maps that aren't scraped using batches.
+    pub fn par_items(&self, shards: u32) -> XDPResult<Vec<KeyValue<K, MapValue<V>>>> {
+        if shards <= 1 || self.map_type.is_array() || !is_batching_supported() {
+            return self.items();
+        }
+
+        let (map_fd, codec) = (self.map_fd, self.codec);
+        par_scrape(
+            crate::map_batch::shards(self.map_type, self.max_entries, shards),
+            |shard| Self::scrape(map_fd, codec, shard),
+        )
+    }
+}
+
 impl<K: Default + Copy, V: ByteAligned> MapLike<K, V> for PerCpuMap<K, V> {
     fn update_batching_not_supported(&self) -> bool {
         self.map_type.is_array() || !is_batching_supported()
@@ -91,40 +599,44 @@ impl<K: Default + Copy, V: ByteAligned> MapLike<K, V> for PerCpuMap<K, V> {
         self.max_entries
     }
 
+    fn deadline(&self) -> Option<&Deadline> {
+        self.deadline.as_ref()
+    }
+
     fn update(&self, key: &K, value: &V, flags: MapFlags) -> XDPResult<()> {
-        let mut values: Vec<u8> = Vec::with_capacity(*NUM_CPUS);
-        for _ in 0..*NUM_CPUS {
-            values.extend_from_slice(value.align().as_slice());
+        self.codec.with_entry(|values| {
+            self.codec.fill(*value, values);
+            self.update_aligned(key, values, flags)
+        })
+    }
+
+    fn update_values(&self, key: &K, values: &[V], flags: MapFlags) -> XDPResult<()> {
+        if values.len() != *NUM_CPUS {
+            set_errno(Errno(22));
+            fail!(
+                "Expected {} per-cpu values, got {}",
+                *NUM_CPUS,
+                values.len()
+            );
         }
 
-        mc::update_elem(
-            self.map_fd,
-            key as *const _ as *const c_void,
-            values.as_mut_ptr() as *const c_void,
-            flags as u64,
-        )
+        let mut encoded = Vec::with_capacity(self.codec.entry_size());
+        for v in values {
+            self.codec.encode(*v, &mut encoded);
+        }
+
+        self.update_aligned(key, &encoded, flags)
     }
 
     fn lookup(&self, key: &K) -> XDPResult<MapValue<V>> {
-        let s: usize = *NUM_CPUS * self.value_size;
-        let mut value: Vec<u8> = Vec::with_capacity(s);
-        value.resize_with(s, Default::default);
-
-        let rc = mc::lookup_elem(
-            self.map_fd,
-            key as *const _ as *const c_void,
-            value.as_mut_ptr() as *mut c_void,
-        );
-
-        let mut r = Vec::with_capacity(*NUM_CPUS);
-        if rc >= 0 {
-            let mut iter = value.as_mut_slice().chunks_exact_mut(self.value_size);
-            while let Some(chunk) = iter.next() {
-                r.push(V::from_aligned(chunk));
-            }
-        }
+        self.get(key).map(MapValue::from)
+    }
 
-        return mc::check_rc(rc, MapValue::Multi(r), "Error looking up elem");
+    fn lookup_with_flags(&self, key: &K, flags: MapFlags) -> XDPResult<MapValue<V>> {
+        let flags = flags.checked_bits()?;
+        let mut values = Vec::with_capacity(*NUM_CPUS);
+        self.lookup_flags_into(key, &mut values, flags)?;
+        Ok(MapValue::Multi(values))
     }
 
     fn update_batch_impl(
@@ -134,11 +646,9 @@ impl<K: Default + Copy, V: ByteAligned> MapLike<K, V> for PerCpuMap<K, V> {
         opts: &bpf::bpf_map_batch_opts,
     ) -> (i32, u32) {
         let mut count: u32 = keys.len() as u32;
-        let mut per_cpu_values: Vec<u8> = Vec::with_capacity(*NUM_CPUS * values.len());
+        let mut per_cpu_values = Vec::with_capacity(self.codec.entry_size() * values.len());
         for v in values {
-            for _ in 0..*NUM_CPUS {
-                per_cpu_values.extend_from_slice(v.align().as_slice());
-            }
+            self.codec.encode_all(*v, &mut per_cpu_values);
         }
 
         let rc = mc::update_batch(
@@ -152,39 +662,67 @@ impl<K: Default + Copy, V: ByteAligned> MapLike<K, V> for PerCpuMap<K, V> {
         (rc, count)
     }
 
+    fn lookup_batch_into_impl(
+        &self,
+        keys: &mut [K],
+        vals: &mut [V],
+        next_key: Option<&[u8]>,
+    ) -> XDPResult<(u32, Option<BatchCursor>)> {
+        // The kernel pads each CPU's value to 8 bytes.
+        if self.codec.stride() != size_of::<V>() {
+            set_errno(Errno(22));
+            fail!(
+                "Per-cpu values of {} bytes are padded by the kernel, use lookup_batch",
+                size_of::<V>()
+            );
+        }
+        if vals.len() != keys.len() * *NUM_CPUS {
+            set_errno(Errno(22));
+            fail!(
+                "Expected {} values for {} keys, got {}",
+                keys.len() * *NUM_CPUS,
+                keys.len(),
+                vals.len()
+            );
+        }
+
+        let r = mc::lookup_batch_prealloc(
+            self.map_fd,
+            keys.len() as u32,
+            size_of::<K>(),
+            next_key,
+            keys,
+            vals,
+            false,
+        )?;
+        Ok((r.num_items, r.next_key.map(BatchCursor::batch)))
+    }
+
     fn lookup_batch_impl(
         &self,
         batch_size: u32,
-        next_key: Option<u32>,
+        next_key: Option<&[u8]>,
         delete: bool,
     ) -> XDPResult<BatchResult<K, MapValue<V>>> {
         let mut keys: Vec<K> = Vec::with_capacity(batch_size as usize);
-
-        let vals_size = batch_size as usize * *NUM_CPUS * self.value_size;
-        let mut vals: Vec<u8> = Vec::with_capacity(vals_size);
         keys.resize_with(batch_size as usize, Default::default);
-        vals.resize_with(vals_size, Default::default);
+        let mut vals = self.codec.buffer(batch_size as usize);
 
         let r = mc::lookup_batch_prealloc(
             self.map_fd,
             batch_size,
+            size_of::<K>(),
             next_key,
             &mut keys,
             &mut vals,
             delete,
         )?;
         let mut result = Vec::with_capacity(r.num_items as usize);
-        populate_batch_result(
-            r.num_items,
-            &mut result,
-            &mut keys,
-            &mut vals,
-            self.value_size,
-        );
+        populate_batch_result(&self.codec, r.num_items, &mut result, &mut keys, &vals);
 
         Ok(BatchResult {
             items: result,
-            next_key: r.next_key,
+            next_key: r.next_key.map(BatchCursor::batch),
             num_items: r.num_items,
         })
     }
@@ -213,77 +751,69 @@ impl<K: Default + Copy, V: ByteAligned> MapLike<K, V> for PerCpuMap<K, V> {
     }
 
     fn items(&self) -> XDPResult<Vec<KeyValue<K, MapValue<V>>>> {
-        if self.map_type.is_array() || self.max_entries < 50 || !is_batching_supported() {
+        if self.max_entries < 50 || !reads_in_batches(self.map_type) {
+            if self.map_type == MapType::PerCPUArray {
+                let values = self.read_range(0, self.max_entries)?;
+                return Ok((0..self.max_entries)
+                    .zip(values)
+                    .map(|(i, v)| KeyValue {
+                        key: utils::from_bytes(&i.to_ne_bytes()),
+                        value: MapValue::Multi(v.into_vec()),
+                    })
+                    .collect());
+            }
             return self._items();
         }
-        let mut keys: Vec<K> = Vec::with_capacity(BATCH_SIZE as usize);
-
-        let vals_size = BATCH_SIZE as usize * *NUM_CPUS * self.value_size;
-        let mut vals: Vec<u8> = Vec::with_capacity(vals_size);
-
-        let mut result = Vec::with_capacity(BATCH_SIZE as usize);
-        let mut next_key = None;
-
-        loop {
-            keys.resize_with(BATCH_SIZE as usize, Default::default);
-            vals.resize_with(vals_size, Default::default);
 
-            let r = mc::lookup_batch_prealloc(
-                self.map_fd,
-                BATCH_SIZE,
-                next_key,
-                &mut keys,
-                &mut vals,
-                false,
-            )?;
-            populate_batch_result(
-                r.num_items,
-                &mut result,
-                &mut keys,
-                &mut vals,
-                self.value_size,
-            );
+        Self::scrape(self.map_fd, self.codec, Shard::ALL)
+    }
+}
 
-            if r.next_key.is_none() {
-                break;
-            }
-            next_key = r.next_key;
+impl<K, V> Drop for PerCpuMap<K, V> {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { libc::close(self.map_fd) };
         }
-
-        Ok(result)
     }
 }
 
 fn populate_batch_result<K, V: ByteAligned>(
+    codec: &PerCpuCodec,
     n: u32,
     result: &mut Vec<KeyValue<K, MapValue<V>>>,
     keys: &mut Vec<K>,
-    vals: &mut Vec<u8>,
-    value_size: usize,
+    vals: &[u8],
 ) {
-    vals.truncate(n as usize * *NUM_CPUS * value_size);
-    let mut iter = vals.as_mut_slice().chunks_exact_mut(value_size).rev();
-
-    for k in keys.drain(..n as usize).rev() {
-        let mut r = Vec::with_capacity(*NUM_CPUS);
-        let mut count = 0;
-        while let Some(chunk) = iter.next() {
-            r.push(V::from_aligned(chunk));
-            count += 1;
-            if count == *NUM_CPUS {
-                break;
-            }
-        }
-
+    let values = codec.entries(vals, n as usize);
+    for (key, entry) in keys.drain(..n as usize).zip(values) {
         result.push(KeyValue {
-            key: k,
-            value: MapValue::Multi(r),
+            key,
+            value: MapValue::Multi(codec.decode(entry).collect()),
         })
     }
 }
 
-fn align(v: u32) -> usize {
-    (((v + 7) / 8) * 8) as usize
+/// How the values of all CPUs are combined by [`PerCpuMap::items_aggregated`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregation {
+    fn fold<V, I>(&self, values: I) -> V
+    where
+        V: Default + Add<Output = V> + Ord,
+        I: Iterator<Item = V>,
+    {
+        let f = match self {
+            Aggregation::Sum => |a: V, b: V| a + b,
+            Aggregation::Min => std::cmp::min,
+            Aggregation::Max => std::cmp::max,
+        };
+        values.reduce(f).unwrap_or_default()
+    }
 }
 
 /// Number of possible CPUs (not online CPUs).
@@ -293,14 +823,22 @@ pub fn num_cpus() -> usize {
 
 /// Trait used to convert types to/from 8 byte aligned `Vec<u8>` (required by per-cpu eBPF maps).
 pub trait ByteAligned: Default + Copy {
-    /// Convert a type to a Vec<u8>, padded to the next closest 8 byte alignment:
+    /// Bytes a value takes in a per-cpu map buffer: the size of the type rounded up to a
+    /// multiple of 8, the same rounding the kernel applies to per-cpu values. E.g. 8 bytes for
+    /// `u32`, 16 bytes for `u128` and 24 bytes for a 20 byte struct.
+    const ALIGNED_SIZE: usize = crate::percpu_codec::stride(size_of::<Self>());
+
+    /// Convert a type to a Vec<u8> of [`ALIGNED_SIZE`](ByteAligned::ALIGNED_SIZE) bytes, the
+    /// value in host byte order (as the kernel stores it) followed by zero padding:
     /// ```
     /// use rxdp::ByteAligned;
-    /// assert_eq!(101u32.align(), vec![101, 0, 0, 0, 0, 0, 0, 0]);
+    /// let mut expected = 101u32.to_ne_bytes().to_vec();
+    /// expected.resize(8, 0);
+    /// assert_eq!(101u32.align(), expected);
     /// ```
     fn align(self) -> Vec<u8>;
 
-    /// Convert a 8 byte aligned `Vec<u8>` to a type:
+    /// Convert [`ALIGNED_SIZE`](ByteAligned::ALIGNED_SIZE) bytes to a type:
     /// ```
     /// use rxdp::ByteAligned;
     /// assert_eq!(101u8, u8::from_aligned(&vec![101, 0, 0, 0, 0, 0, 0, 0]))
@@ -308,74 +846,93 @@ pub trait ByteAligned: Default + Copy {
     fn from_aligned(chunk: &[u8]) -> Self;
 }
 
+// Numbers are stored in host byte order, so only the bytes of the type itself are significant,
+// converting to a wider type first would move them on big-endian hosts.
 macro_rules! impl_num_byte_aligned {
-    ($t:ty, $c:ty) => {
-        impl ByteAligned for $t {
+    ($($t:ty),*) => {
+        $(impl ByteAligned for $t {
             fn align(self) -> Vec<u8> {
-                (self as $c).to_le_bytes().to_vec()
+                let mut v = self.to_ne_bytes().to_vec();
+                v.resize(Self::ALIGNED_SIZE, 0);
+                v
             }
 
             fn from_aligned(chunk: &[u8]) -> Self {
-                <$c>::from_le_bytes(chunk.try_into().unwrap()) as $t
+                <$t>::from_ne_bytes(chunk[..size_of::<$t>()].try_into().unwrap())
             }
-        }
+        })*
     };
 }
 
-impl_num_byte_aligned!(u8, u64);
-impl_num_byte_aligned!(u16, u64);
-impl_num_byte_aligned!(u32, u64);
-impl_num_byte_aligned!(u64, u64);
-impl_num_byte_aligned!(u128, u128);
-impl_num_byte_aligned!(usize, u64);
-impl_num_byte_aligned!(i8, i64);
-impl_num_byte_aligned!(i16, i64);
-impl_num_byte_aligned!(i32, i64);
-impl_num_byte_aligned!(i64, i64);
-impl_num_byte_aligned!(i128, i128);
-impl_num_byte_aligned!(isize, i64);
+impl_num_byte_aligned!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_byte_align_numbers() {
-        let expected = vec![100, 0, 0, 0, 0, 0, 0, 0];
-        let expected_big = vec![100, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    fn test_aggregation() {
+        let values = vec![3u64, 1, 5];
+        assert_eq!(Aggregation::Sum.fold(values.iter().copied()), 9);
+        assert_eq!(Aggregation::Min.fold(values.iter().copied()), 1);
+        assert_eq!(Aggregation::Max.fold(values.iter().copied()), 5);
+        assert_eq!(Aggregation::Min.fold(std::iter::empty::<u64>()), 0);
+    }
 
-        assert_eq!(100u8.align(), expected);
-        assert_eq!(100u16.align(), expected);
-        assert_eq!(100u32.align(), expected);
-        assert_eq!(100u64.align(), expected);
-        assert_eq!(100u128.align(), expected_big);
-        assert_eq!(100usize.align(), expected);
+    // The host byte order bytes of a value, zero padded to `n` bytes.
+    fn padded(bytes: &[u8], n: usize) -> Vec<u8> {
+        let mut v = bytes.to_vec();
+        v.resize(n, 0);
+        v
+    }
+
+    #[test]
+    fn test_byte_align_numbers() {
+        assert_eq!(100u8.align(), padded(&100u8.to_ne_bytes(), 8));
+        assert_eq!(100u16.align(), padded(&100u16.to_ne_bytes(), 8));
+        assert_eq!(100u32.align(), padded(&100u32.to_ne_bytes(), 8));
+        assert_eq!(100u64.align(), padded(&100u64.to_ne_bytes(), 8));
+        assert_eq!(100u128.align(), padded(&100u128.to_ne_bytes(), 16));
+        assert_eq!(100usize.align(), padded(&100usize.to_ne_bytes(), 8));
+
+        assert_eq!(100i8.align(), padded(&100i8.to_ne_bytes(), 8));
+        assert_eq!(100i16.align(), padded(&100i16.to_ne_bytes(), 8));
+        assert_eq!((-100i32).align(), padded(&(-100i32).to_ne_bytes(), 8));
+        assert_eq!(100i64.align(), padded(&100i64.to_ne_bytes(), 8));
+        assert_eq!((-100i128).align(), padded(&(-100i128).to_ne_bytes(), 16));
+        assert_eq!(100isize.align(), padded(&100isize.to_ne_bytes(), 8));
+    }
 
-        assert_eq!(100i8.align(), expected);
-        assert_eq!(100i16.align(), expected);
-        assert_eq!(100i32.align(), expected);
-        assert_eq!(100i64.align(), expected);
-        assert_eq!(100i128.align(), expected_big);
-        assert_eq!(100isize.align(), expected);
+    #[test]
+    fn test_aligned_size() {
+        assert_eq!(u8::ALIGNED_SIZE, 8);
+        assert_eq!(u64::ALIGNED_SIZE, 8);
+        assert_eq!(u128::ALIGNED_SIZE, 16);
+        assert_eq!(i128::ALIGNED_SIZE, 16);
+
+        // The top bits of a u128 survive the roundtrip on either byte order.
+        let v = u128::MAX - 1;
+        assert_eq!(v.align().len(), 16);
+        assert_eq!(u128::from_aligned(&v.align()), v);
     }
 
     #[test]
     fn test_byte_from_aligned_numbers() {
-        let chunk = vec![100, 0, 0, 0, 0, 0, 0, 0];
-        let chunk_big = vec![100, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        macro_rules! roundtrip {
+            ($($v:expr),*) => {
+                $(assert_eq!($v, ByteAligned::from_aligned(&padded(&$v.to_ne_bytes(), 8)));)*
+            };
+        }
+        roundtrip!(100u8, 100u16, 100u32, 100u64, 100usize);
+        roundtrip!(-100i8, -100i16, -100i32, -100i64, -100isize);
 
-        assert_eq!(100u8, u8::from_aligned(&chunk));
-        assert_eq!(100u16, u16::from_aligned(&chunk));
-        assert_eq!(100u32, u32::from_aligned(&chunk));
-        assert_eq!(100u64, u64::from_aligned(&chunk));
+        let chunk_big = padded(&100u128.to_ne_bytes(), 16);
         assert_eq!(100u128, u128::from_aligned(&chunk_big));
-        assert_eq!(100usize, usize::from_aligned(&chunk));
-
-        assert_eq!(100u8, u8::from_aligned(&chunk));
-        assert_eq!(100u16, u16::from_aligned(&chunk));
-        assert_eq!(100u32, u32::from_aligned(&chunk));
-        assert_eq!(100u64, u64::from_aligned(&chunk));
         assert_eq!(100i128, i128::from_aligned(&chunk_big));
-        assert_eq!(100usize, usize::from_aligned(&chunk));
+
+        // Only the bytes of the type are read, whatever the padding holds.
+        let mut chunk = padded(&7u32.to_ne_bytes(), 8);
+        chunk[4..].copy_from_slice(&[0xff; 4]);
+        assert_eq!(7u32, u32::from_aligned(&chunk));
     }
 }
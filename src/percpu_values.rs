@@ -0,0 +1,105 @@
+use std::ops::{Add, Index};
+
+use crate::map_common::MapValue;
+
+/// Values of a per-cpu map entry, one for each possible CPU, see
+/// [`PerCpuMap::get`](crate::PerCpuMap::get).
+///
+/// # Example
+/// ```
+/// use rxdp::PerCpuValues;
+///
+/// let v = PerCpuValues::from(vec![1u64, 5, 2]);
+/// assert_eq!(v[1], 5);
+/// assert_eq!(v.sum(), 8);
+/// assert_eq!(v.max(), Some(5));
+/// assert_eq!(v.iter().filter(|c| **c > 1).count(), 2);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PerCpuValues<V>(Vec<V>);
+
+impl<V> PerCpuValues<V> {
+    /// Number of values, i.e. the number of possible CPUs.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The value for `cpu`, `None` if `cpu` is out of range.
+    pub fn get(&self, cpu: usize) -> Option<&V> {
+        self.0.get(cpu)
+    }
+
+    /// Iterate over the values, in CPU order.
+    pub fn iter(&self) -> std::slice::Iter<'_, V> {
+        self.0.iter()
+    }
+
+    pub fn as_slice(&self) -> &[V] {
+        &self.0
+    }
+
+    pub fn into_vec(self) -> Vec<V> {
+        self.0
+    }
+}
+
+impl<V: Copy + Default + Add<Output = V>> PerCpuValues<V> {
+    /// Sum of the values of all CPUs.
+    pub fn sum(&self) -> V {
+        self.0.iter().fold(V::default(), |acc, v| acc + *v)
+    }
+}
+
+impl<V: Copy + Ord> PerCpuValues<V> {
+    /// Largest value of any CPU, `None` if there are no values.
+    pub fn max(&self) -> Option<V> {
+        self.0.iter().max().copied()
+    }
+
+    /// Smallest value of any CPU, `None` if there are no values.
+    pub fn min(&self) -> Option<V> {
+        self.0.iter().min().copied()
+    }
+}
+
+impl<V> Index<usize> for PerCpuValues<V> {
+    type Output = V;
+
+    fn index(&self, cpu: usize) -> &V {
+        &self.0[cpu]
+    }
+}
+
+impl<V> From<Vec<V>> for PerCpuValues<V> {
+    fn from(values: Vec<V>) -> Self {
+        PerCpuValues(values)
+    }
+}
+
+impl<V> From<PerCpuValues<V>> for MapValue<V> {
+    fn from(values: PerCpuValues<V>) -> Self {
+        MapValue::Multi(values.0)
+    }
+}
+
+impl<V> IntoIterator for PerCpuValues<V> {
+    type Item = V;
+    type IntoIter = std::vec::IntoIter<V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a PerCpuValues<V> {
+    type Item = &'a V;
+    type IntoIter = std::slice::Iter<'a, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
@@ -1,78 +1,358 @@
 #![allow(no_mangle_generic_items)]
-use crossbeam_channel::Sender;
+use errno::{set_errno, Errno};
 use libbpf_sys as bpf;
-use std::os::raw::c_void;
+use std::{
+    any::Any,
+    collections::VecDeque,
+    mem::size_of,
+    os::raw::c_void,
+    panic::{self, AssertUnwindSafe},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use crate::perf_map::{EventType, PerfEvent};
+use crate::compat;
+use crate::error::XDPError;
+use crate::perf_map::{ChannelStats, EventSender, EventType, PerfEvent};
+use crate::result::XDPResult;
+
+// Counters shared between a polling thread and its `PollHandle`.
+pub(crate) struct PollStats {
+    sent: AtomicU64,
+    send_failures: AtomicU64,
+    lost: AtomicU64,
+    // Nanoseconds after `start` since which the channel hasn't been empty, 0 if it was empty
+    // on the last send.
+    behind_since: AtomicU64,
+    queue_len: AtomicU64,
+    capacity: Option<usize>,
+    start: Instant,
+}
+
+impl PollStats {
+    fn new(capacity: Option<usize>) -> PollStats {
+        PollStats {
+            sent: AtomicU64::new(0),
+            send_failures: AtomicU64::new(0),
+            lost: AtomicU64::new(0),
+            behind_since: AtomicU64::new(0),
+            queue_len: AtomicU64::new(u64::MAX),
+            capacity,
+            start: Instant::now(),
+        }
+    }
+
+    // Records the queue length seen before sending an event, `None` if the sender can't tell.
+    fn record_queue_len(&self, len: Option<usize>) {
+        let len = match len {
+            Some(l) => l,
+            None => return,
+        };
+        self.queue_len.store(len as u64, Ordering::Relaxed);
+        if len == 0 {
+            self.behind_since.store(0, Ordering::Relaxed);
+        } else if self.behind_since.load(Ordering::Relaxed) == 0 {
+            let now = self.start.elapsed().as_nanos().max(1) as u64;
+            self.behind_since.store(now, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> ChannelStats {
+        let behind_since = self.behind_since.load(Ordering::Relaxed);
+        let lag = match behind_since {
+            0 => Duration::from_secs(0),
+            t => self.start.elapsed().saturating_sub(Duration::from_nanos(t)),
+        };
+        let queue_len = match self.queue_len.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            l => Some(l as usize),
+        };
+
+        ChannelStats {
+            sent: self.sent.load(Ordering::Relaxed),
+            send_failures: self.send_failures.load(Ordering::Relaxed),
+            lost: self.lost.load(Ordering::Relaxed),
+            queue_len,
+            capacity: self.capacity,
+            lag,
+        }
+    }
+}
+
+// Callback for when the consumer falls behind by more than `threshold`.
+pub(crate) struct LagWarning {
+    pub(crate) threshold: Duration,
+    pub(crate) callback: Box<dyn FnMut(&ChannelStats) + Send>,
+    pub(crate) last: Option<Instant>,
+}
 
 pub(crate) struct EventHandler<T> {
-    sender: Sender<PerfEvent<T>>,
+    sender: Box<dyn EventSender<T>>,
     pb: *mut bpf::perf_buffer,
     map_fd: i32,
+    pending: VecDeque<PerfEvent<T>>,
+    // Set once the sender reports that the receiving side has gone away.
+    disconnected: bool,
+    stats: Arc<PollStats>,
+    lag_warning: Option<LagWarning>,
 }
 
-impl<T: Copy> EventHandler<T> {
-    pub(crate) fn new(s: Sender<PerfEvent<T>>, map_fd: i32) -> EventHandler<T> {
+// The perf buffer is only ever accessed by the thread that owns the handler.
+unsafe impl<T: Send> Send for EventHandler<T> {}
+
+impl<T: 'static + Copy> EventHandler<T> {
+    pub(crate) fn new(s: Box<dyn EventSender<T>>, map_fd: i32) -> EventHandler<T> {
+        let stats = Arc::new(PollStats::new(s.capacity()));
         EventHandler {
             sender: s,
             pb: std::ptr::null_mut(),
             map_fd,
+            pending: VecDeque::new(),
+            disconnected: false,
+            stats,
+            lag_warning: None,
         }
     }
 
-    fn init_perf_buffer(&mut self) {
-        let pb_opts = bpf::perf_buffer_opts {
-            sample_cb: Some(EventHandler::<T>::sample_event),
-            lost_cb: Some(EventHandler::<T>::lost_event),
-            ctx: self as *mut _ as *mut c_void,
+    pub(crate) fn stats(&self) -> Arc<PollStats> {
+        self.stats.clone()
+    }
+
+    pub(crate) fn set_lag_warning(&mut self, w: Option<LagWarning>) {
+        self.lag_warning = w;
+    }
+
+    fn check_lag(&mut self) {
+        let w = match self.lag_warning.as_mut() {
+            Some(w) => w,
+            None => return,
         };
+        let stats = self.stats.snapshot();
+        let due = w.last.is_none_or(|t| t.elapsed() >= w.threshold);
+        if stats.lag >= w.threshold && due {
+            w.last = Some(Instant::now());
+            (w.callback)(&stats);
+        }
+    }
 
-        self.pb = unsafe {
-            let pb = bpf::perf_buffer__new(self.map_fd, 8, &pb_opts);
-            let err = libbpf_sys::libbpf_get_error(pb as *const _ as *const std::os::raw::c_void);
-            if err != 0 {
-                // TODO: handle this
-                println!("error creating perf buff: {}", err);
-            }
-            pb
+    fn init_perf_buffer(&mut self) -> XDPResult<()> {
+        let pb = compat::perf_buffer_new(
+            self.map_fd,
+            8,
+            Some(EventHandler::<T>::sample_event),
+            Some(EventHandler::<T>::lost_event),
+            self as *mut _ as *mut c_void,
+        );
+        let pb = match pb {
+            Ok(pb) => pb,
+            Err(rc) => fail_rc!(rc, "Error creating perf buffer"),
         };
+
+        self.pb = pb;
+        Ok(())
     }
 
-    pub(crate) fn poll(&mut self, time_ms: i32) {
-        self.init_perf_buffer();
-        loop {
-            unsafe { bpf::perf_buffer__poll(self.pb, time_ms) };
+    /// Polls until the receiving side goes away or `stop` is set. Errors are sent as
+    /// [`EventType::Error`](EventType::Error) events, and polling stops after `max_errors`
+    /// consecutive errors (never, if `None`).
+    pub(crate) fn poll(&mut self, time_ms: i32, max_errors: Option<u32>, stop: &AtomicBool) {
+        let mut errors = 0;
+        while !self.disconnected && !stop.load(Ordering::Relaxed) {
+            let r = self.poll_n(time_ms, usize::MAX);
+            self.check_lag();
+            match r {
+                Ok(_) => errors = 0,
+                Err(e) => {
+                    errors += 1;
+                    self.send(PerfEvent {
+                        cpu: -1,
+                        event: EventType::Error(e),
+                    });
+                    if max_errors.is_some_and(|m| errors >= m) {
+                        return;
+                    }
+                }
+            }
         }
     }
 
-    fn send_perf_event(&self, cpu: i32, event: EventType<T>) {
-        self.sender.send(PerfEvent { cpu, event }).ok();
+    /// Sends at most `max_events` events to the channel, returning the number sent. Events
+    /// that were read from the perf buffer but not sent are kept, in order, for the next call.
+    /// The perf buffer is only polled (waiting up to `time_ms`) when there is room left after
+    /// sending previously read events, so every call makes progress without blocking on new
+    /// events while a backlog exists.
+    pub(crate) fn poll_n(&mut self, time_ms: i32, max_events: usize) -> XDPResult<usize> {
+        if self.pb.is_null() {
+            self.init_perf_buffer()?;
+        }
+
+        let mut sent = self.flush(max_events);
+        if sent < max_events && !self.disconnected {
+            let rc = unsafe { bpf::perf_buffer__poll(self.pb, time_ms) };
+            // Interrupted by a signal, not an error.
+            if rc < 0 && rc != -libc::EINTR {
+                fail_rc!(rc, "Error polling perf buffer");
+            }
+            sent += self.flush(max_events - sent);
+        }
+
+        Ok(sent)
+    }
+
+    // Stops at the first event the sender rejects, the receiving side is gone then.
+    fn flush(&mut self, max_events: usize) -> usize {
+        let n = max_events.min(self.pending.len());
+        for i in 0..n {
+            if self.disconnected {
+                return i;
+            }
+            let event = self.pending.pop_front().unwrap();
+            self.send(event);
+        }
+        n
+    }
+
+    // Senders are user code. A panicking sender is reported with an error event, and treated
+    // as disconnected if it panics on that too.
+    fn send(&mut self, event: PerfEvent<T>) {
+        let sender = &self.sender;
+        self.stats.record_queue_len(sender.queue_len());
+        if let EventType::Lost(n) = event.event {
+            self.stats.lost.fetch_add(n, Ordering::Relaxed);
+        }
+
+        match panic::catch_unwind(AssertUnwindSafe(|| sender.send_event(event))) {
+            Ok(true) => {
+                self.stats.sent.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            Ok(false) => {}
+            Err(p) => {
+                self.stats.send_failures.fetch_add(1, Ordering::Relaxed);
+                let event = PerfEvent {
+                    cpu: -1,
+                    event: EventType::Error(panic_error("event sender", p)),
+                };
+                if let Ok(true) = panic::catch_unwind(AssertUnwindSafe(|| sender.send_event(event)))
+                {
+                    return;
+                }
+            }
+        }
+        self.stats.send_failures.fetch_add(1, Ordering::Relaxed);
+        self.disconnected = true;
     }
 
-    fn handle_sample_event(&self, cpu: i32, data: *mut c_void, _size: u32) {
-        let r: &mut T = unsafe { &mut *(data as *mut T) };
-        self.send_perf_event(cpu, EventType::Sample(*r));
+    fn queue_perf_event(&mut self, cpu: i32, event: EventType<T>) {
+        self.pending.push_back(PerfEvent { cpu, event });
+    }
+
+    fn handle_sample_event(&mut self, cpu: i32, data: *mut c_void, size: u32) {
+        if (size as usize) < size_of::<T>() {
+            set_errno(Errno(libc::EINVAL));
+            let e = XDPError::new(&format!(
+                "Perf sample of {} bytes is smaller than the event type ({} bytes)",
+                size,
+                size_of::<T>()
+            ));
+            self.queue_perf_event(cpu, EventType::Error(e));
+            return;
+        }
+
+        // Samples in the perf ring are only 4 byte aligned.
+        let r: T = unsafe { std::ptr::read_unaligned(data as *const T) };
+        self.queue_perf_event(cpu, EventType::Sample(r));
     }
 
-    fn handle_lost_event(&self, cpu: i32, cnt: u64) {
-        self.send_perf_event(cpu, EventType::Lost(cnt));
+    fn handle_lost_event(&mut self, cpu: i32, cnt: u64) {
+        self.queue_perf_event(cpu, EventType::Lost(cnt));
     }
 
+    // Panics must not unwind into libbpf, they are queued as error events instead.
     #[no_mangle]
     unsafe extern "C" fn sample_event(ctx: *mut c_void, cpu: i32, data: *mut c_void, size: u32) {
         let handler: &mut EventHandler<T> = &mut *(ctx as *mut EventHandler<T>);
-        handler.handle_sample_event(cpu, data, size);
+        let r = panic::catch_unwind(AssertUnwindSafe(|| {
+            handler.handle_sample_event(cpu, data, size)
+        }));
+        if let Err(p) = r {
+            let e = panic_error("perf sample callback", p);
+            handler.queue_perf_event(cpu, EventType::Error(e));
+        }
     }
 
     #[no_mangle]
     unsafe extern "C" fn lost_event(ctx: *mut c_void, cpu: i32, cnt: u64) {
         let handler: &mut EventHandler<T> = &mut *(ctx as *mut EventHandler<T>);
-        handler.handle_lost_event(cpu, cnt);
+        let r = panic::catch_unwind(AssertUnwindSafe(|| handler.handle_lost_event(cpu, cnt)));
+        if let Err(p) = r {
+            let e = panic_error("perf lost callback", p);
+            handler.queue_perf_event(cpu, EventType::Error(e));
+        }
     }
 }
 
+// Converts a caught panic into an error, keeping the panic message if it has one.
+fn panic_error(what: &str, payload: Box<dyn Any + Send>) -> XDPError {
+    let msg = match payload.downcast_ref::<&str>() {
+        Some(m) => m.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(m) => m.clone(),
+            None => "unknown panic".to_string(),
+        },
+    };
+    set_errno(Errno(libc::ECANCELED));
+    XDPError::new(&format!("Panic in {}: {}", what, msg))
+}
+
 impl<T> Drop for EventHandler<T> {
     fn drop(&mut self) {
-        unsafe { bpf::perf_buffer__free(self.pb) }
+        if !self.pb.is_null() {
+            unsafe { bpf::perf_buffer__free(self.pb) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_poll_stats() {
+        let stats = PollStats::new(Some(8));
+        assert_eq!(stats.snapshot().queue_len, None);
+
+        stats.record_queue_len(Some(3));
+        std::thread::sleep(Duration::from_millis(5));
+        stats.record_queue_len(Some(5));
+        let s = stats.snapshot();
+        assert_eq!(s.queue_len, Some(5));
+        assert_eq!(s.capacity, Some(8));
+        assert!(s.lag >= Duration::from_millis(5));
+
+        // The consumer caught up.
+        stats.record_queue_len(Some(0));
+        assert_eq!(stats.snapshot().lag, Duration::from_secs(0));
+
+        // Senders that can't tell don't change anything.
+        stats.record_queue_len(None);
+        assert_eq!(stats.snapshot().queue_len, Some(0));
+    }
+
+    #[test]
+    fn test_panic_error() {
+        let p = panic::catch_unwind(|| panic!("bad event")).unwrap_err();
+        let e = panic_error("event sender", p);
+        assert_eq!(e.code(), libc::ECANCELED);
+        assert!(e
+            .description()
+            .starts_with("Panic in event sender: bad event"));
+
+        let p = panic::catch_unwind(|| panic!("event {}", 7)).unwrap_err();
+        let e = panic_error("event sender", p);
+        assert!(e
+            .description()
+            .starts_with("Panic in event sender: event 7"));
     }
 }
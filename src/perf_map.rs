@@ -1,15 +1,21 @@
-use crossbeam_channel::{unbounded, Receiver, Sender};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
 use errno::{set_errno, Errno};
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
 use crate::map_common as mc;
-use crate::perf_event_handler::EventHandler;
+use crate::perf_event_handler::{EventHandler, LagWarning, PollStats};
 use crate::{MapType, XDPError, XDPLoadedObject, XDPResult};
 
 /// Used for working with a perf eBPF map.
 pub struct PerfMap<T> {
     map_fd: i32,
     _t: PhantomData<T>,
+    handler: Option<Box<EventHandler<T>>>,
+    receiver: Option<Receiver<PerfEvent<T>>>,
 }
 
 /// The event sent from eBPF.
@@ -23,11 +29,284 @@ pub struct PerfEvent<T> {
 
 /// Event type from eBPF perf event map.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum EventType<T> {
     /// The data as generated by the eBPF code.
     Sample(T),
     /// How many events were lost because they weren't read by user-space fast enough.
     Lost(u64),
+    /// An event couldn't be delivered: a sample was too small for `T`, or user code (e.g. an
+    /// [`EventSender`]) panicked while handling an event. Also sent with a `cpu` of -1 when
+    /// polling fails on a background thread ([`PerfMapBuilder::spawn`]), see
+    /// [`max_poll_errors`](PerfMapBuilder::max_poll_errors) for when polling stops.
+    Error(XDPError),
+}
+
+/// Destination for events read from a perf eBPF map.
+///
+/// Implemented for the `crossbeam_channel` and `std::sync::mpsc` senders. Other channels (e.g.
+/// async runtimes) can be used by implementing this trait on a wrapper type:
+/// ```
+/// use rxdp::{EventSender, PerfEvent};
+/// use std::sync::{Arc, Mutex};
+///
+/// struct Collect(Arc<Mutex<Vec<PerfEvent<u32>>>>);
+///
+/// impl EventSender<u32> for Collect {
+///     fn send_event(&self, event: PerfEvent<u32>) -> bool {
+///         self.0.lock().unwrap().push(event);
+///         true
+///     }
+/// }
+/// ```
+pub trait EventSender<T>: Send + 'static {
+    /// Send an event. Returns `false` if the receiving side has gone away.
+    fn send_event(&self, event: PerfEvent<T>) -> bool;
+
+    /// Number of events sent but not received yet, if the channel can tell. Used for
+    /// [`ChannelStats`].
+    fn queue_len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Maximum number of events the channel holds, `None` if unbounded or unknown.
+    fn capacity(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<T: Send + 'static> EventSender<T> for Sender<PerfEvent<T>> {
+    fn send_event(&self, event: PerfEvent<T>) -> bool {
+        self.send(event).is_ok()
+    }
+
+    fn queue_len(&self) -> Option<usize> {
+        Some(self.len())
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        Sender::capacity(self)
+    }
+}
+
+impl<T: Send + 'static> EventSender<T> for std::sync::mpsc::Sender<PerfEvent<T>> {
+    fn send_event(&self, event: PerfEvent<T>) -> bool {
+        self.send(event).is_ok()
+    }
+}
+
+impl<T: Send + 'static> EventSender<T> for std::sync::mpsc::SyncSender<PerfEvent<T>> {
+    fn send_event(&self, event: PerfEvent<T>) -> bool {
+        self.send(event).is_ok()
+    }
+}
+
+/// Builder for consuming events from a perf eBPF map, either on a background thread
+/// ([`spawn`](PerfMapBuilder::spawn)) or on the calling thread ([`manual`](PerfMapBuilder::manual)).
+///
+/// Events are sent on an unbounded channel unless configured otherwise:
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let handle = rxdp::PerfMapBuilder::<u32>::new(&obj, "map_name")
+///     .unwrap()
+///     .bounded(1024)
+///     .spawn(10000);
+///
+/// let r = handle.receiver().unwrap();
+/// loop {
+///     println!("event: {:?}", r.recv().unwrap());
+/// }
+/// ```
+pub struct PerfMapBuilder<T> {
+    map: PerfMap<T>,
+    sender: Option<Box<dyn EventSender<T>>>,
+    max_poll_errors: Option<u32>,
+    lag_warning: Option<LagWarning>,
+}
+
+const DEFAULT_MAX_POLL_ERRORS: u32 = 10;
+
+impl<T: 'static + Copy + Send> PerfMapBuilder<T> {
+    /// Get access to the eBPF map `map_name`. Fails in the same cases as
+    /// [`PerfMap::new`](PerfMap::new).
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<PerfMapBuilder<T>> {
+        Ok(PerfMapBuilder {
+            map: PerfMap::new(xdp, map_name)?,
+            sender: None,
+            max_poll_errors: Some(DEFAULT_MAX_POLL_ERRORS),
+            lag_warning: None,
+        })
+    }
+
+    /// Send events on an unbounded channel (the default).
+    pub fn unbounded(self) -> Self {
+        let (s, r) = unbounded();
+        self.with_channel(s, r)
+    }
+
+    /// Send events on a channel holding at most `cap` events. Polling blocks while the channel
+    /// is full, and events the kernel can't deliver in the meantime are reported as
+    /// [`EventType::Lost`](EventType::Lost).
+    pub fn bounded(self, cap: usize) -> Self {
+        let (s, r) = bounded(cap);
+        self.with_channel(s, r)
+    }
+
+    /// Send events to an externally owned `sender`. No receiver is available from the
+    /// resulting handle.
+    pub fn sender<S: EventSender<T>>(mut self, sender: S) -> Self {
+        self.map.receiver = None;
+        self.sender = Some(Box::new(sender));
+        self
+    }
+
+    /// Stop polling on the background thread after `max` consecutive poll errors (10 by
+    /// default), each of which is sent as an [`EventType::Error`](EventType::Error) event.
+    /// `None` keeps polling regardless of errors. Polling also stops once the receiving side
+    /// of the channel goes away.
+    pub fn max_poll_errors(mut self, max: Option<u32>) -> Self {
+        self.max_poll_errors = max;
+        self
+    }
+
+    /// Call `on_lag` from the polling thread when the consumer has been behind (the channel
+    /// hasn't been empty) for at least `threshold`, at most once per `threshold`. Requires a
+    /// channel that reports its length, i.e. not an external sender unless it implements
+    /// [`EventSender::queue_len`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// use std::time::Duration;
+    ///
+    /// let handle = rxdp::PerfMapBuilder::<u32>::new(&obj, "map_name")
+    ///     .unwrap()
+    ///     .bounded(1024)
+    ///     .lag_warning(Duration::from_secs(1), |stats| {
+    ///         eprintln!("WARNING: perf event consumer is lagging: {:?}", stats);
+    ///     })
+    ///     .spawn(100);
+    /// ```
+    pub fn lag_warning<F>(mut self, threshold: Duration, on_lag: F) -> Self
+    where
+        F: FnMut(&ChannelStats) + Send + 'static,
+    {
+        self.lag_warning = Some(LagWarning {
+            threshold,
+            callback: Box::new(on_lag),
+            last: None,
+        });
+        self
+    }
+
+    fn with_channel(mut self, s: Sender<PerfEvent<T>>, r: Receiver<PerfEvent<T>>) -> Self {
+        self.map.receiver = Some(r);
+        self.sender = Some(Box::new(s));
+        self
+    }
+
+    fn into_parts(self) -> (PerfMap<T>, EventHandler<T>) {
+        let b = match self.sender {
+            Some(_) => self,
+            None => self.unbounded(),
+        };
+        let mut handler = EventHandler::new(b.sender.unwrap(), b.map.map_fd);
+        handler.set_lag_warning(b.lag_warning);
+        (b.map, handler)
+    }
+
+    /// Start polling the map on a background thread, waiting up to `time_ms` milliseconds for
+    /// an event on each poll. Polling stops once the receiving side of the channel goes away,
+    /// or with [`PollHandle::stop`].
+    pub fn spawn(self, time_ms: i32) -> PollHandle<T> {
+        let max_errors = self.max_poll_errors;
+        let (map, mut handler) = self.into_parts();
+        let stats = handler.stats();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_thread = stop.clone();
+        let thread = std::thread::spawn(move || {
+            handler.poll(time_ms, max_errors, &stop_thread);
+        });
+
+        PollHandle {
+            receiver: map.receiver,
+            stats,
+            stop,
+            thread,
+        }
+    }
+
+    /// Return a [`PerfMap`](PerfMap) that is polled on the calling thread via
+    /// [`poll_n`](PerfMap::poll_n).
+    pub fn manual(self) -> PerfMap<T> {
+        let (mut map, handler) = self.into_parts();
+        map.handler = Some(Box::new(handler));
+        map
+    }
+}
+
+/// Metrics of the channel a background poller sends events on, see [`PollHandle::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChannelStats {
+    /// Events sent to the channel.
+    pub sent: u64,
+    /// Events that couldn't be sent, because the receiving side went away or the sender
+    /// panicked.
+    pub send_failures: u64,
+    /// Events the kernel dropped, the sum of all [`EventType::Lost`](EventType::Lost) counts.
+    pub lost: u64,
+    /// Events waiting in the channel, as of the last send. `None` if the channel doesn't
+    /// report its length.
+    pub queue_len: Option<usize>,
+    /// Capacity of a bounded channel.
+    pub capacity: Option<usize>,
+    /// Estimated consumer lag: how long the channel has been non-empty, as of the last send.
+    /// Zero if the consumer has kept up (or the channel doesn't report its length).
+    pub lag: Duration,
+}
+
+/// Handle to a perf map being polled on a background thread.
+pub struct PollHandle<T> {
+    receiver: Option<Receiver<PerfEvent<T>>>,
+    stats: Arc<PollStats>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl<T> PollHandle<T> {
+    /// The receiving side of the channel events are sent on. `None` if events are sent to an
+    /// external sender.
+    pub fn receiver(&self) -> Option<&Receiver<PerfEvent<T>>> {
+        self.receiver.as_ref()
+    }
+
+    /// Metrics of the event channel, to detect a slow consumer before events are lost.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let handle = rxdp::PerfMapBuilder::<u32>::new(&obj, "map_name")
+    ///     .unwrap()
+    ///     .bounded(1024)
+    ///     .spawn(100);
+    ///
+    /// let stats = handle.stats();
+    /// println!("{}/{:?} queued, lagging {:?}", stats.queue_len.unwrap_or(0), stats.capacity, stats.lag);
+    /// ```
+    pub fn stats(&self) -> ChannelStats {
+        self.stats.snapshot()
+    }
+
+    /// Stop polling, waiting for the background thread to finish its current poll (up to the
+    /// `time_ms` passed to [`spawn`](PerfMapBuilder::spawn)). Events read by that poll are
+    /// still sent.
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
 }
 
 impl<T: 'static + Copy + Send> PerfMap<T> {
@@ -36,31 +315,78 @@ impl<T: 'static + Copy + Send> PerfMap<T> {
     /// # Errors
     ///
     /// Returns an error in the following cases:
-    /// * The requested key size doesn't match the key size defined in the ELF file.
     /// * The map_type is not `MapType::PerfEventArray`.
     pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<PerfMap<T>> {
-        let (map_fd, _vsize, mtype, _max_entries) = mc::validate_map::<i32>(xdp, map_name)?;
-        let map_type: MapType = mtype.into();
-        if map_type != MapType::PerfEventArray {
-            set_errno(Errno(22));
-            fail!("Improper map type, must be MapType::PerfEventArray");
+        let def = mc::validate_map::<i32>(xdp, map_name)?;
+        if def.map_type != MapType::PerfEventArray {
+            return mc::improper_type(map_name, def.map_type);
         }
         Ok(PerfMap {
-            map_fd,
+            map_fd: def.fd,
             _t: PhantomData,
+            handler: None,
+            receiver: None,
         })
     }
 
     /// Start polling the underlying eBPF map for events, waiting up to `time_ms` milliseconds
     /// for an event. Returns the receiver side of an unbounded channel, which will receive all
     /// events.
+    #[deprecated(note = "use PerfMapBuilder::spawn")]
     pub fn start_polling(&mut self, time_ms: i32) -> Receiver<PerfEvent<T>> {
         let (s, r): (Sender<PerfEvent<T>>, Receiver<PerfEvent<T>>) = unbounded();
         let fd = self.map_fd;
         std::thread::spawn(move || {
-            let mut e = EventHandler::new(s, fd);
-            e.poll(time_ms);
+            let mut e = EventHandler::new(Box::new(s), fd);
+            e.poll(
+                time_ms,
+                Some(DEFAULT_MAX_POLL_ERRORS),
+                &AtomicBool::new(false),
+            );
         });
         r
     }
+
+    /// The receiving side of the channel events are sent on, if this map was created by
+    /// [`PerfMapBuilder::manual`](PerfMapBuilder::manual) with an rxdp-owned channel.
+    pub fn receiver(&self) -> Option<&Receiver<PerfEvent<T>>> {
+        self.receiver.as_ref()
+    }
+
+    /// Poll the underlying eBPF map on the calling thread, sending at most `max_events` events
+    /// to the configured sender. Returns the number of events sent.
+    ///
+    /// Events read from the kernel beyond `max_events` are kept and sent, in order, by
+    /// subsequent calls. The kernel is only polled (waiting up to `time_ms` milliseconds) when
+    /// fewer than `max_events` events are already buffered, so a call never blocks while there
+    /// are events it could send. This makes it suitable for embedding in a cooperative loop:
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let mut perfmap = rxdp::PerfMapBuilder::<u32>::new(&obj, "map_name")
+    ///     .unwrap()
+    ///     .manual();
+    ///
+    /// loop {
+    ///     let n = perfmap.poll_n(10, 64).unwrap();
+    ///     for event in perfmap.receiver().unwrap().try_iter().take(n) {
+    ///         println!("event: {:?}", event);
+    ///     }
+    ///     // do other work...
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the map wasn't created via
+    /// [`PerfMapBuilder::manual`](PerfMapBuilder::manual), or if polling the perf buffer fails.
+    pub fn poll_n(&mut self, time_ms: i32, max_events: usize) -> XDPResult<usize> {
+        match self.handler.as_mut() {
+            Some(h) => h.poll_n(time_ms, max_events),
+            None => {
+                set_errno(Errno(22));
+                fail!("No sender set, use PerfMapBuilder::manual before polling");
+            }
+        }
+    }
 }
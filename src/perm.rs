@@ -0,0 +1,167 @@
+//! Diagnostics for `EPERM`/`EACCES` errors from the bpf syscall, which can come from missing
+//! capabilities, the `kernel.unprivileged_bpf_disabled` sysctl or an LSM policy.
+use errno::{set_errno, Errno};
+
+use crate::error::get_errno;
+
+const EPERM: i32 = 1;
+const EACCES: i32 = 13;
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_BPF: u32 = 39;
+
+/// Capability a bpf operation needs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Requires {
+    /// CAP_BPF, or CAP_SYS_ADMIN on kernels before 5.8.
+    Bpf,
+    /// CAP_SYS_ADMIN, e.g. to open programs and maps by id.
+    SysAdmin,
+}
+
+// What the permission checks of the host look like.
+#[derive(Debug, Default)]
+struct Host {
+    // Effective capabilities, `None` if unknown.
+    cap_eff: Option<u64>,
+    unprivileged_bpf_disabled: Option<u32>,
+    lsms: Vec<String>,
+    lockdown: Option<String>,
+}
+
+/// If errno is a permission error, the likely reason for it, to append to the error message.
+/// Empty otherwise.
+pub(crate) fn hint(requires: Requires) -> String {
+    let e = get_errno();
+    let hint = match e {
+        EPERM | EACCES => format!(" ({})", diagnose(requires, &Host::current())),
+        _ => String::new(),
+    };
+    // Reading the host state may change errno.
+    set_errno(Errno(e));
+    hint
+}
+
+fn diagnose(requires: Requires, host: &Host) -> String {
+    let has = |cap: u32| host.cap_eff.map(|c| c & (1 << cap) != 0);
+    let missing = match requires {
+        Requires::SysAdmin => match has(CAP_SYS_ADMIN) {
+            Some(false) => Some("CAP_SYS_ADMIN"),
+            _ => None,
+        },
+        Requires::Bpf => match (has(CAP_BPF), has(CAP_SYS_ADMIN)) {
+            (Some(false), Some(false)) => Some("CAP_BPF"),
+            _ => None,
+        },
+    };
+
+    if let Some(cap) = missing {
+        let mut msg = format!("process is missing {}", cap);
+        match host.unprivileged_bpf_disabled {
+            Some(v) if v != 0 && requires == Requires::Bpf => msg.push_str(&format!(
+                ", and kernel.unprivileged_bpf_disabled={} blocks unprivileged bpf()",
+                v
+            )),
+            _ => (),
+        }
+        return msg;
+    }
+
+    let mut msg = match host.cap_eff {
+        Some(_) => "process has the required capabilities".to_string(),
+        None => "unable to read the process capabilities".to_string(),
+    };
+    let lsms: Vec<&str> = host
+        .lsms
+        .iter()
+        .map(|l| l.as_str())
+        .filter(|l| *l != "capability")
+        .collect();
+    if !lsms.is_empty() {
+        msg.push_str(&format!(
+            ", likely denied by an LSM (active: {}), check the audit log",
+            lsms.join(",")
+        ));
+    }
+    if let Some(mode) = host.lockdown.as_ref().filter(|m| *m != "none") {
+        msg.push_str(&format!(", kernel lockdown is {}", mode));
+    }
+
+    msg
+}
+
+impl Host {
+    fn current() -> Host {
+        let read = |path: &str| std::fs::read_to_string(path).ok();
+        Host {
+            cap_eff: read("/proc/self/status").and_then(|s| parse_cap_eff(&s)),
+            unprivileged_bpf_disabled: read("/proc/sys/kernel/unprivileged_bpf_disabled")
+                .and_then(|s| s.trim().parse().ok()),
+            lsms: read("/sys/kernel/security/lsm")
+                .map(|s| s.trim().split(',').map(String::from).collect())
+                .unwrap_or_default(),
+            lockdown: read("/sys/kernel/security/lockdown").and_then(|s| parse_lockdown(&s)),
+        }
+    }
+}
+
+fn parse_cap_eff(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("CapEff:"))?;
+    u64::from_str_radix(line["CapEff:".len()..].trim(), 16).ok()
+}
+
+// The active mode is in brackets, e.g. "none [integrity] confidentiality".
+fn parse_lockdown(s: &str) -> Option<String> {
+    let start = s.find('[')? + 1;
+    let end = start + s[start..].find(']')?;
+    Some(s[start..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let status = "Name:\ttest\nCapPrm:\t0000000000000000\nCapEff:\t000001ffffffffff\n";
+        assert_eq!(parse_cap_eff(status), Some(0x1ffffffffff));
+        assert_eq!(parse_cap_eff("Name:\ttest\n"), None);
+        assert_eq!(
+            parse_lockdown("none [integrity] confidentiality\n"),
+            Some("integrity".to_string())
+        );
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let mut host = Host {
+            cap_eff: Some(0),
+            unprivileged_bpf_disabled: Some(2),
+            lsms: vec!["capability".to_string(), "selinux".to_string()],
+            lockdown: Some("none".to_string()),
+        };
+        assert_eq!(
+            diagnose(Requires::Bpf, &host),
+            "process is missing CAP_BPF, and kernel.unprivileged_bpf_disabled=2 blocks \
+             unprivileged bpf()"
+        );
+        assert_eq!(
+            diagnose(Requires::SysAdmin, &host),
+            "process is missing CAP_SYS_ADMIN"
+        );
+
+        // CAP_SYS_ADMIN covers CAP_BPF.
+        host.cap_eff = Some(1 << CAP_SYS_ADMIN);
+        host.lockdown = Some("integrity".to_string());
+        assert_eq!(
+            diagnose(Requires::Bpf, &host),
+            "process has the required capabilities, likely denied by an LSM (active: selinux), \
+             check the audit log, kernel lockdown is integrity"
+        );
+
+        host.cap_eff = Some(1 << CAP_BPF);
+        assert_eq!(
+            diagnose(Requires::SysAdmin, &host),
+            "process is missing CAP_SYS_ADMIN"
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Pinning maps when their handle is dropped or the process exits, see
+//! [`Map::persist_on_exit`](crate::Map::persist_on_exit).
+use lazy_static::lazy_static;
+use libbpf_sys as bpf;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, Once};
+
+use crate::error::XDPError;
+use crate::object;
+use crate::result::XDPResult;
+use crate::utils;
+
+lazy_static! {
+    // Maps still to be pinned, by registration id: (duplicated map fd, pin path).
+    static ref PENDING: Mutex<HashMap<u64, (i32, String)>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static EXIT_HOOK: Once = Once::new();
+
+/// Pins the map at `path` when dropped, unless the process exits first, in which case the
+/// exit hook pins it.
+pub(crate) struct Persist {
+    id: u64,
+}
+
+impl Persist {
+    pub(crate) fn new(map_fd: i32, path: &str) -> XDPResult<Persist> {
+        object::check_pin_dir(Path::new(path))?;
+
+        // The map fd might be closed (with the object) before the process exits.
+        let fd = unsafe { libc::fcntl(map_fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            fail!("Error duplicating map fd");
+        }
+
+        EXIT_HOOK.call_once(|| unsafe {
+            libc::atexit(pin_pending);
+        });
+
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        PENDING.lock().unwrap().insert(id, (fd, path.to_string()));
+
+        Ok(Persist { id })
+    }
+
+    // Drops the registration without pinning.
+    pub(crate) fn cancel(self) {
+        if let Some((fd, _)) = take(self.id) {
+            unsafe { libc::close(fd) };
+        }
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for Persist {
+    fn drop(&mut self) {
+        if let Some((fd, path)) = take(self.id) {
+            pin(fd, &path);
+        }
+    }
+}
+
+fn take(id: u64) -> Option<(i32, String)> {
+    PENDING.lock().ok()?.remove(&id)
+}
+
+extern "C" fn pin_pending() {
+    // Don't block the exit if another thread holds the lock.
+    let pending: Vec<(i32, String)> = match PENDING.try_lock() {
+        Ok(mut p) => p.drain().map(|(_, v)| v).collect(),
+        Err(_) => return,
+    };
+    for (fd, path) in pending {
+        pin(fd, &path);
+    }
+}
+
+// Pins the map and closes `fd`. Errors are ignored, there is no one left to report them to. A
+// map that is already pinned at `path` (e.g. it was loaded from there) stays pinned.
+fn pin(fd: i32, path: &str) {
+    if let Some(dir) = Path::new(path).parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Ok(p) = utils::str_to_cstring(path) {
+        unsafe { bpf::bpf_obj_pin(fd, p.as_ptr()) };
+    }
+    unsafe { libc::close(fd) };
+}
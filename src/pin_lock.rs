@@ -0,0 +1,140 @@
+use errno::{set_errno, Errno};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use crate::error::{get_errno, XDPError};
+use crate::result::XDPResult;
+
+/// An exclusive, advisory lock on a pin directory, so that multiple agents on a host (e.g. the
+/// old and the new version during an upgrade) don't update the same pinned maps or attachments
+/// at the same time.
+///
+/// The lock is a `flock(2)` on the directory itself, since BPF filesystems can't hold regular
+/// lock files. It is only honoured by processes that take it as well, and is released when the
+/// `PinLock` is dropped or the process exits, including when it crashes.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// let _lock = rxdp::PinLock::acquire("/sys/fs/bpf/myapp").unwrap();
+/// let obj = rxdp::XDPObjectBuilder::new("/path/to/elf/file")
+///     .pin_root_path("/sys/fs/bpf/myapp")
+///     .build()
+///     .unwrap()
+///     .load()
+///     .unwrap();
+/// // Update pinned maps and attach programs, then drop the lock.
+/// ```
+pub struct PinLock {
+    fd: i32,
+    path: PathBuf,
+}
+
+impl PinLock {
+    /// Lock the pin directory `base_path`, waiting for other holders to release it. The
+    /// directory is created if it doesn't exist.
+    pub fn acquire<P: AsRef<Path>>(base_path: P) -> XDPResult<PinLock> {
+        let lock = PinLock::open(base_path.as_ref())?;
+        lock.flock(libc::LOCK_EX)?;
+        Ok(lock)
+    }
+
+    /// Same as [`acquire`](PinLock::acquire), without waiting. Returns `None` if another
+    /// process holds the lock.
+    pub fn try_acquire<P: AsRef<Path>>(base_path: P) -> XDPResult<Option<PinLock>> {
+        let lock = PinLock::open(base_path.as_ref())?;
+        match lock.flock(libc::LOCK_EX | libc::LOCK_NB) {
+            Ok(()) => Ok(Some(lock)),
+            Err(e) if e.code() == libc::EWOULDBLOCK => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The locked directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn open(path: &Path) -> XDPResult<PinLock> {
+        if let Err(e) = std::fs::create_dir_all(path) {
+            set_errno(Errno(e.raw_os_error().unwrap_or(5)));
+            fail!("Error creating pin directory {}: {}", path.display(), e);
+        }
+
+        // Paths aren't necessarily UTF-8, so use the raw bytes rather than a lossy string.
+        let s = match CString::new(path.as_os_str().as_bytes()) {
+            Ok(s) => s,
+            Err(e) => fail!("Error creating C string: {:?}", e),
+        };
+        let fd = unsafe {
+            libc::open(
+                s.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            fail!("Error opening pin directory {}", path.display());
+        }
+
+        Ok(PinLock {
+            fd,
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn flock(&self, op: i32) -> XDPResult<()> {
+        loop {
+            if unsafe { libc::flock(self.fd, op) } == 0 {
+                return Ok(());
+            }
+            if get_errno() != libc::EINTR {
+                fail!("Error locking pin directory {}", self.path.display());
+            }
+        }
+    }
+}
+
+impl Drop for PinLock {
+    fn drop(&mut self) {
+        // Closing the last fd of the open file releases the lock.
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_lock() {
+        let dir = std::env::temp_dir().join(format!("rxdp-pin-lock-{}", std::process::id()));
+
+        let lock = PinLock::acquire(&dir).unwrap();
+        assert_eq!(lock.path(), dir.as_path());
+        // Locks belong to the open file, so a second one conflicts even in the same process.
+        assert!(PinLock::try_acquire(&dir).unwrap().is_none());
+
+        drop(lock);
+        let lock = PinLock::try_acquire(&dir).unwrap();
+        assert!(lock.is_some());
+
+        drop(lock);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pin_lock_non_utf8() {
+        use std::ffi::OsStr;
+
+        let mut name = b"rxdp-pin-lock-\xff-".to_vec();
+        name.extend_from_slice(std::process::id().to_string().as_bytes());
+        let dir = std::env::temp_dir().join(OsStr::from_bytes(&name));
+
+        let lock = PinLock::acquire(&dir).unwrap();
+        assert!(PinLock::try_acquire(&dir).unwrap().is_none());
+
+        drop(lock);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+//! Probes for kernel features that change how objects are loaded.
+use lazy_static::lazy_static;
+use libbpf_sys as bpf;
+
+const BPF_PROG_LOAD: libc::c_long = 5;
+
+lazy_static! {
+    static ref XDP_ATTACH_TYPE_SUPPORTED: bool = check_xdp_attach_type_supported();
+}
+
+// Instruction layout of `struct bpf_insn`, with the src/dst registers packed into `regs`.
+#[repr(C)]
+struct Insn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+// Prefix of `union bpf_attr` used by BPF_PROG_LOAD, up to `expected_attach_type`.
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+    prog_ifindex: u32,
+    expected_attach_type: u32,
+}
+
+/// True if the kernel accepts XDP programs with `expected_attach_type` set to `BPF_XDP`.
+/// Older kernels reject any non-zero attach type for XDP programs.
+pub(crate) fn xdp_attach_type_supported() -> bool {
+    *XDP_ATTACH_TYPE_SUPPORTED
+}
+
+fn check_xdp_attach_type_supported() -> bool {
+    // r0 = XDP_PASS; exit
+    let insns = [
+        Insn {
+            code: 0xb7,
+            regs: 0,
+            off: 0,
+            imm: 2,
+        },
+        Insn {
+            code: 0x95,
+            regs: 0,
+            off: 0,
+            imm: 0,
+        },
+    ];
+    let license = b"GPL\0";
+
+    let attr = ProgLoadAttr {
+        prog_type: bpf::BPF_PROG_TYPE_XDP,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        expected_attach_type: bpf::BPF_XDP,
+        ..Default::default()
+    };
+
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_PROG_LOAD,
+            &attr as *const ProgLoadAttr,
+            std::mem::size_of::<ProgLoadAttr>() as u32,
+        )
+    };
+    if fd < 0 {
+        return false;
+    }
+
+    unsafe { libc::close(fd as i32) };
+    true
+}
@@ -0,0 +1,119 @@
+use std::os::raw::c_void;
+
+use crate::error::get_errno;
+use crate::map_common as mc;
+use crate::object::XDPLoadedObject;
+use crate::result::XDPResult;
+use crate::{MapFlags, MapKind, MapType, Program};
+
+/// Used for working with `BPF_MAP_TYPE_PROG_ARRAY` maps, the jump tables of `bpf_tail_call`.
+///
+/// Programs are inserted by index. The kernel keeps a reference to each inserted program, so
+/// it stays in the table after the [`Program`] (or the object it came from) is dropped, until
+/// it is [`remove`](ProgArrayMap::remove)d or the map itself is released.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let progs = rxdp::ProgArrayMap::new(&obj, "jump_table").unwrap();
+/// progs.set_by_name(&obj, 0, "parse_ipv4").unwrap();
+/// progs.set_by_name(&obj, 1, "parse_ipv6").unwrap();
+///
+/// assert!(progs.prog_id(0).unwrap().is_some());
+/// progs.remove(1).unwrap();
+/// ```
+pub struct ProgArrayMap {
+    map_fd: i32,
+    max_entries: u32,
+    // True if the handle created `map_fd` itself, rather than borrowing it from an object.
+    owned: bool,
+}
+
+impl ProgArrayMap {
+    /// Create a new program array with `max_entries` indices.
+    pub fn create(max_entries: u32) -> XDPResult<ProgArrayMap> {
+        let map_fd = mc::create_map(MapType::ProgArray, 4, 4, max_entries, 0);
+        mc::check_rc(map_fd, (), "Error creating new map")?;
+
+        Ok(ProgArrayMap {
+            map_fd,
+            max_entries,
+            owned: true,
+        })
+    }
+
+    /// Get access to the program array `map_name`.
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<ProgArrayMap> {
+        let def = mc::validate_map::<u32>(xdp, map_name)?;
+        if def.map_type.kind() != MapKind::ProgArrayMap {
+            return mc::improper_type(map_name, def.map_type);
+        }
+
+        Ok(ProgArrayMap {
+            map_fd: def.fd,
+            max_entries: def.max_entries,
+            owned: false,
+        })
+    }
+
+    /// Insert `prog` at `index`, replacing the program that was there. The kernel rejects
+    /// programs of a different type than the programs already using the table.
+    pub fn set(&self, index: u32, prog: &Program) -> XDPResult<()> {
+        let fd = prog.fd();
+        let rc = mc::update_elem(
+            self.map_fd,
+            &index as *const _ as *const c_void,
+            &fd as *const _ as *const c_void,
+            MapFlags::BpfAny.bits(),
+        );
+
+        mc::check_rc(rc, (), "Error setting program in prog array")
+    }
+
+    /// Insert the program `prog_name` of `xdp` at `index`.
+    pub fn set_by_name(&self, xdp: &XDPLoadedObject, index: u32, prog_name: &str) -> XDPResult<()> {
+        self.set(index, xdp.get_program(prog_name)?)
+    }
+
+    /// The id of the program at `index`, `None` if the index is empty.
+    pub fn prog_id(&self, index: u32) -> XDPResult<Option<u32>> {
+        let mut id = 0u32;
+        let rc = mc::lookup_elem(
+            self.map_fd,
+            &index as *const _ as *const c_void,
+            &mut id as *mut _ as *mut c_void,
+        );
+        if rc == -2 || (rc < 0 && get_errno() == 2) {
+            return Ok(None);
+        }
+
+        mc::check_rc(rc, Some(id), "Error looking up program in prog array")
+    }
+
+    /// Remove the program at `index`, so tail calls to it fall through.
+    pub fn remove(&self, index: u32) -> XDPResult<()> {
+        let rc = unsafe {
+            libbpf_sys::bpf_map_delete_elem(self.map_fd, &index as *const _ as *const c_void)
+        };
+        mc::check_rc(rc, (), "Error removing program from prog array")
+    }
+
+    /// File descriptor for this map.
+    pub fn map_fd(&self) -> i32 {
+        self.map_fd
+    }
+
+    /// The number of indices in the table.
+    pub fn max_entries(&self) -> u32 {
+        self.max_entries
+    }
+}
+
+impl Drop for ProgArrayMap {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { libc::close(self.map_fd) };
+        }
+    }
+}
@@ -1,17 +1,62 @@
+use crate::compat;
+use crate::config;
+use crate::deadline::ETIMEDOUT;
 use crate::error::XDPError;
+use crate::raw::{self, Attr, Cmd};
 use crate::result::XDPResult;
+use crate::sys;
 use crate::utils;
 
 use errno::{set_errno, Errno};
-use std::{cell::RefCell, os::raw::c_int};
+use std::{
+    cell::RefCell,
+    fmt,
+    os::raw::c_int,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+const ENODEV: i32 = 19;
+const EBUSY: i32 = 16;
+// How often `attach_when_ready` checks for the interface.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(20);
 
 /// Convenience wrapper around a BPF program
 #[allow(dead_code)]
 pub struct Program {
     prog: *const libbpf_sys::bpf_program,
     fd: c_int,
-    flags: RefCell<u32>,
-    link: RefCell<*mut libbpf_sys::bpf_link>,
+    // Indexes of the interfaces the program was attached to.
+    attachments: RefCell<Vec<i32>>,
+    build_id: Option<String>,
+}
+
+/// Kernel information about a loaded program, to correlate it with the build it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgInfo {
+    /// Kernel id of the program.
+    pub id: u32,
+    /// Name of the program. The kernel only keeps the first 15 characters.
+    pub name: String,
+    /// Tag the kernel computes from the program's instructions, as shown by `bpftool prog`.
+    /// The same program loaded on different hosts has the same tag.
+    pub tag: String,
+    /// When the program was loaded, in nanoseconds since boot.
+    pub load_time: u64,
+    /// GNU build-id of the ELF file the program was loaded from, if it has one.
+    pub build_id: Option<String>,
+}
+
+impl ProgInfo {
+    pub(crate) fn from_raw(info: &libbpf_sys::bpf_prog_info, build_id: Option<String>) -> ProgInfo {
+        ProgInfo {
+            id: info.id,
+            name: utils::cstring_to_str(info.name.as_ptr()),
+            tag: utils::hex(&info.tag),
+            load_time: info.load_time,
+            build_id,
+        }
+    }
 }
 
 bitflags::bitflags! {
@@ -27,13 +72,400 @@ bitflags::bitflags! {
     }
 }
 
+impl AttachFlags {
+    /// The process-wide default flags, from [`ATTACH_FLAGS_ENV`](config::ATTACH_FLAGS_ENV) or
+    /// [`config::set_default_attach_flags`], see [`config::default_attach_flags`].
+    pub fn from_env() -> AttachFlags {
+        config::default_attach_flags()
+    }
+}
+
+impl fmt::Display for AttachFlags {
+    /// The attach mode (`skb`, `drv`, `hw`, or `auto` if no mode is set), followed by any
+    /// other flags, e.g. `drv|update_if_noexist`. Parses back with `str::parse`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = Vec::new();
+        for (flag, name) in ATTACH_FLAG_NAMES.iter() {
+            if self.contains(*flag) {
+                names.push(*name);
+            }
+        }
+        if !self.intersects(AttachFlags::MODES) {
+            names.insert(0, "auto");
+        }
+        f.write_str(&names.join("|"))
+    }
+}
+
+impl FromStr for AttachFlags {
+    type Err = XDPError;
+
+    /// Parse `|` or `,` separated flags, case-insensitive. Modes are `skb` (or `generic`),
+    /// `drv` (or `native`), `hw` (or `offload`) and `auto`, other flags `update_if_noexist`
+    /// and `replace`. The constant names (e.g. `SKB_MODE`) are accepted too.
+    ///
+    /// # Example
+    /// ```
+    /// use rxdp::AttachFlags;
+    /// let flags: AttachFlags = "drv|update_if_noexist".parse().unwrap();
+    /// assert_eq!(flags, AttachFlags::DRV_MODE | AttachFlags::UPDATE_IF_NOEXIST);
+    /// assert_eq!("auto".parse::<AttachFlags>().unwrap(), AttachFlags::empty());
+    /// assert_eq!(flags.to_string(), "drv|update_if_noexist");
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut flags = AttachFlags::empty();
+        for name in s.split(['|', ',']).map(str::trim) {
+            let name = name.to_ascii_lowercase();
+            let name = name.strip_suffix("_mode").unwrap_or(&name);
+            flags |= match name {
+                "auto" | "" => AttachFlags::empty(),
+                "generic" => AttachFlags::SKB_MODE,
+                "native" => AttachFlags::DRV_MODE,
+                "offload" => AttachFlags::HW_MODE,
+                _ => match ATTACH_FLAG_NAMES.iter().find(|(_, n)| *n == name) {
+                    Some((flag, _)) => *flag,
+                    None => {
+                        set_errno(Errno(22));
+                        fail!("Unknown attach flag '{}'", name);
+                    }
+                },
+            };
+        }
+
+        Ok(flags)
+    }
+}
+
+const ATTACH_FLAG_NAMES: [(AttachFlags, &str); 5] = [
+    (AttachFlags::SKB_MODE, "skb"),
+    (AttachFlags::DRV_MODE, "drv"),
+    (AttachFlags::HW_MODE, "hw"),
+    (AttachFlags::UPDATE_IF_NOEXIST, "update_if_noexist"),
+    (AttachFlags::REPLACE, "replace"),
+];
+
+/// Mode an XDP program was attached to an interface in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachMode {
+    /// Native mode, run by the network driver (`XDP_FLAGS_DRV_MODE`).
+    Driver,
+    /// Generic mode, run by the kernel after the driver hands over the packet
+    /// (`XDP_FLAGS_SKB_MODE`). Much slower than native mode.
+    Generic,
+}
+
+impl AttachMode {
+    fn flags(&self) -> AttachFlags {
+        match self {
+            AttachMode::Driver => AttachFlags::DRV_MODE,
+            AttachMode::Generic => AttachFlags::SKB_MODE,
+        }
+    }
+}
+
+impl fmt::Display for AttachMode {
+    /// `drv` or `skb`, the same names as [`AttachFlags`].
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.flags().fmt(f)
+    }
+}
+
+impl FromStr for AttachMode {
+    type Err = XDPError;
+
+    /// Parse `drv` (or `native`) and `skb` (or `generic`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let flags: AttachFlags = s.parse()?;
+        if flags == AttachFlags::DRV_MODE {
+            Ok(AttachMode::Driver)
+        } else if flags == AttachFlags::SKB_MODE {
+            Ok(AttachMode::Generic)
+        } else {
+            set_errno(Errno(22));
+            fail!("Invalid attach mode '{}', expected drv or skb", s);
+        }
+    }
+}
+
+/// Expected attach type of an XDP program, which determines where the program can run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExpectedAttachType {
+    /// Attached to a network interface (`BPF_XDP`).
+    Xdp,
+    /// Run on packets redirected to a DEVMAP/DEVMAP_HASH entry (`BPF_XDP_DEVMAP`).
+    DevMap,
+    /// Run on packets redirected to a CPUMAP entry (`BPF_XDP_CPUMAP`).
+    CpuMap,
+}
+
+impl ExpectedAttachType {
+    pub(crate) fn raw(&self) -> u32 {
+        match self {
+            ExpectedAttachType::Xdp => libbpf_sys::BPF_XDP,
+            ExpectedAttachType::DevMap => libbpf_sys::BPF_XDP_DEVMAP,
+            ExpectedAttachType::CpuMap => libbpf_sys::BPF_XDP_CPUMAP,
+        }
+    }
+
+    fn from_raw(v: u32) -> Option<ExpectedAttachType> {
+        match v {
+            libbpf_sys::BPF_XDP => Some(ExpectedAttachType::Xdp),
+            libbpf_sys::BPF_XDP_DEVMAP => Some(ExpectedAttachType::DevMap),
+            libbpf_sys::BPF_XDP_CPUMAP => Some(ExpectedAttachType::CpuMap),
+            _ => None,
+        }
+    }
+}
+
+/// Type of a BPF program, see [`XDPObject::set_program_type`](crate::XDPObject::set_program_type).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProgType {
+    /// `BPF_PROG_TYPE_XDP`.
+    Xdp,
+    /// `BPF_PROG_TYPE_SOCKET_FILTER`.
+    SocketFilter,
+    /// `BPF_PROG_TYPE_SCHED_CLS`, i.e. tc classifiers.
+    SchedCls,
+    /// `BPF_PROG_TYPE_SCHED_ACT`, i.e. tc actions.
+    SchedAct,
+    /// `BPF_PROG_TYPE_KPROBE`.
+    Kprobe,
+    /// `BPF_PROG_TYPE_TRACEPOINT`.
+    Tracepoint,
+    /// `BPF_PROG_TYPE_RAW_TRACEPOINT`.
+    RawTracepoint,
+    /// `BPF_PROG_TYPE_PERF_EVENT`.
+    PerfEvent,
+    /// `BPF_PROG_TYPE_CGROUP_SKB`.
+    CgroupSkb,
+    /// `BPF_PROG_TYPE_SOCK_OPS`.
+    SockOps,
+    /// `BPF_PROG_TYPE_SK_SKB`.
+    SkSkb,
+    /// `BPF_PROG_TYPE_SK_MSG`.
+    SkMsg,
+}
+
+impl ProgType {
+    pub(crate) fn raw(&self) -> u32 {
+        match self {
+            ProgType::Xdp => libbpf_sys::BPF_PROG_TYPE_XDP,
+            ProgType::SocketFilter => libbpf_sys::BPF_PROG_TYPE_SOCKET_FILTER,
+            ProgType::SchedCls => libbpf_sys::BPF_PROG_TYPE_SCHED_CLS,
+            ProgType::SchedAct => libbpf_sys::BPF_PROG_TYPE_SCHED_ACT,
+            ProgType::Kprobe => libbpf_sys::BPF_PROG_TYPE_KPROBE,
+            ProgType::Tracepoint => libbpf_sys::BPF_PROG_TYPE_TRACEPOINT,
+            ProgType::RawTracepoint => libbpf_sys::BPF_PROG_TYPE_RAW_TRACEPOINT,
+            ProgType::PerfEvent => libbpf_sys::BPF_PROG_TYPE_PERF_EVENT,
+            ProgType::CgroupSkb => libbpf_sys::BPF_PROG_TYPE_CGROUP_SKB,
+            ProgType::SockOps => libbpf_sys::BPF_PROG_TYPE_SOCK_OPS,
+            ProgType::SkSkb => libbpf_sys::BPF_PROG_TYPE_SK_SKB,
+            ProgType::SkMsg => libbpf_sys::BPF_PROG_TYPE_SK_MSG,
+        }
+    }
+}
+
+// Names of the `BPF_PROG_TYPE_*` values, as used by bpftool.
+const PROG_TYPE_NAMES: [&str; 30] = [
+    "unspec",
+    "socket_filter",
+    "kprobe",
+    "sched_cls",
+    "sched_act",
+    "tracepoint",
+    "xdp",
+    "perf_event",
+    "cgroup_skb",
+    "cgroup_sock",
+    "lwt_in",
+    "lwt_out",
+    "lwt_xmit",
+    "sock_ops",
+    "sk_skb",
+    "cgroup_device",
+    "sk_msg",
+    "raw_tracepoint",
+    "cgroup_sock_addr",
+    "lwt_seg6local",
+    "lirc_mode2",
+    "sk_reuseport",
+    "flow_dissector",
+    "cgroup_sysctl",
+    "raw_tracepoint_writable",
+    "cgroup_sockopt",
+    "tracing",
+    "struct_ops",
+    "ext",
+    "lsm",
+];
+
+// Name of the program type `prog_type`, or its number if it is unknown.
+pub(crate) fn prog_type_name(prog_type: u32) -> String {
+    match PROG_TYPE_NAMES.get(prog_type as usize) {
+        Some(name) => name.to_string(),
+        None => format!("type {}", prog_type),
+    }
+}
+
+/// What a program is attached to, derived from its type, see [`Program::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ProgramKind {
+    /// XDP programs, attached to network interfaces with
+    /// [`attach_to_interface`](Program::attach_to_interface).
+    Xdp,
+    /// tc classifiers and actions.
+    Tc,
+    /// Kprobes, tracepoints, perf events, fentry/fexit and LSM programs, attached with
+    /// [`attach`](Program::attach).
+    Tracing,
+    /// Programs attached to cgroups.
+    Cgroup,
+    /// Socket filters, sockmap and sock_ops programs.
+    Socket,
+    /// Any other program type, by its `BPF_PROG_TYPE_*` value.
+    Other(u32),
+}
+
+impl ProgramKind {
+    /// The kind of programs of type `prog_type` (a `BPF_PROG_TYPE_*` value).
+    pub fn from_prog_type(prog_type: u32) -> ProgramKind {
+        use libbpf_sys::*;
+
+        match prog_type {
+            BPF_PROG_TYPE_XDP => ProgramKind::Xdp,
+            BPF_PROG_TYPE_SCHED_CLS | BPF_PROG_TYPE_SCHED_ACT => ProgramKind::Tc,
+            BPF_PROG_TYPE_KPROBE
+            | BPF_PROG_TYPE_TRACEPOINT
+            | BPF_PROG_TYPE_RAW_TRACEPOINT
+            | BPF_PROG_TYPE_RAW_TRACEPOINT_WRITABLE
+            | BPF_PROG_TYPE_PERF_EVENT
+            | BPF_PROG_TYPE_TRACING
+            | BPF_PROG_TYPE_LSM => ProgramKind::Tracing,
+            BPF_PROG_TYPE_CGROUP_SKB
+            | BPF_PROG_TYPE_CGROUP_SOCK
+            | BPF_PROG_TYPE_CGROUP_DEVICE
+            | BPF_PROG_TYPE_CGROUP_SOCK_ADDR
+            | BPF_PROG_TYPE_CGROUP_SYSCTL
+            | BPF_PROG_TYPE_CGROUP_SOCKOPT => ProgramKind::Cgroup,
+            BPF_PROG_TYPE_SOCKET_FILTER
+            | BPF_PROG_TYPE_SOCK_OPS
+            | BPF_PROG_TYPE_SK_SKB
+            | BPF_PROG_TYPE_SK_MSG
+            | BPF_PROG_TYPE_SK_REUSEPORT => ProgramKind::Socket,
+            t => ProgramKind::Other(t),
+        }
+    }
+}
+
+/// Details about an attach that fell back from native to generic mode, see
+/// [`Program::attach_best_effort_with`].
+#[derive(Debug)]
+pub struct AttachDowngrade {
+    /// Name of the interface.
+    pub interface: String,
+    /// Name of the interface's driver, if it has one (virtual interfaces may not).
+    pub driver: Option<String>,
+    /// Mode that was attempted first.
+    pub from: AttachMode,
+    /// Mode the program was attached in instead.
+    pub to: AttachMode,
+    /// Why attaching in `from` mode failed.
+    pub reason: XDPError,
+}
+
+/// Latency statistics from [`Program::benchmark`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchmarkStats {
+    /// Number of runs.
+    pub iterations: u32,
+    /// Return value (XDP action) of the last run.
+    pub retval: u32,
+    pub min: Duration,
+    pub max: Duration,
+    pub avg: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+impl BenchmarkStats {
+    pub(crate) fn from_samples(mut samples: Vec<u32>, retval: u32) -> BenchmarkStats {
+        samples.sort_unstable();
+        let n = samples.len();
+        let total: u64 = samples.iter().map(|s| *s as u64).sum();
+        let pct = |p: usize| Duration::from_nanos(samples[(n * p / 100).min(n - 1)] as u64);
+
+        BenchmarkStats {
+            iterations: n as u32,
+            retval,
+            min: Duration::from_nanos(samples[0] as u64),
+            max: Duration::from_nanos(samples[n - 1] as u64),
+            avg: Duration::from_nanos(total / n as u64),
+            p50: pct(50),
+            p90: pct(90),
+            p99: pct(99),
+        }
+    }
+}
+
 impl Program {
     /// Returns the file descriptor for this program.
     pub fn fd(&self) -> i32 {
         self.fd
     }
 
-    pub(crate) fn new(prog: *mut libbpf_sys::bpf_program) -> XDPResult<Program> {
+    /// Returns the expected attach type the program was loaded with, or `None` for non-XDP
+    /// attach types.
+    pub fn expected_attach_type(&self) -> Option<ExpectedAttachType> {
+        let t = unsafe {
+            libbpf_sys::bpf_program__get_expected_attach_type(
+                self.prog as *mut libbpf_sys::bpf_program,
+            )
+        };
+        ExpectedAttachType::from_raw(t)
+    }
+
+    /// Kernel information about the program, including the build-id of its object file.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let info = obj.get_program("prog_name").unwrap().info().unwrap();
+    /// println!("{} tag {} build-id {:?}", info.name, info.tag, info.build_id);
+    /// ```
+    pub fn info(&self) -> XDPResult<ProgInfo> {
+        let info = sys::prog_info_fd(self.fd, None)?;
+        Ok(ProgInfo::from_raw(&info, self.build_id.clone()))
+    }
+
+    /// What the program is attached to, based on the type the kernel loaded it as.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let prog = obj.get_program("prog_name").unwrap();
+    /// if prog.kind().unwrap() == rxdp::ProgramKind::Xdp {
+    ///     prog.attach_to_interface("eth0", rxdp::AttachFlags::empty()).unwrap();
+    /// }
+    /// ```
+    pub fn kind(&self) -> XDPResult<ProgramKind> {
+        Ok(ProgramKind::from_prog_type(self.prog_type()?))
+    }
+
+    // The `BPF_PROG_TYPE_*` the kernel loaded the program as.
+    pub(crate) fn prog_type(&self) -> XDPResult<u32> {
+        Ok(sys::prog_info_fd(self.fd, None)?.type_)
+    }
+
+    pub(crate) fn new(
+        prog: *mut libbpf_sys::bpf_program,
+        build_id: Option<String>,
+    ) -> XDPResult<Program> {
         let fd = unsafe { libbpf_sys::bpf_program__fd(prog) };
         if fd < 0 {
             fail!("Error getting program fd");
@@ -41,45 +473,530 @@ impl Program {
         Ok(Program {
             prog,
             fd,
-            flags: RefCell::new(0u32),
-            link: RefCell::new(std::ptr::null_mut()),
+            attachments: RefCell::new(Vec::new()),
+            build_id,
         })
     }
 
     /// Attaches the XDP program to an interface
     pub fn attach_to_interface(&self, interface_name: &str, flags: AttachFlags) -> XDPResult<()> {
         let if_index = utils::lookup_interface_by_name(interface_name)?;
-        let rc = unsafe { libbpf_sys::bpf_set_link_xdp_fd(if_index, self.fd, flags.bits()) };
+        let rc = compat::set_xdp_fd(if_index, self.fd, flags.bits());
         if rc < 0 {
-            set_errno(Errno(rc * -1));
-            fail!("Error attaching to interface");
+            fail_rc!(rc, "Error attaching to interface");
         }
 
-        *self.flags.borrow_mut() = flags.bits();
+        let mut attachments = self.attachments.borrow_mut();
+        attachments.retain(|i| *i != if_index);
+        attachments.push(if_index);
         Ok(())
     }
 
-    /// Detaches the XDP program from an interface
+    /// Same as [`attach_to_interface`](Program::attach_to_interface), but waits up to `timeout`
+    /// for the interface to appear, e.g. when it is still being created by a container
+    /// runtime. Fails with `ETIMEDOUT` if the interface doesn't show up in time.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # use std::time::Duration;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let prog = obj.get_program("prog_name").unwrap();
+    /// prog.attach_when_ready("veth1234", rxdp::AttachFlags::SKB_MODE, Duration::from_secs(5))
+    ///     .unwrap();
+    /// ```
+    pub fn attach_when_ready(
+        &self,
+        interface_name: &str,
+        flags: AttachFlags,
+        timeout: Duration,
+    ) -> XDPResult<()> {
+        let start = Instant::now();
+        loop {
+            // ENODEV: the interface doesn't exist yet, or went away between the lookup and
+            // the attach.
+            match self.attach_to_interface(interface_name, flags) {
+                Err(e) if e.code() == ENODEV => (),
+                r => return r,
+            }
+
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                set_errno(Errno(ETIMEDOUT));
+                fail!(
+                    "Interface {} didn't appear within {:?}",
+                    interface_name,
+                    timeout
+                );
+            }
+            std::thread::sleep(READY_POLL_INTERVAL.min(timeout - elapsed));
+        }
+    }
+
+    /// Attaches the XDP program to an interface in native mode, falling back to generic mode if
+    /// the driver doesn't support XDP. Any mode bits in `flags` are ignored. Returns the mode
+    /// the program was attached in.
+    pub fn attach_best_effort(
+        &self,
+        interface_name: &str,
+        flags: AttachFlags,
+    ) -> XDPResult<AttachMode> {
+        self.attach_best_effort_with(interface_name, flags, |_| ())
+    }
+
+    /// Same as [`attach_best_effort`](Program::attach_best_effort), but calls `on_downgrade`
+    /// if the program had to be attached in generic mode.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let prog = obj.get_program("prog_name").unwrap();
+    /// let mode = prog
+    ///     .attach_best_effort_with("eth0", rxdp::AttachFlags::empty(), |d| {
+    ///         println!(
+    ///             "{} ({:?}) running in {:?} mode: {}",
+    ///             d.interface, d.driver, d.to, d.reason
+    ///         );
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn attach_best_effort_with<F>(
+        &self,
+        interface_name: &str,
+        flags: AttachFlags,
+        on_downgrade: F,
+    ) -> XDPResult<AttachMode>
+    where
+        F: FnOnce(&AttachDowngrade),
+    {
+        let flags = flags - AttachFlags::MODES;
+        let reason =
+            match self.attach_to_interface(interface_name, flags | AttachMode::Driver.flags()) {
+                Ok(()) => return Ok(AttachMode::Driver),
+                Err(e) => e,
+            };
+
+        self.attach_to_interface(interface_name, flags | AttachMode::Generic.flags())?;
+        on_downgrade(&AttachDowngrade {
+            interface: interface_name.to_string(),
+            driver: utils::interface_driver(interface_name),
+            from: AttachMode::Driver,
+            to: AttachMode::Generic,
+            reason,
+        });
+
+        Ok(AttachMode::Generic)
+    }
+
+    /// Runs the program `iterations` times against `packet` using `BPF_PROG_TEST_RUN`, and
+    /// returns latency statistics for the individual runs, as measured by the kernel.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let prog = obj.get_program("prog_name").unwrap();
+    /// let packet = [0u8; 64];
+    /// let stats = prog.benchmark(&packet, 1000).unwrap();
+    /// println!("p99: {:?}", stats.p99);
+    /// ```
+    pub fn benchmark(&self, packet: &[u8], iterations: u32) -> XDPResult<BenchmarkStats> {
+        if iterations == 0 {
+            set_errno(Errno(22));
+            fail!("Benchmark needs at least 1 iteration");
+        }
+
+        let mut samples = Vec::with_capacity(iterations as usize);
+        let mut retval = 0u32;
+        for _ in 0..iterations {
+            let (r, duration) = self.test_run(packet)?;
+            retval = r;
+            samples.push(duration);
+        }
+
+        Ok(BenchmarkStats::from_samples(samples, retval))
+    }
+
+    // Runs the program once against `packet` with `BPF_PROG_TEST_RUN`, returning the XDP action
+    // and the run time in nanoseconds.
+    pub(crate) fn test_run(&self, packet: &[u8]) -> XDPResult<(u32, u32)> {
+        match compat::prog_test_run(self.fd, packet) {
+            Ok(r) => Ok(r),
+            Err(rc) => fail_rc!(rc, "Error running program"),
+        }
+    }
+
+    /// Detaches the XDP program from an interface.
+    ///
+    /// The mode to detach in is queried from the kernel, so this works no matter which flags
+    /// the program was attached with, or by which process. If this program isn't attached, the
+    /// program attached in its place is detached, e.g. one attached by a previous version of
+    /// the agent. This fails with `EBUSY` if programs are attached in several modes and none of
+    /// them is this one, and does nothing if no program is attached.
     pub fn detach_from_interface(&self, interface_name: &str) -> XDPResult<()> {
         let if_index = utils::lookup_interface_by_name(interface_name)?;
-        let rc = unsafe { libbpf_sys::bpf_set_link_xdp_fd(if_index, -1, *self.flags.borrow()) };
-        if rc < 0 {
-            fail!("Error attaching to interface");
+        let prog_id = sys::prog_info_fd(self.fd, None)?.id;
+        let attached = match compat::query_xdp(if_index) {
+            Ok(ids) => ids,
+            Err(rc) => fail_rc!(rc, "Error querying XDP programs on {}", interface_name),
+        };
+
+        let mode = match detach_mode(prog_id, attached) {
+            Ok(Some(mode)) => mode,
+            Ok(None) => AttachFlags::empty(),
+            Err(()) => {
+                set_errno(Errno(EBUSY));
+                fail!(
+                    "Programs are attached to {} in several modes, not detaching",
+                    interface_name
+                );
+            }
+        };
+        if !mode.is_empty() {
+            let rc = compat::set_xdp_fd(if_index, -1, mode.bits());
+            if rc < 0 {
+                fail_rc!(rc, "Error detaching from interface");
+            }
         }
+
+        self.attachments.borrow_mut().retain(|i| *i != if_index);
         Ok(())
     }
 
-    /// Attach a BPF program
-    pub fn attach(&self) -> XDPResult<()> {
+    // Removes all attachments made through this program that are still in place, in the mode
+    // the kernel reports. Errors are ignored, since the interface might be gone by now.
+    pub(crate) fn detach_all(&self) {
+        let prog_id = match sys::prog_info_fd(self.fd, None) {
+            Ok(info) => info.id,
+            Err(_) => return,
+        };
+        for if_index in self.attachments.borrow_mut().drain(..) {
+            if let Ok(ids) = compat::query_xdp(if_index) {
+                if let Some(mode) = own_mode(prog_id, ids) {
+                    compat::set_xdp_fd(if_index, -1, mode.bits());
+                }
+            }
+        }
+    }
+
+    /// Attach a BPF program, using the attach point libbpf derives from its section name. The
+    /// program stays attached until the returned [`Link`] is dropped or detached, unless the
+    /// link is pinned.
+    pub fn attach(&self) -> XDPResult<Link> {
         let link = unsafe {
             let link = libbpf_sys::bpf_program__attach(self.prog as *mut libbpf_sys::bpf_program);
             let err = libbpf_sys::libbpf_get_error(link as *const _ as *const std::os::raw::c_void);
             if err != 0 {
-                fail!("error attaching: {}", err);
+                fail_rc!(err as i32, "Error attaching program");
             }
             link
         };
-        *self.link.borrow_mut() = link;
+        Ok(Link {
+            inner: LinkInner::Libbpf(link),
+        })
+    }
+
+    /// Attach the XDP program to the interface with a BPF link (kernel 5.9 or later). Unlike
+    /// [`attach_to_interface`](Program::attach_to_interface), the program is detached when the
+    /// link is dropped, unless it is [pinned](Link::pin), and other processes can't replace it
+    /// without the link.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let link = obj.get_program("prog_name").unwrap().attach_xdp_link("eth0").unwrap();
+    /// link.pin("/sys/fs/bpf/myapp/eth0_link").unwrap();
+    /// // The program stays attached after the process exits.
+    /// ```
+    pub fn attach_xdp_link(&self, interface_name: &str) -> XDPResult<Link> {
+        let if_index = utils::lookup_interface_by_name(interface_name)?;
+        let fd = compat::attach_xdp_link(self.fd, if_index);
+        if fd < 0 {
+            fail_rc!(fd, "Error attaching link to interface {}", interface_name);
+        }
+        Ok(Link {
+            inner: LinkInner::Fd(fd, RefCell::new(None)),
+        })
+    }
+}
+
+/// A BPF link, created by [`Program::attach`] or [`Program::attach_xdp_link`]. Dropping the link
+/// destroys it, which detaches the program unless the link is pinned.
+///
+/// Pinned links keep the program attached without a process holding them. After a restart, the
+/// link is reopened with [`open_pinned`](Link::open_pinned) and the new version of the program
+/// swapped in with [`update_program`](Link::update_program).
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let link = obj.get_program("prog_name").unwrap().attach().unwrap();
+///
+/// // Replace the program without detaching in between.
+/// link.update_program(obj.get_program("new_prog").unwrap()).unwrap();
+///
+/// link.detach().unwrap();
+/// ```
+#[must_use = "the program is detached when the link is dropped"]
+pub struct Link {
+    inner: LinkInner,
+}
+
+enum LinkInner {
+    // Created or opened by libbpf.
+    Libbpf(*mut libbpf_sys::bpf_link),
+    // An XDP link created with BPF_LINK_CREATE (see `compat::attach_xdp_link`), and the path
+    // it is pinned at.
+    Fd(i32, RefCell<Option<String>>),
+}
+
+impl Link {
+    /// Open the link pinned at `path`, e.g. by a previous run of the loader.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let link = rxdp::Link::open_pinned("/sys/fs/bpf/myapp/eth0_link").unwrap();
+    /// link.update_program(obj.get_program("prog_name").unwrap()).unwrap();
+    /// ```
+    pub fn open_pinned(path: &str) -> XDPResult<Link> {
+        let s = utils::str_to_cstring(path)?;
+        let link = unsafe {
+            let link = libbpf_sys::bpf_link__open(s.as_ptr());
+            let err = libbpf_sys::libbpf_get_error(link as *const _ as *const std::os::raw::c_void);
+            if err != 0 {
+                fail_rc!(err as i32, "Error opening link pinned at {}", path);
+            }
+            link
+        };
+        Ok(Link {
+            inner: LinkInner::Libbpf(link),
+        })
+    }
+
+    /// Detach the program, even if the link is pinned or also held by other processes.
+    pub fn detach(self) -> XDPResult<()> {
+        let rc = match &self.inner {
+            LinkInner::Libbpf(link) => unsafe { libbpf_sys::bpf_link__detach(*link) },
+            LinkInner::Fd(fd, _) => {
+                // struct { link_fd }
+                let mut attr = Attr::new().u32(0, *fd as u32);
+                match unsafe { raw::bpf(Cmd::LinkDetach, &mut attr) } {
+                    Ok(_) => 0,
+                    Err(_) => -1,
+                }
+            }
+        };
+        if rc < 0 {
+            fail_rc!(rc, "Error detaching link");
+        }
         Ok(())
     }
+
+    /// Pin the link at `path`, which must be on a BPF filesystem. A pinned link (and the
+    /// program attached by it) outlives this handle and the process.
+    pub fn pin(&self, path: &str) -> XDPResult<()> {
+        crate::object::check_pin_dir(std::path::Path::new(path))?;
+        let s = utils::str_to_cstring(path)?;
+        let rc = match &self.inner {
+            LinkInner::Libbpf(link) => unsafe { libbpf_sys::bpf_link__pin(*link, s.as_ptr()) },
+            LinkInner::Fd(fd, pin_path) => {
+                let rc = unsafe { libbpf_sys::bpf_obj_pin(*fd, s.as_ptr()) };
+                if rc == 0 {
+                    *pin_path.borrow_mut() = Some(path.to_string());
+                }
+                rc
+            }
+        };
+        if rc < 0 {
+            fail_rc!(rc, "Error pinning link at {}", path);
+        }
+        Ok(())
+    }
+
+    /// Remove the pin created by [`pin`](Link::pin).
+    pub fn unpin(&self) -> XDPResult<()> {
+        let rc = match &self.inner {
+            LinkInner::Libbpf(link) => unsafe { libbpf_sys::bpf_link__unpin(*link) },
+            LinkInner::Fd(_, pin_path) => {
+                let path = match pin_path.borrow().as_ref() {
+                    Some(p) => utils::str_to_cstring(p)?,
+                    None => {
+                        set_errno(Errno(22));
+                        fail!("Error unpinning link, the link isn't pinned");
+                    }
+                };
+                let rc = unsafe { libc::unlink(path.as_ptr()) };
+                if rc == 0 {
+                    pin_path.borrow_mut().take();
+                }
+                rc
+            }
+        };
+        if rc < 0 {
+            fail_rc!(rc, "Error unpinning link");
+        }
+        Ok(())
+    }
+
+    /// Atomically replace the program attached by this link with `prog`.
+    pub fn update_program(&self, prog: &Program) -> XDPResult<()> {
+        let rc = match &self.inner {
+            LinkInner::Libbpf(link) => unsafe {
+                libbpf_sys::bpf_link__update_program(
+                    *link,
+                    prog.prog as *mut libbpf_sys::bpf_program,
+                )
+            },
+            LinkInner::Fd(fd, _) => {
+                // struct { link_fd, new_prog_fd, flags, old_prog_fd }
+                let mut attr = Attr::new().u32(0, *fd as u32).u32(4, prog.fd as u32);
+                match unsafe { raw::bpf(Cmd::LinkUpdate, &mut attr) } {
+                    Ok(_) => 0,
+                    Err(_) => -1,
+                }
+            }
+        };
+        if rc < 0 {
+            fail_rc!(rc, "Error updating link program");
+        }
+        Ok(())
+    }
+
+    /// File descriptor of the link.
+    pub fn fd(&self) -> i32 {
+        match &self.inner {
+            LinkInner::Libbpf(link) => unsafe { libbpf_sys::bpf_link__fd(*link) },
+            LinkInner::Fd(fd, _) => *fd,
+        }
+    }
+}
+
+// Pairs the program ids returned by `query_xdp` with their attach modes.
+fn by_mode((drv, skb, hw): (u32, u32, u32)) -> [(AttachFlags, u32); 3] {
+    [
+        (AttachFlags::DRV_MODE, drv),
+        (AttachFlags::SKB_MODE, skb),
+        (AttachFlags::HW_MODE, hw),
+    ]
+}
+
+// The mode program `prog_id` is attached in, given the program ids per mode.
+fn own_mode(prog_id: u32, ids: (u32, u32, u32)) -> Option<AttachFlags> {
+    by_mode(ids)
+        .iter()
+        .find(|(_, id)| *id == prog_id)
+        .map(|(mode, _)| *mode)
+}
+
+// The mode to detach in: the mode of program `prog_id`, else the only mode a program is
+// attached in. `None` if nothing is attached, an error if that is ambiguous.
+fn detach_mode(prog_id: u32, ids: (u32, u32, u32)) -> Result<Option<AttachFlags>, ()> {
+    if let Some(mode) = own_mode(prog_id, ids) {
+        return Ok(Some(mode));
+    }
+
+    let attached: Vec<AttachFlags> = by_mode(ids)
+        .iter()
+        .filter(|(_, id)| *id != 0)
+        .map(|(mode, _)| *mode)
+        .collect();
+    match attached.as_slice() {
+        [] => Ok(None),
+        [mode] => Ok(Some(*mode)),
+        _ => Err(()),
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        match &self.inner {
+            LinkInner::Libbpf(link) => unsafe { libbpf_sys::bpf_link__destroy(*link) },
+            LinkInner::Fd(fd, _) => unsafe { libc::close(*fd) },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attach_flags_strings() {
+        let cases = [
+            ("auto", AttachFlags::empty()),
+            ("skb", AttachFlags::SKB_MODE),
+            ("drv", AttachFlags::DRV_MODE),
+            ("hw|replace", AttachFlags::HW_MODE | AttachFlags::REPLACE),
+            ("auto|update_if_noexist", AttachFlags::UPDATE_IF_NOEXIST),
+        ];
+        for (s, flags) in cases.iter() {
+            assert_eq!(flags.to_string(), *s);
+            assert_eq!(s.parse::<AttachFlags>().unwrap(), *flags);
+        }
+
+        assert_eq!(
+            "SKB_MODE, Update_If_NoExist"
+                .parse::<AttachFlags>()
+                .unwrap(),
+            AttachFlags::SKB_MODE | AttachFlags::UPDATE_IF_NOEXIST
+        );
+        assert_eq!(
+            "generic".parse::<AttachMode>().unwrap(),
+            AttachMode::Generic
+        );
+        assert_eq!(AttachMode::Driver.to_string(), "drv");
+        assert!("fast".parse::<AttachFlags>().is_err());
+        assert!("auto".parse::<AttachMode>().is_err());
+    }
+
+    #[test]
+    fn test_program_kind() {
+        assert_eq!(ProgramKind::from_prog_type(6), ProgramKind::Xdp);
+        assert_eq!(ProgramKind::from_prog_type(3), ProgramKind::Tc);
+        assert_eq!(ProgramKind::from_prog_type(5), ProgramKind::Tracing);
+        assert_eq!(ProgramKind::from_prog_type(18), ProgramKind::Cgroup);
+        assert_eq!(ProgramKind::from_prog_type(14), ProgramKind::Socket);
+        assert_eq!(ProgramKind::from_prog_type(22), ProgramKind::Other(22));
+
+        assert_eq!(prog_type_name(libbpf_sys::BPF_PROG_TYPE_XDP), "xdp");
+        assert_eq!(prog_type_name(libbpf_sys::BPF_PROG_TYPE_LSM), "lsm");
+        assert_eq!(prog_type_name(99), "type 99");
+    }
+
+    #[test]
+    fn test_detach_mode() {
+        // Our program, in whichever mode it is attached
+        assert_eq!(detach_mode(7, (0, 7, 0)), Ok(Some(AttachFlags::SKB_MODE)));
+        assert_eq!(detach_mode(7, (7, 3, 0)), Ok(Some(AttachFlags::DRV_MODE)));
+        // Someone else's program, if there is only one
+        assert_eq!(detach_mode(7, (3, 0, 0)), Ok(Some(AttachFlags::DRV_MODE)));
+        assert_eq!(detach_mode(7, (0, 0, 0)), Ok(None));
+        assert_eq!(detach_mode(7, (3, 4, 0)), Err(()));
+
+        assert_eq!(own_mode(7, (3, 0, 7)), Some(AttachFlags::HW_MODE));
+        assert_eq!(own_mode(7, (3, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_benchmark_stats() {
+        let samples = (1..=100).rev().collect();
+        let stats = BenchmarkStats::from_samples(samples, 2);
+
+        assert_eq!(stats.iterations, 100);
+        assert_eq!(stats.retval, 2);
+        assert_eq!(stats.min, Duration::from_nanos(1));
+        assert_eq!(stats.max, Duration::from_nanos(100));
+        assert_eq!(stats.avg, Duration::from_nanos(50));
+        assert_eq!(stats.p50, Duration::from_nanos(51));
+        assert_eq!(stats.p90, Duration::from_nanos(91));
+        assert_eq!(stats.p99, Duration::from_nanos(100));
+
+        let stats = BenchmarkStats::from_samples(vec![7], 0);
+        assert_eq!(stats.p99, Duration::from_nanos(7));
+    }
 }
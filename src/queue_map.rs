@@ -0,0 +1,168 @@
+use errno::{set_errno, Errno};
+use std::{marker::PhantomData, mem::size_of, os::raw::c_void};
+
+use crate::error::{get_errno, XDPError};
+use crate::map_common as mc;
+use crate::object::XDPLoadedObject;
+use crate::result::XDPResult;
+use crate::utils;
+use crate::{MapFlags, MapType};
+
+/// Used for working with `BPF_MAP_TYPE_QUEUE` (FIFO) and `BPF_MAP_TYPE_STACK` (LIFO) maps,
+/// which have no keys.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let q: rxdp::QueueMap<u32> = rxdp::QueueMap::new(&obj, "events").unwrap();
+/// q.push(&7, rxdp::MapFlags::BpfAny).unwrap();
+/// while let Some(v) = q.pop().unwrap() {
+///     println!("{}", v);
+/// }
+/// ```
+pub struct QueueMap<V> {
+    map_fd: i32,
+    _val: PhantomData<V>,
+    map_type: MapType,
+    max_entries: u32,
+    // True if the handle created `map_fd` itself, rather than borrowing it from an object.
+    owned: bool,
+}
+
+/// A [`QueueMap`] for `BPF_MAP_TYPE_STACK` maps, which pop the most recently pushed value.
+pub type StackMap<V> = QueueMap<V>;
+
+impl<V: Default> QueueMap<V> {
+    /// Create a new queue or stack map, holding up to `max_entries` values.
+    pub fn create(map_type: MapType, max_entries: u32) -> XDPResult<QueueMap<V>> {
+        if !is_queue(map_type) {
+            set_errno(Errno(22));
+            fail!("Improper map type {:?}, expected Queue or Stack", map_type);
+        }
+
+        let value_size = size_of::<V>() as u32;
+        let map_fd = mc::create_map(map_type, 0, value_size, max_entries, 0);
+        mc::check_rc(map_fd, (), "Error creating new map")?;
+
+        Ok(QueueMap {
+            map_fd,
+            _val: PhantomData,
+            map_type,
+            max_entries,
+            owned: true,
+        })
+    }
+
+    /// Get access to the queue or stack map `map_name`. This will fail if the requested value
+    /// size doesn't match the value size defined in the ELF file.
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<QueueMap<V>> {
+        let def = mc::find_map(xdp, map_name)?;
+        if !is_queue(def.map_type) {
+            return mc::improper_type(map_name, def.map_type);
+        }
+
+        let req_val_size = size_of::<V>() as u32;
+        if req_val_size != def.value_size {
+            fail!(
+                "Incorrect value size, XDP map has size: {}, requested value size is {}.",
+                def.value_size,
+                req_val_size,
+            );
+        }
+
+        Ok(QueueMap {
+            map_fd: def.fd,
+            _val: PhantomData,
+            map_type: def.map_type,
+            max_entries: def.max_entries,
+            owned: false,
+        })
+    }
+
+    /// Add `value` to the map. When the map is full, this fails with `E2BIG`, unless `flags`
+    /// is `BpfExist`, which makes room by removing the oldest value.
+    pub fn push(&self, value: &V, flags: MapFlags) -> XDPResult<()> {
+        let flags = flags.checked_bits()?;
+        let rc = mc::update_elem(
+            self.map_fd,
+            std::ptr::null(),
+            value as *const _ as *const c_void,
+            flags,
+        );
+
+        mc::check_rc(rc, (), "Error pushing value")
+    }
+
+    /// Remove and return the next value: the oldest for queues, the newest for stacks. Returns
+    /// `None` if the map is empty.
+    pub fn pop(&self) -> XDPResult<Option<V>> {
+        let mut value: V = Default::default();
+        let rc = unsafe {
+            libbpf_sys::bpf_map_lookup_and_delete_elem(
+                self.map_fd,
+                std::ptr::null(),
+                utils::as_bytes_mut(&mut value).as_mut_ptr() as *mut c_void,
+            )
+        };
+
+        empty_as_none(rc, value, "Error popping value")
+    }
+
+    /// Return the next value without removing it. Returns `None` if the map is empty.
+    pub fn peek(&self) -> XDPResult<Option<V>> {
+        let mut value: V = Default::default();
+        let rc = mc::lookup_elem(
+            self.map_fd,
+            std::ptr::null(),
+            utils::as_bytes_mut(&mut value).as_mut_ptr() as *mut c_void,
+        );
+
+        empty_as_none(rc, value, "Error peeking value")
+    }
+
+    /// Pop all values, in the order they are popped.
+    pub fn drain(&self) -> XDPResult<Vec<V>> {
+        let mut values = Vec::new();
+        while let Some(v) = self.pop()? {
+            values.push(v);
+        }
+
+        Ok(values)
+    }
+
+    /// File descriptor for this map.
+    pub fn map_fd(&self) -> i32 {
+        self.map_fd
+    }
+
+    pub fn map_type(&self) -> MapType {
+        self.map_type
+    }
+
+    /// The maximum number of values the map holds.
+    pub fn max_entries(&self) -> u32 {
+        self.max_entries
+    }
+}
+
+impl<V> Drop for QueueMap<V> {
+    fn drop(&mut self) {
+        if self.owned {
+            unsafe { libc::close(self.map_fd) };
+        }
+    }
+}
+
+fn is_queue(map_type: MapType) -> bool {
+    map_type == MapType::Queue || map_type == MapType::Stack
+}
+
+// The kernel returns ENOENT for pops and peeks on an empty map.
+fn empty_as_none<V>(rc: i32, value: V, err_msg: &str) -> XDPResult<Option<V>> {
+    if rc == -2 || (rc < 0 && get_errno() == 2) {
+        return Ok(None);
+    }
+
+    mc::check_rc(rc, Some(value), err_msg)
+}
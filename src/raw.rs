@@ -0,0 +1,245 @@
+//! Direct access to the bpf(2) syscall, for commands or attributes that neither rxdp nor libbpf
+//! wrap yet.
+//!
+//! An [`Attr`] is the `union bpf_attr` argument of the syscall as bytes, filled in at the
+//! offsets of the command's fields in `include/uapi/linux/bpf.h`. Fields that aren't set are
+//! zero, as the kernel requires.
+//!
+//! # Example
+//! ```no_run
+//! use rxdp::raw::{self, Attr, Cmd};
+//! use rxdp::MapLike;
+//!
+//! let m = rxdp::MapBuilder::<u32, u64>::new().create().unwrap();
+//!
+//! // struct { __u32 map_fd; } for BPF_MAP_FREEZE.
+//! let mut attr = Attr::new().u32(0, m.map_fd() as u32);
+//! unsafe { raw::bpf(Cmd::MapFreeze, &mut attr) }.unwrap();
+//! ```
+use std::fmt;
+use std::os::raw::c_void;
+
+use crate::error::XDPError;
+use crate::result::XDPResult;
+
+/// A bpf(2) command (`enum bpf_cmd`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmd {
+    MapCreate,
+    MapLookupElem,
+    MapUpdateElem,
+    MapDeleteElem,
+    MapGetNextKey,
+    ProgLoad,
+    ObjPin,
+    ObjGet,
+    ProgAttach,
+    ProgDetach,
+    ProgTestRun,
+    ProgGetNextId,
+    MapGetNextId,
+    ProgGetFdById,
+    MapGetFdById,
+    ObjGetInfoByFd,
+    ProgQuery,
+    RawTracepointOpen,
+    BtfLoad,
+    BtfGetFdById,
+    TaskFdQuery,
+    MapLookupAndDeleteElem,
+    MapFreeze,
+    BtfGetNextId,
+    MapLookupBatch,
+    MapLookupAndDeleteBatch,
+    MapUpdateBatch,
+    MapDeleteBatch,
+    LinkCreate,
+    LinkUpdate,
+    LinkGetFdById,
+    LinkGetNextId,
+    EnableStats,
+    IterCreate,
+    LinkDetach,
+    ProgBindMap,
+    TokenCreate,
+    /// A command not listed here, by its number.
+    Other(u32),
+}
+
+// In `enum bpf_cmd` order, so the index is the command number.
+const CMDS: [Cmd; 37] = [
+    Cmd::MapCreate,
+    Cmd::MapLookupElem,
+    Cmd::MapUpdateElem,
+    Cmd::MapDeleteElem,
+    Cmd::MapGetNextKey,
+    Cmd::ProgLoad,
+    Cmd::ObjPin,
+    Cmd::ObjGet,
+    Cmd::ProgAttach,
+    Cmd::ProgDetach,
+    Cmd::ProgTestRun,
+    Cmd::ProgGetNextId,
+    Cmd::MapGetNextId,
+    Cmd::ProgGetFdById,
+    Cmd::MapGetFdById,
+    Cmd::ObjGetInfoByFd,
+    Cmd::ProgQuery,
+    Cmd::RawTracepointOpen,
+    Cmd::BtfLoad,
+    Cmd::BtfGetFdById,
+    Cmd::TaskFdQuery,
+    Cmd::MapLookupAndDeleteElem,
+    Cmd::MapFreeze,
+    Cmd::BtfGetNextId,
+    Cmd::MapLookupBatch,
+    Cmd::MapLookupAndDeleteBatch,
+    Cmd::MapUpdateBatch,
+    Cmd::MapDeleteBatch,
+    Cmd::LinkCreate,
+    Cmd::LinkUpdate,
+    Cmd::LinkGetFdById,
+    Cmd::LinkGetNextId,
+    Cmd::EnableStats,
+    Cmd::IterCreate,
+    Cmd::LinkDetach,
+    Cmd::ProgBindMap,
+    Cmd::TokenCreate,
+];
+
+impl Cmd {
+    /// The command number passed to the kernel.
+    pub fn code(&self) -> u32 {
+        match *self {
+            Cmd::Other(c) => c,
+            cmd => CMDS.iter().position(|c| *c == cmd).unwrap() as u32,
+        }
+    }
+}
+
+impl From<u32> for Cmd {
+    fn from(code: u32) -> Self {
+        CMDS.get(code as usize).copied().unwrap_or(Cmd::Other(code))
+    }
+}
+
+impl fmt::Display for Cmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Cmd::Other(c) => write!(f, "command {}", c),
+            cmd => write!(f, "{:?}", cmd),
+        }
+    }
+}
+
+/// The attributes of a bpf(2) command, zero initialized. The buffer grows to fit the fields
+/// that are set, so it is only as large as the command needs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Attr {
+    buf: Vec<u8>,
+}
+
+impl Attr {
+    pub fn new() -> Attr {
+        Attr::default()
+    }
+
+    /// Set the `__u32` field at `offset`.
+    pub fn u32(mut self, offset: usize, value: u32) -> Attr {
+        self.put(offset, &value.to_ne_bytes());
+        self
+    }
+
+    /// Set the `__u64` field at `offset`.
+    pub fn u64(mut self, offset: usize, value: u64) -> Attr {
+        self.put(offset, &value.to_ne_bytes());
+        self
+    }
+
+    /// Set the pointer field (`__aligned_u64`) at `offset`. The memory `ptr` points to must
+    /// stay valid until [`bpf`] returns.
+    pub fn ptr<T>(self, offset: usize, ptr: *const T) -> Attr {
+        self.u64(offset, ptr as usize as u64)
+    }
+
+    /// The `__u32` field at `offset`, e.g. one filled in by the kernel. Zero if the buffer
+    /// doesn't reach it.
+    pub fn get_u32(&self, offset: usize) -> u32 {
+        let mut b = [0u8; 4];
+        self.get(offset, &mut b);
+        u32::from_ne_bytes(b)
+    }
+
+    /// The `__u64` field at `offset`. Zero if the buffer doesn't reach it.
+    pub fn get_u64(&self, offset: usize) -> u64 {
+        let mut b = [0u8; 8];
+        self.get(offset, &mut b);
+        u64::from_ne_bytes(b)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    fn put(&mut self, offset: usize, bytes: &[u8]) {
+        let end = offset + bytes.len();
+        if self.buf.len() < end {
+            self.buf.resize(end, 0);
+        }
+        self.buf[offset..end].copy_from_slice(bytes);
+    }
+
+    fn get(&self, offset: usize, out: &mut [u8]) {
+        let end = (offset + out.len()).min(self.buf.len());
+        if offset < end {
+            out[..end - offset].copy_from_slice(&self.buf[offset..end]);
+        }
+    }
+}
+
+/// Run the bpf(2) command `cmd`. Returns the syscall's return value, e.g. a new fd for
+/// commands that create objects. On failure, the error carries errno.
+///
+/// # Safety
+/// The kernel reads and writes the memory at the pointers in `attr`, which must be valid for
+/// the command, with the sizes given in `attr`.
+pub unsafe fn bpf(cmd: Cmd, attr: &mut Attr) -> XDPResult<i32> {
+    let rc = libc::syscall(
+        libc::SYS_bpf,
+        cmd.code(),
+        attr.buf.as_mut_ptr() as *mut c_void,
+        attr.buf.len() as u32,
+    );
+    if rc < 0 {
+        fail!("bpf({}) failed", cmd);
+    }
+
+    Ok(rc as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmd_codes() {
+        assert_eq!(Cmd::MapCreate.code(), 0);
+        assert_eq!(Cmd::MapFreeze.code(), 22);
+        assert_eq!(Cmd::TokenCreate.code(), 36);
+        for code in 0..40 {
+            assert_eq!(Cmd::from(code).code(), code);
+        }
+        assert_eq!(Cmd::from(99), Cmd::Other(99));
+        assert_eq!(Cmd::Other(99).to_string(), "command 99");
+    }
+
+    #[test]
+    fn test_attr() {
+        let attr = Attr::new().u32(4, 7).u64(8, u64::MAX);
+        assert_eq!(attr.as_bytes().len(), 16);
+        assert_eq!(&attr.as_bytes()[..4], &[0; 4]);
+        assert_eq!(attr.get_u32(4), 7);
+        assert_eq!(attr.get_u64(8), u64::MAX);
+        assert_eq!(attr.get_u32(64), 0);
+    }
+}
@@ -0,0 +1,394 @@
+//! Helpers for setting up the maps used by `bpf_redirect_map`.
+use errno::{set_errno, Errno};
+use std::collections::HashSet;
+
+use crate::error::XDPError;
+use crate::map_common::MapLike;
+use crate::padding::NoPadding;
+use crate::result::XDPResult;
+use crate::utils;
+use crate::{num_cpus, DynMap, Map, MapFlags, MapType, Program, XDPLoadedObject};
+
+/// Populates the DEVMAP (or DEVMAP_HASH) `map_name` with `routes`, a list of
+/// (key, interface name) pairs, and returns the map.
+///
+/// All routes are validated before the map is touched: the map has to be a devmap with 4 byte
+/// keys and values, keys must be unique (and within `max_entries` for a DEVMAP), and every
+/// interface must exist. If writing any route fails, the routes already written are rolled
+/// back to their previous values.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// use rxdp::redirect;
+///
+/// // bpf_redirect_map(&tx_ports, 0, 0) sends packets out of eth1
+/// let m = redirect::setup_devmap(&obj, "tx_ports", &[(0, "eth1"), (1, "eth2")]).unwrap();
+/// ```
+pub fn setup_devmap(
+    obj: &XDPLoadedObject,
+    map_name: &str,
+    routes: &[(u32, &str)],
+) -> XDPResult<Map<u32, u32>> {
+    let m: Map<u32, u32> = Map::new(obj, map_name)?;
+    match m.map_type() {
+        MapType::DevMap | MapType::DevMapHash => (),
+        _ => {
+            set_errno(Errno(22));
+            fail!("Map {} is not a DEVMAP or DEVMAP_HASH", map_name);
+        }
+    }
+
+    let mut seen = HashSet::with_capacity(routes.len());
+    let mut entries = Vec::with_capacity(routes.len());
+    for (key, iface) in routes.iter() {
+        if !seen.insert(*key) {
+            set_errno(Errno(22));
+            fail!("Duplicate devmap key {}", key);
+        }
+        if m.map_type() == MapType::DevMap && *key >= m.max_entries() {
+            set_errno(Errno(7));
+            fail!(
+                "Devmap key {} out of range, max_entries {}",
+                key,
+                m.max_entries()
+            );
+        }
+        let if_index = utils::lookup_interface_by_name(iface)? as u32;
+        entries.push((*key, if_index));
+    }
+
+    let mut previous = Vec::with_capacity(entries.len());
+    for (key, if_index) in entries.iter() {
+        let old = m.lookup(key).ok().map(|v| v.into_single());
+        if let Err(e) = m.update(key, if_index, MapFlags::BpfAny) {
+            rollback(&m, &previous);
+            return Err(e);
+        }
+        previous.push((*key, old));
+    }
+
+    Ok(m)
+}
+
+// Best effort, the original error is what gets reported.
+fn rollback(m: &Map<u32, u32>, previous: &[(u32, Option<u32>)]) {
+    for (key, old) in previous.iter().rev() {
+        let _ = match old {
+            Some(v) => m.update(key, v, MapFlags::BpfAny),
+            None => m.delete(key),
+        };
+    }
+}
+
+/// Typed handle for DEVMAP and DEVMAP_HASH maps, addressing interfaces by name.
+///
+/// Maps with 4 byte values hold interface indices. Maps with 8 byte values hold a
+/// `struct bpf_devmap_val`, which can also run a program (loaded with
+/// [`ExpectedAttachType::DevMap`](crate::ExpectedAttachType::DevMap)) on packets redirected
+/// through the entry, see [`set_with_program`](DevMap::set_with_program).
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// use rxdp::redirect::DevMap;
+///
+/// let m = DevMap::new(&obj, "tx_ports").unwrap();
+/// m.set(0, "eth1").unwrap();
+/// m.set_with_program(1, "eth2", obj.get_program("egress").unwrap()).unwrap();
+///
+/// for (key, entry) in m.stale_entries().unwrap() {
+///     println!("{} points at missing interface {}", key, entry.if_index);
+/// }
+/// ```
+pub struct DevMap {
+    map: DynMap,
+}
+
+/// An entry of a [`DevMap`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DevMapEntry {
+    pub if_index: u32,
+    /// Name of the interface, `None` if there is no interface with `if_index` in the current
+    /// network namespace.
+    pub if_name: Option<String>,
+    /// Id of the program run for the entry, if any.
+    pub prog_id: Option<u32>,
+}
+
+impl DevMap {
+    /// Get access to the DEVMAP or DEVMAP_HASH `map_name`.
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<DevMap> {
+        let map = DynMap::new(xdp, map_name)?;
+        match map.map_type() {
+            MapType::DevMap | MapType::DevMapHash => (),
+            _ => {
+                set_errno(Errno(22));
+                fail!("Map {} is not a DEVMAP or DEVMAP_HASH", map_name);
+            }
+        }
+        if map.key_size() != 4 || (map.value_size() != 4 && map.value_size() != 8) {
+            set_errno(Errno(22));
+            fail!(
+                "Unsupported devmap key/value sizes {}/{}, expected 4/4 or 4/8",
+                map.key_size(),
+                map.value_size()
+            );
+        }
+
+        Ok(DevMap { map })
+    }
+
+    /// Redirect packets sent to `key` out of the interface `if_name`.
+    pub fn set(&self, key: u32, if_name: &str) -> XDPResult<()> {
+        self.write(key, if_name, 0)
+    }
+
+    /// Redirect packets sent to `key` out of the interface `if_name`, running `prog` on them
+    /// first. Requires a map with 8 byte values.
+    pub fn set_with_program(&self, key: u32, if_name: &str, prog: &Program) -> XDPResult<()> {
+        if !self.has_programs() {
+            set_errno(Errno(22));
+            fail!("Devmap values are 4 bytes, per-entry programs need 8 byte values");
+        }
+        self.write(key, if_name, prog.fd())
+    }
+
+    /// The entry at `key`, `None` if there is none.
+    pub fn get(&self, key: u32) -> XDPResult<Option<DevMapEntry>> {
+        match self.map.lookup(&key.to_ne_bytes()) {
+            Ok(v) => Ok(Some(self.entry(&v))),
+            Err(e) if e.code() == 2 => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Remove the entry at `key`.
+    pub fn remove(&self, key: u32) -> XDPResult<()> {
+        self.map.delete(&key.to_ne_bytes())
+    }
+
+    /// All entries, in key order for DEVMAPs.
+    pub fn entries(&self) -> XDPResult<Vec<(u32, DevMapEntry)>> {
+        let mut entries = Vec::new();
+        for k in self.map.keys()? {
+            let key = u32::from_ne_bytes([k[0], k[1], k[2], k[3]]);
+            // Keys of empty DEVMAP slots are listed too.
+            if let Some(e) = self.get(key)? {
+                entries.push((key, e));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Entries whose interface doesn't exist in the current network namespace, e.g. because
+    /// it was removed or the entry was written from another namespace. Packets redirected to
+    /// them are dropped.
+    pub fn stale_entries(&self) -> XDPResult<Vec<(u32, DevMapEntry)>> {
+        let mut entries = self.entries()?;
+        entries.retain(|(_, e)| e.if_name.is_none());
+        Ok(entries)
+    }
+
+    /// Removes the [`stale_entries`](DevMap::stale_entries), returning their keys.
+    pub fn remove_stale(&self) -> XDPResult<Vec<u32>> {
+        let mut keys = Vec::new();
+        for (key, _) in self.stale_entries()? {
+            self.remove(key)?;
+            keys.push(key);
+        }
+
+        Ok(keys)
+    }
+
+    /// True if the map's values can hold a per-entry program.
+    pub fn has_programs(&self) -> bool {
+        self.map.value_size() == 8
+    }
+
+    /// File descriptor for this map.
+    pub fn map_fd(&self) -> i32 {
+        self.map.map_fd()
+    }
+
+    pub fn map_type(&self) -> MapType {
+        self.map.map_type()
+    }
+
+    pub fn max_entries(&self) -> u32 {
+        self.map.max_entries()
+    }
+
+    fn write(&self, key: u32, if_name: &str, prog_fd: i32) -> XDPResult<()> {
+        if self.map_type() == MapType::DevMap && key >= self.max_entries() {
+            set_errno(Errno(7));
+            fail!(
+                "Devmap key {} out of range, max_entries {}",
+                key,
+                self.max_entries()
+            );
+        }
+        let if_index = utils::lookup_interface_by_name(if_name)? as u32;
+
+        // struct bpf_devmap_val { __u32 ifindex; union { int fd; __u32 id; } bpf_prog; }
+        let mut value = if_index.to_ne_bytes().to_vec();
+        if self.has_programs() {
+            value.extend_from_slice(&prog_fd.to_ne_bytes());
+        }
+        self.map
+            .update(&key.to_ne_bytes(), &value, MapFlags::BpfAny)
+    }
+
+    fn entry(&self, value: &[u8]) -> DevMapEntry {
+        let if_index = u32::from_ne_bytes([value[0], value[1], value[2], value[3]]);
+        // The kernel returns the program id in place of the fd.
+        let prog_id = match value.len() {
+            8 => Some(u32::from_ne_bytes([value[4], value[5], value[6], value[7]])),
+            _ => None,
+        };
+
+        DevMapEntry {
+            if_index,
+            if_name: utils::interface_name(if_index),
+            prog_id: prog_id.filter(|id| *id != 0),
+        }
+    }
+}
+
+/// Value of a CPUMAP entry, laid out like the kernel's `struct bpf_cpumap_val`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuMapVal {
+    /// Size of the queue of packets redirected to the CPU. Zero removes the entry on update.
+    pub qsize: u32,
+    /// Program run on the CPU for the redirected packets, loaded with
+    /// [`ExpectedAttachType::CpuMap`](crate::ExpectedAttachType::CpuMap), 0 for none. Lookups
+    /// return the program's id instead of an fd.
+    pub bpf_prog_fd: i32,
+}
+
+unsafe impl NoPadding for CpuMapVal {}
+
+/// Typed handle for CPUMAP maps, which `bpf_redirect_map` uses to move packet processing to
+/// another CPU. Keys are CPU ids.
+///
+/// Maps with 4 byte values only hold the queue size, maps with 8 byte values hold a full
+/// [`CpuMapVal`].
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// use rxdp::redirect::{CpuMap, CpuMapVal};
+///
+/// let m = CpuMap::new(&obj, "cpus").unwrap();
+/// m.set(2, &CpuMapVal { qsize: 2048, bpf_prog_fd: 0 }).unwrap();
+/// m.set_with_program(3, 2048, obj.get_program("on_cpu").unwrap()).unwrap();
+/// ```
+pub struct CpuMap {
+    map: DynMap,
+}
+
+impl CpuMap {
+    /// Get access to the CPUMAP `map_name`.
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<CpuMap> {
+        let map = DynMap::new(xdp, map_name)?;
+        if map.map_type() != MapType::CPUMap {
+            set_errno(Errno(22));
+            fail!("Map {} is not a CPUMAP", map_name);
+        }
+        if map.key_size() != 4 || (map.value_size() != 4 && map.value_size() != 8) {
+            set_errno(Errno(22));
+            fail!(
+                "Unsupported cpumap key/value sizes {}/{}, expected 4/4 or 4/8",
+                map.key_size(),
+                map.value_size()
+            );
+        }
+
+        Ok(CpuMap { map })
+    }
+
+    /// Set the entry of `cpu` to `value`. Fails with `E2BIG` if `cpu` isn't a possible CPU or
+    /// is out of the map's range, and with `EINVAL` if the map can't hold a program.
+    pub fn set(&self, cpu: u32, value: &CpuMapVal) -> XDPResult<()> {
+        let limit = self.max_entries().min(num_cpus() as u32);
+        if cpu >= limit {
+            set_errno(Errno(7));
+            fail!(
+                "CPU {} out of range, max_entries {}, {} possible CPUs",
+                cpu,
+                self.max_entries(),
+                num_cpus()
+            );
+        }
+        if value.bpf_prog_fd != 0 && !self.has_programs() {
+            set_errno(Errno(22));
+            fail!("Cpumap values are 4 bytes, programs need 8 byte values");
+        }
+
+        let bytes = utils::as_bytes(value);
+        self.map.update(
+            &cpu.to_ne_bytes(),
+            &bytes[..self.map.value_size() as usize],
+            MapFlags::BpfAny,
+        )
+    }
+
+    /// Redirect packets sent to `cpu` to a queue of `qsize` packets.
+    pub fn set_queue(&self, cpu: u32, qsize: u32) -> XDPResult<()> {
+        self.set(
+            cpu,
+            &CpuMapVal {
+                qsize,
+                bpf_prog_fd: 0,
+            },
+        )
+    }
+
+    /// Redirect packets sent to `cpu` to a queue of `qsize` packets, and run `prog` on them.
+    pub fn set_with_program(&self, cpu: u32, qsize: u32, prog: &Program) -> XDPResult<()> {
+        self.set(
+            cpu,
+            &CpuMapVal {
+                qsize,
+                bpf_prog_fd: prog.fd(),
+            },
+        )
+    }
+
+    /// The entry of `cpu`, `None` if there is none. `bpf_prog_fd` holds the program id.
+    pub fn get(&self, cpu: u32) -> XDPResult<Option<CpuMapVal>> {
+        let v = match self.map.lookup(&cpu.to_ne_bytes()) {
+            Ok(v) => v,
+            Err(e) if e.code() == 2 => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut value = CpuMapVal::default();
+        utils::as_bytes_mut(&mut value)[..v.len()].copy_from_slice(&v);
+        Ok(Some(value))
+    }
+
+    /// Remove the entry of `cpu`.
+    pub fn remove(&self, cpu: u32) -> XDPResult<()> {
+        self.map.delete(&cpu.to_ne_bytes())
+    }
+
+    /// True if the map's values can hold a program.
+    pub fn has_programs(&self) -> bool {
+        self.map.value_size() == 8
+    }
+
+    /// File descriptor for this map.
+    pub fn map_fd(&self) -> i32 {
+        self.map.map_fd()
+    }
+
+    pub fn max_entries(&self) -> u32 {
+        self.map.max_entries()
+    }
+}
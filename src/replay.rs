@@ -0,0 +1,244 @@
+use errno::{set_errno, Errno};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::error::XDPError;
+use crate::program::{BenchmarkStats, Program};
+use crate::result::XDPResult;
+
+// LINKTYPE_ETHERNET, the only link type XDP programs see.
+const LINKTYPE_ETHERNET: u32 = 1;
+
+// Larger captured lengths mean a corrupt file, no link has frames that big.
+const MAX_PACKET_LEN: u32 = 256 * 1024;
+
+/// The outcome of replaying a capture through a program, see
+/// [`Program::replay_pcap`](crate::Program::replay_pcap).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Number of packets in the capture.
+    pub packets: u64,
+    /// Number of packets per XDP action returned by the program, e.g. `XDP_DROP` (1).
+    pub actions: BTreeMap<u32, u64>,
+    /// Packets the kernel refused to run the program on with `EINVAL`, e.g. because they are
+    /// shorter than an Ethernet header. Other errors stop the replay.
+    pub rejected: u64,
+    /// Packets that were captured partially (snaplen shorter than the packet). The program
+    /// only sees the captured bytes.
+    pub truncated: u64,
+    /// Run time statistics over all packets the program ran on, `None` if there were none.
+    pub latency: Option<BenchmarkStats>,
+}
+
+impl ReplayReport {
+    /// The number of packets the program returned `action` for.
+    pub fn count(&self, action: u32) -> u64 {
+        self.actions.get(&action).copied().unwrap_or(0)
+    }
+}
+
+impl Program {
+    /// Runs the program on every packet of the pcap file at `path` with `BPF_PROG_TEST_RUN`,
+    /// and returns which actions it took and how long it ran. Map updates made by the program
+    /// are kept, as with live traffic.
+    ///
+    /// Only classic pcap files with Ethernet frames are supported, not pcapng. Convert them with
+    /// `editcap -F pcap`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use rxdp;
+    /// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+    /// let prog = obj.get_program("prog_name").unwrap();
+    /// let report = prog.replay_pcap("capture.pcap").unwrap();
+    /// println!("dropped {}/{}", report.count(1), report.packets);
+    /// ```
+    pub fn replay_pcap<P: AsRef<Path>>(&self, path: P) -> XDPResult<ReplayReport> {
+        let path = path.as_ref();
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                set_errno(Errno(e.raw_os_error().unwrap_or(5)));
+                fail!("Error opening {}: {}", path.display(), e);
+            }
+        };
+
+        self.replay_pcap_from(&mut BufReader::new(file))
+    }
+
+    /// Same as [`replay_pcap`](Program::replay_pcap), reading the capture from `reader`.
+    pub fn replay_pcap_from(&self, reader: &mut dyn Read) -> XDPResult<ReplayReport> {
+        let mut pcap = PcapReader::new(reader)?;
+        let mut report = ReplayReport {
+            packets: 0,
+            actions: BTreeMap::new(),
+            rejected: 0,
+            truncated: 0,
+            latency: None,
+        };
+
+        let mut samples = Vec::new();
+        let mut retval = 0;
+        while let Some((packet, orig_len)) = pcap.next_packet()? {
+            report.packets += 1;
+            if (packet.len() as u32) < orig_len {
+                report.truncated += 1;
+            }
+
+            match self.test_run(&packet) {
+                Ok((r, duration)) => {
+                    *report.actions.entry(r).or_default() += 1;
+                    samples.push(duration);
+                    retval = r;
+                }
+                Err(e) if e.code() == 22 => report.rejected += 1,
+                Err(e) => return Err(e),
+            }
+        }
+
+        if !samples.is_empty() {
+            report.latency = Some(BenchmarkStats::from_samples(samples, retval));
+        }
+
+        Ok(report)
+    }
+}
+
+// Reader for classic pcap files, with microsecond or nanosecond timestamps in either byte
+// order.
+struct PcapReader<'a> {
+    reader: &'a mut dyn Read,
+    big_endian: bool,
+}
+
+impl<'a> PcapReader<'a> {
+    fn new(reader: &'a mut dyn Read) -> XDPResult<PcapReader<'a>> {
+        let mut header = [0u8; 24];
+        read_exact(reader, &mut header)?;
+
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let big_endian = match magic {
+            0xa1b2_c3d4 | 0xa1b2_3c4d => false,
+            0xd4c3_b2a1 | 0x4d3c_b2a1 => true,
+            _ => {
+                set_errno(Errno(22));
+                fail!(
+                    "Not a pcap file, magic {:#x} (pcapng isn't supported)",
+                    magic
+                );
+            }
+        };
+
+        let pcap = PcapReader { reader, big_endian };
+        // The upper bits hold the FCS length.
+        let link_type = pcap.u32(&header[20..24]) & 0x0fff_ffff;
+        if link_type != LINKTYPE_ETHERNET {
+            set_errno(Errno(22));
+            fail!(
+                "Unsupported pcap link type {}, expected Ethernet",
+                link_type
+            );
+        }
+
+        Ok(pcap)
+    }
+
+    // The next packet and its original length, `None` at the end of the file.
+    fn next_packet(&mut self) -> XDPResult<Option<(Vec<u8>, u32)>> {
+        let mut header = [0u8; 16];
+        let n = match self.reader.read(&mut header) {
+            Ok(n) => n,
+            Err(e) => {
+                set_errno(Errno(e.raw_os_error().unwrap_or(5)));
+                fail!("Error reading pcap: {}", e);
+            }
+        };
+        if n == 0 {
+            return Ok(None);
+        }
+        read_exact(self.reader, &mut header[n..])?;
+
+        let incl_len = self.u32(&header[8..12]);
+        let orig_len = self.u32(&header[12..16]);
+        if incl_len > MAX_PACKET_LEN {
+            set_errno(Errno(22));
+            fail!("Corrupt pcap, packet of {} bytes", incl_len);
+        }
+
+        let mut packet = vec![0u8; incl_len as usize];
+        read_exact(self.reader, &mut packet)?;
+        Ok(Some((packet, orig_len)))
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        match self.big_endian {
+            true => u32::from_be_bytes(b),
+            false => u32::from_le_bytes(b),
+        }
+    }
+}
+
+fn read_exact(reader: &mut dyn Read, buf: &mut [u8]) -> XDPResult<()> {
+    if let Err(e) = reader.read_exact(buf) {
+        set_errno(Errno(e.raw_os_error().unwrap_or(5)));
+        fail!("Error reading pcap: {}", e);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcap(big_endian: bool, link_type: u32, packets: &[(&[u8], u32)]) -> Vec<u8> {
+        let u32b = |v: u32| match big_endian {
+            true => v.to_be_bytes(),
+            false => v.to_le_bytes(),
+        };
+
+        let mut b = Vec::new();
+        b.extend(u32b(0xa1b2_c3d4));
+        b.extend([0u8; 16]);
+        b.extend(u32b(link_type));
+        for (data, orig_len) in packets {
+            b.extend([0u8; 8]);
+            b.extend(u32b(data.len() as u32));
+            b.extend(u32b(*orig_len));
+            b.extend(data.iter());
+        }
+        b
+    }
+
+    #[test]
+    fn test_pcap_reader() {
+        for big_endian in [false, true] {
+            let file = pcap(big_endian, 1, &[(&[1, 2, 3], 3), (&[4, 5], 60)]);
+            let mut r = file.as_slice();
+            let mut pcap = PcapReader::new(&mut r).unwrap();
+            assert_eq!(pcap.next_packet().unwrap(), Some((vec![1, 2, 3], 3)));
+            assert_eq!(pcap.next_packet().unwrap(), Some((vec![4, 5], 60)));
+            assert_eq!(pcap.next_packet().unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn test_pcap_reader_invalid() {
+        let file = pcap(false, 113, &[]);
+        let err = PcapReader::new(&mut file.as_slice()).err().unwrap();
+        assert_eq!(err.code(), 22);
+
+        let mut file = pcap(false, 1, &[(&[1, 2, 3], 3)]);
+        file[0] = 0x0a;
+        assert!(PcapReader::new(&mut file.as_slice()).is_err());
+
+        let mut file = pcap(false, 1, &[(&[1, 2, 3], 3)]);
+        file.pop();
+        let mut r = file.as_slice();
+        let mut pcap = PcapReader::new(&mut r).unwrap();
+        assert!(pcap.next_packet().is_err());
+    }
+}
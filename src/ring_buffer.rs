@@ -0,0 +1,279 @@
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::error::XDPError;
+use crate::map_common as mc;
+use crate::result::XDPResult;
+use crate::{MapType, XDPLoadedObject};
+
+const BPF_RINGBUF_BUSY_BIT: u32 = 1 << 31;
+const BPF_RINGBUF_DISCARD_BIT: u32 = 1 << 30;
+const BPF_RINGBUF_HDR_SZ: u64 = 8;
+
+/// Consumer for one or more `BPF_MAP_TYPE_RINGBUF` maps.
+///
+/// Records are passed to a callback together with the index of the ring they came from (as
+/// returned by [`add`](RingBuffer::add)). Use [`poll`](RingBuffer::poll) to wait for records,
+/// [`consume_nowait`](RingBuffer::consume_nowait) to drain whatever is available without
+/// blocking, or register [`epoll_fd`](RingBuffer::epoll_fd) with an existing event loop.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let mut rb = rxdp::RingBuffer::new(&obj, "events").unwrap();
+/// loop {
+///     rb.poll(100, |_ring, data| println!("record: {:?}", data)).unwrap();
+/// }
+/// ```
+pub struct RingBuffer {
+    rings: Vec<Ring>,
+    epoll_fd: i32,
+    events: Vec<libc::epoll_event>,
+}
+
+struct Ring {
+    name: String,
+    mask: u64,
+    consumer: *mut c_void,
+    producer: *mut c_void,
+    producer_len: usize,
+    consumed: u64,
+    consumed_bytes: u64,
+}
+
+/// Statistics for a single ring, see [`RingBuffer::stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingStats {
+    /// Name of the ring buffer map.
+    pub name: String,
+    /// Number of records passed to the callback.
+    pub consumed: u64,
+    /// Total size of the records passed to the callback, in bytes.
+    pub consumed_bytes: u64,
+    /// Bytes committed or reserved by the producer but not consumed yet.
+    pub pending_bytes: u64,
+    /// Records dropped because the ring was full. The kernel doesn't track drops for ring
+    /// buffers (`bpf_ringbuf_output` returns an error to the eBPF program instead), so this
+    /// is `None` unless the eBPF side counts them itself.
+    pub dropped: Option<u64>,
+}
+
+impl RingBuffer {
+    /// Create a consumer for the ring buffer map `map_name`.
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<RingBuffer> {
+        let epoll_fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if epoll_fd < 0 {
+            fail!("Error creating epoll instance");
+        }
+
+        let mut rb = RingBuffer {
+            rings: Vec::new(),
+            epoll_fd,
+            events: Vec::new(),
+        };
+        rb.add(xdp, map_name)?;
+
+        Ok(rb)
+    }
+
+    /// Add the ring buffer map `map_name` to this consumer. Returns the index of the ring,
+    /// which is passed to the callback for records from this ring.
+    pub fn add(&mut self, xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<usize> {
+        let def = mc::find_map(xdp, map_name)?;
+        if def.map_type != MapType::RingBuffer {
+            return mc::improper_type(map_name, def.map_type);
+        }
+
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let consumer = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                page_size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                def.fd,
+                0,
+            )
+        };
+        if consumer == libc::MAP_FAILED {
+            fail!("Error mapping ring buffer consumer page");
+        }
+
+        // The data pages are mapped twice in a row, so records wrapping around the end of the
+        // ring can be read as a contiguous slice.
+        let producer_len = page_size + 2 * def.max_entries as usize;
+        let producer = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                producer_len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                def.fd,
+                page_size as libc::off_t,
+            )
+        };
+        if producer == libc::MAP_FAILED {
+            let err = XDPError::new("Error mapping ring buffer data pages");
+            unsafe { libc::munmap(consumer, page_size) };
+            return Err(err);
+        }
+
+        let ring = Ring {
+            name: map_name.to_string(),
+            mask: def.max_entries as u64 - 1,
+            consumer,
+            producer,
+            producer_len,
+            consumed: 0,
+            consumed_bytes: 0,
+        };
+
+        let index = self.rings.len();
+        let mut event = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: index as u64,
+        };
+        // The ring is pushed first, so it gets unmapped on error.
+        self.rings.push(ring);
+        let rc = unsafe { libc::epoll_ctl(self.epoll_fd, libc::EPOLL_CTL_ADD, def.fd, &mut event) };
+        if rc < 0 {
+            self.rings.pop();
+            fail!("Error adding ring buffer to epoll");
+        }
+        self.events.push(libc::epoll_event { events: 0, u64: 0 });
+
+        Ok(index)
+    }
+
+    /// Wait up to `time_ms` milliseconds (-1 to wait indefinitely) for records, and pass all
+    /// available records of the rings that have data to `f`. Returns the number of records
+    /// consumed.
+    pub fn poll<F>(&mut self, time_ms: i32, mut f: F) -> XDPResult<usize>
+    where
+        F: FnMut(usize, &[u8]),
+    {
+        let n = unsafe {
+            libc::epoll_wait(
+                self.epoll_fd,
+                self.events.as_mut_ptr(),
+                self.events.len() as i32,
+                time_ms,
+            )
+        };
+        if n < 0 {
+            fail!("Error polling ring buffer");
+        }
+
+        let mut count = 0;
+        for i in 0..n as usize {
+            let index = self.events[i].u64 as usize;
+            count += self.rings[index].consume(index, &mut f);
+        }
+
+        Ok(count)
+    }
+
+    /// Pass all available records of all rings to `f` without waiting. Returns the number of
+    /// records consumed.
+    pub fn consume_nowait<F>(&mut self, mut f: F) -> XDPResult<usize>
+    where
+        F: FnMut(usize, &[u8]),
+    {
+        let mut count = 0;
+        for (index, ring) in self.rings.iter_mut().enumerate() {
+            count += ring.consume(index, &mut f);
+        }
+
+        Ok(count)
+    }
+
+    /// The epoll file descriptor that becomes readable when any of the rings has data. Can be
+    /// registered with an external event loop, followed by
+    /// [`consume_nowait`](RingBuffer::consume_nowait) once it is readable.
+    pub fn epoll_fd(&self) -> i32 {
+        self.epoll_fd
+    }
+
+    /// Returns statistics for each ring, in the order the rings were added.
+    pub fn stats(&self) -> Vec<RingStats> {
+        self.rings
+            .iter()
+            .map(|r| RingStats {
+                name: r.name.clone(),
+                consumed: r.consumed,
+                consumed_bytes: r.consumed_bytes,
+                pending_bytes: r.producer_pos().saturating_sub(r.consumer_pos()),
+                dropped: None,
+            })
+            .collect()
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        self.rings.clear();
+        unsafe { libc::close(self.epoll_fd) };
+    }
+}
+
+impl Ring {
+    fn consumer_pos(&self) -> u64 {
+        unsafe { (*(self.consumer as *const AtomicU64)).load(Ordering::Acquire) }
+    }
+
+    fn producer_pos(&self) -> u64 {
+        unsafe { (*(self.producer as *const AtomicU64)).load(Ordering::Acquire) }
+    }
+
+    fn data(&self) -> *const u8 {
+        let page_size = self.producer_len - 2 * (self.mask as usize + 1);
+        unsafe { (self.producer as *const u8).add(page_size) }
+    }
+
+    fn consume<F: FnMut(usize, &[u8])>(&mut self, index: usize, f: &mut F) -> usize {
+        let mut count = 0;
+        let mut cons = self.consumer_pos();
+        loop {
+            let prod = self.producer_pos();
+            if cons >= prod {
+                break;
+            }
+
+            let hdr = unsafe { self.data().add((cons & self.mask) as usize) };
+            let len = unsafe { (*(hdr as *const AtomicU32)).load(Ordering::Acquire) };
+            if len & BPF_RINGBUF_BUSY_BIT != 0 {
+                break;
+            }
+
+            let data_len = len & !BPF_RINGBUF_DISCARD_BIT;
+            if len & BPF_RINGBUF_DISCARD_BIT == 0 {
+                let data = unsafe {
+                    std::slice::from_raw_parts(
+                        hdr.add(BPF_RINGBUF_HDR_SZ as usize),
+                        data_len as usize,
+                    )
+                };
+                f(index, data);
+                count += 1;
+                self.consumed += 1;
+                self.consumed_bytes += data_len as u64;
+            }
+
+            cons += (data_len as u64 + BPF_RINGBUF_HDR_SZ + 7) & !7;
+            unsafe { (*(self.consumer as *const AtomicU64)).store(cons, Ordering::Release) };
+        }
+
+        count
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        let page_size = self.producer_len - 2 * (self.mask as usize + 1);
+        unsafe {
+            libc::munmap(self.consumer, page_size);
+            libc::munmap(self.producer, self.producer_len);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use crossbeam_channel::{bounded, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::error::XDPError;
+use crate::map_common::MapLike;
+use crate::raw::{self, Attr, Cmd};
+use crate::result::XDPResult;
+use crate::{sys, ByteAligned, Map, PerCpuMap, Program};
+
+// BPF_STATS_RUN_TIME, the only `enum bpf_stats_type`.
+const BPF_STATS_RUN_TIME: u32 = 0;
+
+type Counter = Box<dyn FnMut() -> XDPResult<u64> + Send>;
+
+/// Builder for a [`Sampler`], which reads program run stats and map counters on a background
+/// thread every `interval` and hands them to a callback as one [`StatsSnapshot`].
+///
+/// Program run counts and times are only updated by the kernel while stats are enabled, either
+/// with the `kernel.bpf_stats_enabled` sysctl or with
+/// [`enable_stats`](SamplerBuilder::enable_stats).
+///
+/// # Example
+/// ```no_run
+/// use rxdp::{Map, SamplerBuilder};
+/// use std::time::Duration;
+///
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let counters: Map<u32, u64> = Map::new(&obj, "counters").unwrap();
+/// let sampler = SamplerBuilder::new(Duration::from_secs(1))
+///     .enable_stats()
+///     .program("main", obj.get_program("xdp_main").unwrap())
+///     .unwrap()
+///     .map_counter("dropped", &counters, 0)
+///     .unwrap()
+///     .spawn(|s| {
+///         let main = s.program("main").unwrap();
+///         println!("{} runs, {:?} dropped", main.run_cnt, s.counter("dropped"));
+///     })
+///     .unwrap();
+///
+/// // ...
+/// sampler.stop();
+/// ```
+pub struct SamplerBuilder {
+    enable_stats: bool,
+    sampling: Sampling,
+}
+
+/// The stats read by a [`Sampler`] at one point in time. Stats that couldn't be read are
+/// missing, and listed in `errors` instead.
+#[derive(Debug)]
+pub struct StatsSnapshot {
+    /// When the stats were read.
+    pub taken_at: Instant,
+    /// Time since the previous snapshot, or since the sampler started for the first one.
+    pub elapsed: Duration,
+    /// Run stats of the sampled programs, in the order they were added.
+    pub programs: Vec<ProgStats>,
+    /// Values of the sampled counters, in the order they were added.
+    pub counters: Vec<(String, u64)>,
+    /// The names of the stats that couldn't be read, with the error.
+    pub errors: Vec<(String, XDPError)>,
+}
+
+/// Cumulative run stats of a program, as kept by the kernel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgStats {
+    /// The name the program was added to the sampler with.
+    pub name: String,
+    /// Number of times the program ran.
+    pub run_cnt: u64,
+    /// Total time the program ran, in nanoseconds.
+    pub run_time_ns: u64,
+}
+
+impl StatsSnapshot {
+    /// The run stats of the program added as `name`.
+    pub fn program(&self, name: &str) -> Option<&ProgStats> {
+        self.programs.iter().find(|p| p.name == name)
+    }
+
+    /// The value of the counter added as `name`.
+    pub fn counter(&self, name: &str) -> Option<u64> {
+        self.counters
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| *v)
+    }
+}
+
+impl SamplerBuilder {
+    /// Sample every `interval`.
+    pub fn new(interval: Duration) -> SamplerBuilder {
+        SamplerBuilder {
+            enable_stats: false,
+            sampling: Sampling {
+                interval,
+                stats_fd: None,
+                programs: Vec::new(),
+                counters: Vec::new(),
+            },
+        }
+    }
+
+    /// Have the kernel collect program run stats while the sampler is running. Requires
+    /// `CAP_SYS_ADMIN` and kernel 5.8 or later, otherwise [`spawn`](SamplerBuilder::spawn)
+    /// fails.
+    pub fn enable_stats(mut self) -> Self {
+        self.enable_stats = true;
+        self
+    }
+
+    /// Sample the run stats of `prog` as `name`. The sampler holds its own reference to the
+    /// program, so it can outlive the object `prog` belongs to.
+    pub fn program(mut self, name: &str, prog: &Program) -> XDPResult<Self> {
+        let fd = unsafe { libc::fcntl(prog.fd(), libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            fail!("Error duplicating fd of program {}", name);
+        }
+        self.sampling.programs.push((name.to_string(), fd));
+        Ok(self)
+    }
+
+    /// Sample the value of `key` in `map` as `name`. For per-cpu maps, the value is the sum of
+    /// all CPUs. Like [`program`](SamplerBuilder::program), the sampler holds its own reference
+    /// to the map.
+    pub fn map_counter<K, V, M>(mut self, name: &str, map: &M, key: K) -> XDPResult<Self>
+    where
+        K: Default + Copy + Send + 'static,
+        V: ByteAligned + Into<u64> + Send + 'static,
+        M: MapLike<K, V>,
+    {
+        let counter: Counter = match map.map_type().is_per_cpu() {
+            true => Box::new(lookup_sum(PerCpuMap::<K, V>::from_fd(map.map_fd())?, key)),
+            false => Box::new(lookup_sum(Map::<K, V>::from_fd(map.map_fd())?, key)),
+        };
+        self.sampling.counters.push((name.to_string(), counter));
+        Ok(self)
+    }
+
+    /// Sample the value returned by `f` as `name`, e.g. for counters that are computed from
+    /// several map entries.
+    pub fn counter<F>(mut self, name: &str, f: F) -> Self
+    where
+        F: FnMut() -> XDPResult<u64> + Send + 'static,
+    {
+        self.sampling.counters.push((name.to_string(), Box::new(f)));
+        self
+    }
+
+    /// Start sampling on a background thread, calling `on_sample` with each snapshot. The
+    /// first snapshot is taken after one interval.
+    pub fn spawn<F>(self, on_sample: F) -> XDPResult<Sampler>
+    where
+        F: FnMut(StatsSnapshot) + Send + 'static,
+    {
+        let mut sampling = self.sampling;
+        if self.enable_stats {
+            // Stats stay enabled until the returned fd is closed.
+            let mut attr = Attr::new().u32(0, BPF_STATS_RUN_TIME);
+            sampling.stats_fd = Some(unsafe { raw::bpf(Cmd::EnableStats, &mut attr) }?);
+        }
+
+        let (stop, stopped) = bounded::<()>(0);
+        let mut on_sample = on_sample;
+        let thread = std::thread::spawn(move || {
+            let mut last = Instant::now();
+            // Stops once the `Sampler` drops its side of the channel.
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(sampling.interval) {
+                let snapshot = sampling.sample(last);
+                last = snapshot.taken_at;
+                on_sample(snapshot);
+            }
+        });
+
+        Ok(Sampler {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Handle to a running sampler, see [`SamplerBuilder`]. Sampling stops when it is dropped.
+pub struct Sampler {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Sampler {
+    /// Stop sampling, waiting for a callback in progress to return.
+    pub fn stop(self) {}
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+// What to sample, owned by the sampling thread once it is spawned. Closes the fds it holds.
+struct Sampling {
+    interval: Duration,
+    stats_fd: Option<i32>,
+    programs: Vec<(String, i32)>,
+    counters: Vec<(String, Counter)>,
+}
+
+impl Sampling {
+    fn sample(&mut self, last: Instant) -> StatsSnapshot {
+        let mut snapshot = StatsSnapshot {
+            taken_at: Instant::now(),
+            elapsed: Duration::default(),
+            programs: Vec::with_capacity(self.programs.len()),
+            counters: Vec::with_capacity(self.counters.len()),
+            errors: Vec::new(),
+        };
+
+        for (name, fd) in &self.programs {
+            match sys::prog_info_fd(*fd, None) {
+                Ok(info) => snapshot.programs.push(ProgStats {
+                    name: name.clone(),
+                    run_cnt: info.run_cnt,
+                    run_time_ns: info.run_time_ns,
+                }),
+                Err(e) => snapshot.errors.push((name.clone(), e)),
+            }
+        }
+        for (name, f) in self.counters.iter_mut() {
+            match f() {
+                Ok(v) => snapshot.counters.push((name.clone(), v)),
+                Err(e) => snapshot.errors.push((name.clone(), e)),
+            }
+        }
+
+        snapshot.elapsed = snapshot.taken_at.duration_since(last);
+        snapshot
+    }
+}
+
+impl Drop for Sampling {
+    fn drop(&mut self) {
+        for (_, fd) in &self.programs {
+            unsafe { libc::close(*fd) };
+        }
+        if let Some(fd) = self.stats_fd {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+// Reads `key` from `map`, summing the values of all CPUs for per-cpu maps.
+fn lookup_sum<K, V, M>(map: M, key: K) -> impl FnMut() -> XDPResult<u64> + Send
+where
+    K: Send + 'static,
+    V: Default + Into<u64>,
+    M: MapLike<K, V> + Send + 'static,
+{
+    move || {
+        let values = map.lookup(&key)?.into_vec();
+        Ok(values.into_iter().map(Into::into).sum())
+    }
+}
@@ -0,0 +1,269 @@
+//! Self-tests to measure eBPF map performance on the current host.
+//!
+//! The tests create a scratch map and run the same syscalls used by [`Map`](crate::Map) and
+//! [`PerCpuMap`](crate::PerCpuMap), so the results reflect what those will achieve, e.g. to
+//! validate a host after a kernel upgrade.
+use errno::{set_errno, Errno};
+use std::fmt;
+use std::os::raw::c_void;
+use std::time::{Duration, Instant};
+
+use crate::error::XDPError;
+use crate::map_batch::{is_batching_supported, BATCH_OPTS, BATCH_SIZE};
+use crate::map_common as mc;
+use crate::percpu_codec::align;
+use crate::percpu_map::num_cpus;
+use crate::{MapFlags, MapType, XDPResult};
+
+/// Number of operations of a single kind, and how long they took.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throughput {
+    pub ops: u64,
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    pub fn ops_per_sec(&self) -> f64 {
+        self.ops as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ops in {:?} ({:.0} ops/s)",
+            self.ops,
+            self.elapsed,
+            self.ops_per_sec()
+        )
+    }
+}
+
+/// Result of [`map_throughput`]. Batch results are `None` if the kernel doesn't support batch
+/// operations on the map type.
+#[derive(Debug, Clone)]
+pub struct ThroughputReport {
+    pub map_type: MapType,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub entries: u32,
+    pub update: Throughput,
+    pub lookup: Throughput,
+    pub update_batch: Option<Throughput>,
+    pub lookup_batch: Option<Throughput>,
+}
+
+impl fmt::Display for ThroughputReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:?}, key size {}, value size {}, {} entries",
+            self.map_type, self.key_size, self.value_size, self.entries
+        )?;
+        writeln!(f, "  update:       {}", self.update)?;
+        writeln!(f, "  lookup:       {}", self.lookup)?;
+        for (name, t) in [
+            ("update_batch", self.update_batch),
+            ("lookup_batch", self.lookup_batch),
+        ] {
+            match t {
+                Some(t) => writeln!(f, "  {}: {}", name, t)?,
+                None => writeln!(f, "  {}: not supported", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Measure the update, lookup and batch update/lookup throughput of a map of `map_type`, with
+/// `entries` elements of the given key and value sizes. The map is created (and removed) by the
+/// test, so this requires the same privileges as [`Map::create`](crate::Map::create).
+///
+/// Supported map types are the (per-cpu) hash, array and LRU hash maps. Keys must be at least 4
+/// bytes, and exactly 4 for array maps.
+///
+/// # Example
+/// ```no_run
+/// use rxdp::{selftest, MapType};
+///
+/// let report = selftest::map_throughput(MapType::LRUHash, 16, 64, 100_000).unwrap();
+/// println!("{}", report);
+/// ```
+pub fn map_throughput(
+    map_type: MapType,
+    key_size: u32,
+    value_size: u32,
+    entries: u32,
+) -> XDPResult<ThroughputReport> {
+    match map_type {
+        MapType::Hash
+        | MapType::Array
+        | MapType::PerCPUHash
+        | MapType::PerCPUArray
+        | MapType::LRUHash
+        | MapType::LRUPerCPUHash => (),
+        _ => {
+            set_errno(Errno(22));
+            fail!("Unsupported map type for throughput test: {:?}", map_type);
+        }
+    }
+    if key_size < 4 || (map_type.is_array() && key_size != 4) || value_size == 0 || entries == 0 {
+        set_errno(Errno(22));
+        fail!("Invalid key size, value size or number of entries");
+    }
+
+    let map_fd = mc::create_map(map_type, key_size, value_size, entries, 0);
+    mc::check_rc(map_fd, (), "Error creating new map")?;
+
+    let test = ThroughputTest::new(map_fd, map_type, key_size, value_size, entries);
+    let report = test.run();
+    unsafe { libc::close(map_fd) };
+
+    report
+}
+
+struct ThroughputTest {
+    map_fd: i32,
+    map_type: MapType,
+    key_size: usize,
+    value_size: u32,
+    // Size of the value in the syscalls, i.e. for all CPUs of a per-cpu map.
+    value_len: usize,
+    entries: u32,
+    // Keys and values of all entries, in order.
+    keys: Vec<u8>,
+    values: Vec<u8>,
+}
+
+impl ThroughputTest {
+    fn new(
+        map_fd: i32,
+        map_type: MapType,
+        key_size: u32,
+        value_size: u32,
+        entries: u32,
+    ) -> ThroughputTest {
+        let value_len = match map_type.is_per_cpu() {
+            true => align(value_size) * num_cpus(),
+            false => value_size as usize,
+        };
+
+        let mut keys = vec![0u8; key_size as usize * entries as usize];
+        for (i, k) in keys.chunks_exact_mut(key_size as usize).enumerate() {
+            k[..4].copy_from_slice(&(i as u32).to_le_bytes());
+        }
+        let values = (0..value_len * entries as usize).map(|i| i as u8).collect();
+
+        ThroughputTest {
+            map_fd,
+            map_type,
+            key_size: key_size as usize,
+            value_size,
+            value_len,
+            entries,
+            keys,
+            values,
+        }
+    }
+
+    fn run(&self) -> XDPResult<ThroughputReport> {
+        let update = self.update()?;
+        let lookup = self.lookup()?;
+
+        // Batch lookups of per-cpu arrays aren't supported, see `MapLike::lookup_batch`.
+        let batching = is_batching_supported() && self.map_type != MapType::PerCPUArray;
+        let (update_batch, lookup_batch) = match batching {
+            true => (Some(self.update_batch()?), Some(self.lookup_batch()?)),
+            false => (None, None),
+        };
+
+        Ok(ThroughputReport {
+            map_type: self.map_type,
+            key_size: self.key_size as u32,
+            value_size: self.value_size,
+            entries: self.entries,
+            update,
+            lookup,
+            update_batch,
+            lookup_batch,
+        })
+    }
+
+    fn key(&self, i: usize) -> *const c_void {
+        self.keys[i * self.key_size..].as_ptr() as *const c_void
+    }
+
+    fn update(&self) -> XDPResult<Throughput> {
+        let start = Instant::now();
+        for i in 0..self.entries as usize {
+            let value = self.values[i * self.value_len..].as_ptr() as *const c_void;
+            let rc = mc::update_elem(self.map_fd, self.key(i), value, MapFlags::BpfAny.bits());
+            mc::check_rc(rc, (), "Error updating elem")?;
+        }
+
+        Ok(throughput(self.entries, start))
+    }
+
+    fn lookup(&self) -> XDPResult<Throughput> {
+        let mut value = vec![0u8; self.value_len];
+        let start = Instant::now();
+        for i in 0..self.entries as usize {
+            let rc = mc::lookup_elem(self.map_fd, self.key(i), value.as_mut_ptr() as *mut c_void);
+            mc::check_rc(rc, (), "Error looking up elem")?;
+        }
+
+        Ok(throughput(self.entries, start))
+    }
+
+    fn update_batch(&self) -> XDPResult<Throughput> {
+        let start = Instant::now();
+        for i in (0..self.entries).step_by(BATCH_SIZE as usize) {
+            let mut count = BATCH_SIZE.min(self.entries - i);
+            let rc = mc::update_batch(
+                self.map_fd,
+                self.key(i as usize) as *mut c_void,
+                self.values[i as usize * self.value_len..].as_ptr() as *mut c_void,
+                &mut count,
+                &BATCH_OPTS,
+            );
+            mc::check_rc(rc, (), "Error updating batch of elements")?;
+        }
+
+        Ok(throughput(self.entries, start))
+    }
+
+    fn lookup_batch(&self) -> XDPResult<Throughput> {
+        let mut keys = vec![0u8; BATCH_SIZE as usize * self.key_size];
+        let mut vals = vec![0u8; BATCH_SIZE as usize * self.value_len];
+        let mut next_key = None;
+        let mut ops = 0;
+
+        let start = Instant::now();
+        loop {
+            let r = mc::lookup_batch_prealloc(
+                self.map_fd,
+                BATCH_SIZE,
+                self.key_size,
+                next_key.as_deref(),
+                &mut keys,
+                &mut vals,
+                false,
+            )?;
+            ops += r.num_items;
+            next_key = r.next_key;
+            if next_key.is_none() || (self.map_type.is_array() && ops >= self.entries) {
+                break;
+            }
+        }
+
+        Ok(throughput(ops, start))
+    }
+}
+
+fn throughput(ops: u32, start: Instant) -> Throughput {
+    Throughput {
+        ops: ops as u64,
+        elapsed: start.elapsed(),
+    }
+}
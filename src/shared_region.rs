@@ -0,0 +1,220 @@
+use errno::{set_errno, Errno};
+use std::marker::PhantomData;
+use std::mem::{align_of, size_of};
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicU32, AtomicU64};
+
+use crate::error::XDPError;
+use crate::map_common as mc;
+use crate::map_compat;
+use crate::object::XDPLoadedObject;
+use crate::padding::NoPadding;
+use crate::result::XDPResult;
+use crate::MapType;
+
+/// A `BPF_MAP_TYPE_ARRAY` map created with `BPF_F_MMAPABLE`, mapped into user space as a region
+/// of memory shared with eBPF programs, e.g. for stats or control knobs that are read and
+/// written on every packet without a syscall.
+///
+/// Values are accessed in place. [`read`](SharedRegion::read) and
+/// [`write`](SharedRegion::write) copy a whole slot with volatile accesses, which aren't torn
+/// for naturally aligned fields of up to 8 bytes, but aren't atomic as a whole. Fields that are
+/// updated concurrently (e.g. with `__sync_fetch_and_add` on the eBPF side) should be accessed
+/// with [`atomic_u64`](SharedRegion::atomic_u64) or [`atomic_u32`](SharedRegion::atomic_u32)
+/// instead.
+///
+/// # Example
+/// ```no_run
+/// use std::sync::atomic::Ordering;
+///
+/// #[derive(Default, Clone, Copy)]
+/// #[repr(C)]
+/// struct Stats {
+///     packets: u64,
+///     sample_rate: u32,
+///     _pad: u32,
+/// }
+/// rxdp::no_padding!(Stats { packets: u64, sample_rate: u32, _pad: u32 });
+///
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let region: rxdp::SharedRegion<Stats> = rxdp::SharedRegion::new(&obj, "stats").unwrap();
+/// let packets = region.atomic_u64(0, 0).unwrap();
+/// println!("{} packets", packets.load(Ordering::Relaxed));
+///
+/// let mut stats = region.read(0).unwrap();
+/// stats.sample_rate = 100;
+/// region.write(0, &stats).unwrap();
+/// ```
+pub struct SharedRegion<V> {
+    map_fd: i32,
+    owned: bool,
+    data: *mut c_void,
+    data_len: usize,
+    elem_size: usize,
+    max_entries: u32,
+    _val: PhantomData<V>,
+}
+
+// The region is only accessed through volatile and atomic operations.
+unsafe impl<V: Send> Send for SharedRegion<V> {}
+
+impl<V: NoPadding> SharedRegion<V> {
+    /// Create a new shared array with `max_entries` slots.
+    pub fn create(max_entries: u32) -> XDPResult<SharedRegion<V>> {
+        let map_fd = mc::create_map(
+            MapType::Array,
+            4,
+            size_of::<V>() as u32,
+            max_entries,
+            libbpf_sys::BPF_F_MMAPABLE,
+        );
+        mc::check_rc(map_fd, (), "Error creating new map")?;
+
+        SharedRegion::map(map_fd, true, max_entries).inspect_err(|_| unsafe {
+            libc::close(map_fd);
+        })
+    }
+
+    /// Get access to the shared array `map_name`, which must be defined with
+    /// `BPF_F_MMAPABLE`. This will fail if the requested value size doesn't match the value size
+    /// defined in the ELF file.
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<SharedRegion<V>> {
+        let def = mc::validate_map::<u32>(xdp, map_name)?;
+        if def.map_type != MapType::Array {
+            set_errno(Errno(22));
+            fail!(
+                "Improper map type {:?} for {}, expected Array",
+                def.map_type,
+                map_name
+            );
+        }
+
+        let req_val_size = size_of::<V>() as u32;
+        if req_val_size != def.value_size {
+            fail!(
+                "Incorrect value size, XDP map has size: {}, requested value size is {}.",
+                def.value_size,
+                req_val_size,
+            );
+        }
+
+        let info = map_compat::map_info(def.fd)?;
+        if info.map_flags & libbpf_sys::BPF_F_MMAPABLE == 0 {
+            set_errno(Errno(22));
+            fail!(
+                "Map {} isn't mmapable, it needs the BPF_F_MMAPABLE flag",
+                map_name
+            );
+        }
+
+        SharedRegion::map(def.fd, false, def.max_entries)
+    }
+
+    fn map(map_fd: i32, owned: bool, max_entries: u32) -> XDPResult<SharedRegion<V>> {
+        // Array elements are 8 byte aligned.
+        let elem_size = (size_of::<V>() + 7) & !7;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let data_len = (elem_size * max_entries as usize + page_size - 1) & !(page_size - 1);
+        let data = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                data_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                map_fd,
+                0,
+            )
+        };
+        if data == libc::MAP_FAILED {
+            fail!("Error mapping shared array");
+        }
+
+        Ok(SharedRegion {
+            map_fd,
+            owned,
+            data,
+            data_len,
+            elem_size,
+            max_entries,
+            _val: PhantomData,
+        })
+    }
+
+    /// Copy the value of slot `index`.
+    pub fn read(&self, index: u32) -> XDPResult<V> {
+        let ptr = self.slot(index)? as *const V;
+        Ok(unsafe { std::ptr::read_volatile(ptr) })
+    }
+
+    /// Overwrite slot `index` with `value`.
+    pub fn write(&self, index: u32, value: &V) -> XDPResult<()> {
+        let ptr = self.slot(index)? as *mut V;
+        unsafe { std::ptr::write_volatile(ptr, *value) };
+        Ok(())
+    }
+
+    /// The `u64` at byte `offset` of slot `index`, for atomic accesses. Fails with `EINVAL` if
+    /// it isn't within the value or isn't 8 byte aligned.
+    pub fn atomic_u64(&self, index: u32, offset: usize) -> XDPResult<&AtomicU64> {
+        let ptr = self.field(index, offset, size_of::<u64>(), align_of::<AtomicU64>())?;
+        Ok(unsafe { &*(ptr as *const AtomicU64) })
+    }
+
+    /// The `u32` at byte `offset` of slot `index`, for atomic accesses. Fails with `EINVAL` if
+    /// it isn't within the value or isn't 4 byte aligned.
+    pub fn atomic_u32(&self, index: u32, offset: usize) -> XDPResult<&AtomicU32> {
+        let ptr = self.field(index, offset, size_of::<u32>(), align_of::<AtomicU32>())?;
+        Ok(unsafe { &*(ptr as *const AtomicU32) })
+    }
+
+    /// File descriptor for this map.
+    pub fn map_fd(&self) -> i32 {
+        self.map_fd
+    }
+
+    /// The number of slots.
+    pub fn max_entries(&self) -> u32 {
+        self.max_entries
+    }
+
+    fn slot(&self, index: u32) -> XDPResult<*mut u8> {
+        if index >= self.max_entries {
+            set_errno(Errno(7));
+            fail!(
+                "Index {} out of range, the map has {} slots",
+                index,
+                self.max_entries
+            );
+        }
+
+        Ok(unsafe { (self.data as *mut u8).add(index as usize * self.elem_size) })
+    }
+
+    fn field(&self, index: u32, offset: usize, size: usize, align: usize) -> XDPResult<*mut u8> {
+        let slot = self.slot(index)?;
+        let ptr = slot.wrapping_add(offset);
+        let in_value = offset
+            .checked_add(size)
+            .is_some_and(|end| end <= size_of::<V>());
+        if !in_value || !(ptr as usize).is_multiple_of(align) {
+            set_errno(Errno(22));
+            fail!(
+                "Invalid field at offset {}, size {} of a {} byte value",
+                offset,
+                size,
+                size_of::<V>()
+            );
+        }
+
+        Ok(ptr)
+    }
+}
+
+impl<V> Drop for SharedRegion<V> {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.data, self.data_len) };
+        if self.owned {
+            unsafe { libc::close(self.map_fd) };
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use errno::{set_errno, Errno};
+
+use crate::error::XDPError;
+use crate::map_common::{self as mc, MapLike};
+use crate::object::XDPLoadedObject;
+use crate::padding::NoPadding;
+use crate::result::XDPResult;
+use crate::utils;
+use crate::{Map, MapFlags, MapType};
+
+/// `struct bpf_spin_lock`, for map values that BPF programs update under a lock.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct BpfSpinLock {
+    val: u32,
+}
+
+/// `struct bpf_timer`, for map values that hold a timer armed by a BPF program.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(C, align(8))]
+pub struct BpfTimer {
+    opaque: [u64; 2],
+}
+
+unsafe impl NoPadding for BpfSpinLock {}
+unsafe impl NoPadding for BpfTimer {}
+
+/// A field of a map value that is owned by the kernel, see [`SpecialFields`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SpecialField {
+    SpinLock,
+    Timer,
+}
+
+impl SpecialField {
+    /// Size of the field in the value.
+    pub fn size(&self) -> usize {
+        match *self {
+            SpecialField::SpinLock => std::mem::size_of::<BpfSpinLock>(),
+            SpecialField::Timer => std::mem::size_of::<BpfTimer>(),
+        }
+    }
+}
+
+#[doc(hidden)]
+pub trait SpecialFieldType {
+    const KIND: SpecialField;
+}
+
+impl SpecialFieldType for BpfSpinLock {
+    const KIND: SpecialField = SpecialField::SpinLock;
+}
+
+impl SpecialFieldType for BpfTimer {
+    const KIND: SpecialField = SpecialField::Timer;
+}
+
+/// The [`BpfSpinLock`] and [`BpfTimer`] fields of a map value, by offset.
+///
+/// The kernel owns these fields: it skips them when copying a value in from user space, and
+/// zeroes them when copying one out. Implement it for your own `#[repr(C)]` structs with
+/// [`special_fields!`](crate::special_fields), and access the map with a [`SlotArray`].
+///
+/// # Safety
+/// `FIELDS` must list every spin lock and timer of the type, at their offsets.
+pub unsafe trait SpecialFields {
+    const FIELDS: &'static [(usize, SpecialField)];
+}
+
+/// Implements [`SpecialFields`](crate::SpecialFields) for a `#[repr(C)]` struct, listing its
+/// [`BpfSpinLock`](crate::BpfSpinLock) and [`BpfTimer`](crate::BpfTimer) fields. Fails to
+/// compile if a field is missing or has a different type.
+///
+/// # Example
+/// ```
+/// use rxdp::{BpfSpinLock, BpfTimer};
+///
+/// #[derive(Default, Clone, Copy)]
+/// #[repr(C)]
+/// struct Session {
+///     lock: BpfSpinLock,
+///     packets: u32,
+///     expiry: BpfTimer,
+/// }
+///
+/// rxdp::special_fields!(Session { lock: BpfSpinLock, expiry: BpfTimer });
+/// ```
+#[macro_export]
+macro_rules! special_fields {
+    ($t:ident { $($field:ident : $ft:ty),+ $(,)? }) => {
+        const _: fn(&$t) = |v| {
+            $(let _: &$ft = &v.$field;)+
+        };
+        unsafe impl $crate::SpecialFields for $t {
+            const FIELDS: &'static [(usize, $crate::SpecialField)] = &[
+                $((
+                    ::std::mem::offset_of!($t, $field),
+                    <$ft as $crate::SpecialFieldType>::KIND,
+                ),)+
+            ];
+        }
+    };
+}
+
+/// Used for working with `BPF_MAP_TYPE_ARRAY` maps whose values contain a spin lock or timers,
+/// e.g. per-slot timeouts armed by a BPF program.
+///
+/// Such values can't be treated as plain bytes:
+/// * Values are read and written with [`BpfLock`](MapFlags::BpfLock) if they have a spin lock,
+///   so they aren't torn by a BPF program updating them at the same time.
+/// * Updates are rejected with `EINVAL` if the spin lock or timer bytes of the new value aren't
+///   zero, e.g. because they were set by hand. They belong to the kernel.
+/// * Writing a slot from user space cancels its timers. BPF programs must re-arm them (e.g.
+///   after checking for an uninitialized timer), and [`reset`](SlotArray::reset) relies on this
+///   to return slots to their initial state.
+///
+/// The map needs BTF describing the special fields, as generated for `.maps` definitions by
+/// clang, or the kernel rejects locked operations with `EINVAL`.
+///
+/// # Example
+/// ```no_run
+/// use rxdp::{BpfTimer, SlotArray};
+///
+/// #[derive(Default, Clone, Copy)]
+/// #[repr(C)]
+/// struct Slot {
+///     timeout_ms: u64,
+///     timer: BpfTimer,
+/// }
+/// rxdp::special_fields!(Slot { timer: BpfTimer });
+///
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let slots: SlotArray<Slot> = SlotArray::new(&obj, "slots").unwrap();
+/// let mut slot = slots.get(0).unwrap();
+/// slot.timeout_ms = 500;
+/// slots.update(0, &slot).unwrap();
+/// ```
+pub struct SlotArray<V> {
+    map: Map<u32, V>,
+}
+
+impl<V: Default + SpecialFields> SlotArray<V> {
+    /// Create a new array with `max_entries` slots. The map has no BTF, so it only works for
+    /// values without a spin lock.
+    pub fn create(max_entries: u32) -> XDPResult<SlotArray<V>> {
+        let value_size = std::mem::size_of::<V>() as u32;
+        let map_fd = mc::create_map(MapType::Array, 4, value_size, max_entries, 0);
+        mc::check_rc(map_fd, (), "Error creating new map")?;
+
+        Ok(SlotArray {
+            map: Map::from_owned_fd(map_fd, MapType::Array, max_entries),
+        })
+    }
+
+    /// Get access to the array `map_name`. This will fail if the requested value size doesn't
+    /// match the value size defined in the ELF file.
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<SlotArray<V>> {
+        let def = mc::find_map(xdp, map_name)?;
+        if def.map_type != MapType::Array {
+            set_errno(Errno(22));
+            fail!(
+                "Improper map type {:?} for {}, expected Array",
+                def.map_type,
+                map_name
+            );
+        }
+
+        Ok(SlotArray {
+            map: Map::new(xdp, map_name)?,
+        })
+    }
+
+    /// The value of slot `index`. Its spin lock and timers are zero.
+    pub fn get(&self, index: u32) -> XDPResult<V> {
+        let value = self.map.lookup_with_flags(&index, Self::flags())?;
+        Ok(value.into_single())
+    }
+
+    /// Write `value` to slot `index`, cancelling the slot's timers.
+    pub fn update(&self, index: u32, value: &V) -> XDPResult<()> {
+        check_special_fields(value)?;
+        self.map
+            .update(&index, value, MapFlags::BpfExist | Self::flags())
+    }
+
+    /// Zero slot `index`, like when the map was created.
+    pub fn reset(&self, index: u32) -> XDPResult<()> {
+        self.update(index, &V::default())
+    }
+
+    /// Zero every slot.
+    pub fn reset_all(&self) -> XDPResult<()> {
+        let value = V::default();
+        check_special_fields(&value)?;
+        for index in 0..self.map.max_entries() {
+            self.map
+                .update(&index, &value, MapFlags::BpfExist | Self::flags())?;
+        }
+
+        Ok(())
+    }
+
+    /// File descriptor for this map.
+    pub fn map_fd(&self) -> i32 {
+        self.map.map_fd()
+    }
+
+    /// The number of slots.
+    pub fn max_entries(&self) -> u32 {
+        self.map.max_entries()
+    }
+
+    // `BpfLock` if the values have a spin lock.
+    fn flags() -> MapFlags {
+        match V::FIELDS.iter().any(|(_, f)| *f == SpecialField::SpinLock) {
+            true => MapFlags::BpfLock,
+            false => MapFlags::empty(),
+        }
+    }
+}
+
+// Fails with EINVAL if any of the special fields of `value` isn't zero.
+fn check_special_fields<V: SpecialFields>(value: &V) -> XDPResult<()> {
+    let bytes = utils::as_bytes(value);
+    for (offset, field) in V::FIELDS {
+        let set = bytes
+            .get(*offset..*offset + field.size())
+            .is_none_or(|b| b.iter().any(|b| *b != 0));
+        if set {
+            set_errno(Errno(22));
+            fail!(
+                "The {:?} at offset {} is managed by the kernel and must be zero",
+                field,
+                offset
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default, Clone, Copy)]
+    #[repr(C)]
+    struct Session {
+        lock: BpfSpinLock,
+        packets: u32,
+        expiry: BpfTimer,
+    }
+
+    crate::special_fields!(Session {
+        lock: BpfSpinLock,
+        expiry: BpfTimer
+    });
+
+    #[test]
+    fn test_special_fields() {
+        assert_eq!(std::mem::size_of::<BpfTimer>(), 16);
+        assert_eq!(
+            Session::FIELDS,
+            &[(0, SpecialField::SpinLock), (8, SpecialField::Timer)]
+        );
+
+        let mut s = Session {
+            packets: 7,
+            ..Default::default()
+        };
+        assert!(check_special_fields(&s).is_ok());
+
+        s.expiry.opaque[1] = 1;
+        let err = check_special_fields(&s).unwrap_err();
+        assert_eq!(err.code(), 22);
+        assert!(err.description().contains("Timer at offset 8"));
+
+        s.expiry = BpfTimer::default();
+        s.lock.val = 1;
+        assert!(check_special_fields(&s).is_err());
+    }
+}
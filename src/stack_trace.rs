@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::os::raw::c_void;
+
+use crate::elf;
+use crate::error::{get_errno, XDPError};
+use crate::map_common as mc;
+use crate::object::XDPLoadedObject;
+use crate::result::XDPResult;
+use crate::MapKind;
+
+/// Used for working with `BPF_MAP_TYPE_STACK_TRACE` maps, filled in by `bpf_get_stackid`.
+///
+/// Each stack id maps to the instruction pointers of the stack, innermost frame first. Use a
+/// [`Symbolizer`] to resolve them to function names.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let stacks = rxdp::StackTraceMap::new(&obj, "stacks").unwrap();
+/// let syms = rxdp::Symbolizer::kernel().unwrap();
+///
+/// for id in stacks.stack_ids().unwrap() {
+///     for ip in stacks.get(id).unwrap().unwrap_or_default() {
+///         match syms.resolve(ip) {
+///             Some(sym) => println!("  {}", sym),
+///             None => println!("  {:#x}", ip),
+///         }
+///     }
+/// }
+/// ```
+pub struct StackTraceMap {
+    map_fd: i32,
+    max_depth: usize,
+}
+
+impl StackTraceMap {
+    /// Get access to the stack trace map `map_name`.
+    pub fn new(xdp: &XDPLoadedObject, map_name: &str) -> XDPResult<StackTraceMap> {
+        let def = mc::validate_map::<u32>(xdp, map_name)?;
+        if def.map_type.kind() != MapKind::StackTraceMap {
+            return mc::improper_type(map_name, def.map_type);
+        }
+
+        Ok(StackTraceMap {
+            map_fd: def.fd,
+            max_depth: def.value_size as usize / 8,
+        })
+    }
+
+    /// The instruction pointers of the stack `stack_id`, innermost frame first. `None` if there
+    /// is no such stack.
+    pub fn get(&self, stack_id: u32) -> XDPResult<Option<Vec<u64>>> {
+        let mut ips = vec![0u64; self.max_depth];
+        let rc = mc::lookup_elem(
+            self.map_fd,
+            &stack_id as *const _ as *const c_void,
+            ips.as_mut_ptr() as *mut c_void,
+        );
+        if rc == -2 || (rc < 0 && get_errno() == 2) {
+            return Ok(None);
+        }
+        mc::check_rc(rc, (), "Error looking up stack trace")?;
+
+        // Stacks shorter than the max depth are zero filled.
+        let depth = ips.iter().position(|ip| *ip == 0).unwrap_or(ips.len());
+        ips.truncate(depth);
+        Ok(Some(ips))
+    }
+
+    /// The ids of the stacks in the map.
+    ///
+    /// **NOTE**: Ids are read one at a time with `bpf_map_get_next_key`. If a stack is removed
+    /// while the ids are read (e.g. by [`remove`](StackTraceMap::remove) on another thread),
+    /// the walk starts over from the first id and can return ids more than once.
+    pub fn stack_ids(&self) -> XDPResult<Vec<u32>> {
+        let mut ids = Vec::new();
+        let mut key = 0u32;
+        let mut prev: *const c_void = std::ptr::null();
+        loop {
+            let rc = unsafe {
+                libbpf_sys::bpf_map_get_next_key(
+                    self.map_fd,
+                    prev,
+                    &mut key as *mut _ as *mut c_void,
+                )
+            };
+            if rc < 0 {
+                match get_errno() {
+                    2 => break,
+                    _ => fail!("Error reading stack ids"),
+                }
+            }
+            ids.push(key);
+            prev = ids.last().unwrap() as *const _ as *const c_void;
+        }
+
+        Ok(ids)
+    }
+
+    /// Remove the stack `stack_id`, so its id can be reused.
+    pub fn remove(&self, stack_id: u32) -> XDPResult<()> {
+        let rc = unsafe {
+            libbpf_sys::bpf_map_delete_elem(self.map_fd, &stack_id as *const _ as *const c_void)
+        };
+        mc::check_rc(rc, (), "Error removing stack trace")
+    }
+
+    /// File descriptor for this map.
+    pub fn map_fd(&self) -> i32 {
+        self.map_fd
+    }
+
+    /// The maximum number of frames of a stack, set by the value size of the map.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+}
+
+/// A function an instruction pointer resolved to, see [`Symbolizer::resolve`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// The function name, as found in the symbol table (i.e. mangled for C++ and Rust).
+    pub name: String,
+    /// Offset of the instruction pointer from the start of the function.
+    pub offset: u64,
+    /// The kernel module, or the file the function was mapped from. `None` for the kernel
+    /// itself.
+    pub module: Option<String>,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)?;
+        if let Some(m) = &self.module {
+            write!(f, " [{}]", m)?;
+        }
+
+        Ok(())
+    }
+}
+
+// A function at its runtime address. A `size` of 0 is unknown, and covers everything up to the
+// next function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Sym {
+    addr: u64,
+    size: u64,
+    name: String,
+    module: Option<String>,
+}
+
+/// Resolves instruction pointers to function names, using the kernel's symbols
+/// (`/proc/kallsyms`) or the symbol tables of the files mapped into a process
+/// (`/proc/<pid>/maps`).
+///
+/// The symbols are read once, when the symbolizer is created. Kernel addresses are only
+/// visible with `CAP_SYSLOG` (see `kernel.kptr_restrict`), and user space symbols are only found
+/// for files that still have their symbol tables.
+pub struct Symbolizer {
+    syms: Vec<Sym>,
+}
+
+impl Symbolizer {
+    /// A symbolizer for kernel stacks.
+    pub fn kernel() -> XDPResult<Symbolizer> {
+        let kallsyms = match std::fs::read_to_string("/proc/kallsyms") {
+            Ok(s) => s,
+            Err(e) => fail!("Error reading /proc/kallsyms: {}", e),
+        };
+
+        Ok(Symbolizer::new(parse_kallsyms(&kallsyms)))
+    }
+
+    /// A symbolizer for user space stacks (`BPF_F_USER_STACK`) of the process `pid`. Files
+    /// mapped after it is created aren't included.
+    pub fn process(pid: u32) -> XDPResult<Symbolizer> {
+        let path = format!("/proc/{}/maps", pid);
+        let maps = match std::fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(e) => fail!("Error reading {}: {}", path, e),
+        };
+
+        let mut files: HashMap<String, Option<Vec<u8>>> = HashMap::new();
+        let mut syms = Vec::new();
+        for (start, end, offset, file) in maps.lines().filter_map(parse_maps_line) {
+            let elf = match files
+                .entry(file.to_string())
+                .or_insert_with(|| std::fs::read(file).ok())
+            {
+                Some(elf) => elf,
+                None => continue,
+            };
+            let bias = match elf::segment_bias(elf, offset) {
+                Some(b) => b,
+                None => continue,
+            };
+
+            // Runtime address = address in the file - bias - file offset + mapping start.
+            let load = start.wrapping_sub(offset).wrapping_sub(bias);
+            for (name, addr, size) in elf::functions(elf).unwrap_or_default() {
+                let addr = addr.wrapping_add(load);
+                if (start..end).contains(&addr) {
+                    syms.push(Sym {
+                        addr,
+                        size,
+                        name,
+                        module: Some(file.to_string()),
+                    });
+                }
+            }
+        }
+
+        Ok(Symbolizer::new(syms))
+    }
+
+    fn new(mut syms: Vec<Sym>) -> Symbolizer {
+        syms.sort_by_key(|s| s.addr);
+        Symbolizer { syms }
+    }
+
+    /// The function containing `ip`, `None` if it isn't in a known function.
+    pub fn resolve(&self, ip: u64) -> Option<Symbol> {
+        let i = match self.syms.binary_search_by_key(&ip, |s| s.addr) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let sym = &self.syms[i];
+        if sym.size != 0 && ip >= sym.addr + sym.size {
+            return None;
+        }
+
+        Some(Symbol {
+            name: sym.name.clone(),
+            offset: ip - sym.addr,
+            module: sym.module.clone(),
+        })
+    }
+
+    /// Resolve each instruction pointer of `stack`, e.g. as returned by
+    /// [`StackTraceMap::get`].
+    pub fn resolve_stack(&self, stack: &[u64]) -> Vec<Option<Symbol>> {
+        stack.iter().map(|ip| self.resolve(*ip)).collect()
+    }
+}
+
+// Parses the function symbols of /proc/kallsyms, e.g.
+// `ffffffffc0a01000 t nf_nat_ipv4_fn	[nf_nat]`. Addresses hidden by `kptr_restrict` are 0.
+//
+// kallsyms has no sizes, so each function ends at the next symbol of any type (e.g. data, or
+// `_etext` at the end of the kernel's text), rather than at the next function.
+fn parse_kallsyms(kallsyms: &str) -> Vec<Sym> {
+    // (address, is a function, name, module)
+    let mut all = Vec::new();
+    for line in kallsyms.lines() {
+        let mut parts = line.split_whitespace();
+        let (addr, sym_type, name) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(a), Some(t), Some(n)) => (a, t, n),
+            _ => continue,
+        };
+        let addr = match u64::from_str_radix(addr, 16) {
+            Ok(a) if a != 0 => a,
+            _ => continue,
+        };
+
+        let is_func = (sym_type == "t" || sym_type == "T") && name != "_etext";
+        let module = parts
+            .next()
+            .map(|m| m.trim_start_matches('[').trim_end_matches(']'));
+        all.push((addr, is_func, name, module));
+    }
+    all.sort_by_key(|s| s.0);
+
+    let mut syms = Vec::new();
+    for (i, &(addr, is_func, name, module)) in all.iter().enumerate() {
+        if !is_func {
+            continue;
+        }
+
+        // Aliases share an address, so skip ahead to the next distinct one.
+        let size = all[i + 1..]
+            .iter()
+            .find(|s| s.0 > addr)
+            .map_or(0, |s| s.0 - addr);
+        syms.push(Sym {
+            addr,
+            size,
+            name: name.to_string(),
+            module: module.map(str::to_string),
+        });
+    }
+
+    syms
+}
+
+// Parses an executable, file backed mapping of /proc/<pid>/maps, e.g.
+// `7f2c1a000000-7f2c1a1b5000 r-xp 00028000 fd:01 1843 /usr/lib/libc.so.6`, into
+// `(start, end, file offset, path)`.
+fn parse_maps_line(line: &str) -> Option<(u64, u64, u64, &str)> {
+    let mut parts = line.split_whitespace();
+    let (range, perms, offset) = (parts.next()?, parts.next()?, parts.next()?);
+    let path = parts.nth(2)?;
+    if !perms.contains('x') || !path.starts_with('/') {
+        return None;
+    }
+
+    let (start, end) = range.split_once('-')?;
+    Some((
+        u64::from_str_radix(start, 16).ok()?,
+        u64::from_str_radix(end, 16).ok()?,
+        u64::from_str_radix(offset, 16).ok()?,
+        path,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kernel_symbols() {
+        let kallsyms = "0000000000000000 A fixed_percpu_data\n\
+                        ffffffff81000000 T _stext\n\
+                        ffffffff81000100 t do_one_initcall\n\
+                        ffffffff81000100 T do_one_initcall_alias\n\
+                        ffffffff81e00000 T _etext\n\
+                        ffffffff82000000 D jiffies\n\
+                        ffffffff82000100 t after_data\n\
+                        ffffffff82000200 d some_data\n\
+                        ffffffffc0a01000 t nf_nat_ipv4_fn\t[nf_nat]\n";
+        let syms = Symbolizer::new(parse_kallsyms(kallsyms));
+        assert_eq!(syms.syms.len(), 5);
+
+        assert_eq!(syms.resolve(0xffffffff80000000), None);
+        let sym = syms.resolve(0xffffffff81000110).unwrap();
+        assert_eq!(sym.offset, 0x10);
+        assert!(sym.name.starts_with("do_one_initcall"));
+        // Past _etext, or in data after a function, isn't in any function
+        assert_eq!(syms.resolve(0xffffffff81f00000), None);
+        let sym = syms.resolve(0xffffffff82000104).unwrap();
+        assert_eq!(sym.to_string(), "after_data+0x4");
+        assert_eq!(syms.resolve(0xffffffff82000204), None);
+        let sym = syms.resolve(0xffffffffc0a01004).unwrap();
+        assert_eq!(sym.to_string(), "nf_nat_ipv4_fn+0x4 [nf_nat]");
+    }
+
+    #[test]
+    fn test_user_symbols() {
+        let line = "7f2c1a000000-7f2c1a1b5000 r-xp 00028000 fd:01 1843   /usr/lib/libc.so.6";
+        assert_eq!(
+            parse_maps_line(line),
+            Some((
+                0x7f2c1a000000,
+                0x7f2c1a1b5000,
+                0x28000,
+                "/usr/lib/libc.so.6"
+            ))
+        );
+        assert_eq!(
+            parse_maps_line("7ffd1000-7ffd2000 rw-p 00000000 00:00 0   [stack]"),
+            None
+        );
+        assert_eq!(
+            parse_maps_line("7ffd1000-7ffd2000 r--p 00000000 fd:01 1843 /usr/lib/libc.so.6"),
+            None
+        );
+
+        let syms = Symbolizer::new(vec![Sym {
+            addr: 0x1000,
+            size: 0x20,
+            name: "main".to_string(),
+            module: Some("/bin/foo".to_string()),
+        }]);
+        assert_eq!(
+            syms.resolve_stack(&[0x1004, 0x1020]),
+            vec![
+                Some(Symbol {
+                    name: "main".to_string(),
+                    offset: 4,
+                    module: Some("/bin/foo".to_string()),
+                }),
+                None
+            ]
+        );
+    }
+}
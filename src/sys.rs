@@ -0,0 +1,221 @@
+//! Host-wide operations on XDP programs, independent of any loaded object.
+use std::ffi::CStr;
+
+use crate::compat;
+use crate::error::{get_errno, XDPError};
+use crate::perm::{self, Requires};
+use crate::result::XDPResult;
+use crate::utils;
+use crate::{AttachFlags, ProgInfo};
+
+const ENOENT: i32 = 2;
+const ENODEV: i32 = 19;
+
+/// An XDP program attached to a network interface, see [`attached_programs`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachedProgram {
+    /// Name of the interface.
+    pub interface: String,
+    /// Index of the interface.
+    pub if_index: i32,
+    /// Kernel id of the program.
+    pub prog_id: u32,
+    /// Name of the program. The kernel only keeps the first 15 characters.
+    pub name: String,
+    /// Mode the program is attached in, one of `DRV_MODE`, `SKB_MODE` or `HW_MODE`.
+    pub mode: AttachFlags,
+}
+
+/// Returns the XDP programs attached to any interface on the host (in the current network
+/// namespace), no matter which process attached them. An interface can have a program
+/// attached in more than one mode, which are returned as separate entries.
+pub fn attached_programs() -> XDPResult<Vec<AttachedProgram>> {
+    let mut progs = Vec::new();
+    for (if_index, interface) in interfaces()? {
+        match interface_programs(if_index, &interface) {
+            Ok(p) => progs.extend(p),
+            // The interface went away after it was listed.
+            Err(e) if e.code() == ENODEV => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(progs)
+}
+
+// Returns the XDP programs attached to the interface, one per attach mode.
+pub(crate) fn interface_programs(
+    if_index: i32,
+    interface: &str,
+) -> XDPResult<Vec<AttachedProgram>> {
+    let (drv, skb, hw) = match compat::query_xdp(if_index) {
+        Ok(ids) => ids,
+        Err(rc) => fail_rc!(rc, "Error querying XDP programs on {}", interface),
+    };
+
+    let mut progs = Vec::new();
+    let modes = [
+        (drv, AttachFlags::DRV_MODE),
+        (skb, AttachFlags::SKB_MODE),
+        (hw, AttachFlags::HW_MODE),
+    ];
+    for (prog_id, mode) in modes.iter() {
+        if *prog_id == 0 {
+            continue;
+        }
+        let name = match prog_name(*prog_id)? {
+            Some(n) => n,
+            // The program was detached in the meantime.
+            None => continue,
+        };
+        progs.push(AttachedProgram {
+            interface: interface.to_string(),
+            if_index,
+            prog_id: *prog_id,
+            name,
+            mode: *mode,
+        });
+    }
+
+    Ok(progs)
+}
+
+/// Detaches every XDP program on the host for which `predicate` returns true, and returns
+/// the programs that were detached.
+///
+/// All matching programs are tried, even if detaching one of them fails, in which case the
+/// first error is returned once the others are done.
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// // Remove all of our programs, wherever they are attached.
+/// let detached = rxdp::sys::detach_matching(|p| p.name.starts_with("rxdp_")).unwrap();
+/// for p in detached {
+///     println!("detached {} ({}) from {}", p.name, p.prog_id, p.interface);
+/// }
+/// ```
+pub fn detach_matching<F>(mut predicate: F) -> XDPResult<Vec<AttachedProgram>>
+where
+    F: FnMut(&AttachedProgram) -> bool,
+{
+    let mut detached = Vec::new();
+    let mut first_err = None;
+    for p in attached_programs()?.into_iter().filter(|p| predicate(p)) {
+        let rc = compat::set_xdp_fd(p.if_index, -1, p.mode.bits());
+        if rc < 0 {
+            let msg = format!("Error detaching {} from {}", p.name, p.interface);
+            first_err.get_or_insert(XDPError::with_return_code(&msg, rc));
+            continue;
+        }
+        detached.push(p);
+    }
+
+    match first_err {
+        Some(e) => Err(e),
+        None => Ok(detached),
+    }
+}
+
+// Returns the (index, name) of all network interfaces.
+fn interfaces() -> XDPResult<Vec<(i32, String)>> {
+    let head = unsafe { libc::if_nameindex() };
+    if head.is_null() {
+        fail!("Error listing network interfaces");
+    }
+
+    let mut ifaces = Vec::new();
+    let mut p = head;
+    unsafe {
+        while (*p).if_index != 0 {
+            ifaces.push(((*p).if_index as i32, utils::cstring_to_str((*p).if_name)));
+            p = p.add(1);
+        }
+        libc::if_freenameindex(head);
+    }
+
+    Ok(ifaces)
+}
+
+/// Returns the kernel info of the program with id `prog_id` (e.g. from [`AttachedProgram`]),
+/// `None` if it no longer exists. The build-id isn't known to the kernel, so it is always
+/// `None`, see [`Program::info`](crate::Program::info).
+pub fn program_info(prog_id: u32) -> XDPResult<Option<ProgInfo>> {
+    Ok(prog_info(prog_id, None)?.map(|i| ProgInfo::from_raw(&i, None)))
+}
+
+/// Returns the ids of the maps used by the program with id `prog_id`, `None` if it no longer
+/// exists. The maps can be opened with [`DynMap::from_id`](crate::DynMap::from_id), e.g. to
+/// read the maps of a program loaded by another process.
+pub fn program_map_ids(prog_id: u32) -> XDPResult<Option<Vec<u32>>> {
+    let mut ids = vec![0u32; 64];
+    let info = match prog_info(prog_id, Some(&mut ids))? {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+    if info.nr_map_ids as usize > ids.len() {
+        ids = vec![0u32; info.nr_map_ids as usize];
+        if prog_info(prog_id, Some(&mut ids))?.is_none() {
+            return Ok(None);
+        }
+    }
+
+    ids.truncate(info.nr_map_ids as usize);
+    Ok(Some(ids))
+}
+
+// Returns the name of the program with id `prog_id`, `None` if it no longer exists.
+pub(crate) fn prog_name(prog_id: u32) -> XDPResult<Option<String>> {
+    let info = match prog_info(prog_id, None)? {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+
+    let name = unsafe { CStr::from_ptr(info.name.as_ptr()) };
+    Ok(Some(name.to_string_lossy().into_owned()))
+}
+
+// Returns the info of the program with id `prog_id`, `None` if it no longer exists. If
+// `map_ids` is given, it is filled with the ids of the maps used by the program (up to its
+// length), and `nr_map_ids` of the returned info is the total number of maps.
+pub(crate) fn prog_info(
+    prog_id: u32,
+    map_ids: Option<&mut [u32]>,
+) -> XDPResult<Option<libbpf_sys::bpf_prog_info>> {
+    let fd = unsafe { libbpf_sys::bpf_prog_get_fd_by_id(prog_id) };
+    if fd < 0 {
+        if get_errno() == ENOENT {
+            return Ok(None);
+        }
+        fail!(
+            "Error getting fd for program {}{}",
+            prog_id,
+            perm::hint(Requires::SysAdmin)
+        );
+    }
+
+    let info = prog_info_fd(fd, map_ids);
+    unsafe { libc::close(fd) };
+    info.map(Some)
+}
+
+// Same as `prog_info`, for the program `fd`.
+pub(crate) fn prog_info_fd(
+    fd: i32,
+    map_ids: Option<&mut [u32]>,
+) -> XDPResult<libbpf_sys::bpf_prog_info> {
+    let mut info = libbpf_sys::bpf_prog_info::default();
+    if let Some(ids) = map_ids {
+        info.nr_map_ids = ids.len() as u32;
+        info.map_ids = ids.as_mut_ptr() as u64;
+    }
+    let mut info_len = std::mem::size_of::<libbpf_sys::bpf_prog_info>() as u32;
+    let rc = unsafe {
+        libbpf_sys::bpf_obj_get_info_by_fd(fd, &mut info as *mut _ as *mut _, &mut info_len)
+    };
+    if rc < 0 {
+        fail_rc!(rc, "Error getting program info");
+    }
+
+    Ok(info)
+}
@@ -0,0 +1,168 @@
+use errno::{set_errno, Errno};
+use std::collections::HashMap;
+
+use crate::error::XDPError;
+use crate::map_common::MapLike;
+use crate::result::XDPResult;
+use crate::{Map, MapFlags, MapType, ProgArrayMap, Program, XDPLoadedObject};
+
+/// Manages named tail-call slots in a `BPF_MAP_TYPE_PROG_ARRAY`, allowing the program behind a
+/// slot to be replaced without packets ever hitting a half-updated chain.
+///
+/// Slot `n` owns the two program array indices `2n` and `2n + 1`. Only one of them is active at
+/// any time, as recorded in the selector array map at key `n`. A new program is first
+/// [`stage`](TailCallTable::stage)d at the inactive index, then made live by a single
+/// [`commit`](TailCallTable::commit) which flips the selector. The eBPF side has to look up
+/// the index in the selector before each tail call:
+///
+/// ```c
+/// __u32 slot = 0;
+/// __u32 *index = bpf_map_lookup_elem(&selector, &slot);
+/// if (index)
+///     bpf_tail_call(ctx, &progs, *index);
+/// ```
+///
+/// # Example
+/// ```no_run
+/// # use rxdp;
+/// # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+/// let mut table = rxdp::TailCallTable::new(&obj, "progs", "selector", &["parse", "filter"])
+///     .unwrap();
+///
+/// let prog = obj.get_program("filter_v2").unwrap();
+/// table.stage("filter", prog).unwrap();
+/// // ... other staged updates ...
+/// let index = table.commit("filter").unwrap();
+/// println!("filter now runs from index {}", index);
+/// ```
+pub struct TailCallTable {
+    progs: ProgArrayMap,
+    selector: Map<u32, u32>,
+    slots: HashMap<String, Slot>,
+    names: Vec<String>,
+}
+
+struct Slot {
+    key: u32,
+    active: u32,
+    staged: bool,
+}
+
+impl Slot {
+    fn shadow(&self) -> u32 {
+        self.active ^ 1
+    }
+}
+
+impl TailCallTable {
+    /// Creates a table for `slots` on top of the program array `prog_array` and the selector
+    /// array `selector` (`u32` keys and values). The prog array needs room for 2 indices per
+    /// slot, the selector for 1 entry per slot. Selector entries that already point at one of
+    /// the slot's indices (e.g. from a pinned map) are kept, the others are set to the slot's
+    /// first index.
+    pub fn new(
+        xdp: &XDPLoadedObject,
+        prog_array: &str,
+        selector: &str,
+        slots: &[&str],
+    ) -> XDPResult<TailCallTable> {
+        let progs = ProgArrayMap::new(xdp, prog_array)?;
+        let selector: Map<u32, u32> = Map::new(xdp, selector)?;
+        if selector.map_type() != MapType::Array {
+            set_errno(Errno(22));
+            fail!("Improper map type, selector must be MapType::Array");
+        }
+
+        let n = slots.len() as u32;
+        if progs.max_entries() < 2 * n || selector.max_entries() < n {
+            set_errno(Errno(7));
+            fail!(
+                "Not enough entries for {} slots. Prog array has {}, selector has {}",
+                n,
+                progs.max_entries(),
+                selector.max_entries()
+            );
+        }
+
+        let mut table = TailCallTable {
+            progs,
+            selector,
+            slots: HashMap::with_capacity(slots.len()),
+            names: Vec::with_capacity(slots.len()),
+        };
+
+        for (key, name) in (0u32..).zip(slots.iter()) {
+            let current = table.selector.lookup(&key)?.into_single();
+            let active = if current / 2 == key { current } else { 2 * key };
+            // The eBPF side reads the selector, so it has to point at the slot's index too.
+            if active != current {
+                table.selector.update(&key, &active, MapFlags::BpfAny)?;
+            }
+            let slot = Slot {
+                key,
+                active,
+                staged: false,
+            };
+
+            if table.slots.insert(name.to_string(), slot).is_some() {
+                set_errno(Errno(22));
+                fail!("Duplicate tail call slot '{}'", name);
+            }
+            table.names.push(name.to_string());
+        }
+
+        Ok(table)
+    }
+
+    /// Writes `prog` to the inactive index of `slot`. The program is not reachable until the
+    /// slot is [`commit`](TailCallTable::commit)ted.
+    pub fn stage(&mut self, slot: &str, prog: &Program) -> XDPResult<()> {
+        let s = get_slot(&mut self.slots, slot)?;
+        self.progs.set(s.shadow(), prog)?;
+        s.staged = true;
+
+        Ok(())
+    }
+
+    /// Makes the program staged for `slot` live by pointing the selector at its index, and
+    /// returns that index. The previously active program stays in the prog array until it is
+    /// overwritten by the next `stage`, so in-flight packets are unaffected.
+    pub fn commit(&mut self, slot: &str) -> XDPResult<u32> {
+        let s = get_slot(&mut self.slots, slot)?;
+        if !s.staged {
+            set_errno(Errno(22));
+            fail!("Nothing staged for tail call slot '{}'", slot);
+        }
+
+        let index = s.shadow();
+        self.selector.update(&s.key, &index, MapFlags::BpfAny)?;
+        s.active = index;
+        s.staged = false;
+
+        Ok(index)
+    }
+
+    /// Stages and commits `prog` for `slot`, returning the new active index.
+    pub fn update(&mut self, slot: &str, prog: &Program) -> XDPResult<u32> {
+        self.stage(slot, prog)?;
+        self.commit(slot)
+    }
+
+    /// Returns the active prog array index of every slot, in the order the slots were given.
+    pub fn mapping(&self) -> Vec<(&str, u32)> {
+        self.names
+            .iter()
+            .map(|n| (n.as_str(), self.slots[n].active))
+            .collect()
+    }
+}
+
+fn get_slot<'a>(slots: &'a mut HashMap<String, Slot>, name: &str) -> XDPResult<&'a mut Slot> {
+    match slots.get_mut(name) {
+        Some(s) => Ok(s),
+        None => {
+            set_errno(Errno(2));
+            fail!("No such tail call slot '{}'", name);
+        }
+    }
+}
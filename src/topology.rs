@@ -0,0 +1,146 @@
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::ops::Add;
+
+use crate::percpu_map::num_cpus;
+
+const SYSFS_NODE: &str = "/sys/devices/system/node";
+const SYSFS_CPU: &str = "/sys/devices/system/cpu";
+
+lazy_static! {
+    static ref CPU_NODES: Vec<u32> = read_cpu_nodes();
+}
+
+/// Topology information for a single (possible) CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuInfo {
+    /// The CPU id, which is also its index in per-cpu map values.
+    pub cpu: usize,
+    /// The NUMA node the CPU belongs to. 0 on systems without NUMA.
+    pub node: u32,
+    /// The physical package (socket) id. `None` if the CPU is offline or the kernel doesn't
+    /// report it.
+    pub socket: Option<u32>,
+}
+
+/// Returns the topology of all possible CPUs, ordered by CPU id. The result has one entry for
+/// each value in a per-cpu map lookup.
+///
+/// # Example
+/// ```no_run
+/// for cpu in rxdp::cpu_topology() {
+///     println!("cpu {}: node {}, socket {:?}", cpu.cpu, cpu.node, cpu.socket);
+/// }
+/// ```
+pub fn cpu_topology() -> Vec<CpuInfo> {
+    CPU_NODES
+        .iter()
+        .enumerate()
+        .map(|(cpu, &node)| CpuInfo {
+            cpu,
+            node,
+            socket: read_socket(cpu),
+        })
+        .collect()
+}
+
+/// Returns the NUMA node of each possible CPU, indexed by CPU id.
+pub(crate) fn cpu_nodes() -> &'static [u32] {
+    &CPU_NODES
+}
+
+/// Sums per-cpu `values` by the NUMA node of each CPU in `nodes`.
+pub(crate) fn sum_by_node<V: Copy + Add<Output = V>>(
+    values: &[V],
+    nodes: &[u32],
+) -> BTreeMap<u32, V> {
+    let mut sums = BTreeMap::new();
+    for (i, &v) in values.iter().enumerate() {
+        let node = nodes.get(i).copied().unwrap_or(0);
+        sums.entry(node)
+            .and_modify(|s: &mut V| *s = *s + v)
+            .or_insert(v);
+    }
+    sums
+}
+
+fn read_cpu_nodes() -> Vec<u32> {
+    let mut nodes = vec![0; num_cpus()];
+    let entries = match std::fs::read_dir(SYSFS_NODE) {
+        Ok(e) => e,
+        Err(_) => return nodes,
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let node = match name
+            .strip_prefix("node")
+            .and_then(|n| n.parse::<u32>().ok())
+        {
+            Some(n) => n,
+            None => continue,
+        };
+        let list = std::fs::read_to_string(entry.path().join("cpulist")).unwrap_or_default();
+        for cpu in parse_cpu_list(&list) {
+            if let Some(n) = nodes.get_mut(cpu) {
+                *n = node;
+            }
+        }
+    }
+
+    nodes
+}
+
+fn read_socket(cpu: usize) -> Option<u32> {
+    let path = format!("{}/cpu{}/topology/physical_package_id", SYSFS_CPU, cpu);
+    let id = std::fs::read_to_string(path)
+        .ok()?
+        .trim()
+        .parse::<i32>()
+        .ok()?;
+    if id < 0 {
+        return None;
+    }
+    Some(id as u32)
+}
+
+// Parses a kernel cpu list, e.g. "0-3,8,10-11".
+fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',').filter(|p| !p.is_empty()) {
+        let mut range = part.splitn(2, '-');
+        let lower = range.next().and_then(|v| v.parse::<usize>().ok());
+        let upper = match range.next() {
+            Some(v) => v.parse::<usize>().ok(),
+            None => lower,
+        };
+        if let (Some(lower), Some(upper)) = (lower, upper) {
+            cpus.extend(lower..=upper);
+        }
+    }
+    cpus
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n"), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_cpu_list("5"), vec![5]);
+        assert!(parse_cpu_list("\n").is_empty());
+        assert!(parse_cpu_list("x-2").is_empty());
+    }
+
+    #[test]
+    fn test_sum_by_node() {
+        let nodes = [0, 0, 1, 1, 0];
+        let sums = sum_by_node(&[1u64, 2, 3, 4, 5], &nodes);
+        assert_eq!(sums.into_iter().collect::<Vec<_>>(), vec![(0, 8), (1, 7)]);
+
+        // CPUs without a known node are counted as node 0
+        let sums = sum_by_node(&[1u64, 2, 3], &[1]);
+        assert_eq!(sums.into_iter().collect::<Vec<_>>(), vec![(0, 5), (1, 1)]);
+    }
+}
@@ -1,9 +1,10 @@
 use crate::error::XDPError;
 use crate::result::XDPResult;
-use libc::if_nametoindex;
+use libc::{if_indextoname, if_nametoindex};
 use std::{
     convert::TryInto,
     ffi::{CStr, CString},
+    mem::size_of,
     os::raw::c_char,
 };
 
@@ -28,6 +29,23 @@ pub(crate) fn lookup_interface_by_name(name: &str) -> XDPResult<i32> {
     }
 }
 
+// Returns the name of the interface with index `if_index`, `None` if there is no such
+// interface in the current network namespace.
+pub(crate) fn interface_name(if_index: u32) -> Option<String> {
+    let mut name = [0 as c_char; libc::IF_NAMESIZE];
+    let ptr = unsafe { if_indextoname(if_index, name.as_mut_ptr()) };
+    match ptr.is_null() {
+        true => None,
+        false => Some(cstring_to_str(name.as_ptr())),
+    }
+}
+
+// Returns the name of the driver bound to the interface, if any.
+pub(crate) fn interface_driver(name: &str) -> Option<String> {
+    let link = std::fs::read_link(format!("/sys/class/net/{}/device/driver", name)).ok()?;
+    link.file_name().map(|n| n.to_string_lossy().into_owned())
+}
+
 pub(crate) fn cstring_to_str(char_ptr: *const c_char) -> String {
     let cs = unsafe { CStr::from_ptr(char_ptr) };
     match cs.to_str() {
@@ -36,6 +54,27 @@ pub(crate) fn cstring_to_str(char_ptr: *const c_char) -> String {
     }
 }
 
+// Lowercase hex representation of `bytes`.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Returns the in-memory representation of `v`.
+pub(crate) fn as_bytes<T>(v: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(v as *const T as *const u8, size_of::<T>()) }
+}
+
+// Returns the in-memory representation of `v`, for the kernel to write to.
+pub(crate) fn as_bytes_mut<T>(v: &mut T) -> &mut [u8] {
+    unsafe { std::slice::from_raw_parts_mut(v as *mut T as *mut u8, size_of::<T>()) }
+}
+
+// Builds a `T` from its in-memory representation. `bytes` must be `size_of::<T>()` long.
+pub(crate) fn from_bytes<T>(bytes: &[u8]) -> T {
+    assert_eq!(bytes.len(), size_of::<T>());
+    unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
 // Returns the number of possible cpus
 pub(crate) fn num_cpus() -> XDPResult<usize> {
     let contents = match std::fs::read_to_string("/sys/devices/system/cpu/possible") {
@@ -61,3 +100,14 @@ pub(crate) fn num_cpus() -> XDPResult<usize> {
 
     Ok((upper - lower) as usize + 1 as usize)
 }
+
+/// Returns the current value of the clock used by the `bpf_ktime_get_ns()` eBPF helper
+/// (`CLOCK_MONOTONIC`), in nanoseconds.
+pub fn ktime_get_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
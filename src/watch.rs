@@ -0,0 +1,139 @@
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::error::get_errno;
+use crate::map_common as mc;
+use crate::utils;
+
+// Polls the keys watched on a map. All subscriptions of a map are served by a single thread,
+// which looks up each key at most once per wake-up no matter how many subscribers it has.
+pub(crate) struct Watcher<K, V> {
+    control: Mutex<Option<PollThread<K, V>>>,
+}
+
+// The channel new subscriptions are sent on, and the thread polling them.
+type PollThread<K, V> = (Sender<Subscription<K, V>>, JoinHandle<()>);
+
+struct Subscription<K, V> {
+    key: K,
+    interval: Duration,
+    next: Instant,
+    // Bytes of the last value sent, `Some(None)` if the key was missing.
+    last: Option<Option<Vec<u8>>>,
+    tx: Sender<Option<V>>,
+}
+
+impl<K, V> Watcher<K, V> {
+    pub(crate) fn new() -> Watcher<K, V> {
+        Watcher {
+            control: Mutex::new(None),
+        }
+    }
+
+    // Stops the poll thread and waits for it to exit, so the map fd can be closed without the
+    // thread still looking up keys on it (or on an unrelated map that reused the fd).
+    pub(crate) fn stop(&mut self) {
+        let control = self.control.get_mut().unwrap_or_else(|e| e.into_inner());
+        if let Some((c, thread)) = control.take() {
+            drop(c);
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<K, V> Watcher<K, V>
+where
+    K: Copy + Send + 'static,
+    V: Default + Copy + Send + 'static,
+{
+    pub(crate) fn watch(&self, map_fd: i32, key: K, interval: Duration) -> Receiver<Option<V>> {
+        let (tx, rx) = unbounded();
+        let mut sub = Subscription {
+            key,
+            interval,
+            next: Instant::now(),
+            last: None,
+            tx,
+        };
+
+        let mut control = self.control.lock().unwrap();
+        if let Some((c, _)) = control.as_ref() {
+            match c.send(sub) {
+                Ok(_) => return rx,
+                Err(e) => sub = e.into_inner(),
+            }
+        }
+
+        let (c, control_rx) = unbounded();
+        c.send(sub).unwrap();
+        let thread = std::thread::spawn(move || poll(map_fd, control_rx));
+        *control = Some((c, thread));
+
+        rx
+    }
+}
+
+fn poll<K: Copy, V: Default + Copy>(map_fd: i32, control: Receiver<Subscription<K, V>>) {
+    let mut subs: Vec<Subscription<K, V>> = Vec::new();
+    loop {
+        let received = match subs.iter().map(|s| s.next).min() {
+            Some(next) => control.recv_timeout(next.saturating_duration_since(Instant::now())),
+            None => control.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(sub) => subs.push(sub),
+            Err(RecvTimeoutError::Timeout) => {}
+            // The map handle is gone.
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        let mut values: HashMap<Vec<u8>, Option<Option<Vec<u8>>>> = HashMap::new();
+        subs.retain_mut(|s| {
+            if s.next > now {
+                return true;
+            }
+            s.next = now + s.interval;
+
+            let key = utils::as_bytes(&s.key).to_vec();
+            let value = match values
+                .entry(key)
+                .or_insert_with(|| lookup::<K, V>(map_fd, &s.key))
+            {
+                Some(v) => v.clone(),
+                // Try again on the next interval.
+                None => return true,
+            };
+            if s.last.as_ref() == Some(&value) {
+                return true;
+            }
+
+            let v = value.as_ref().map(|b| utils::from_bytes::<V>(b));
+            s.last = Some(value);
+            s.tx.send(v).is_ok()
+        });
+    }
+}
+
+// Returns the value bytes for `key`, `Some(None)` if the key doesn't exist, or `None` if the
+// lookup failed for another reason.
+fn lookup<K, V: Default>(map_fd: i32, key: &K) -> Option<Option<Vec<u8>>> {
+    let mut value = V::default();
+    let rc = mc::lookup_elem(
+        map_fd,
+        key as *const K as *const c_void,
+        &mut value as *mut V as *mut c_void,
+    );
+    if rc < 0 {
+        return match get_errno() {
+            2 => Some(None),
+            _ => None,
+        };
+    }
+
+    Some(Some(utils::as_bytes(&value).to_vec()))
+}
@@ -0,0 +1,495 @@
+//! AF_XDP sockets, for receiving and sending packets redirected by an XDP program to user
+//! space with `bpf_redirect_map` on an XSKMAP.
+//!
+//! A [`Umem`] is the packet buffer area shared with the kernel, split into fixed size frames.
+//! Frames are handed to the kernel on the fill ring for receiving, and come back on the
+//! completion ring once sent. An [`XskSocket`] binds a UMEM to a queue of an interface and has
+//! the RX and TX rings.
+//!
+//! Only available without the `libbpf-1` feature: libbpf 1.0 moved the AF_XDP API to libxdp.
+//!
+//! # Example
+//! ```no_run
+//! use rxdp::xsk::{Umem, UmemConfig, XskConfig, XskSocket};
+//!
+//! # let obj = rxdp::XDPObject::new("/tmp/foo").unwrap().load().unwrap();
+//! let xsks: rxdp::Map<u32, i32> = rxdp::Map::new(&obj, "xsks_map").unwrap();
+//! let mut umem = Umem::new(4096, UmemConfig::new()).unwrap();
+//! let frames: Vec<u64> = umem.frame_addrs().collect();
+//! umem.fill(&frames);
+//!
+//! let config = XskConfig::new().xsks_map(&xsks);
+//! let mut sock = XskSocket::new("eth0", 0, umem, config).unwrap();
+//!
+//! let mut descs = Vec::new();
+//! loop {
+//!     sock.poll(1000).unwrap();
+//!     sock.recv(&mut descs);
+//!     for d in descs.iter() {
+//!         println!("{} bytes", sock.umem().frame(d).len());
+//!     }
+//!     let addrs: Vec<u64> = descs.iter().map(|d| d.addr).collect();
+//!     sock.umem_mut().fill(&addrs);
+//! }
+//! ```
+use errno::{set_errno, Errno};
+use libbpf_sys as bpf;
+use std::os::raw::c_void;
+
+use crate::error::{get_errno, XDPError};
+use crate::map_common as mc;
+use crate::map_common::MapLike;
+use crate::result::XDPResult;
+use crate::utils;
+use crate::{AttachFlags, Map, MapFlags, MapType};
+
+const DEFAULT_RING_SIZE: u32 = 2048;
+const DEFAULT_FRAME_SIZE: u32 = 4096;
+
+// Flags of `xsk_socket_config`.
+const INHIBIT_PROG_LOAD: u32 = 1;
+const XDP_COPY: u16 = 1 << 1;
+const XDP_ZEROCOPY: u16 = 1 << 2;
+const XDP_USE_NEED_WAKEUP: u16 = 1 << 3;
+
+/// Options for a [`Umem`].
+#[derive(Debug, Clone, Copy)]
+pub struct UmemConfig {
+    fill_size: u32,
+    comp_size: u32,
+    frame_size: u32,
+    frame_headroom: u32,
+}
+
+impl UmemConfig {
+    /// Fill and completion rings of 2048 entries, frames of 4096 bytes without headroom.
+    pub fn new() -> UmemConfig {
+        UmemConfig {
+            fill_size: DEFAULT_RING_SIZE,
+            comp_size: DEFAULT_RING_SIZE,
+            frame_size: DEFAULT_FRAME_SIZE,
+            frame_headroom: 0,
+        }
+    }
+
+    /// Number of entries of the fill ring, a power of 2.
+    pub fn fill_size(mut self, size: u32) -> UmemConfig {
+        self.fill_size = size;
+        self
+    }
+
+    /// Number of entries of the completion ring, a power of 2.
+    pub fn comp_size(mut self, size: u32) -> UmemConfig {
+        self.comp_size = size;
+        self
+    }
+
+    /// Size of a frame, 2048 or 4096 bytes.
+    pub fn frame_size(mut self, size: u32) -> UmemConfig {
+        self.frame_size = size;
+        self
+    }
+
+    /// Bytes reserved at the start of every frame, before the packet data.
+    pub fn frame_headroom(mut self, headroom: u32) -> UmemConfig {
+        self.frame_headroom = headroom;
+        self
+    }
+}
+
+impl Default for UmemConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A packet in a [`Umem`] frame, received on or sent to a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desc {
+    /// Offset of the packet in the UMEM.
+    pub addr: u64,
+    /// Length of the packet in bytes.
+    pub len: u32,
+}
+
+/// Packet buffer area shared with the kernel, with its fill and completion rings.
+pub struct Umem {
+    umem: *mut bpf::xsk_umem,
+    area: *mut c_void,
+    size: usize,
+    frame_size: u32,
+    frame_count: u32,
+    // libbpf keeps pointers to the rings, they can't move.
+    fill: Box<bpf::xsk_ring_prod>,
+    comp: Box<bpf::xsk_ring_cons>,
+}
+
+// SAFETY: the UMEM area and its rings are only touched through `&mut self` (or `&self` for
+// reads of frames the application owns), and libbpf keeps no thread-local state for them.
+unsafe impl Send for Umem {}
+
+impl Umem {
+    /// Allocate a UMEM of `frame_count` frames and register it with the kernel.
+    pub fn new(frame_count: u32, config: UmemConfig) -> XDPResult<Umem> {
+        let size = frame_count as usize * config.frame_size as usize;
+        if size == 0 {
+            set_errno(Errno(22));
+            fail!("UMEM needs at least one frame");
+        }
+
+        let area = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if area == libc::MAP_FAILED {
+            fail!("Error allocating UMEM of {} bytes", size);
+        }
+
+        let mut umem = Umem {
+            umem: std::ptr::null_mut(),
+            area,
+            size,
+            frame_size: config.frame_size,
+            frame_count,
+            fill: Box::new(bpf::xsk_ring_prod::default()),
+            comp: Box::new(bpf::xsk_ring_cons::default()),
+        };
+        let cfg = bpf::xsk_umem_config {
+            fill_size: config.fill_size,
+            comp_size: config.comp_size,
+            frame_size: config.frame_size,
+            frame_headroom: config.frame_headroom,
+            flags: 0,
+        };
+        let rc = unsafe {
+            bpf::xsk_umem__create(
+                &mut umem.umem,
+                area,
+                size as u64,
+                &mut *umem.fill,
+                &mut *umem.comp,
+                &cfg,
+            )
+        };
+        if rc < 0 {
+            fail_rc!(rc, "Error creating UMEM");
+        }
+
+        Ok(umem)
+    }
+
+    pub fn frame_size(&self) -> u32 {
+        self.frame_size
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    /// Addresses of all frames, e.g. to initially fill the fill ring.
+    pub fn frame_addrs(&self) -> impl Iterator<Item = u64> {
+        let frame_size = self.frame_size as u64;
+        (0..self.frame_count as u64).map(move |i| i * frame_size)
+    }
+
+    /// The packet data of `desc`.
+    pub fn frame(&self, desc: &Desc) -> &[u8] {
+        let (start, len) = self.bounds(desc);
+        unsafe { std::slice::from_raw_parts((self.area as *const u8).add(start), len) }
+    }
+
+    /// The packet data of `desc`, e.g. to write a packet before sending it.
+    pub fn frame_mut(&mut self, desc: &Desc) -> &mut [u8] {
+        let (start, len) = self.bounds(desc);
+        unsafe { std::slice::from_raw_parts_mut((self.area as *mut u8).add(start), len) }
+    }
+
+    // Clamps `desc` to the UMEM, so a bad descriptor can't read out of bounds.
+    fn bounds(&self, desc: &Desc) -> (usize, usize) {
+        let start = (desc.addr as usize).min(self.size);
+        (start, (desc.len as usize).min(self.size - start))
+    }
+
+    /// Hand frames to the kernel for received packets. Returns the number of frames added,
+    /// less than `addrs.len()` if the fill ring is full.
+    pub fn fill(&mut self, addrs: &[u64]) -> usize {
+        let mut idx = 0u32;
+        let n =
+            unsafe { bpf::_xsk_ring_prod__reserve(&mut *self.fill, addrs.len() as _, &mut idx) };
+        for (i, addr) in addrs[..n as usize].iter().enumerate() {
+            unsafe { *bpf::_xsk_ring_prod__fill_addr(&mut *self.fill, idx + i as u32) = *addr };
+        }
+        unsafe { bpf::_xsk_ring_prod__submit(&mut *self.fill, n) };
+        n as usize
+    }
+
+    /// Appends the addresses of frames the kernel is done sending to `out`, so they can be
+    /// reused. Returns the number of frames added.
+    pub fn complete(&mut self, out: &mut Vec<u64>) -> usize {
+        let mut idx = 0u32;
+        let n =
+            unsafe { bpf::_xsk_ring_cons__peek(&mut *self.comp, self.comp.size as _, &mut idx) };
+        for i in 0..n as u32 {
+            out.push(unsafe { *bpf::_xsk_ring_cons__comp_addr(&*self.comp, idx + i) });
+        }
+        unsafe { bpf::_xsk_ring_cons__release(&mut *self.comp, n) };
+        n as usize
+    }
+}
+
+impl Drop for Umem {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.umem.is_null() {
+                bpf::xsk_umem__delete(self.umem);
+            }
+            libc::munmap(self.area, self.size);
+        }
+    }
+}
+
+/// Options for an [`XskSocket`].
+#[derive(Debug, Clone, Copy)]
+pub struct XskConfig {
+    rx_size: u32,
+    tx_size: u32,
+    bind_flags: u16,
+    attach_flags: AttachFlags,
+    xsks_map: Option<(i32, MapType)>,
+}
+
+impl XskConfig {
+    /// RX and TX rings of 2048 entries, using `need_wakeup`. Unless
+    /// [`xsks_map`](XskConfig::xsks_map) is set, libbpf attaches its own XDP program that
+    /// redirects every packet of the queue to the socket.
+    pub fn new() -> XskConfig {
+        XskConfig {
+            rx_size: DEFAULT_RING_SIZE,
+            tx_size: DEFAULT_RING_SIZE,
+            bind_flags: XDP_USE_NEED_WAKEUP,
+            attach_flags: AttachFlags::empty(),
+            xsks_map: None,
+        }
+    }
+
+    /// Number of entries of the RX ring, a power of 2.
+    pub fn rx_size(mut self, size: u32) -> XskConfig {
+        self.rx_size = size;
+        self
+    }
+
+    /// Number of entries of the TX ring, a power of 2.
+    pub fn tx_size(mut self, size: u32) -> XskConfig {
+        self.tx_size = size;
+        self
+    }
+
+    /// Force copy mode, e.g. for drivers without zero-copy support.
+    pub fn copy_mode(mut self) -> XskConfig {
+        self.bind_flags = (self.bind_flags & !XDP_ZEROCOPY) | XDP_COPY;
+        self
+    }
+
+    /// Force zero-copy mode, creating the socket fails if the driver doesn't support it.
+    pub fn zero_copy(mut self) -> XskConfig {
+        self.bind_flags = (self.bind_flags & !XDP_COPY) | XDP_ZEROCOPY;
+        self
+    }
+
+    /// Flags for attaching libbpf's XDP program, if it is used.
+    pub fn attach_flags(mut self, flags: AttachFlags) -> XskConfig {
+        self.attach_flags = flags;
+        self
+    }
+
+    /// Use the XSKMAP `map` of an already loaded program instead of libbpf's program. The
+    /// socket is inserted into the map under its queue id. Creating the socket fails if `map`
+    /// isn't an XSKMAP.
+    pub fn xsks_map(mut self, map: &Map<u32, i32>) -> XskConfig {
+        self.xsks_map = Some((map.map_fd(), map.map_type()));
+        self
+    }
+}
+
+impl Default for XskConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An AF_XDP socket bound to a queue of an interface.
+pub struct XskSocket {
+    xsk: *mut bpf::xsk_socket,
+    fd: i32,
+    rx: Box<bpf::xsk_ring_cons>,
+    tx: Box<bpf::xsk_ring_prod>,
+    umem: Umem,
+}
+
+// SAFETY: like `Umem`, the rings are only accessed through `&mut self`.
+unsafe impl Send for XskSocket {}
+
+impl XskSocket {
+    /// Create a socket for queue `queue_id` of `if_name`, using the frames of `umem`.
+    pub fn new(
+        if_name: &str,
+        queue_id: u32,
+        umem: Umem,
+        config: XskConfig,
+    ) -> XDPResult<XskSocket> {
+        if let Some((_, map_type)) = config.xsks_map {
+            if map_type != MapType::XSKMap {
+                set_errno(Errno(22));
+                fail!(
+                    "Improper map type, expected an XSKMAP, got MapType::{:?}",
+                    map_type
+                );
+            }
+        }
+        let name = utils::str_to_cstring(if_name)?;
+        let mut sock = XskSocket {
+            xsk: std::ptr::null_mut(),
+            fd: -1,
+            rx: Box::new(bpf::xsk_ring_cons::default()),
+            tx: Box::new(bpf::xsk_ring_prod::default()),
+            umem,
+        };
+        let cfg = bpf::xsk_socket_config {
+            rx_size: config.rx_size,
+            tx_size: config.tx_size,
+            libbpf_flags: match config.xsks_map {
+                Some(_) => INHIBIT_PROG_LOAD,
+                None => 0,
+            },
+            xdp_flags: config.attach_flags.bits(),
+            bind_flags: config.bind_flags,
+        };
+        let rc = unsafe {
+            bpf::xsk_socket__create(
+                &mut sock.xsk,
+                name.as_ptr(),
+                queue_id,
+                sock.umem.umem,
+                &mut *sock.rx,
+                &mut *sock.tx,
+                &cfg,
+            )
+        };
+        if rc < 0 {
+            fail_rc!(
+                rc,
+                "Error creating AF_XDP socket on {} queue {}",
+                if_name,
+                queue_id
+            );
+        }
+        sock.fd = unsafe { bpf::xsk_socket__fd(sock.xsk) };
+
+        if let Some((map_fd, _)) = config.xsks_map {
+            let key = &queue_id as *const u32 as *const c_void;
+            let val = &sock.fd as *const i32 as *const c_void;
+            let rc = mc::update_elem(map_fd, key, val, MapFlags::BpfAny.bits());
+            mc::check_rc(rc, (), "Error inserting AF_XDP socket into XSKMAP")?;
+        }
+
+        Ok(sock)
+    }
+
+    pub fn fd(&self) -> i32 {
+        self.fd
+    }
+
+    pub fn umem(&self) -> &Umem {
+        &self.umem
+    }
+
+    pub fn umem_mut(&mut self) -> &mut Umem {
+        &mut self.umem
+    }
+
+    /// Wait up to `timeout_ms` for received packets. Returns true if there are packets to read.
+    pub fn poll(&self, timeout_ms: i32) -> XDPResult<bool> {
+        let mut pfd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let rc = unsafe { libc::poll(&mut pfd, 1, timeout_ms) };
+        if rc < 0 {
+            fail!("Error polling AF_XDP socket");
+        }
+
+        Ok(rc > 0)
+    }
+
+    /// Replaces the contents of `out` with the packets received since the last call. The
+    /// frames belong to the application until they are handed back with [`Umem::fill`].
+    pub fn recv(&mut self, out: &mut Vec<Desc>) -> usize {
+        out.clear();
+        let mut idx = 0u32;
+        let n = unsafe { bpf::_xsk_ring_cons__peek(&mut *self.rx, self.rx.size as _, &mut idx) };
+        for i in 0..n as u32 {
+            let d = unsafe { &*bpf::_xsk_ring_cons__rx_desc(&*self.rx, idx + i) };
+            out.push(Desc {
+                addr: d.addr,
+                len: d.len,
+            });
+        }
+        unsafe { bpf::_xsk_ring_cons__release(&mut *self.rx, n) };
+        n as usize
+    }
+
+    /// Queue the packets in `descs` for sending. Returns the number of packets queued, less than
+    /// `descs.len()` if the TX ring is full. The frames come back through [`Umem::complete`].
+    pub fn send(&mut self, descs: &[Desc]) -> XDPResult<usize> {
+        let mut idx = 0u32;
+        let n = unsafe { bpf::_xsk_ring_prod__reserve(&mut *self.tx, descs.len() as _, &mut idx) };
+        for (i, desc) in descs[..n as usize].iter().enumerate() {
+            let d = unsafe { &mut *bpf::_xsk_ring_prod__tx_desc(&mut *self.tx, idx + i as u32) };
+            d.addr = desc.addr;
+            d.len = desc.len;
+            d.options = 0;
+        }
+        unsafe { bpf::_xsk_ring_prod__submit(&mut *self.tx, n) };
+
+        if unsafe { bpf::_xsk_ring_prod__needs_wakeup(&*self.tx) } != 0 {
+            self.kick()?;
+        }
+        Ok(n as usize)
+    }
+
+    // Tells the kernel there are packets on the TX ring.
+    fn kick(&self) -> XDPResult<()> {
+        let rc = unsafe {
+            libc::sendto(
+                self.fd,
+                std::ptr::null(),
+                0,
+                libc::MSG_DONTWAIT,
+                std::ptr::null(),
+                0,
+            )
+        };
+        // The kernel is busy with the ring already.
+        let busy = [libc::EAGAIN, libc::EBUSY, libc::ENOBUFS, libc::ENETDOWN];
+        if rc < 0 && !busy.contains(&get_errno()) {
+            fail!("Error waking up AF_XDP socket");
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for XskSocket {
+    fn drop(&mut self) {
+        if !self.xsk.is_null() {
+            unsafe { bpf::xsk_socket__delete(self.xsk) };
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use rxdp;
 use std::collections::HashMap;
 use std::path::Path;
+use std::time::Duration;
 
 mod utils;
 use rxdp::{MapLike, MapValue};
@@ -19,8 +20,25 @@ const MAP_PERCPU_ARRAY: &'static str = "per_cpu_array";
 const MAP_PERCPU_ARRAY_BIG: &'static str = "pc_array_big";
 
 const DEV_MAP: &'static str = "dev_map";
+const DEV_MAP_HASH: &'static str = "dev_map_hash";
+const CPU_MAP: &'static str = "cpu_map";
+const STACK_TRACES: &'static str = "stack_traces";
+const XSK_MAP: &'static str = "xsk_map";
+const SOCK_MAP: &'static str = "sock_map";
 const PERF_MAP: &'static str = "perf_event";
+const PROG_ARRAY: &'static str = "prog_array";
+const TAIL_SELECTOR: &'static str = "tail_selector";
+const CONFIG_A: &'static str = "config_a";
+const CONFIG_B: &'static str = "config_b";
+const CONFIG_SELECTOR: &'static str = "config_selector";
+const MAP_FLOWS: &'static str = "flows";
 const PROG_TEST: &'static str = "rxdp_test";
+const PROG_DROP: &'static str = "rxdp_drop";
+const PROG_DEVMAP: &'static str = "rxdp_devmap";
+const PROG_RINGBUF: &'static str = "rxdp_ringbuf";
+const RING_BUF: &'static str = "ring_buf";
+const LOCK_SLOTS: &'static str = "lock_slots";
+const PINNED_BY_NAME: &'static str = "pinned_by_name";
 
 #[test]
 fn test_open_valid_elf() {
@@ -117,6 +135,223 @@ fn test_pinned_per_cpu_map_values() {
     assert_eq!(got.into_vec(), expected);
 }
 
+#[test]
+fn test_pin_root_path() {
+    let test_dir = utils::pin_dir();
+    let mut pinned_maps = std::collections::HashSet::new();
+    pinned_maps.insert(MAP_HASH.to_string());
+
+    let obj = rxdp::XDPObjectBuilder::new(utils::TEST_FILE.as_str())
+        .pin_root_path(&test_dir.path)
+        .build()
+        .unwrap();
+    assert_eq!(obj.pin_root_path(), test_dir.path);
+
+    // Maps are pinned under the pin root path by default.
+    obj.pinned_maps(&pinned_maps, None).unwrap();
+    let _obj = obj.load().unwrap();
+    assert!(Path::new(&format!("{}/{}", test_dir.path, MAP_HASH)).exists());
+}
+
+#[test]
+fn test_pin_by_name() {
+    let test_dir = utils::pin_dir();
+    let load = || {
+        rxdp::XDPObjectBuilder::new(utils::TEST_FILE.as_str())
+            .pin_root_path(&test_dir.path)
+            .build()
+            .unwrap()
+            .load()
+            .unwrap()
+    };
+
+    // Declared with LIBBPF_PIN_BY_NAME, so pinned under the pin root path on load.
+    let obj = load();
+    assert!(Path::new(&format!("{}/{}", test_dir.path, PINNED_BY_NAME)).exists());
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, PINNED_BY_NAME).unwrap();
+    m.update(&1, &7, rxdp::MapFlags::BpfAny).unwrap();
+
+    // The second load reuses the pinned map.
+    let obj2 = load();
+    let m2: rxdp::Map<u32, u32> = rxdp::Map::new(&obj2, PINNED_BY_NAME).unwrap();
+    assert_eq!(m2.get(&1).unwrap(), 7);
+}
+
+#[test]
+fn test_config_defaults() {
+    // Same as the built-in default, other tests rely on it.
+    rxdp::config::set_default_pin_path(utils::PIN_PATH.as_str());
+    assert_eq!(rxdp::config::default_pin_path(), *utils::PIN_PATH);
+    assert_eq!(test_object().pin_root_path(), *utils::PIN_PATH);
+
+    rxdp::config::set_default_attach_flags(rxdp::AttachFlags::SKB_MODE);
+    assert_eq!(rxdp::AttachFlags::from_env(), rxdp::AttachFlags::SKB_MODE);
+
+    let obj = loaded_object();
+    let iface = utils::test_iface();
+    obj.get_program(PROG_TEST)
+        .unwrap()
+        .attach_to_interface(&iface.name, rxdp::AttachFlags::from_env())
+        .unwrap();
+    assert!(utils::xdp_attached(&iface.name));
+}
+
+#[test]
+fn test_pinned_maps_reuse_matrix() {
+    let maps = [
+        MAP_HASH,
+        MAP_ARRAY,
+        MAP_PERCPU_HASH,
+        DEV_MAP,
+        DEV_MAP_HASH,
+        XSK_MAP,
+        SOCK_MAP,
+        PERF_MAP,
+    ];
+
+    for map_name in maps.iter() {
+        let test_dir = utils::pin_dir();
+        let mut pinned_maps = std::collections::HashSet::new();
+        pinned_maps.insert(map_name.to_string());
+
+        // Pin with obj1, then reuse the pinned map from obj2
+        for _ in 0..2 {
+            let obj = test_object();
+            obj.pinned_maps(&pinned_maps, Some(&test_dir.path))
+                .unwrap_or_else(|e| panic!("{}: {}", map_name, e));
+            obj.load().unwrap_or_else(|e| panic!("{}: {}", map_name, e));
+        }
+    }
+}
+
+#[test]
+fn test_pinned_map_mismatch() {
+    let test_dir = utils::pin_dir();
+    let mut pinned_maps = std::collections::HashSet::new();
+    pinned_maps.insert(DEV_MAP.to_string());
+
+    let obj = test_object();
+    obj.pinned_maps(&pinned_maps, Some(&test_dir.path)).unwrap();
+    obj.load().unwrap();
+
+    // Pin a map of a different type in place of `dev_map_hash`.
+    std::fs::rename(
+        format!("{}/{}", &test_dir.path, DEV_MAP),
+        format!("{}/{}", &test_dir.path, DEV_MAP_HASH),
+    )
+    .unwrap();
+    pinned_maps.insert(DEV_MAP_HASH.to_string());
+
+    let obj = test_object();
+    let err = obj.pinned_maps(&pinned_maps, Some(&test_dir.path));
+    assert_eq!(err.unwrap_err().code(), 22);
+}
+
+#[test]
+fn test_pinned_map_adopt_flags() {
+    let test_dir = utils::pin_dir();
+    let mut pinned_maps = std::collections::HashSet::new();
+    pinned_maps.insert(MAP_HASH.to_string());
+
+    // Same definition as `hash`, created without preallocation.
+    let pin_path = format!("{}/{}", &test_dir.path, MAP_HASH);
+    let m = rxdp::MapBuilder::<u32, u32>::new()
+        .max_entries(10)
+        .no_prealloc()
+        .pin_path(&pin_path)
+        .create()
+        .unwrap();
+    m.update(&1, &2, rxdp::MapFlags::BpfAny).unwrap();
+
+    let obj = test_object();
+    let err = obj
+        .pinned_maps(&pinned_maps, Some(&test_dir.path))
+        .unwrap_err();
+    assert_eq!(err.code(), 22);
+    assert!(err.description().contains("map_flags"));
+
+    let obj = test_object();
+    let config = rxdp::PinConfig::new(&pinned_maps)
+        .path(&test_dir.path)
+        .adopt_pinned_flags();
+    obj.pin_maps(config).unwrap();
+    let obj = obj.load().unwrap();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    assert_eq!(m.lookup(&1).unwrap().into_single(), 2);
+}
+
+#[test]
+fn test_compact() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH_BIG).unwrap();
+    for i in 0..1000u32 {
+        m.update(&i, &(i + 1), rxdp::MapFlags::BpfAny).unwrap();
+    }
+    for i in (0..1000u32).step_by(2) {
+        m.delete(&i).unwrap();
+    }
+
+    let fresh = rxdp::compact::compact(&m).unwrap();
+    assert_ne!(fresh.map_fd(), m.map_fd());
+    assert_eq!(fresh.max_entries(), m.max_entries());
+    let mut items: Vec<(u32, u32)> = fresh
+        .items()
+        .unwrap()
+        .into_iter()
+        .map(|kv| (kv.key, kv.value.into_single()))
+        .collect();
+    items.sort();
+    let expected: Vec<(u32, u32)> = (1..1000u32).step_by(2).map(|i| (i, i + 1)).collect();
+    assert_eq!(items, expected);
+
+    let test_dir = utils::pin_dir();
+    let pin_path = format!("{}/{}", test_dir.path, MAP_HASH_BIG);
+    let fresh = rxdp::compact::compact_pinned(&m, &pin_path).unwrap();
+    assert!(!Path::new(&format!("{}.compact", pin_path)).exists());
+
+    // Objects loaded after the swap reuse the compacted map.
+    let mut pinned_maps = std::collections::HashSet::new();
+    pinned_maps.insert(MAP_HASH_BIG.to_string());
+    let obj2 = test_object();
+    obj2.pinned_maps(&pinned_maps, Some(&test_dir.path))
+        .unwrap();
+    let obj2 = obj2.load().unwrap();
+    let pinned: rxdp::Map<u32, u32> = rxdp::Map::new(&obj2, MAP_HASH_BIG).unwrap();
+    assert_eq!(pinned.lookup(&1).unwrap().into_single(), 2);
+    fresh.delete(&1).unwrap();
+    assert!(pinned.lookup(&1).is_err());
+
+    let a: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_ARRAY).unwrap();
+    assert_eq!(rxdp::compact::compact(&a).err().unwrap().code(), 22);
+}
+
+#[test]
+fn test_pin_maps_fallback_to_unpinned() {
+    let mut pinned_maps = std::collections::HashSet::new();
+    pinned_maps.insert(MAP_LRU_HASH.to_string());
+
+    // Not on a bpffs
+    let path = format!("/tmp/{}", utils::random_string());
+
+    // Without a fallback, the kernel refuses the pin on load
+    let obj = test_object();
+    obj.pin_maps(rxdp::PinConfig::new(&pinned_maps).path(&path))
+        .unwrap();
+    assert!(obj.load().is_err());
+
+    let obj = test_object();
+    let mut fallbacks = Vec::new();
+    let config = rxdp::PinConfig::new(&pinned_maps)
+        .path(&path)
+        .fallback_to_unpinned(|name, _| fallbacks.push(name.to_string()));
+    obj.pin_maps(config).unwrap();
+    let obj = obj.load().unwrap();
+
+    assert_eq!(fallbacks, vec![MAP_LRU_HASH.to_string()]);
+    assert!(!Path::new(&path).exists());
+    let _m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_LRU_HASH).unwrap();
+}
+
 #[test]
 fn test_pinned_maps_default_path() {
     let obj = test_object();
@@ -160,6 +395,156 @@ fn test_get_program() {
         .expect("Unable to load test program");
 }
 
+#[test]
+fn test_object_metadata() {
+    let obj = test_object();
+    assert_eq!(obj.name(), "test");
+    assert_eq!(obj.path(), utils::TEST_FILE.as_str());
+
+    let obj = obj.load().unwrap();
+    assert_eq!(obj.name(), "test");
+    assert_eq!(obj.path(), utils::TEST_FILE.as_str());
+    assert!(format!("{:?}", obj).contains(PROG_TEST));
+
+    let err = obj.get_program("missing").err().unwrap();
+    assert!(err.description().contains(utils::TEST_FILE.as_str()));
+}
+
+#[test]
+fn test_map_from_id() {
+    let obj = loaded_object();
+    let info = obj.get_program(PROG_RINGBUF).unwrap().info().unwrap();
+
+    let ids = rxdp::sys::program_map_ids(info.id).unwrap().unwrap();
+    assert_eq!(ids.len(), 1);
+    let m = rxdp::DynMap::from_id(ids[0]).unwrap();
+    assert_eq!(m.map_type(), rxdp::MapType::RingBuffer);
+
+    assert!(rxdp::DynMap::from_id(u32::MAX).is_err());
+}
+
+#[test]
+fn test_unsupported_policy() {
+    // The test kernel supports every map and program in the test object.
+    let obj = test_object();
+    assert!(obj.unsupported().unwrap().is_empty());
+
+    let obj = rxdp::XDPObjectBuilder::new(utils::TEST_FILE.as_str())
+        .unsupported_policy(rxdp::UnsupportedPolicy::Skip)
+        .build()
+        .unwrap()
+        .load()
+        .unwrap();
+    assert!(obj.skipped().is_empty());
+    obj.get_program(PROG_TEST).unwrap();
+    obj.get_program(PROG_RINGBUF).unwrap();
+}
+
+#[test]
+fn test_program_info() {
+    let obj = loaded_object();
+    let info = obj.get_program(PROG_TEST).unwrap().info().unwrap();
+    assert_eq!(info.name, PROG_TEST);
+    assert_eq!(info.tag.len(), 16);
+    assert!(info.tag.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(info.build_id, obj.build_id());
+
+    let by_id = rxdp::sys::program_info(info.id).unwrap().unwrap();
+    assert_eq!(by_id.tag, info.tag);
+    assert_eq!(by_id.build_id, None);
+}
+
+#[test]
+fn test_legacy_attach_type_workaround() {
+    for enable in [true, false].iter() {
+        let obj = rxdp::XDPObjectBuilder::new(&utils::TEST_FILE)
+            .legacy_attach_type_workaround(*enable)
+            .build()
+            .unwrap();
+        obj.set_expected_attach_type(PROG_DEVMAP, rxdp::ExpectedAttachType::DevMap)
+            .unwrap();
+        let obj = obj.load().unwrap();
+
+        // DEVMAP programs keep their attach type either way
+        let prog = obj.get_program(PROG_DEVMAP).unwrap();
+        assert_eq!(
+            prog.expected_attach_type(),
+            Some(rxdp::ExpectedAttachType::DevMap)
+        );
+        let prog = obj.get_program(PROG_TEST).unwrap();
+        assert_eq!(
+            prog.expected_attach_type(),
+            Some(rxdp::ExpectedAttachType::Xdp)
+        );
+    }
+}
+
+#[test]
+fn test_kconfig_override() {
+    let obj = rxdp::XDPObjectBuilder::new(&utils::TEST_FILE)
+        .kconfig_override("CONFIG_HZ", 250)
+        .kconfig_override("CONFIG_BPF_JIT", "y")
+        .build()
+        .unwrap();
+    obj.load().unwrap();
+
+    for (name, value) in [("HZ", "250"), ("CONFIG_HZ", ""), ("CONFIG_HZ", "250\n")].iter() {
+        let r = rxdp::XDPObjectBuilder::new(&utils::TEST_FILE)
+            .kconfig_override(name, value)
+            .build();
+        assert_eq!(r.err().unwrap().code(), 22);
+    }
+}
+
+#[test]
+fn test_set_program_type() {
+    let obj = test_object();
+    obj.set_program_type(PROG_TEST, rxdp::ProgType::Xdp)
+        .unwrap();
+    let err = obj.set_program_type("missing", rxdp::ProgType::Xdp);
+    assert_eq!(err.unwrap_err().code(), 2);
+    assert!(obj.load().is_ok());
+}
+
+#[test]
+fn test_get_xdp_program() {
+    let obj = test_object();
+    obj.set_program_type(PROG_DROP, rxdp::ProgType::SchedCls)
+        .unwrap();
+    let obj = obj.load().unwrap();
+
+    let prog = obj.get_xdp_program(PROG_TEST).unwrap();
+    assert_eq!(prog.kind().unwrap(), rxdp::ProgramKind::Xdp);
+
+    let err = obj.get_xdp_program(PROG_DROP).err().unwrap();
+    assert_eq!(err.code(), 22);
+    assert!(err.description().contains("is a sched_cls program"));
+    let prog = obj.get_program(PROG_DROP).unwrap();
+    assert_eq!(prog.kind().unwrap(), rxdp::ProgramKind::Tc);
+}
+
+#[test]
+fn test_expected_attach_type() {
+    let obj = test_object();
+    obj.set_expected_attach_type(PROG_DEVMAP, rxdp::ExpectedAttachType::DevMap)
+        .unwrap();
+    let err = obj.set_expected_attach_type("missing", rxdp::ExpectedAttachType::Xdp);
+    assert_eq!(err.unwrap_err().code(), 2);
+
+    let obj = obj.load().unwrap();
+    let prog = obj.get_program(PROG_DEVMAP).unwrap();
+    assert_eq!(
+        prog.expected_attach_type(),
+        Some(rxdp::ExpectedAttachType::DevMap)
+    );
+
+    let prog = obj.get_program(PROG_TEST).unwrap();
+    assert_eq!(
+        prog.expected_attach_type(),
+        Some(rxdp::ExpectedAttachType::Xdp)
+    );
+}
+
 #[test]
 fn test_attach_program_to_interface() {
     let obj = loaded_object();
@@ -170,6 +555,54 @@ fn test_attach_program_to_interface() {
         .unwrap();
 }
 
+#[test]
+fn test_attach_when_ready() {
+    let obj = loaded_object();
+    let prog = obj.get_program(PROG_TEST).unwrap();
+    let timeout = std::time::Duration::from_millis(100);
+
+    let err = prog
+        .attach_when_ready("rxdp_missing", rxdp::AttachFlags::SKB_MODE, timeout)
+        .unwrap_err();
+    assert!(err.is_timed_out());
+
+    // Create the interface while the attach is waiting for it.
+    let name = utils::random_string();
+    let creator = {
+        let name = name.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            utils::test_iface_named(name)
+        })
+    };
+    prog.attach_when_ready(&name, rxdp::AttachFlags::SKB_MODE, timeout * 50)
+        .unwrap();
+    let iface = creator.join().unwrap();
+    assert!(utils::xdp_attached(&iface.name));
+}
+
+#[test]
+fn test_drop_policy() {
+    let iface = utils::test_iface();
+
+    for policy in [rxdp::DropPolicy::Leave, rxdp::DropPolicy::Detach].iter() {
+        let mut obj = loaded_object();
+        assert!(obj.drop_policy() == rxdp::DropPolicy::Leave);
+        obj.set_drop_policy(*policy);
+
+        let prog = obj.get_program(PROG_TEST).unwrap();
+        prog.attach_to_interface(&iface.name, rxdp::AttachFlags::SKB_MODE)
+            .unwrap();
+        assert!(utils::xdp_attached(&iface.name));
+
+        drop(obj);
+        assert_eq!(
+            utils::xdp_attached(&iface.name),
+            *policy == rxdp::DropPolicy::Leave
+        );
+    }
+}
+
 #[test]
 fn test_attach_program_unsupported_mode() {
     let obj = loaded_object();
@@ -181,6 +614,60 @@ fn test_attach_program_unsupported_mode() {
         .is_err());
 }
 
+#[test]
+fn test_attach_program_error_return_code() {
+    let obj = loaded_object();
+    let prog = obj.get_program(PROG_TEST).unwrap();
+
+    let iface = utils::test_iface();
+    let e = prog
+        .attach_to_interface(&iface.name, rxdp::AttachFlags::HW_MODE)
+        .unwrap_err();
+    let rc = e.return_code().unwrap();
+    assert!(rc < 0);
+    assert!(e.code() > 0);
+}
+
+#[test]
+fn test_attach_program_best_effort() {
+    let obj = loaded_object();
+    let prog = obj.get_program(PROG_TEST).unwrap();
+
+    let iface = utils::test_iface();
+    let mut downgrade = None;
+    let mode = prog
+        .attach_best_effort_with(&iface.name, rxdp::AttachFlags::SKB_MODE, |d| {
+            downgrade = Some((d.interface.clone(), d.from, d.to))
+        })
+        .unwrap();
+
+    match mode {
+        rxdp::AttachMode::Driver => assert!(downgrade.is_none()),
+        rxdp::AttachMode::Generic => {
+            let expected = (
+                iface.name.clone(),
+                rxdp::AttachMode::Driver,
+                rxdp::AttachMode::Generic,
+            );
+            assert_eq!(downgrade, Some(expected));
+        }
+    }
+}
+
+#[test]
+fn test_program_benchmark() {
+    let obj = loaded_object();
+    let prog = obj.get_program(PROG_DROP).unwrap();
+
+    let packet = [0u8; 64];
+    let stats = prog.benchmark(&packet, 100).unwrap();
+    assert_eq!(stats.iterations, 100);
+    assert_eq!(stats.retval, 1); // XDP_DROP
+    assert!(stats.min <= stats.p50 && stats.p50 <= stats.p99 && stats.p99 <= stats.max);
+
+    assert_eq!(prog.benchmark(&packet, 0).unwrap_err().code(), 22);
+}
+
 #[test]
 fn test_attach_program_no_interface() {
     let obj = loaded_object();
@@ -195,6 +682,7 @@ fn test_attach_program_no_interface() {
 }
 
 #[test]
+#[allow(deprecated)]
 fn test_create_hash_map() {
     let m = rxdp::Map::<u32, u32>::create(rxdp::MapType::Hash, 4, 4, 10, 0).unwrap();
     let key = 100u32;
@@ -203,6 +691,7 @@ fn test_create_hash_map() {
 }
 
 #[test]
+#[allow(deprecated)]
 fn test_create_array_map() {
     let m = rxdp::Map::<u32, u32>::create(rxdp::MapType::Array, 4, 4, 10, 0).unwrap();
     let key = 0u32;
@@ -210,6 +699,77 @@ fn test_create_array_map() {
     test_map_operations(&m, key, val);
 }
 
+#[test]
+fn test_map_builder() {
+    let m = rxdp::MapBuilder::<u32, u32>::new()
+        .map_type(rxdp::MapType::LRUHash)
+        .max_entries(10)
+        .name("builder_map")
+        .create()
+        .unwrap();
+    assert_eq!(m.map_type(), rxdp::MapType::LRUHash);
+    test_map_operations(&m, 100u32, 101u32);
+
+    let r = rxdp::MapBuilder::<u32, u32>::new()
+        .map_type(rxdp::MapType::PerCPUHash)
+        .max_entries(10)
+        .create();
+    assert!(r.is_err());
+    assert!(rxdp::MapBuilder::<u32, u32>::new().create().is_err());
+
+    let obj = loaded_object();
+    let m = rxdp::MapBuilder::<u32, u32>::new()
+        .name(MAP_HASH)
+        .map_type(rxdp::MapType::Hash)
+        .build(&obj)
+        .unwrap();
+    test_map_operations(&m, 100u32, 101u32);
+
+    let r = rxdp::MapBuilder::<u32, u32>::new()
+        .name(MAP_HASH)
+        .map_type(rxdp::MapType::Array)
+        .build(&obj);
+    assert_eq!(r.err().unwrap().code(), 22);
+}
+
+#[test]
+fn test_per_cpu_map_builder() {
+    let m = rxdp::PerCpuMapBuilder::<u32, u32>::new()
+        .map_type(rxdp::MapType::PerCPUArray)
+        .max_entries(10)
+        .create()
+        .unwrap();
+    test_map_operations(&m, 0u32, 101u32);
+
+    let obj = loaded_object();
+    let m = rxdp::PerCpuMapBuilder::<u32, u32>::new()
+        .name(MAP_PERCPU_HASH)
+        .build(&obj)
+        .unwrap();
+    test_map_operations(&m, 100u32, 101u32);
+}
+
+#[test]
+fn test_per_cpu_value_stride() {
+    // 12 byte map values read as u64: values are 16 bytes apart for every CPU.
+    let m = rxdp::PerCpuMapBuilder::<u32, u64>::new()
+        .value_size(12)
+        .max_entries(10)
+        .create()
+        .unwrap();
+    assert_eq!(m.value_stride(), 16);
+    m.update(&1u32, &7u64, rxdp::MapFlags::BpfAny).unwrap();
+    let got = m.lookup(&1u32).unwrap().into_vec();
+    assert_eq!(got.len(), rxdp::num_cpus());
+    assert!(got.iter().all(|v| *v == 7));
+
+    let r = rxdp::PerCpuMapBuilder::<u32, u128>::new()
+        .value_size(12)
+        .max_entries(10)
+        .create();
+    assert!(r.is_err());
+}
+
 #[test]
 fn test_lru_hash_map_operations() {
     let obj = loaded_object();
@@ -249,79 +809,638 @@ fn test_dev_map_operations() {
 }
 
 #[test]
-fn test_create_per_cpu_hash_map() {
-    let m = rxdp::PerCpuMap::<u32, u32>::create(rxdp::MapType::PerCPUHash, 4, 4, 10, 0).unwrap();
-    let key = 100u32;
-    let val = 101u32;
-    test_map_operations(&m, key, val);
-}
+fn test_setup_devmap() {
+    let obj = loaded_object();
 
-#[test]
-fn test_create_per_cpu_array_map() {
-    let m = rxdp::PerCpuMap::<u32, u32>::create(rxdp::MapType::PerCPUArray, 4, 4, 10, 0).unwrap();
-    let key = 0u32;
-    let val = 101u32;
-    test_map_operations(&m, key, val);
-}
+    let iface1 = utils::test_iface();
+    let iface2 = utils::test_iface();
+    let routes = [(1, iface1.name.as_str()), (3, iface2.name.as_str())];
 
-#[test]
-fn test_create_normal_hash_fails() {
-    let r = rxdp::PerCpuMap::<u32, u32>::create(rxdp::MapType::Hash, 4, 4, 10, 0);
-    assert!(r.is_err());
+    for map_name in [DEV_MAP, DEV_MAP_HASH].iter() {
+        let m = rxdp::redirect::setup_devmap(&obj, map_name, &routes).unwrap();
+        for (key, name) in routes.iter() {
+            let index = utils::lookup_interface_by_name(name).unwrap() as u32;
+            assert_eq!(m.lookup(key).unwrap().into_single(), index);
+        }
+    }
 }
 
 #[test]
-fn test_per_cpu_lru_hash_map_operations() {
+fn test_setup_devmap_invalid_routes() {
     let obj = loaded_object();
-    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_LRU_HASH).unwrap();
-    let key = 100u32;
-    let val = 101u32;
-    test_map_operations(&m, key, val);
+    let iface = utils::test_iface();
+    let name = iface.name.as_str();
+
+    let err = rxdp::redirect::setup_devmap(&obj, MAP_HASH, &[(0, name)]);
+    assert_eq!(err.err().unwrap().code(), 22);
+
+    let err = rxdp::redirect::setup_devmap(&obj, DEV_MAP, &[(0, name), (0, name)]);
+    assert_eq!(err.err().unwrap().code(), 22);
+
+    let err = rxdp::redirect::setup_devmap(&obj, DEV_MAP, &[(100, name)]);
+    assert_eq!(err.err().unwrap().code(), 7);
+
+    let missing = utils::random_string();
+    let err = rxdp::redirect::setup_devmap(&obj, DEV_MAP, &[(0, name), (1, &missing)]);
+    assert!(err.is_err());
+
+    // Nothing was written
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, DEV_MAP).unwrap();
+    assert!(m.lookup(&0).is_err());
 }
 
 #[test]
-fn test_per_cpu_hash_map_operations() {
+fn test_tail_call_table() {
     let obj = loaded_object();
-    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_HASH).unwrap();
-    let key = 100u32;
-    let val = 101u32;
-    test_map_operations(&m, key, val);
+    let mut table =
+        rxdp::TailCallTable::new(&obj, PROG_ARRAY, TAIL_SELECTOR, &["first", "second"]).unwrap();
+    assert_eq!(table.mapping(), vec![("first", 0), ("second", 2)]);
+
+    // The selector points at the active index of every slot
+    let selector: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, TAIL_SELECTOR).unwrap();
+    assert_eq!(selector.lookup(&0).unwrap().into_single(), 0);
+    assert_eq!(selector.lookup(&1).unwrap().into_single(), 2);
+    let prog = obj.get_program(PROG_DROP).unwrap();
+
+    // Staging doesn't change the active index
+    table.stage("second", prog).unwrap();
+    assert_eq!(table.mapping(), vec![("first", 0), ("second", 2)]);
+    assert_eq!(selector.lookup(&1).unwrap().into_single(), 2);
+
+    assert_eq!(table.commit("second").unwrap(), 3);
+    assert_eq!(table.mapping(), vec![("first", 0), ("second", 3)]);
+    assert_eq!(selector.lookup(&1).unwrap().into_single(), 3);
+
+    assert_eq!(table.update("second", prog).unwrap(), 2);
+    assert_eq!(selector.lookup(&1).unwrap().into_single(), 2);
+
+    // A new table picks up the current selector values
+    let table = rxdp::TailCallTable::new(&obj, PROG_ARRAY, TAIL_SELECTOR, &["a", "b"]).unwrap();
+    assert_eq!(table.mapping(), vec![("a", 0), ("b", 2)]);
 }
 
 #[test]
-fn test_per_cpu_array_map_operations() {
+fn test_tail_call_table_errors() {
     let obj = loaded_object();
-    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_ARRAY).unwrap();
-    let key = 0u32;
-    let val = 100u32;
-    test_map_operations(&m, key, val);
+    let prog = obj.get_program(PROG_DROP).unwrap();
+
+    let slots = ["a", "b", "c", "d", "e"];
+    let err = rxdp::TailCallTable::new(&obj, PROG_ARRAY, TAIL_SELECTOR, &slots);
+    assert_eq!(err.err().unwrap().code(), 7);
+
+    let err = rxdp::TailCallTable::new(&obj, MAP_ARRAY, TAIL_SELECTOR, &["a"]);
+    assert_eq!(err.err().unwrap().code(), 22);
+
+    let err = rxdp::TailCallTable::new(&obj, PROG_ARRAY, TAIL_SELECTOR, &["a", "a"]);
+    assert_eq!(err.err().unwrap().code(), 22);
+
+    let mut table = rxdp::TailCallTable::new(&obj, PROG_ARRAY, TAIL_SELECTOR, &["a"]).unwrap();
+    assert_eq!(table.stage("missing", prog).unwrap_err().code(), 2);
+    assert_eq!(table.commit("a").unwrap_err().code(), 22);
 }
 
 #[test]
-fn test_items_hash_map() {
+fn test_double_buffered_config() {
     let obj = loaded_object();
-    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
-    test_items(&m);
+    let mut config: rxdp::DoubleBufferedConfig<u32, u64> =
+        rxdp::DoubleBufferedConfig::new(&obj, [CONFIG_A, CONFIG_B], CONFIG_SELECTOR).unwrap();
+    assert_eq!(config.active(), 0);
+
+    let selector: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, CONFIG_SELECTOR).unwrap();
+    let b: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, CONFIG_B).unwrap();
+
+    // Staging writes to the inactive map only
+    config.stage(&[(1, 100), (2, 200)]).unwrap();
+    assert_eq!(selector.lookup(&0).unwrap().into_single(), 0);
+    assert_eq!(b.lookup(&2).unwrap().into_single(), 200);
+
+    assert_eq!(config.commit().unwrap(), 1);
+    assert_eq!(selector.lookup(&0).unwrap().into_single(), 1);
+    assert_eq!(config.commit().unwrap_err().code(), 22);
+
+    // Old entries are removed from the map being staged
+    config.update(&[(3, 300)]).unwrap();
+    assert_eq!(config.update(&[(4, 400)]).unwrap(), 1);
+    assert!(b.lookup(&1).is_err());
+    assert_eq!(b.lookup(&4).unwrap().into_single(), 400);
+    assert_eq!(b.items().unwrap().len(), 1);
+
+    // A new handle picks up the current selector value
+    let config: rxdp::DoubleBufferedConfig<u32, u64> =
+        rxdp::DoubleBufferedConfig::new(&obj, [CONFIG_A, CONFIG_B], CONFIG_SELECTOR).unwrap();
+    assert_eq!(config.active(), 1);
 }
 
 #[test]
-fn test_items_array_map() {
+fn test_double_buffered_config_errors() {
     let obj = loaded_object();
-    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_ARRAY).unwrap();
-    test_items(&m);
+
+    let r = rxdp::DoubleBufferedConfig::<u32, u32>::new(&obj, [MAP_HASH, MAP_HASH_BIG], MAP_ARRAY);
+    assert_eq!(r.err().unwrap().code(), 22);
+
+    let r = rxdp::DoubleBufferedConfig::<u32, u64>::new(&obj, [CONFIG_A, CONFIG_B], MAP_HASH);
+    assert_eq!(r.err().unwrap().code(), 22);
+
+    let selector: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, CONFIG_SELECTOR).unwrap();
+    selector.update(&0, &2, rxdp::MapFlags::BpfAny).unwrap();
+    let r =
+        rxdp::DoubleBufferedConfig::<u32, u64>::new(&obj, [CONFIG_A, CONFIG_B], CONFIG_SELECTOR);
+    assert_eq!(r.err().unwrap().code(), 22);
 }
 
 #[test]
-fn test_items_big_hash_map() {
+fn test_ring_buffer() {
     let obj = loaded_object();
-    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH_BIG).unwrap();
-    test_items(&m);
+    let mut rb = rxdp::RingBuffer::new(&obj, RING_BUF).unwrap();
+    assert!(rb.epoll_fd() >= 0);
+    assert_eq!(
+        rb.consume_nowait(|_, _| panic!("unexpected record"))
+            .unwrap(),
+        0
+    );
+
+    // Every run of the program writes 1 record
+    let prog = obj.get_program(PROG_RINGBUF).unwrap();
+    prog.benchmark(&[0u8; 64], 3).unwrap();
+
+    let stats = rb.stats();
+    assert_eq!(stats[0].name, RING_BUF);
+    assert_eq!(stats[0].pending_bytes, 3 * 16);
+
+    let mut records = Vec::new();
+    let n = rb
+        .poll(1000, |ring, data| records.push((ring, data.to_vec())))
+        .unwrap();
+    assert_eq!(n, 3);
+    assert_eq!(records, vec![(0, 7u32.to_ne_bytes().to_vec()); 3]);
+
+    let stats = rb.stats();
+    assert_eq!(stats[0].consumed, 3);
+    assert_eq!(stats[0].consumed_bytes, 12);
+    assert_eq!(stats[0].pending_bytes, 0);
+    assert_eq!(stats[0].dropped, None);
+
+    let err = rxdp::RingBuffer::new(&obj, MAP_HASH);
+    assert_eq!(err.err().unwrap().code(), 22);
 }
 
 #[test]
-fn test_items_big_array_map() {
+fn test_dyn_map_operations() {
     let obj = loaded_object();
-    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_ARRAY_BIG).unwrap();
+    let m = rxdp::DynMap::new(&obj, MAP_HASH).unwrap();
+    assert!(m.map_type() == rxdp::MapType::Hash);
+    assert_eq!(m.raw_map_type(), rxdp::MapType::Hash as u32);
+    assert_eq!(m.value_len(), 4);
+
+    let key = 5u32.to_ne_bytes();
+    let val = 50u32.to_ne_bytes();
+    m.update(&key, &val, rxdp::MapFlags::BpfAny).unwrap();
+    assert_eq!(m.lookup(&key).unwrap(), val.to_vec());
+    assert_eq!(m.keys().unwrap(), vec![key.to_vec()]);
+
+    m.delete(&key).unwrap();
+    assert!(m.lookup(&key).is_err());
+    assert!(m.keys().unwrap().is_empty());
+
+    assert_eq!(m.lookup(&[0u8; 8]).unwrap_err().code(), 22);
+    let err = m.update(&key, &[0u8; 8], rxdp::MapFlags::BpfAny);
+    assert_eq!(err.unwrap_err().code(), 22);
+}
+
+#[test]
+fn test_dyn_map_per_cpu() {
+    let obj = loaded_object();
+    let m = rxdp::DynMap::new(&obj, MAP_PERCPU_HASH).unwrap();
+    assert_eq!(m.value_len(), 8 * rxdp::num_cpus());
+
+    let key = 1u32.to_ne_bytes();
+    let val = vec![1u8; m.value_len()];
+    m.update(&key, &val, rxdp::MapFlags::BpfAny).unwrap();
+
+    let got = m.lookup(&key).unwrap();
+    for chunk in got.chunks(8) {
+        assert_eq!(chunk[..4], [1u8; 4]);
+    }
+}
+
+rxdp::bind_maps!(
+    struct TestMaps {
+    hash: rxdp::Map<u32, u32> = MAP_HASH,
+    percpu: rxdp::PerCpuMap<u32, u64> = MAP_PERCPU_HASH,
+    dynamic: rxdp::DynMap = MAP_ARRAY,
+}
+);
+
+#[test]
+fn test_bind_maps() {
+    let obj = loaded_object();
+    let maps = TestMaps::bind(&obj).unwrap();
+    maps.hash.update(&1, &2, rxdp::MapFlags::BpfAny).unwrap();
+    assert_eq!(maps.percpu.max_entries(), 10);
+    assert_eq!(maps.dynamic.map_type(), rxdp::MapType::Array);
+
+    let err = rxdp::bind_maps!(
+        &obj,
+        struct Broken {
+        hash: rxdp::Map<u64, u32> = MAP_HASH,
+        ok: rxdp::Map<u32, u32> = MAP_HASH,
+        missing: rxdp::DynMap = "not_a_map",
+    }
+    )
+    .err()
+    .unwrap();
+    assert_eq!(err.code(), 22);
+    assert!(err.description().starts_with("Error binding 2 map(s)"));
+    assert!(err.description().contains(MAP_HASH));
+    assert!(err.description().contains("not_a_map"));
+}
+
+#[test]
+fn test_max_entries_from_kernel() {
+    let obj = loaded_object();
+
+    // The perf event array doesn't set max_entries, libbpf sizes it to the number of CPUs.
+    let mut m = rxdp::DynMap::new(&obj, PERF_MAP).unwrap();
+    assert_eq!(m.max_entries() as usize, rxdp::num_cpus());
+    m.refresh_info().unwrap();
+    assert_eq!(m.max_entries() as usize, rxdp::num_cpus());
+
+    let mut m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    m.refresh_info().unwrap();
+    assert_eq!(m.max_entries(), 10);
+}
+
+#[test]
+fn test_key_histogram() {
+    let obj = loaded_object();
+
+    for map_name in [MAP_HASH_BIG, MAP_ARRAY_BIG].iter() {
+        let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, map_name).unwrap();
+        for i in 0..400u32 {
+            m.update(&i, &i, rxdp::MapFlags::BpfAny).unwrap();
+        }
+
+        let hist = m.key_histogram(|k| *k / 100).unwrap();
+        for bucket in 0..4 {
+            assert_eq!(hist[&bucket], 100);
+        }
+
+        // Array maps always contain all keys
+        let expected = match *map_name {
+            MAP_ARRAY_BIG => m.max_entries() as usize / 100,
+            _ => 4,
+        };
+        assert_eq!(hist.len(), expected);
+    }
+}
+
+#[test]
+fn test_populate_from_iter() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH_BIG).unwrap();
+
+    // Existing entries are overwritten
+    m.update(&5, &0, rxdp::MapFlags::BpfAny).unwrap();
+
+    let mut calls = Vec::new();
+    let n = m
+        .populate_from_iter(
+            (0..2500u32).map(|i| (i, i * 2)),
+            1000,
+            rxdp::MapFlags::BpfAny,
+            |done, total| calls.push((done, total)),
+        )
+        .unwrap();
+    assert_eq!(n, 2500);
+    assert_eq!(
+        calls,
+        vec![(1000, Some(2500)), (2000, Some(2500)), (2500, Some(2500))]
+    );
+    assert_eq!(m.items().unwrap().len(), 2500);
+    assert_eq!(m.lookup(&5).unwrap().into_single(), 10);
+
+    let r = m.populate_from_iter(vec![(1, 1)], 0, rxdp::MapFlags::BpfAny, |_, _| ());
+    assert_eq!(r.unwrap_err().code(), 22);
+}
+
+#[test]
+fn test_update_batch_partial() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+
+    // The map holds 10 entries
+    let mut keys: Vec<u32> = (0..12).collect();
+    let mut values = keys.clone();
+    let err = m
+        .update_batch(&mut keys, &mut values, rxdp::MapFlags::BpfAny)
+        .unwrap_err();
+    assert_eq!(err.code(), 7);
+
+    let p = err.partial_update().unwrap();
+    assert_eq!(p.applied, 10);
+    assert_eq!(p.failed_index, 10);
+    assert_eq!(m.items().unwrap().len(), 10);
+
+    let err = m.update(&20, &20, rxdp::MapFlags::BpfAny).unwrap_err();
+    assert!(err.partial_update().is_none());
+}
+
+#[test]
+fn test_delete_batch() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    for i in 0..10u32 {
+        m.update(&i, &i, rxdp::MapFlags::BpfAny).unwrap();
+    }
+
+    assert_eq!(m.delete_batch(&[]).unwrap(), 0);
+    assert_eq!(m.delete_batch(&[0, 1, 2, 3]).unwrap(), 4);
+    assert_eq!(m.items().unwrap().len(), 6);
+
+    // Key 2 is already gone
+    let err = m.delete_batch(&[4, 5, 2, 6]).unwrap_err();
+    assert_eq!(err.code(), 2);
+    assert_eq!(err.partial_update().unwrap().applied, 2);
+    let mut keys: Vec<u32> = m.items().unwrap().iter().map(|kv| kv.key).collect();
+    keys.sort();
+    assert_eq!(keys, vec![6, 7, 8, 9]);
+
+    let a: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_ARRAY).unwrap();
+    assert_eq!(a.delete_batch(&[0]).unwrap_err().code(), 22);
+}
+
+#[test]
+fn test_keys() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    assert!(m.keys().unwrap().is_empty());
+    for i in 0..10u32 {
+        m.update(&(i * 3), &i, rxdp::MapFlags::BpfAny).unwrap();
+    }
+    let mut keys = m.keys().unwrap();
+    keys.sort();
+    assert_eq!(keys, (0..10).map(|i| i * 3).collect::<Vec<u32>>());
+
+    let pc: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_ARRAY).unwrap();
+    assert_eq!(
+        pc.keys().unwrap(),
+        (0..pc.max_entries()).collect::<Vec<u32>>()
+    );
+}
+
+#[test]
+fn test_read_range() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_ARRAY).unwrap();
+    for i in 0..m.max_entries() {
+        m.update(&i, &(i * 10), rxdp::MapFlags::BpfAny).unwrap();
+    }
+    assert_eq!(m.read_range(1, 3).unwrap(), vec![10, 20, 30]);
+    assert!(m.read_range(0, 0).unwrap().is_empty());
+    assert_eq!(m.read_range(4, 2).err().unwrap().code(), 7);
+
+    let h: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    assert_eq!(h.read_range(0, 1).err().unwrap().code(), 22);
+
+    let mmapable = rxdp::MapBuilder::<u32, u64>::new()
+        .map_type(rxdp::MapType::Array)
+        .max_entries(600)
+        .map_flags(libbpf_sys::BPF_F_MMAPABLE)
+        .create()
+        .unwrap();
+    mmapable.update(&599, &7, rxdp::MapFlags::BpfAny).unwrap();
+    assert_eq!(mmapable.read_range(598, 2).unwrap(), vec![0, 7]);
+    let items = mmapable.items().unwrap();
+    assert_eq!(items.len(), 600);
+    assert_eq!(items[599].key, 599);
+    assert_eq!(items[599].value.clone().into_single(), 7);
+
+    let pc: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_ARRAY).unwrap();
+    pc.update(&1, &5, rxdp::MapFlags::BpfAny).unwrap();
+    let values = pc.read_range(1, 1).unwrap();
+    assert_eq!(values.len(), 1);
+    assert_eq!(values[0].sum(), 5 * rxdp::num_cpus() as u32);
+}
+
+#[derive(Default, Clone, Copy)]
+#[repr(C)]
+struct Flow {
+    packets: u64,
+    last_seen: u64,
+}
+
+#[test]
+fn test_expired_entries() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, Flow> = rxdp::Map::new(&obj, MAP_FLOWS).unwrap();
+
+    let now = rxdp::ktime_get_ns();
+    let ttl = std::time::Duration::from_secs(60);
+    let stale = now - 2 * ttl.as_nanos() as u64;
+    for i in 0..300u32 {
+        let last_seen = if i % 3 == 0 { stale } else { now };
+        let flow = Flow {
+            packets: 1,
+            last_seen,
+        };
+        m.update(&i, &flow, rxdp::MapFlags::BpfAny).unwrap();
+    }
+
+    let mut expired = m.expired_entries(8, ttl).unwrap();
+    expired.sort();
+    assert_eq!(expired, (0..300).step_by(3).collect::<Vec<u32>>());
+
+    assert_eq!(m.delete_expired(8, ttl).unwrap(), 100);
+    assert!(m.expired_entries(8, ttl).unwrap().is_empty());
+    assert_eq!(m.items().unwrap().len(), 200);
+
+    assert_eq!(m.expired_entries(9, ttl).unwrap_err().code(), 22);
+}
+
+rxdp::no_padding!(Flow {
+    packets: u64,
+    last_seen: u64
+});
+
+#[test]
+fn test_new_checked() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, Flow> = rxdp::Map::new_checked(&obj, MAP_FLOWS).unwrap();
+    let flow = Flow {
+        packets: 1,
+        last_seen: 2,
+    };
+    m.update(&0, &flow, rxdp::MapFlags::BpfAny).unwrap();
+    assert_eq!(m.lookup(&0).unwrap().into_single().last_seen, 2);
+
+    let m = rxdp::PerCpuMap::<u32, u64>::new_checked(&obj, MAP_PERCPU_HASH);
+    assert!(m.is_ok());
+}
+
+#[test]
+fn test_map_operations_with_timeout() {
+    let obj = loaded_object();
+    let mut m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    m.set_timeout(Some(std::time::Duration::from_secs(1)));
+    test_map_operations(&m, 100u32, 101u32);
+
+    let mut m = rxdp::PerCpuMap::<u32, u32>::new(&obj, MAP_PERCPU_HASH).unwrap();
+    m.set_timeout(Some(std::time::Duration::from_secs(1)));
+    test_map_operations(&m, 100u32, 101u32);
+
+    if let Err(e) = m.lookup(&100u32) {
+        assert!(!e.is_timed_out());
+    }
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_create_per_cpu_hash_map() {
+    let m = rxdp::PerCpuMap::<u32, u32>::create(rxdp::MapType::PerCPUHash, 4, 4, 10, 0).unwrap();
+    let key = 100u32;
+    let val = 101u32;
+    test_map_operations(&m, key, val);
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_create_per_cpu_array_map() {
+    let m = rxdp::PerCpuMap::<u32, u32>::create(rxdp::MapType::PerCPUArray, 4, 4, 10, 0).unwrap();
+    let key = 0u32;
+    let val = 101u32;
+    test_map_operations(&m, key, val);
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_create_normal_hash_fails() {
+    let r = rxdp::PerCpuMap::<u32, u32>::create(rxdp::MapType::Hash, 4, 4, 10, 0);
+    assert!(r.is_err());
+}
+
+#[test]
+fn test_per_cpu_lru_hash_map_operations() {
+    let obj = loaded_object();
+    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_LRU_HASH).unwrap();
+    let key = 100u32;
+    let val = 101u32;
+    test_map_operations(&m, key, val);
+}
+
+#[test]
+fn test_per_cpu_hash_map_operations() {
+    let obj = loaded_object();
+    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_HASH).unwrap();
+    let key = 100u32;
+    let val = 101u32;
+    test_map_operations(&m, key, val);
+}
+
+#[test]
+fn test_per_cpu_array_map_operations() {
+    let obj = loaded_object();
+    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_ARRAY).unwrap();
+    let key = 0u32;
+    let val = 100u32;
+    test_map_operations(&m, key, val);
+}
+
+#[test]
+fn test_per_cpu_lookup_into() {
+    let obj = loaded_object();
+    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_ARRAY).unwrap();
+    m.update(&2, &7, rxdp::MapFlags::BpfAny).unwrap();
+
+    let mut values = vec![1, 2, 3];
+    m.lookup_into(&2, &mut values).unwrap();
+    assert_eq!(values, vec![7; rxdp::num_cpus()]);
+
+    m.lookup_into(&0, &mut values).unwrap();
+    assert_eq!(values, vec![0; rxdp::num_cpus()]);
+
+    assert!(m.lookup_into(&1000, &mut values).is_err());
+    assert!(values.is_empty());
+}
+
+#[test]
+fn test_per_cpu_lookup_with_flags() {
+    let obj = loaded_object();
+    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_ARRAY).unwrap();
+    m.update(&1, &9, rxdp::MapFlags::BpfAny).unwrap();
+
+    let v = m.lookup_with_flags(&1, rxdp::MapFlags::empty()).unwrap();
+    assert_eq!(v.into_vec(), vec![9; rxdp::num_cpus()]);
+}
+
+#[test]
+fn test_per_cpu_lookup_by_node() {
+    let obj = loaded_object();
+    let m: rxdp::PerCpuMap<u32, u64> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_ARRAY).unwrap();
+    let values: Vec<u64> = (0..rxdp::num_cpus() as u64).collect();
+    m.update_values(&0, &values, rxdp::MapFlags::BpfAny)
+        .unwrap();
+
+    let topology = rxdp::cpu_topology();
+    assert_eq!(topology.len(), rxdp::num_cpus());
+
+    let mut expected = std::collections::BTreeMap::new();
+    for cpu in topology.iter() {
+        *expected.entry(cpu.node).or_insert(0u64) += values[cpu.cpu];
+    }
+    assert_eq!(m.lookup_by_node(&0).unwrap(), expected);
+}
+
+#[test]
+fn test_typed_get() {
+    let obj = loaded_object();
+    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_HASH).unwrap();
+    let values: Vec<u32> = (0..rxdp::num_cpus() as u32).collect();
+    m.update_values(&1, &values, rxdp::MapFlags::BpfAny)
+        .unwrap();
+
+    let got = m.get(&1).unwrap();
+    assert_eq!(got.len(), rxdp::num_cpus());
+    assert_eq!(got[0], 0);
+    assert_eq!(got.sum(), values.iter().sum());
+    assert_eq!(got.max(), values.last().copied());
+    assert_eq!(rxdp::MapValue::from(got), m.lookup(&1).unwrap());
+    assert_eq!(m.get(&2).err().unwrap().code(), 2);
+
+    let items = m.get_items().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].value.as_slice(), values.as_slice());
+
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    m.update(&1, &10, rxdp::MapFlags::BpfAny).unwrap();
+    assert_eq!(m.get(&1).unwrap(), 10);
+    assert_eq!(m.get(&2).err().unwrap().code(), 2);
+}
+
+#[test]
+fn test_items_hash_map() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    test_items(&m);
+}
+
+#[test]
+fn test_items_array_map() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_ARRAY).unwrap();
+    test_items(&m);
+}
+
+#[test]
+fn test_items_big_hash_map() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH_BIG).unwrap();
+    test_items(&m);
+}
+
+#[test]
+fn test_items_big_array_map() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_ARRAY_BIG).unwrap();
     test_items(&m);
 }
 
@@ -353,6 +1472,96 @@ fn test_items_per_cpu_big_array_map() {
     test_items(&m);
 }
 
+#[test]
+fn test_export_import_hash_map() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    for i in 0..5u32 {
+        m.update(&i, &(i + 100), rxdp::MapFlags::BpfAny).unwrap();
+    }
+
+    let mut dump = Vec::new();
+    assert_eq!(m.export_to(&mut dump).unwrap(), 5);
+
+    let m2 = rxdp::MapBuilder::<u32, u32>::new()
+        .max_entries(10)
+        .create()
+        .unwrap();
+    assert_eq!(
+        m2.import_from(&mut dump.as_slice(), rxdp::MapFlags::BpfAny)
+            .unwrap(),
+        5
+    );
+    for i in 0..5u32 {
+        assert_eq!(m2.lookup(&i).unwrap().into_single(), i + 100);
+    }
+
+    // Mismatched value size
+    let m3 = rxdp::MapBuilder::<u32, u64>::new()
+        .max_entries(10)
+        .create()
+        .unwrap();
+    assert!(m3
+        .import_from(&mut dump.as_slice(), rxdp::MapFlags::BpfAny)
+        .is_err());
+}
+
+#[test]
+fn test_export_import_per_cpu_map() {
+    let obj = loaded_object();
+    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_HASH).unwrap();
+    m.update(&1u32, &101u32, rxdp::MapFlags::BpfAny).unwrap();
+
+    let mut dump = Vec::new();
+    assert_eq!(m.export_to(&mut dump).unwrap(), 1);
+
+    let m2 = rxdp::PerCpuMapBuilder::<u32, u32>::new()
+        .max_entries(10)
+        .create()
+        .unwrap();
+    m2.import_from(&mut dump.as_slice(), rxdp::MapFlags::BpfAny)
+        .unwrap();
+    assert_eq!(
+        m2.lookup(&1u32).unwrap().into_vec(),
+        vec![101u32; rxdp::num_cpus()]
+    );
+}
+
+#[test]
+fn test_par_items() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH_BIG).unwrap();
+    let a: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_ARRAY_BIG).unwrap();
+    let pc: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_HASH_BIG).unwrap();
+
+    for shards in &[1, 3, 8] {
+        test_par_items_map(&m, |m| m.par_items(*shards));
+        test_par_items_map(&a, |m| m.par_items(*shards));
+        test_par_items_map(&pc, |m| m.par_items(*shards));
+    }
+}
+
+fn test_par_items_map<M, F>(m: &M, par_items: F)
+where
+    M: MapLike<u32, u32>,
+    F: Fn(&M) -> rxdp::XDPResult<Vec<rxdp::KeyValue<u32, MapValue<u32>>>>,
+{
+    let total = m.max_entries();
+    let mut keys: Vec<u32> = (0..total).collect();
+    let mut vals: Vec<u32> = (0..total).map(|i| i + 100).collect();
+    m.update_batch(&mut keys, &mut vals, rxdp::MapFlags::BpfAny)
+        .unwrap();
+
+    let items = par_items(m).unwrap();
+    assert_eq!(items.len(), total as usize);
+
+    let mut seen = std::collections::HashSet::new();
+    for kv in items {
+        assert!(seen.insert(kv.key));
+        assert_eq!(kv.key + 100, kv.value.into_single());
+    }
+}
+
 #[test]
 fn test_perf_map_invalid_map_type() {
     let obj = loaded_object();
@@ -361,6 +1570,33 @@ fn test_perf_map_invalid_map_type() {
 }
 
 #[test]
+fn test_map_kind() {
+    let obj = loaded_object();
+    assert_eq!(obj.map_kind(MAP_HASH).unwrap(), rxdp::MapKind::Map);
+    assert_eq!(
+        obj.map_kind(MAP_PERCPU_HASH).unwrap(),
+        rxdp::MapKind::PerCpuMap
+    );
+    assert_eq!(obj.map_kind(PERF_MAP).unwrap(), rxdp::MapKind::PerfMap);
+    assert_eq!(obj.map_kind(RING_BUF).unwrap(), rxdp::MapKind::RingBuffer);
+    assert!(obj.map_kind("not_a_map").is_err());
+
+    let err = rxdp::Map::<u32, u32>::new(&obj, MAP_PERCPU_HASH)
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), 22);
+    assert!(err
+        .description()
+        .contains("'per_cpu_hash' is a MapType::PerCPUHash map, use rxdp::PerCpuMap::new"));
+
+    let err = rxdp::PerCpuMap::<u32, u32>::new(&obj, MAP_HASH)
+        .err()
+        .unwrap();
+    assert!(err.description().contains("use rxdp::Map::new"));
+}
+
+#[test]
+#[allow(deprecated)]
 fn test_perf_map_events_crossbeam_channel() {
     let obj = loaded_object();
     let mut m = rxdp::PerfMap::<u32>::new(&obj, PERF_MAP).unwrap();
@@ -385,13 +1621,102 @@ fn test_perf_map_events_crossbeam_channel() {
     receiver.join().expect("Error joining receiver thread");
 }
 
-fn test_items(m: &dyn MapLike<u32, u32>) {
-    let mut keys = Vec::new();
-    let mut vals = Vec::new();
-    let total = m.max_entries();
-    for i in 0..total {
-        keys.push(i as u32);
-        vals.push((i + 100) as u32);
+#[test]
+fn test_perf_map_poll_n() {
+    let obj = loaded_object();
+    assert!(rxdp::PerfMap::<u32>::new(&obj, PERF_MAP)
+        .unwrap()
+        .poll_n(10, 1)
+        .is_err());
+
+    let mut m = rxdp::PerfMapBuilder::<u32>::new(&obj, PERF_MAP)
+        .unwrap()
+        .manual();
+    let r = m.receiver().unwrap().clone();
+
+    let pair = utils::VethPair::new("192.168.101.2", "192.168.101.3");
+    let prog = obj.get_program("rxdp_perf").unwrap();
+    prog.attach_to_interface(&pair.one.name, rxdp::AttachFlags::SKB_MODE)
+        .unwrap();
+
+    let num_events = 10;
+    for _ in 0..num_events {
+        pair.two.ping(&pair.one.ip, 1);
+    }
+
+    let mut received = 0;
+    while received < num_events {
+        let n = m.poll_n(1000, 3).unwrap();
+        assert!(n <= 3);
+        assert_eq!(r.try_iter().count(), n);
+        received += n;
+    }
+}
+
+#[test]
+fn test_perf_map_builder_bounded() {
+    let obj = loaded_object();
+    let handle = rxdp::PerfMapBuilder::<u32>::new(&obj, PERF_MAP)
+        .unwrap()
+        .bounded(2)
+        .spawn(1000);
+    let r = handle.receiver().unwrap().clone();
+
+    let num_events = 10;
+    let receiver = std::thread::spawn(move || {
+        for _ in 0..num_events {
+            r.recv().unwrap();
+        }
+    });
+
+    let pair = utils::VethPair::new("192.168.102.2", "192.168.102.3");
+    let prog = obj.get_program("rxdp_perf").unwrap();
+    prog.attach_to_interface(&pair.one.name, rxdp::AttachFlags::SKB_MODE)
+        .unwrap();
+
+    for _ in 0..num_events {
+        pair.two.ping(&pair.one.ip, 1);
+    }
+    receiver.join().expect("Error joining receiver thread");
+
+    let stats = handle.stats();
+    assert!(stats.sent >= num_events);
+    assert_eq!(stats.send_failures, 0);
+    assert_eq!(stats.capacity, Some(2));
+    assert!(stats.queue_len.is_some());
+}
+
+#[test]
+fn test_perf_map_builder_external_sender() {
+    let obj = loaded_object();
+    let (s, r) = std::sync::mpsc::channel();
+    let handle = rxdp::PerfMapBuilder::<u32>::new(&obj, PERF_MAP)
+        .unwrap()
+        .sender(s)
+        .spawn(1000);
+    assert!(handle.receiver().is_none());
+
+    let pair = utils::VethPair::new("192.168.103.2", "192.168.103.3");
+    let prog = obj.get_program("rxdp_perf").unwrap();
+    prog.attach_to_interface(&pair.one.name, rxdp::AttachFlags::SKB_MODE)
+        .unwrap();
+
+    pair.two.ping(&pair.one.ip, 1);
+    r.recv().unwrap();
+
+    handle.stop();
+    while r.try_recv().is_ok() {}
+    pair.two.ping(&pair.one.ip, 1);
+    assert!(r.recv_timeout(Duration::from_millis(200)).is_err());
+}
+
+fn test_items(m: &dyn MapLike<u32, u32>) {
+    let mut keys = Vec::new();
+    let mut vals = Vec::new();
+    let total = m.max_entries();
+    for i in 0..total {
+        keys.push(i as u32);
+        vals.push((i + 100) as u32);
     }
 
     let num_added = m
@@ -553,3 +1878,1434 @@ where
         assert!(items.is_empty());
     }
 }
+
+#[test]
+fn test_watch_key() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    let timeout = Duration::from_secs(2);
+
+    let r1 = m.watch_key(&1, Duration::from_millis(10));
+    let r2 = m.watch_key(&1, Duration::from_millis(10));
+    assert_eq!(r1.recv_timeout(timeout).unwrap(), None);
+    assert_eq!(r2.recv_timeout(timeout).unwrap(), None);
+
+    m.update(&1, &100, rxdp::MapFlags::BpfAny).unwrap();
+    assert_eq!(r1.recv_timeout(timeout).unwrap(), Some(100));
+    assert_eq!(r2.recv_timeout(timeout).unwrap(), Some(100));
+
+    // Dropping one receiver doesn't affect the other
+    drop(r2);
+    m.delete(&1).unwrap();
+    assert_eq!(r1.recv_timeout(timeout).unwrap(), None);
+
+    // No change, no event
+    assert!(r1.recv_timeout(Duration::from_millis(50)).is_err());
+
+    // Dropping the map stops the poll thread before its fd is closed
+    drop(m);
+    assert_eq!(
+        r1.recv_timeout(timeout),
+        Err(crossbeam_channel::RecvTimeoutError::Disconnected)
+    );
+}
+
+#[test]
+fn test_detach_matching() {
+    let obj = loaded_object();
+    let prog = obj.get_program(PROG_TEST).unwrap();
+
+    let iface = utils::test_iface();
+    prog.attach_to_interface(&iface.name, rxdp::AttachFlags::SKB_MODE)
+        .unwrap();
+
+    let attached = rxdp::sys::attached_programs().unwrap();
+    let ours: Vec<_> = attached
+        .iter()
+        .filter(|p| p.interface == iface.name)
+        .collect();
+    assert_eq!(ours.len(), 1);
+    assert_eq!(ours[0].name, PROG_TEST);
+    assert_eq!(ours[0].mode, rxdp::AttachFlags::SKB_MODE);
+
+    let detached =
+        rxdp::sys::detach_matching(|p| p.interface == iface.name && p.name == PROG_TEST).unwrap();
+    assert_eq!(detached, vec![ours[0].clone()]);
+
+    let attached = rxdp::sys::attached_programs().unwrap();
+    assert!(attached.iter().all(|p| p.interface != iface.name));
+}
+
+#[test]
+fn test_detach_discovers_mode() {
+    let iface = utils::test_iface();
+    let old = loaded_object();
+    old.get_program(PROG_TEST)
+        .unwrap()
+        .attach_to_interface(&iface.name, rxdp::AttachFlags::SKB_MODE)
+        .unwrap();
+
+    // A new instance, e.g. after an upgrade, that never attached itself.
+    let obj = loaded_object();
+    let prog = obj.get_program(PROG_TEST).unwrap();
+    prog.detach_from_interface(&iface.name).unwrap();
+
+    let attached = rxdp::sys::attached_programs().unwrap();
+    assert!(attached.iter().all(|p| p.interface != iface.name));
+
+    // Nothing left to detach
+    prog.detach_from_interface(&iface.name).unwrap();
+}
+
+#[test]
+fn test_xdp_chain() {
+    let obj = loaded_object();
+    let prog = obj.get_program(PROG_TEST).unwrap();
+
+    let iface = utils::test_iface();
+    let chain = rxdp::iface::xdp_chain(&iface.name).unwrap();
+    assert!(chain.attached.is_empty());
+    assert!(chain.dispatcher.is_none());
+
+    prog.attach_to_interface(&iface.name, rxdp::AttachFlags::SKB_MODE)
+        .unwrap();
+    let chain = rxdp::iface::xdp_chain(&iface.name).unwrap();
+    assert_eq!(chain.interface, iface.name);
+    assert_eq!(chain.attached.len(), 1);
+    assert_eq!(chain.attached[0].name, PROG_TEST);
+    assert!(chain.dispatcher.is_none());
+
+    let r = rxdp::iface::xdp_chain("not_an_iface");
+    assert!(r.is_err());
+}
+
+#[test]
+fn test_prepare_for_xdp() {
+    use rxdp::iface::{self, Requirements};
+
+    let iface = utils::test_iface();
+    let mtu = || std::fs::read_to_string(format!("/sys/class/net/{}/mtu", iface.name)).unwrap();
+    let original = mtu();
+
+    let prepared = iface::prepare_for_xdp(&iface.name, Requirements::new().max_mtu(1280)).unwrap();
+    assert_eq!(prepared.interface(), iface.name);
+    assert!(prepared.changed());
+    assert_eq!(mtu().trim(), "1280");
+    prepared.restore().unwrap();
+    assert_eq!(mtu(), original);
+
+    // Nothing to change, the MTU is already lower.
+    let prepared = iface::prepare_for_xdp(&iface.name, Requirements::new().max_mtu(65535)).unwrap();
+    assert!(!prepared.changed());
+
+    let r = iface::prepare_for_xdp("not_an_iface", Requirements::new().disable_lro());
+    assert!(r.is_err());
+}
+
+#[test]
+fn test_byte_apis() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    m.update_bytes(
+        &1u32.to_ne_bytes(),
+        &10u32.to_ne_bytes(),
+        rxdp::MapFlags::BpfAny,
+    )
+    .unwrap();
+    assert_eq!(m.lookup(&1).unwrap().into_single(), 10);
+    assert_eq!(
+        m.lookup_bytes(&1u32.to_ne_bytes()).unwrap(),
+        10u32.to_ne_bytes()
+    );
+    let items = m.items_bytes().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].key, 1u32.to_ne_bytes());
+    assert_eq!(items[0].value, 10u32.to_ne_bytes());
+
+    let err = m.lookup_bytes(&[0u8; 2]).err().unwrap();
+    assert_eq!(err.code(), 22);
+    let r = m.update_bytes(&1u32.to_ne_bytes(), &[0u8; 8], rxdp::MapFlags::BpfAny);
+    assert_eq!(r.err().unwrap().code(), 22);
+
+    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_HASH).unwrap();
+    let values: Vec<u32> = (0..rxdp::num_cpus() as u32).collect();
+    let value_bytes: Vec<u8> = values.iter().flat_map(|v| v.to_ne_bytes()).collect();
+    m.update_bytes(&1u32.to_ne_bytes(), &value_bytes, rxdp::MapFlags::BpfAny)
+        .unwrap();
+    assert_eq!(m.lookup(&1).unwrap().into_vec(), values);
+    assert_eq!(m.lookup_bytes(&1u32.to_ne_bytes()).unwrap(), value_bytes);
+
+    let r = m.update_bytes(&1u32.to_ne_bytes(), &[0u8; 4], rxdp::MapFlags::BpfAny);
+    if rxdp::num_cpus() > 1 {
+        assert_eq!(r.err().unwrap().code(), 22);
+    }
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn test_ffi_map_access() {
+    use rxdp::ffi::*;
+    use std::ffi::CString;
+
+    unsafe extern "C" fn count(
+        ctx: *mut std::os::raw::c_void,
+        _key: *const u8,
+        key_len: usize,
+        _value: *const u8,
+        _value_len: usize,
+    ) -> i32 {
+        assert_eq!(key_len, 4);
+        *(ctx as *mut usize) += 1;
+        0
+    }
+
+    unsafe extern "C" fn stop(
+        _ctx: *mut std::os::raw::c_void,
+        _key: *const u8,
+        _key_len: usize,
+        _value: *const u8,
+        _value_len: usize,
+    ) -> i32 {
+        -1
+    }
+
+    unsafe {
+        let path = CString::new(utils::TEST_FILE.as_str()).unwrap();
+        let obj = rxdp_object_load(rxdp_object_open(path.as_ptr()));
+        assert!(!obj.is_null());
+
+        let name = CString::new(MAP_HASH).unwrap();
+        let m = rxdp_map_open(obj, name.as_ptr());
+        assert!(!m.is_null());
+        assert_eq!(rxdp_map_key_size(m), 4);
+        assert_eq!(rxdp_map_value_len(m), 4);
+
+        let key = 1u32.to_ne_bytes();
+        let value = 10u32.to_ne_bytes();
+        assert_eq!(rxdp_map_update(m, key.as_ptr(), 4, value.as_ptr(), 4, 0), 0);
+        let mut got = [0u8; 4];
+        assert_eq!(rxdp_map_lookup(m, key.as_ptr(), 4, got.as_mut_ptr(), 4), 0);
+        assert_eq!(got, value);
+
+        let mut n = 0usize;
+        let ctx = &mut n as *mut _ as *mut _;
+        assert_eq!(rxdp_map_iterate(m, Some(count), ctx), 0);
+        assert_eq!(n, 1);
+        assert_eq!(rxdp_map_iterate(m, Some(stop), std::ptr::null_mut()), 1);
+        assert_eq!(rxdp_map_iterate(m, None, std::ptr::null_mut()), -22);
+
+        assert_eq!(
+            rxdp_map_lookup(m, key.as_ptr(), 2, got.as_mut_ptr(), 4),
+            -22
+        );
+        assert!(!rxdp_last_error().is_null());
+        assert_eq!(rxdp_map_key_size(std::ptr::null()), 0);
+
+        let name = CString::new(PERF_MAP).unwrap();
+        let pb = rxdp_perf_buffer_new(obj, name.as_ptr(), 8, None, None, std::ptr::null_mut());
+        assert!(pb.is_null());
+
+        rxdp_map_free(m);
+        rxdp_loaded_object_free(obj);
+    }
+}
+
+#[test]
+fn test_persist_on_exit() {
+    let test_dir = utils::pin_dir();
+    let pin_path = format!("{}/{}/{}", &test_dir.path, "sub", MAP_HASH);
+
+    let obj = loaded_object();
+    let mut m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    m.update(&1, &10, rxdp::MapFlags::BpfAny).unwrap();
+
+    let err = m.persist_on_exit("/tmp/not_bpffs/map").err().unwrap();
+    assert_eq!(err.code(), 22);
+
+    m.persist_on_exit(&pin_path).unwrap();
+    assert!(!Path::new(&pin_path).exists());
+
+    // Pinned once the handle is dropped
+    drop(m);
+    assert!(Path::new(&pin_path).exists());
+    let fd = rxdp::load_pinned_object(&pin_path).unwrap();
+    assert!(fd > 0);
+}
+
+#[test]
+fn test_per_cpu_items_aggregated() {
+    let obj = loaded_object();
+    for map_name in [MAP_PERCPU_HASH, MAP_PERCPU_HASH_BIG].iter() {
+        let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, map_name).unwrap();
+        let values: Vec<u32> = (1..=rxdp::num_cpus() as u32).collect();
+        for key in 0..5u32 {
+            let v: Vec<u32> = values.iter().map(|v| v * (key + 1)).collect();
+            m.update_values(&key, &v, rxdp::MapFlags::BpfAny).unwrap();
+        }
+
+        let sum: u32 = values.iter().sum();
+        let mut items = m.items_aggregated(rxdp::Aggregation::Sum).unwrap();
+        items.sort_by_key(|kv| kv.key);
+        assert_eq!(items.len(), 5);
+        for kv in items.iter() {
+            assert_eq!(kv.value, sum * (kv.key + 1));
+        }
+
+        let items = m.items_aggregated(rxdp::Aggregation::Max).unwrap();
+        for kv in items.iter() {
+            assert_eq!(kv.value, rxdp::num_cpus() as u32 * (kv.key + 1));
+        }
+        let items = m.items_aggregated(rxdp::Aggregation::Min).unwrap();
+        for kv in items.iter() {
+            assert_eq!(kv.value, kv.key + 1);
+        }
+    }
+}
+
+#[test]
+fn test_kernel_requires() {
+    use rxdp::kernel::{self, Feature};
+
+    let version = kernel::version().unwrap();
+    assert!(version.major >= 4);
+
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u64> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    match kernel::requires(Feature::BatchOps) {
+        Ok(()) => assert!(m.lookup_batch(10, None).is_ok()),
+        Err(_) => {
+            let err = m.lookup_batch(10, None).err().unwrap();
+            assert_eq!(err.code(), 95);
+            assert!(err
+                .description()
+                .contains("lookup_batch requires kernel >= 5.6"));
+        }
+    }
+}
+
+#[test]
+fn test_selftest_map_throughput() {
+    use rxdp::selftest;
+
+    for map_type in [rxdp::MapType::LRUHash, rxdp::MapType::PerCPUArray].iter() {
+        let r = selftest::map_throughput(*map_type, 4, 16, 1000).unwrap();
+        assert_eq!(r.update.ops, 1000);
+        assert_eq!(r.lookup.ops, 1000);
+        if let Some(t) = r.lookup_batch {
+            assert_eq!(t.ops, 1000);
+        }
+    }
+
+    let err = selftest::map_throughput(rxdp::MapType::DevMap, 4, 4, 10)
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), 22);
+}
+
+#[cfg(not(feature = "libbpf-1"))]
+#[test]
+fn test_xsk_socket() {
+    use rxdp::xsk::{Desc, Umem, UmemConfig, XskConfig, XskSocket};
+
+    let iface = utils::test_iface();
+    let obj = loaded_object();
+    let xsks: rxdp::Map<u32, i32> = rxdp::Map::new(&obj, XSK_MAP).unwrap();
+
+    let mut umem = Umem::new(64, UmemConfig::new().frame_size(2048)).unwrap();
+    let frames: Vec<u64> = umem.frame_addrs().collect();
+    assert_eq!(frames.len(), 64);
+    assert_eq!(frames[1], 2048);
+    assert_eq!(umem.fill(&frames[..32]), 32);
+
+    // Out of bounds descriptors are clamped to the UMEM.
+    let desc = Desc {
+        addr: 63 * 2048,
+        len: 4096,
+    };
+    assert_eq!(umem.frame_mut(&desc).len(), 2048);
+
+    let config = XskConfig::new().copy_mode().xsks_map(&xsks);
+    let mut sock = XskSocket::new(&iface.name, 0, umem, config).unwrap();
+    assert!(sock.fd() > 0);
+
+    let mut descs = Vec::new();
+    assert!(!sock.poll(10).unwrap());
+    assert_eq!(sock.recv(&mut descs), 0);
+
+    let err = XskSocket::new(
+        "rxdp_missing",
+        0,
+        Umem::new(64, UmemConfig::new()).unwrap(),
+        XskConfig::new().xsks_map(&xsks),
+    )
+    .err()
+    .unwrap();
+    assert!(err.description().contains("Error creating AF_XDP socket"));
+
+    let hash: rxdp::Map<u32, i32> = rxdp::MapBuilder::new().create().unwrap();
+    let err = XskSocket::new(
+        &iface.name,
+        0,
+        Umem::new(64, UmemConfig::new()).unwrap(),
+        XskConfig::new().xsks_map(&hash),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(err.code(), 22);
+
+    // Send a broadcast frame from the second half of the UMEM, on another thread.
+    let tx_addr = frames[32];
+    let mut sock = std::thread::spawn(move || {
+        let desc = Desc {
+            addr: tx_addr,
+            len: 64,
+        };
+        let frame = sock.umem_mut().frame_mut(&desc);
+        frame[..6].copy_from_slice(&[0xff; 6]);
+        frame[12..14].copy_from_slice(&0x88b5u16.to_be_bytes());
+        assert_eq!(sock.send(&[desc]).unwrap(), 1);
+        sock
+    })
+    .join()
+    .unwrap();
+
+    let mut done = Vec::new();
+    for _ in 0..100 {
+        if sock.umem_mut().complete(&mut done) > 0 {
+            break;
+        }
+        sock.send(&[]).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(done, vec![tx_addr]);
+}
+
+#[test]
+fn test_items_until() {
+    let m = rxdp::MapBuilder::<u32, u32>::new()
+        .max_entries(1000)
+        .create()
+        .unwrap();
+    for i in 0..500u32 {
+        m.update(&i, &i, rxdp::MapFlags::BpfAny).unwrap();
+    }
+
+    let cancelled = rxdp::CancelToken::new();
+    cancelled.cancel();
+    let r = m.items_until(&cancelled, None).unwrap();
+    assert!(r.items.is_empty());
+    assert!(!r.is_complete());
+
+    let expired = rxdp::CancelToken::with_timeout(Duration::from_secs(0));
+    assert!(m.items_until(&expired, None).unwrap().cursor.is_some());
+
+    let r = m.items_until(&rxdp::CancelToken::new(), r.cursor).unwrap();
+    assert!(r.is_complete());
+    let mut keys: Vec<u32> = r.items.iter().map(|kv| kv.key).collect();
+    keys.sort();
+    assert_eq!(keys, (0..500).collect::<Vec<u32>>());
+
+    let mut dump = Vec::new();
+    let (n, cursor) = m
+        .export_until(&mut dump, &rxdp::CancelToken::new(), None)
+        .unwrap();
+    assert_eq!(n, 500);
+    assert!(cursor.is_none());
+    assert_eq!(
+        rxdp::DumpHeader::read_from(&mut dump.as_slice())
+            .unwrap()
+            .entries,
+        500
+    );
+}
+
+#[test]
+fn test_lookup_batch_cursor() {
+    if !rxdp::is_batching_supported() {
+        return;
+    }
+
+    fn batch_keys<K: Default + Copy + Ord>(m: &rxdp::Map<K, u64>) -> Vec<K> {
+        let mut keys = Vec::new();
+        let mut next_key = None;
+        loop {
+            let r = m.lookup_batch(64, next_key).unwrap();
+            keys.extend(r.items.iter().map(|kv| kv.key));
+            if r.next_key.is_none() {
+                break;
+            }
+            next_key = r.next_key;
+        }
+        keys.sort();
+        keys
+    }
+
+    // Array keys are always u32.
+    let array = rxdp::MapBuilder::<u32, u64>::new()
+        .map_type(rxdp::MapType::Array)
+        .max_entries(300)
+        .create()
+        .unwrap();
+    for i in 0..300u32 {
+        array
+            .update(&i, &(i as u64 * 2), rxdp::MapFlags::BpfAny)
+            .unwrap();
+    }
+    assert_eq!(batch_keys(&array), (0..300).collect::<Vec<u32>>());
+
+    let hash = rxdp::MapBuilder::<u64, u64>::new()
+        .map_type(rxdp::MapType::Hash)
+        .max_entries(300)
+        .create()
+        .unwrap();
+    for i in 0..300u64 {
+        hash.update(&i, &(i * 2), rxdp::MapFlags::BpfAny).unwrap();
+    }
+    assert_eq!(batch_keys(&hash), (0..300).collect::<Vec<u64>>());
+
+    // Cursors are sized for the map's keys.
+    let cursor = hash.lookup_batch(10, None).unwrap().next_key.unwrap();
+    let err = array.lookup_batch(10, Some(cursor)).err().unwrap();
+    assert_eq!(err.code(), 22);
+}
+
+#[test]
+fn test_lookup_batch_into() {
+    if !rxdp::is_batching_supported() {
+        return;
+    }
+
+    let m = rxdp::MapBuilder::<u32, u64>::new()
+        .max_entries(300)
+        .create()
+        .unwrap();
+    for i in 0..300u32 {
+        m.update(&i, &(i as u64 * 2), rxdp::MapFlags::BpfAny)
+            .unwrap();
+    }
+
+    let mut keys = vec![0u32; 64];
+    let mut vals = vec![0u64; 64];
+    let mut seen = Vec::new();
+    let mut next_key = None;
+    loop {
+        let (n, next) = m.lookup_batch_into(&mut keys, &mut vals, next_key).unwrap();
+        for (k, v) in keys[..n as usize].iter().zip(&vals[..n as usize]) {
+            assert_eq!(*v, *k as u64 * 2);
+            seen.push(*k);
+        }
+        match next {
+            Some(c) => next_key = Some(c),
+            None => break,
+        }
+    }
+    seen.sort();
+    assert_eq!(seen, (0..300).collect::<Vec<u32>>());
+
+    let err = m
+        .lookup_batch_into(&mut keys, &mut vals[..10], None)
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), 22);
+
+    let pc = rxdp::PerCpuMapBuilder::<u32, u64>::new()
+        .map_type(rxdp::MapType::PerCPUHash)
+        .max_entries(10)
+        .create()
+        .unwrap();
+    pc.update(&3, &9, rxdp::MapFlags::BpfAny).unwrap();
+    let mut keys = vec![0u32; 8];
+    let mut vals = vec![0u64; 8 * rxdp::num_cpus()];
+    let (n, _) = pc.lookup_batch_into(&mut keys, &mut vals, None).unwrap();
+    assert_eq!(n, 1);
+    assert_eq!(keys[0], 3);
+    assert!(vals[..rxdp::num_cpus()].iter().all(|v| *v == 9));
+}
+
+#[test]
+fn test_items_checked() {
+    let m = rxdp::MapBuilder::<u32, u32>::new()
+        .max_entries(1000)
+        .create()
+        .unwrap();
+    for i in 0..500u32 {
+        m.update(&i, &i, rxdp::MapFlags::BpfAny).unwrap();
+    }
+
+    let r = m.items_checked().unwrap();
+    assert_eq!(r.consistency, rxdp::ScrapeConsistency::Clean);
+    assert_eq!(r.items.len(), 500);
+}
+
+#[test]
+fn test_map_iter() {
+    let m = rxdp::MapBuilder::<u32, u32>::new()
+        .max_entries(1000)
+        .create()
+        .unwrap();
+    assert!(m.iter().next().is_none());
+    for i in 0..500u32 {
+        m.update(&i, &(i + 1), rxdp::MapFlags::BpfAny).unwrap();
+    }
+
+    let mut keys = Vec::new();
+    for kv in m.iter() {
+        let kv = kv.unwrap();
+        assert_eq!(kv.value.into_single(), kv.key + 1);
+        keys.push(kv.key);
+    }
+    keys.sort();
+    assert_eq!(keys, (0..500).collect::<Vec<u32>>());
+
+    let obj = loaded_object();
+    let a: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_ARRAY).unwrap();
+    assert_eq!(a.iter().count(), a.max_entries() as usize);
+}
+
+#[test]
+fn test_cached_map() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    let cached = rxdp::CachedMap::new(m, Duration::from_secs(60));
+
+    cached.update(&1, &10, rxdp::MapFlags::BpfAny).unwrap();
+    assert_eq!(cached.lookup(&1).unwrap().into_single(), 10);
+    assert_eq!(cached.stats().hits, 1);
+
+    // Changes that bypass the cache are only seen after invalidation.
+    cached
+        .inner()
+        .update(&1, &20, rxdp::MapFlags::BpfAny)
+        .unwrap();
+    assert_eq!(cached.lookup(&1).unwrap().into_single(), 10);
+    cached.invalidate(&1);
+    assert_eq!(cached.lookup(&1).unwrap().into_single(), 20);
+    assert_eq!(
+        cached.stats(),
+        rxdp::CacheStats {
+            hits: 2,
+            misses: 1,
+            entries: 1
+        }
+    );
+
+    cached.delete(&1).unwrap();
+    assert!(cached.lookup(&1).is_err());
+
+    let short = rxdp::CachedMap::new(
+        rxdp::Map::<u32, u32>::new(&obj, MAP_HASH).unwrap(),
+        Duration::from_millis(10),
+    );
+    short.update(&2, &1, rxdp::MapFlags::BpfAny).unwrap();
+    short
+        .inner()
+        .update(&2, &2, rxdp::MapFlags::BpfAny)
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(short.lookup(&2).unwrap().into_single(), 2);
+
+    // Expired values of keys that are gone aren't kept.
+    short.inner().delete(&2).unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(short.lookup(&2).is_err());
+    assert_eq!(short.stats().entries, 0);
+}
+
+#[test]
+fn test_queue_map() {
+    let q: rxdp::QueueMap<u64> = rxdp::QueueMap::create(rxdp::MapType::Queue, 3).unwrap();
+    assert_eq!(q.pop().unwrap(), None);
+    for i in 1..=3u64 {
+        q.push(&i, rxdp::MapFlags::BpfAny).unwrap();
+    }
+    assert_eq!(q.push(&4, rxdp::MapFlags::BpfAny).err().unwrap().code(), 7);
+
+    // BpfExist drops the oldest value to make room.
+    q.push(&4, rxdp::MapFlags::BpfExist).unwrap();
+    assert_eq!(q.peek().unwrap(), Some(2));
+    assert_eq!(q.pop().unwrap(), Some(2));
+    assert_eq!(q.drain().unwrap(), vec![3, 4]);
+    assert_eq!(q.peek().unwrap(), None);
+
+    let s: rxdp::StackMap<u64> = rxdp::QueueMap::create(rxdp::MapType::Stack, 3).unwrap();
+    for i in 1..=3u64 {
+        s.push(&i, rxdp::MapFlags::BpfAny).unwrap();
+    }
+    assert_eq!(s.drain().unwrap(), vec![3, 2, 1]);
+
+    let err = rxdp::QueueMap::<u64>::create(rxdp::MapType::Hash, 3)
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), 22);
+
+    let obj = loaded_object();
+    let err = rxdp::QueueMap::<u32>::new(&obj, MAP_HASH).err().unwrap();
+    assert!(err.description().contains("use rxdp::Map::new"));
+}
+
+#[test]
+fn test_lpm_trie() {
+    use rxdp::LpmKey;
+    use std::net::Ipv4Addr;
+
+    let m = rxdp::MapBuilder::<LpmKey<[u8; 4]>, u32>::new()
+        .map_type(rxdp::MapType::LPMTrie)
+        .max_entries(100)
+        .no_prealloc()
+        .create()
+        .unwrap();
+    let prefixes = [
+        (Ipv4Addr::new(10, 0, 0, 0), 8),
+        (Ipv4Addr::new(10, 1, 0, 0), 16),
+        (Ipv4Addr::new(10, 1, 2, 0), 24),
+    ];
+    for (i, (addr, len)) in prefixes.iter().enumerate() {
+        m.update(
+            &LpmKey::ipv4(*addr, *len),
+            &(i as u32),
+            rxdp::MapFlags::BpfAny,
+        )
+        .unwrap();
+    }
+
+    assert_eq!(m.longest_match([10, 1, 2, 3]).unwrap(), Some(2));
+    assert_eq!(m.longest_match([10, 1, 9, 9]).unwrap(), Some(1));
+    assert_eq!(m.longest_match([10, 9, 9, 9]).unwrap(), Some(0));
+    assert_eq!(m.longest_match([192, 168, 0, 1]).unwrap(), None);
+
+    // LPM tries don't support batch lookups, items are read key by key.
+    let mut keys: Vec<u32> = m
+        .items()
+        .unwrap()
+        .iter()
+        .map(|kv| kv.key.prefixlen())
+        .collect();
+    keys.sort();
+    assert_eq!(keys, vec![8, 16, 24]);
+}
+
+#[test]
+fn test_percpu_u128_values() {
+    let m = rxdp::PerCpuMapBuilder::<u32, u128>::new()
+        .max_entries(10)
+        .create()
+        .unwrap();
+    let v = (1u128 << 127) | 1;
+    m.update(&1, &v, rxdp::MapFlags::BpfAny).unwrap();
+
+    let values = m.lookup(&1).unwrap().into_vec();
+    assert_eq!(values.len(), rxdp::num_cpus());
+    assert!(values.iter().all(|x| *x == v));
+}
+
+#[test]
+fn test_outer_map() {
+    let new_inner = || {
+        rxdp::MapBuilder::<u32, u64>::new()
+            .max_entries(10)
+            .create()
+            .unwrap()
+    };
+    let template = new_inner();
+    let outer: rxdp::OuterMap<u32> =
+        rxdp::OuterMap::create(rxdp::MapType::HashOfMaps, template.map_fd(), 4, 0).unwrap();
+    assert_eq!(outer.inner_id(&1).unwrap(), None);
+    assert!(outer.get::<u32, u64>(&1).unwrap().is_none());
+
+    let inner = new_inner();
+    inner.update(&5, &50, rxdp::MapFlags::BpfAny).unwrap();
+    outer
+        .insert(&1, inner.map_fd(), rxdp::MapFlags::BpfAny)
+        .unwrap();
+    assert!(outer.inner_id(&1).unwrap().is_some());
+
+    // The inner map opened by id is the same map.
+    let opened = outer.get::<u32, u64>(&1).unwrap().unwrap();
+    assert_eq!(opened.get(&5).unwrap(), 50);
+    opened.update(&6, &60, rxdp::MapFlags::BpfAny).unwrap();
+    assert_eq!(inner.get(&6).unwrap(), 60);
+
+    let err = outer.get::<u32, u32>(&1).err().unwrap();
+    assert_eq!(err.code(), 22);
+
+    outer.delete(&1).unwrap();
+    assert_eq!(outer.inner_id(&1).unwrap(), None);
+
+    let obj = loaded_object();
+    let err = rxdp::OuterMap::<u32>::new(&obj, MAP_HASH).err().unwrap();
+    assert!(err.description().contains("use rxdp::Map::new"));
+}
+
+#[test]
+fn test_raw_bpf() {
+    use rxdp::raw::{self, Attr, Cmd};
+
+    let m = rxdp::MapBuilder::<u32, u64>::new()
+        .max_entries(10)
+        .create()
+        .unwrap();
+    m.update(&1, &1, rxdp::MapFlags::BpfAny).unwrap();
+
+    // BPF_MAP_FREEZE: struct { __u32 map_fd; }
+    let mut attr = Attr::new().u32(0, m.map_fd() as u32);
+    unsafe { raw::bpf(Cmd::MapFreeze, &mut attr) }.unwrap();
+    assert_eq!(
+        m.update(&1, &2, rxdp::MapFlags::BpfAny)
+            .err()
+            .unwrap()
+            .code(),
+        1
+    );
+
+    // BPF_MAP_LOOKUP_ELEM: struct { __u32 map_fd; __aligned_u64 key; __aligned_u64 value; }
+    let key = 1u32;
+    let mut value = 0u64;
+    let mut attr = Attr::new()
+        .u32(0, m.map_fd() as u32)
+        .ptr(8, &key)
+        .ptr(16, &mut value as *mut u64);
+    unsafe { raw::bpf(Cmd::MapLookupElem, &mut attr) }.unwrap();
+    assert_eq!(value, 1);
+
+    let err = unsafe { raw::bpf(Cmd::Other(u32::MAX), &mut Attr::new()) }
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), 22);
+    assert!(err
+        .description()
+        .starts_with("bpf(command 4294967295) failed"));
+}
+
+#[test]
+fn test_percpu_update_cpu() {
+    let obj = loaded_object();
+    let m: rxdp::PerCpuMap<u32, u64> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_HASH).unwrap();
+    let cpus = rxdp::num_cpus();
+
+    // Missing keys are created with zero values for the other CPUs.
+    m.update_cpu(&3, cpus - 1, &7).unwrap();
+    let mut expected = vec![0u64; cpus];
+    expected[cpus - 1] = 7;
+    assert_eq!(m.get(&3).unwrap().into_vec(), expected);
+
+    m.update(&3, &1, rxdp::MapFlags::BpfAny).unwrap();
+    m.update_cpu(&3, 0, &9).unwrap();
+    let mut expected = vec![1u64; cpus];
+    expected[0] = 9;
+    assert_eq!(m.get(&3).unwrap().into_vec(), expected);
+
+    assert_eq!(m.update_cpu(&3, cpus, &1).err().unwrap().code(), 22);
+}
+
+#[test]
+fn test_prog_array_map() {
+    let obj = loaded_object();
+    let progs = rxdp::ProgArrayMap::new(&obj, PROG_ARRAY).unwrap();
+    assert_eq!(progs.max_entries(), 10);
+    assert!(progs.prog_id(5).unwrap().is_none());
+
+    progs.set_by_name(&obj, 5, PROG_DROP).unwrap();
+    let id = obj.get_program(PROG_DROP).unwrap().info().unwrap().id;
+    assert_eq!(progs.prog_id(5).unwrap(), Some(id));
+
+    progs.remove(5).unwrap();
+    assert!(progs.prog_id(5).unwrap().is_none());
+
+    assert!(progs.set_by_name(&obj, 5, "missing").is_err());
+    assert!(progs.set_by_name(&obj, 10, PROG_DROP).is_err());
+    let err = rxdp::ProgArrayMap::new(&obj, MAP_ARRAY).err().unwrap();
+    assert_eq!(err.code(), 22);
+
+    let created = rxdp::ProgArrayMap::create(2).unwrap();
+    created.set(1, obj.get_program(PROG_DROP).unwrap()).unwrap();
+    assert_eq!(created.prog_id(1).unwrap(), Some(id));
+}
+
+#[test]
+fn test_sampler() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    m.update(&7, &42, rxdp::MapFlags::BpfAny).unwrap();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let sampler = rxdp::SamplerBuilder::new(Duration::from_millis(10))
+        .program("drop", obj.get_program(PROG_DROP).unwrap())
+        .unwrap()
+        .map_counter("seven", &m, 7)
+        .unwrap()
+        .map_counter("missing", &m, 8)
+        .unwrap()
+        .spawn(move |s| {
+            let _ = tx.send(s);
+        })
+        .unwrap();
+
+    let s = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(s.counter("seven"), Some(42));
+    assert_eq!(s.counter("missing"), None);
+    assert_eq!(s.errors.len(), 1);
+    assert_eq!(s.errors[0].0, "missing");
+    assert_eq!(s.errors[0].1.code(), 2);
+    assert!(s.program("drop").is_some());
+    assert!(s.elapsed >= Duration::from_millis(10));
+
+    // The sampler keeps its own references to the program and map.
+    drop(m);
+    drop(obj);
+    while rx.try_recv().is_ok() {}
+    let s = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(s.counter("seven"), Some(42));
+    assert!(s.program("drop").is_some());
+
+    sampler.stop();
+    while rx.try_recv().is_ok() {}
+    assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+}
+
+#[test]
+fn test_devmap() {
+    let obj = loaded_object();
+    let iface = utils::test_iface();
+    let name = iface.name.as_str();
+    let index = utils::lookup_interface_by_name(name).unwrap() as u32;
+
+    for map_name in [DEV_MAP, DEV_MAP_HASH].iter() {
+        let m = rxdp::redirect::DevMap::new(&obj, map_name).unwrap();
+        assert!(!m.has_programs());
+        assert_eq!(m.get(2).unwrap(), None);
+
+        m.set(2, name).unwrap();
+        let entry = m.get(2).unwrap().unwrap();
+        assert_eq!(entry.if_index, index);
+        assert_eq!(entry.if_name.as_deref(), Some(name));
+        assert_eq!(entry.prog_id, None);
+        assert_eq!(m.entries().unwrap(), vec![(2, entry)]);
+        assert!(m.stale_entries().unwrap().is_empty());
+
+        let prog = obj.get_program(PROG_DROP).unwrap();
+        assert_eq!(m.set_with_program(3, name, prog).unwrap_err().code(), 22);
+        assert!(m.set(3, &utils::random_string()).is_err());
+
+        m.remove(2).unwrap();
+        assert!(m.entries().unwrap().is_empty());
+    }
+
+    let m = rxdp::redirect::DevMap::new(&obj, DEV_MAP).unwrap();
+    assert_eq!(m.set(100, name).unwrap_err().code(), 7);
+    let err = rxdp::redirect::DevMap::new(&obj, MAP_HASH).err().unwrap();
+    assert_eq!(err.code(), 22);
+}
+
+#[test]
+fn test_compatibility_report() {
+    let obj = test_object();
+    let report = obj.compatibility_report().unwrap();
+    assert_eq!(report.kernel, rxdp::kernel::version().ok());
+    assert!(report.is_compatible());
+
+    let hash = report.maps.iter().find(|m| m.name == MAP_HASH).unwrap();
+    assert_eq!(hash.map_type, rxdp::MapType::Hash);
+    assert!(hash.supported);
+
+    let prog = report
+        .programs
+        .iter()
+        .find(|p| p.name == PROG_TEST)
+        .unwrap();
+    assert!(prog.type_supported);
+    assert!(report.program_supported(prog));
+    assert!(report.to_string().contains("map hash (hash): ok\n"));
+    assert_eq!(report.features.len(), 5);
+
+    // The report doesn't load anything
+    obj.load().unwrap();
+}
+
+#[test]
+fn test_cpumap() {
+    let obj = test_object();
+    obj.set_expected_attach_type(PROG_DEVMAP, rxdp::ExpectedAttachType::CpuMap)
+        .unwrap();
+    let obj = obj.load().unwrap();
+
+    let m = rxdp::redirect::CpuMap::new(&obj, CPU_MAP).unwrap();
+    assert!(m.has_programs());
+    assert_eq!(m.get(0).unwrap(), None);
+
+    m.set_queue(0, 192).unwrap();
+    let expected = rxdp::redirect::CpuMapVal {
+        qsize: 192,
+        bpf_prog_fd: 0,
+    };
+    assert_eq!(m.get(0).unwrap(), Some(expected));
+
+    let prog = obj.get_program(PROG_DEVMAP).unwrap();
+    m.set_with_program(0, 256, prog).unwrap();
+    let v = m.get(0).unwrap().unwrap();
+    assert_eq!(v.qsize, 256);
+    assert_eq!(v.bpf_prog_fd as u32, prog.info().unwrap().id);
+
+    m.remove(0).unwrap();
+    assert_eq!(m.get(0).unwrap(), None);
+
+    let cpus = rxdp::num_cpus() as u32;
+    assert_eq!(m.set_queue(cpus.min(4), 192).unwrap_err().code(), 7);
+    let err = rxdp::redirect::CpuMap::new(&obj, DEV_MAP).err().unwrap();
+    assert_eq!(err.code(), 22);
+}
+
+#[test]
+fn test_map_flags_combinations() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+
+    let invalid = rxdp::MapFlags::BpfNoExist | rxdp::MapFlags::BpfExist;
+    assert_eq!(m.update(&1, &1, invalid).unwrap_err().code(), 22);
+    assert!(m.lookup(&1).is_err());
+
+    m.update(&1, &1, rxdp::MapFlags::default()).unwrap();
+    assert_eq!(
+        m.lookup_with_flags(&1, rxdp::MapFlags::BpfAny)
+            .unwrap()
+            .into_single(),
+        1
+    );
+
+    // The values of the map don't have a spin lock
+    let locked = rxdp::MapFlags::BpfExist | rxdp::MapFlags::BpfLock;
+    assert_eq!(m.update(&1, &2, locked).unwrap_err().code(), 22);
+    assert_eq!(
+        m.lookup_with_flags(&1, rxdp::MapFlags::BpfLock)
+            .unwrap_err()
+            .code(),
+        22
+    );
+}
+
+#[test]
+fn test_items_sorted_and_paged() {
+    let m = rxdp::MapBuilder::<u32, u32>::new()
+        .max_entries(100)
+        .create()
+        .unwrap();
+    for i in (0..25u32).rev() {
+        m.update(&(i * 3), &i, rxdp::MapFlags::BpfAny).unwrap();
+    }
+
+    let keys: Vec<u32> = m.items_sorted().unwrap().iter().map(|kv| kv.key).collect();
+    assert_eq!(keys, (0..25).map(|i| i * 3).collect::<Vec<u32>>());
+
+    let mut paged = Vec::new();
+    let mut after = None;
+    loop {
+        let page = m.items_page(after.as_ref(), 10).unwrap();
+        assert!(page.items.len() <= 10);
+        paged.extend(page.items.iter().map(|kv| kv.key));
+        // Inserted behind the page being read, so not returned
+        m.update(&1, &1, rxdp::MapFlags::BpfAny).unwrap();
+
+        match page.next_after {
+            Some(k) => after = Some(k),
+            None => break,
+        }
+    }
+    assert_eq!(paged, keys);
+
+    let page = m.items_page(Some(&72), 10).unwrap();
+    assert!(page.items.is_empty());
+    assert!(page.next_after.is_none());
+    assert_eq!(m.items_page(None, 0).err().unwrap().code(), 22);
+}
+
+#[test]
+fn test_stack_trace_map() {
+    let obj = loaded_object();
+    let stacks = rxdp::StackTraceMap::new(&obj, STACK_TRACES).unwrap();
+    assert_eq!(stacks.max_depth(), 127);
+    assert!(stacks.stack_ids().unwrap().is_empty());
+    assert!(stacks.get(0).unwrap().is_none());
+
+    let err = rxdp::StackTraceMap::new(&obj, MAP_HASH).err().unwrap();
+    assert_eq!(err.code(), 22);
+
+    // Resolve a function of the test binary itself.
+    let syms = rxdp::Symbolizer::process(std::process::id()).unwrap();
+    let ip = test_stack_trace_map as fn() as usize as u64;
+    let sym = syms.resolve(ip + 1).unwrap();
+    assert!(sym.name.contains("test_stack_trace_map"));
+    assert_eq!(sym.offset, 1);
+    assert!(sym.module.is_some());
+}
+
+#[test]
+fn test_bloom_filter() {
+    let f: rxdp::BloomFilter<u64> = rxdp::BloomFilter::create(100, Some(3)).unwrap();
+    assert_eq!(f.max_entries(), 100);
+    for v in 0..50u64 {
+        f.push(&(v * 7)).unwrap();
+    }
+    for v in 0..50u64 {
+        assert!(f.contains(&(v * 7)).unwrap());
+    }
+    // Values that weren't pushed can be false positives, but not most of them.
+    let positives = (1000..2000u64)
+        .filter(|v| f.contains(&(v * 7)).unwrap())
+        .count();
+    assert!(positives < 200);
+
+    let err = rxdp::BloomFilter::<u64>::create(100, Some(16))
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), 22);
+    assert!(rxdp::BloomFilter::<u64>::create(100, None).is_ok());
+
+    let obj = loaded_object();
+    let err = rxdp::BloomFilter::<u32>::new(&obj, MAP_HASH).err().unwrap();
+    assert_eq!(err.code(), 22);
+}
+
+#[derive(Default, Clone, Copy)]
+#[repr(C)]
+struct TimerSlot {
+    timeout_ms: u64,
+    timer: rxdp::BpfTimer,
+}
+
+rxdp::special_fields!(TimerSlot {
+    timer: rxdp::BpfTimer
+});
+
+#[test]
+fn test_slot_array() {
+    let slots: rxdp::SlotArray<TimerSlot> = rxdp::SlotArray::create(4).unwrap();
+    assert_eq!(slots.max_entries(), 4);
+
+    let mut slot = slots.get(1).unwrap();
+    assert_eq!(slot.timeout_ms, 0);
+    slot.timeout_ms = 500;
+    slots.update(1, &slot).unwrap();
+    assert_eq!(slots.get(1).unwrap().timeout_ms, 500);
+
+    // Out of range for an array
+    assert!(slots.update(4, &slot).is_err());
+
+    let mut bad = slot;
+    bad.timer = unsafe { std::mem::transmute([1u64, 0u64]) };
+    assert_eq!(slots.update(2, &bad).unwrap_err().code(), 22);
+
+    slots.update(3, &slot).unwrap();
+    slots.reset(1).unwrap();
+    assert_eq!(slots.get(1).unwrap().timeout_ms, 0);
+    slots.reset_all().unwrap();
+    assert_eq!(slots.get(3).unwrap().timeout_ms, 0);
+
+    let obj = loaded_object();
+    // A hash map with smaller values
+    let err = rxdp::SlotArray::<TimerSlot>::new(&obj, MAP_HASH)
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), 22);
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct LockSlot {
+    lock: rxdp::BpfSpinLock,
+    packets: u32,
+}
+
+rxdp::special_fields!(LockSlot {
+    lock: rxdp::BpfSpinLock
+});
+
+#[test]
+fn test_slot_array_spin_lock() {
+    let obj = loaded_object();
+    // Defined in `.maps`, so the kernel knows where the lock is
+    let slots: rxdp::SlotArray<LockSlot> = rxdp::SlotArray::new(&obj, LOCK_SLOTS).unwrap();
+    assert_eq!(slots.max_entries(), 4);
+
+    let slot = LockSlot {
+        packets: 9,
+        ..Default::default()
+    };
+    slots.update(2, &slot).unwrap();
+    assert_eq!(slots.get(2).unwrap().packets, 9);
+
+    slots.reset_all().unwrap();
+    assert_eq!(slots.get(2).unwrap().packets, 0);
+
+    let mut bad = slot;
+    bad.lock = unsafe { std::mem::transmute(1u32) };
+    assert_eq!(slots.update(1, &bad).unwrap_err().code(), 22);
+}
+
+#[derive(Default, Clone, Copy)]
+#[repr(C)]
+struct SharedStats {
+    packets: u64,
+    sample_rate: u32,
+    flags: u32,
+}
+
+rxdp::no_padding!(SharedStats {
+    packets: u64,
+    sample_rate: u32,
+    flags: u32
+});
+
+#[test]
+fn test_shared_region() {
+    use std::sync::atomic::Ordering;
+
+    let region: rxdp::SharedRegion<SharedStats> = rxdp::SharedRegion::create(4).unwrap();
+    assert_eq!(region.max_entries(), 4);
+
+    let stats = SharedStats {
+        packets: 1,
+        sample_rate: 100,
+        flags: 0,
+    };
+    region.write(2, &stats).unwrap();
+    region
+        .atomic_u64(2, 0)
+        .unwrap()
+        .fetch_add(9, Ordering::SeqCst);
+    region.atomic_u32(2, 12).unwrap().store(3, Ordering::SeqCst);
+
+    let read = region.read(2).unwrap();
+    assert_eq!((read.packets, read.sample_rate, read.flags), (10, 100, 3));
+    assert_eq!(region.read(0).unwrap().packets, 0);
+
+    assert_eq!(region.read(4).err().unwrap().code(), 7);
+    assert_eq!(region.atomic_u64(0, 4).unwrap_err().code(), 22);
+    assert_eq!(region.atomic_u32(0, 14).unwrap_err().code(), 22);
+    assert_eq!(region.atomic_u64(0, usize::MAX).unwrap_err().code(), 22);
+
+    // Writes through the mapping and through syscalls are visible to each other
+    let m: rxdp::Map<u32, SharedStats> = rxdp::Map::from_fd(region.map_fd()).unwrap();
+    let got = m.lookup(&2).unwrap().into_single();
+    assert_eq!((got.packets, got.sample_rate, got.flags), (10, 100, 3));
+    let stats = SharedStats {
+        packets: 5,
+        sample_rate: 10,
+        flags: 1,
+    };
+    m.update(&1, &stats, rxdp::MapFlags::BpfAny).unwrap();
+    assert_eq!(region.atomic_u64(1, 0).unwrap().load(Ordering::SeqCst), 5);
+
+    let obj = loaded_object();
+    let err = rxdp::SharedRegion::<u32>::new(&obj, MAP_ARRAY)
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), 22);
+}
+
+#[cfg(feature = "pcap")]
+#[test]
+fn test_replay_pcap() {
+    let obj = loaded_object();
+    let prog = obj.get_program(PROG_DROP).unwrap();
+
+    // 3 Ethernet frames, one truncated by the snaplen and one shorter than a header.
+    let mut pcap = Vec::new();
+    pcap.extend(0xa1b2_c3d4u32.to_le_bytes());
+    pcap.extend([0u8; 16]);
+    pcap.extend(1u32.to_le_bytes());
+    for (len, orig_len) in [(64u32, 64u32), (60, 1500), (4, 4)].iter() {
+        pcap.extend([0u8; 8]);
+        pcap.extend(len.to_le_bytes());
+        pcap.extend(orig_len.to_le_bytes());
+        pcap.extend(vec![0xffu8; *len as usize]);
+    }
+    let path = format!("/tmp/rxdp_replay_{}.pcap", utils::random_string());
+    std::fs::write(&path, &pcap).unwrap();
+
+    let report = prog.replay_pcap(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(report.packets, 3);
+    assert_eq!(report.count(1), 2); // XDP_DROP
+    assert_eq!(report.count(2), 0);
+    assert_eq!(report.rejected, 1);
+    assert_eq!(report.truncated, 1);
+    assert_eq!(report.latency.unwrap().iterations, 2);
+
+    let err = prog
+        .replay_pcap_from(&mut &b"not a pcap"[..])
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), 22);
+}
+
+#[test]
+fn test_items_labeled() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    m.update(&7, &70, rxdp::MapFlags::BpfAny).unwrap();
+    m.update(&1000, &1, rxdp::MapFlags::BpfAny).unwrap();
+
+    let mut items = m.items_labeled().unwrap();
+    items.sort_by(|a, b| a.key.cmp(&b.key));
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].key, "1000");
+    assert_eq!(items[1].key, "7");
+    assert_eq!(items[1].value, rxdp::MapValue::Single(70));
+}
+
+#[test]
+fn test_clear() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH_BIG).unwrap();
+    for i in 0..250u32 {
+        m.update(&i, &i, rxdp::MapFlags::BpfAny).unwrap();
+    }
+    assert_eq!(m.clear().unwrap(), 250);
+    assert!(m.items().unwrap().is_empty());
+    assert_eq!(m.clear().unwrap(), 0);
+
+    let a: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_ARRAY).unwrap();
+    a.update(&1, &10, rxdp::MapFlags::BpfAny).unwrap();
+    assert_eq!(a.clear().unwrap(), a.max_entries());
+    assert_eq!(a.lookup(&1).unwrap().into_single(), 0);
+
+    let pc: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_ARRAY).unwrap();
+    pc.update(&0, &5, rxdp::MapFlags::BpfAny).unwrap();
+    pc.clear().unwrap();
+    assert!(pc.lookup(&0).unwrap().into_vec().iter().all(|v| *v == 0));
+}
+
+#[test]
+fn test_copy_to() {
+    let obj = loaded_object();
+    let src: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH_BIG).unwrap();
+    let dst = rxdp::MapBuilder::<u32, u32>::new()
+        .max_entries(1000)
+        .create()
+        .unwrap();
+    for i in 0..250u32 {
+        src.update(&i, &(i * 2), rxdp::MapFlags::BpfAny).unwrap();
+    }
+    assert_eq!(src.copy_to(&dst).unwrap(), 250);
+    assert_eq!(dst.get(&100).unwrap(), 200);
+
+    let even = rxdp::MapBuilder::<u32, u64>::new()
+        .max_entries(1000)
+        .create()
+        .unwrap();
+    let n = src
+        .copy_to_with(&even, |k, v| match k % 2 {
+            0 => Some((*k, *v as u64 + 1)),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(n, 125);
+    assert_eq!(even.get(&100).unwrap(), 201);
+    assert!(even.lookup(&101).is_err());
+}
+
+#[test]
+fn test_pinned_xdp_link() {
+    let test_dir = utils::pin_dir();
+    let link_path = format!("{}/xdp_link", &test_dir.path);
+    let iface = utils::test_iface();
+
+    let obj = loaded_object();
+    let link = obj
+        .get_program(PROG_TEST)
+        .unwrap()
+        .attach_xdp_link(&iface.name)
+        .unwrap();
+    assert_eq!(link.unpin().err().unwrap().code(), 22);
+    link.update_program(&obj.get_program(PROG_DROP).unwrap())
+        .unwrap();
+    link.pin(&link_path).unwrap();
+    drop(link);
+    drop(obj);
+    // The pinned link keeps the program attached.
+    assert!(utils::xdp_attached(&iface.name));
+
+    let obj = loaded_object();
+    let link = rxdp::Link::open_pinned(&link_path).unwrap();
+    link.update_program(&obj.get_program(PROG_DROP).unwrap())
+        .unwrap();
+    assert!(utils::xdp_attached(&iface.name));
+
+    link.unpin().unwrap();
+    drop(link);
+    assert!(!utils::xdp_attached(&iface.name));
+}
+
+#[test]
+fn test_map_from_pinned() {
+    let test_dir = utils::pin_dir();
+    let pin_path = format!("{}/pinned_hash", &test_dir.path);
+    let m = rxdp::MapBuilder::<u32, u32>::new()
+        .max_entries(10)
+        .pin_path(&pin_path)
+        .create()
+        .unwrap();
+    m.update(&1, &2, rxdp::MapFlags::BpfAny).unwrap();
+
+    let pinned: rxdp::Map<u32, u32> = rxdp::Map::from_pinned(&pin_path).unwrap();
+    assert_eq!(pinned.get(&1).unwrap(), 2);
+    assert_eq!(pinned.max_entries(), 10);
+
+    let err = rxdp::Map::<u32, u64>::from_pinned(&pin_path).err().unwrap();
+    assert!(err.description().contains("value size"));
+    let err = rxdp::PerCpuMap::<u32, u32>::from_pinned(&pin_path)
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), 22);
+    assert!(rxdp::Map::<u32, u32>::from_pinned("/sys/fs/bpf/rxdp_missing").is_err());
+
+    let pin_path = format!("{}/pinned_percpu", &test_dir.path);
+    let pc = rxdp::PerCpuMapBuilder::<u32, u64>::new()
+        .map_type(rxdp::MapType::PerCPUArray)
+        .max_entries(4)
+        .pin_path(&pin_path)
+        .create()
+        .unwrap();
+    pc.update(&2, &7, rxdp::MapFlags::BpfAny).unwrap();
+
+    let pinned: rxdp::PerCpuMap<u32, u64> = rxdp::PerCpuMap::from_pinned(&pin_path).unwrap();
+    assert!(pinned.get(&2).unwrap().iter().all(|v| *v == 7));
+}
+
+#[test]
+fn test_typed_map_from_fd() {
+    let obj = loaded_object();
+    let m: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    m.update(&3, &30, rxdp::MapFlags::BpfAny).unwrap();
+
+    let other: rxdp::Map<u32, u32> = rxdp::Map::from_fd(m.map_fd()).unwrap();
+    assert_ne!(other.map_fd(), m.map_fd());
+    assert_eq!(other.get(&3).unwrap(), 30);
+    drop(other);
+    // The original fd is still open.
+    assert_eq!(m.get(&3).unwrap(), 30);
+
+    let err = rxdp::Map::<u64, u32>::from_fd(m.map_fd()).err().unwrap();
+    assert!(err.description().contains("key size"));
+    assert!(rxdp::Map::<u32, u32>::from_fd(-1).is_err());
+    assert!(rxdp::Map::<u32, u32>::from_id(u32::MAX).is_err());
+}
+
+#[test]
+fn test_per_cpu_map_from_fd() {
+    let obj = loaded_object();
+    let m: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::new(&obj, MAP_PERCPU_HASH).unwrap();
+    m.update(&3, &30, rxdp::MapFlags::BpfAny).unwrap();
+
+    let other: rxdp::PerCpuMap<u32, u32> = rxdp::PerCpuMap::from_fd(m.map_fd()).unwrap();
+    assert_ne!(other.map_fd(), m.map_fd());
+    assert_eq!(other.lookup(&3).unwrap(), m.lookup(&3).unwrap());
+    drop(other);
+    assert!(m.lookup(&3).is_ok());
+
+    let hash: rxdp::Map<u32, u32> = rxdp::Map::new(&obj, MAP_HASH).unwrap();
+    let err = rxdp::PerCpuMap::<u32, u32>::from_fd(hash.map_fd())
+        .err()
+        .unwrap();
+    assert_eq!(err.code(), 22);
+    assert!(rxdp::PerCpuMap::<u32, u32>::from_id(u32::MAX).is_err());
+}
@@ -43,6 +43,46 @@ struct bpf_map_def SEC("maps") prog_array = {
     .max_entries = 10,
 };
 
+struct bpf_map_def SEC("maps") tail_selector = {
+    .type = BPF_MAP_TYPE_ARRAY,
+    .key_size = sizeof(__u32),
+    .value_size = sizeof(__u32),
+    .max_entries = 4,
+};
+
+struct bpf_map_def SEC("maps") config_a = {
+    .type = BPF_MAP_TYPE_HASH,
+    .key_size = sizeof(__u32),
+    .value_size = sizeof(__u64),
+    .max_entries = 16,
+};
+
+struct bpf_map_def SEC("maps") config_b = {
+    .type = BPF_MAP_TYPE_HASH,
+    .key_size = sizeof(__u32),
+    .value_size = sizeof(__u64),
+    .max_entries = 16,
+};
+
+struct bpf_map_def SEC("maps") config_selector = {
+    .type = BPF_MAP_TYPE_ARRAY,
+    .key_size = sizeof(__u32),
+    .value_size = sizeof(__u32),
+    .max_entries = 1,
+};
+
+struct bpf_map_def SEC("maps") flows = {
+    .type = BPF_MAP_TYPE_HASH,
+    .key_size = sizeof(__u32),
+    .value_size = 2 * sizeof(__u64),
+    .max_entries = 1000,
+};
+
+struct bpf_map_def SEC("maps") ring_buf = {
+    .type = BPF_MAP_TYPE_RINGBUF,
+    .max_entries = 4096,
+};
+
 struct bpf_map_def SEC("maps") dev_map = {
     .type = BPF_MAP_TYPE_DEVMAP,
     .key_size = sizeof(__u32),
@@ -50,6 +90,41 @@ struct bpf_map_def SEC("maps") dev_map = {
     .max_entries = 10,
 };
 
+struct bpf_map_def SEC("maps") dev_map_hash = {
+    .type = BPF_MAP_TYPE_DEVMAP_HASH,
+    .key_size = sizeof(__u32),
+    .value_size = sizeof(int),
+    .max_entries = 10,
+};
+
+struct bpf_map_def SEC("maps") cpu_map = {
+    .type = BPF_MAP_TYPE_CPUMAP,
+    .key_size = sizeof(__u32),
+    .value_size = sizeof(struct bpf_cpumap_val),
+    .max_entries = 4,
+};
+
+struct bpf_map_def SEC("maps") stack_traces = {
+    .type = BPF_MAP_TYPE_STACK_TRACE,
+    .key_size = sizeof(__u32),
+    .value_size = 127 * sizeof(__u64),
+    .max_entries = 16,
+};
+
+struct bpf_map_def SEC("maps") xsk_map = {
+    .type = BPF_MAP_TYPE_XSKMAP,
+    .key_size = sizeof(__u32),
+    .value_size = sizeof(int),
+    .max_entries = 10,
+};
+
+struct bpf_map_def SEC("maps") sock_map = {
+    .type = BPF_MAP_TYPE_SOCKMAP,
+    .key_size = sizeof(__u32),
+    .value_size = sizeof(int),
+    .max_entries = 10,
+};
+
 struct bpf_map_def SEC("maps") per_cpu_hash = {
     .type = BPF_MAP_TYPE_PERCPU_HASH,
     .key_size = sizeof(__u32),
@@ -91,6 +166,26 @@ struct bpf_map_def SEC("maps") perf_event = {
     .value_size = sizeof(__u32),
 };
 
+struct lock_slot {
+    struct bpf_spin_lock lock;
+    __u32 packets;
+};
+
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __uint(max_entries, 4);
+    __type(key, __u32);
+    __type(value, struct lock_slot);
+} lock_slots SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, 10);
+    __type(key, __u32);
+    __type(value, __u32);
+    __uint(pinning, LIBBPF_PIN_BY_NAME);
+} pinned_by_name SEC(".maps");
+
 
 
 SEC("xdp_test")
@@ -116,4 +211,18 @@ int rxdp_perf(struct xdp_md *ctx)
     return XDP_PASS;
 }
 
+SEC("xdp_ringbuf")
+int rxdp_ringbuf(struct xdp_md *ctx)
+{
+    __u32 value = 7;
+    bpf_ringbuf_output(&ring_buf, &value, sizeof(value), 0);
+    return XDP_PASS;
+}
+
+SEC("xdp_devmap/rxdp_devmap")
+int rxdp_devmap(struct xdp_md *ctx)
+{
+    return XDP_PASS;
+}
+
 char _license[] SEC("license") = "GPL";
@@ -105,7 +105,10 @@ impl Drop for TestIface {
 }
 
 pub fn test_iface() -> TestIface {
-    let name = random_string();
+    test_iface_named(random_string())
+}
+
+pub fn test_iface_named(name: String) -> TestIface {
     cmd!("ip", "link", "add", &name, "link", "eth0", "type", "macvlan", "mode", "bridge")
         .status()
         .expect("failed to create interface");
@@ -117,6 +120,14 @@ pub fn test_iface() -> TestIface {
     }
 }
 
+// True if a program is attached to `iface` in generic (SKB) mode.
+pub fn xdp_attached(iface: &str) -> bool {
+    let out = cmd!("ip", "link", "show", "dev", iface)
+        .output()
+        .expect("failed to show interface");
+    String::from_utf8_lossy(&out.stdout).contains("xdpgeneric")
+}
+
 pub(crate) fn str_to_cstring(s: &str) -> Result<CString, String> {
     match CString::new(s) {
         Ok(c) => Ok(c),